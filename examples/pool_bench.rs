@@ -0,0 +1,77 @@
+//! Compares stepping many VMs in lockstep as a `Vec<VirtualMachine>` and as a `VmPool`.
+//!
+//! Usage: `cargo run --release --example pool_bench -- [instances] [cycles]`
+//!
+//! Every instance runs its own generated program, with the timers ticking every 10 cycles.
+
+use {
+    crusty_chip::{Quirks, VirtualMachine, pool::VmPool, romgen::Generator},
+    std::{process::ExitCode, time::Instant},
+};
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let mut arg = |default: usize| match args.next().map(|s| s.parse()) {
+        None => Ok(default),
+        Some(result) => result,
+    };
+    let (instances, cycles) = match (arg(4096), arg(10_000)) {
+        (Ok(instances), Ok(cycles)) => (instances, cycles),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("Usage: pool_bench [instances] [cycles]\n{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut generator = Generator::new(0);
+    let roms: Vec<_> = (0..instances).map(|_| generator.generate(200)).collect();
+
+    let mut vms: Vec<_> = roms
+        .iter()
+        .enumerate()
+        .map(|(seed, rom)| {
+            let mut vm = VirtualMachine::new();
+            vm.set_rng_seed(seed as u64);
+            vm.load_rom(rom);
+            vm
+        })
+        .collect();
+    let start = Instant::now();
+    for cycle in 0..cycles {
+        for vm in &mut vms {
+            vm.do_cycle();
+        }
+        if cycle % 10 == 9 {
+            vms.iter_mut().for_each(VirtualMachine::decrement_timers);
+        }
+    }
+    let naive = start.elapsed();
+
+    let mut pool = VmPool::new(Quirks::default());
+    for (seed, rom) in roms.iter().enumerate() {
+        pool.push(rom, seed as u64);
+    }
+    let start = Instant::now();
+    for cycle in 0..cycles {
+        pool.step_all();
+        if cycle % 10 == 9 {
+            pool.decrement_timers();
+        }
+    }
+    let soa = start.elapsed();
+
+    let per_cycle = |elapsed: std::time::Duration| {
+        elapsed.as_secs_f64() * 1e9 / (instances as f64 * cycles as f64)
+    };
+    println!("{} instances, {} cycles each", instances, cycles);
+    println!(
+        "Vec<VirtualMachine>: {:8.1} ms, {:5.2} ns/instruction",
+        naive.as_secs_f64() * 1e3,
+        per_cycle(naive)
+    );
+    println!(
+        "VmPool:              {:8.1} ms, {:5.2} ns/instruction",
+        soa.as_secs_f64() * 1e3,
+        per_cycle(soa)
+    );
+    ExitCode::SUCCESS
+}
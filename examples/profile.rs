@@ -0,0 +1,48 @@
+//! Runs a ROM headless, and prints how often each instruction ran and how long it took.
+//!
+//! Usage: `cargo run --release --example profile -- <rom> [frames]`
+
+use {
+    crusty_chip::VirtualMachine,
+    std::{process::ExitCode, time::Instant},
+};
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let Some(path) = args.next() else {
+        eprintln!("Usage: profile <rom> [frames]");
+        return ExitCode::FAILURE;
+    };
+    let frames = match args.next().map(|s| s.parse()) {
+        None => 600,
+        Some(Ok(frames)) => frames,
+        Some(Err(e)) => {
+            eprintln!("Invalid frame count: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let rom = match std::fs::read(&path) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("Failed to read \"{}\": {}", path, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut vm = VirtualMachine::new();
+    vm.load_rom(&rom);
+    vm.set_profiling(true);
+    let start = Instant::now();
+    for _ in 0..frames {
+        vm.step_frame();
+    }
+    let elapsed = start.elapsed();
+    let profile = vm.profile().unwrap();
+    print!("{}", profile);
+    println!(
+        "{} instructions in {:.1} ms, about {:.1} ms of which executing them",
+        profile.instructions(),
+        elapsed.as_secs_f64() * 1e3,
+        profile.estimated_time().as_secs_f64() * 1e3
+    );
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,41 @@
+//! Runs every ROM in a directory headless, and prints a report.
+//!
+//! Usage: `cargo run --example smoke_test -- <dir> [frames]`
+//!
+//! Exits with a failure status if any ROM looks broken, so it can be used in CI.
+
+use {
+    crusty_chip::smoke::smoke_test_dir,
+    std::{path::Path, process::ExitCode},
+};
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let Some(dir) = args.next() else {
+        eprintln!("Usage: smoke_test <dir> [frames]");
+        return ExitCode::FAILURE;
+    };
+    let frames = match args.next().map(|s| s.parse()) {
+        None => 600,
+        Some(Ok(frames)) => frames,
+        Some(Err(e)) => {
+            eprintln!("Invalid frame count: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let report = match smoke_test_dir(Path::new(&dir), frames) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("Failed to read \"{}\": {}", dir, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    print!("{}", report);
+    let suspicious = report.suspicious().count();
+    println!("{} ROMs, {} suspicious", report.results.len(), suspicious);
+    if suspicious == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
@@ -0,0 +1,115 @@
+//! Coloring sprites by the address they're drawn from.
+//!
+//! Some classic CHIP-8 screenshots were colored by hand, with the player in one color and
+//! the enemies in another. Rules map sprite addresses to colors to get the same look.
+//! They're saved per ROM, in a `<rom>.colors` file with one `<address> <color>` rule per
+//! line, like `0x2A0 #FF0000`.
+
+use {
+    crusty_chip::{
+        Resolution, SpriteDraw,
+        palette::{Rgb, parse_hex_color},
+    },
+    std::{
+        fs, io,
+        path::{Path, PathBuf},
+    },
+};
+
+/// Sprites drawn from `addr` are shown in `color`.
+pub struct Rule {
+    /// The address the sprite is read from.
+    pub addr: u16,
+    /// The color of its pixels.
+    pub color: Rgb,
+}
+
+/// The rules file of a ROM.
+pub fn rules_path(rom_path: &Path) -> PathBuf {
+    let mut name = rom_path.file_name().unwrap_or_default().to_owned();
+    name.push(".colors");
+    rom_path.with_file_name(name)
+}
+
+/// Loads the rules saved for a ROM. Missing files and malformed lines give no rules.
+pub fn load(path: &Path) -> Vec<Rule> {
+    let Ok(text) = fs::read_to_string(path) else {
+        return Vec::new();
+    };
+    text.lines()
+        .filter_map(|line| {
+            let (addr, color) = line.trim().split_once(char::is_whitespace)?;
+            let addr = u16::from_str_radix(addr.trim_start_matches("0x"), 16).ok()?;
+            let color = parse_hex_color(color.trim()).ok()?;
+            Some(Rule { addr, color })
+        })
+        .collect()
+}
+
+/// Saves the rules for a ROM.
+pub fn save(path: &Path, rules: &[Rule]) -> io::Result<()> {
+    let text: String = rules
+        .iter()
+        .map(|rule| {
+            let [r, g, b] = rule.color;
+            format!("{:#05X} #{:02X}{:02X}{:02X}\n", rule.addr, r, g, b)
+        })
+        .collect();
+    fs::write(path, text)
+}
+
+/// The color each pixel was last drawn in, if a rule applied.
+pub struct Overlay {
+    width: usize,
+    height: usize,
+    colors: Vec<Option<Rgb>>,
+}
+
+impl Default for Overlay {
+    fn default() -> Self {
+        Self::new(Resolution::Low)
+    }
+}
+
+impl Overlay {
+    /// Creates an overlay without colors for a display of the given resolution.
+    pub fn new(resolution: Resolution) -> Self {
+        let (width, height) = (resolution.width(), resolution.height());
+        Self {
+            width,
+            height,
+            colors: vec![None; width * height],
+        }
+    }
+
+    /// Colors the pixels set by a sprite, or uncolors them if no rule applies to it.
+    pub fn draw(&mut self, draw: &SpriteDraw, memory: &[u8], rules: &[Rule]) {
+        let color = rules
+            .iter()
+            .find(|rule| rule.addr == draw.addr)
+            .map(|rule| rule.color);
+        let row_len = usize::from(draw.width) / 8;
+        for row in 0..usize::from(draw.height) {
+            let y = usize::from(draw.y) + row;
+            for col in 0..usize::from(draw.width) {
+                let Some(&bits) = memory.get(usize::from(draw.addr) + row * row_len + col / 8)
+                else {
+                    return;
+                };
+                let x = usize::from(draw.x) + col;
+                if bits & (0x80 >> (col % 8)) != 0 && x < self.width && y < self.height {
+                    self.colors[y * self.width + x] = color;
+                }
+            }
+        }
+    }
+
+    /// Returns the color of the pixel at `x`, `y`, if a rule applied to it.
+    pub fn color(&self, x: usize, y: usize) -> Option<Rgb> {
+        if x < self.width && y < self.height {
+            self.colors[y * self.width + x]
+        } else {
+            None
+        }
+    }
+}
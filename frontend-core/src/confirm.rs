@@ -0,0 +1,99 @@
+//! Asking before actions that lose progress.
+//!
+//! A reset pressed by accident throws away a whole run. Depending on the
+//! [settings](crate::settings::Settings), resetting, loading a state over progress that
+//! wasn't saved, and saving over an older state wait for the user to confirm them.
+
+use {crate::settings::Settings, crusty_chip::VirtualMachine};
+
+/// An action that can lose progress.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Starting the ROM over.
+    Reset,
+    /// Loading the state saved to a slot.
+    Load(usize),
+    /// Saving the state to a slot.
+    Save(usize),
+}
+
+impl Action {
+    /// Returns whether `settings` ask to confirm the action first.
+    ///
+    /// `unsaved` is whether the VM ran since it was last saved or loaded, and `slot_taken`
+    /// whether the slot loaded from or saved to holds a state. Loading an empty slot loses
+    /// nothing.
+    pub fn needs_confirmation(self, settings: &Settings, unsaved: bool, slot_taken: bool) -> bool {
+        match self {
+            Action::Reset => settings.confirm_reset,
+            Action::Load(_) => settings.confirm_load && unsaved && slot_taken,
+            Action::Save(_) => settings.confirm_overwrite && slot_taken,
+        }
+    }
+
+    /// The question asking the user to confirm the action.
+    pub fn question(self) -> String {
+        match self {
+            Action::Reset => "Start over? Progress since the last save is lost.".to_owned(),
+            Action::Load(slot) => format!(
+                "Load state {}? Progress since the last save is lost.",
+                slot + 1
+            ),
+            Action::Save(slot) => format!(
+                "Overwrite state {}? The previous save stays in the backup until the next.",
+                slot + 1
+            ),
+        }
+    }
+}
+
+/// Keeps track of whether the VM ran since it was last saved or loaded.
+#[derive(Debug)]
+pub struct Progress {
+    saved_at: u64,
+}
+
+impl Progress {
+    /// Starts tracking, taking the current state of `vm` as saved.
+    pub fn new(vm: &VirtualMachine) -> Self {
+        Self {
+            saved_at: vm.cycle_count(),
+        }
+    }
+
+    /// Takes the current state of `vm` as saved, after saving or loading it, or starting over.
+    pub fn mark_saved(&mut self, vm: &VirtualMachine) {
+        self.saved_at = vm.cycle_count();
+    }
+
+    /// Returns whether `vm` ran since its state was last saved.
+    pub fn is_unsaved(&self, vm: &VirtualMachine) -> bool {
+        vm.cycle_count() != self.saved_at
+    }
+}
+
+#[test]
+fn test_needs_confirmation() {
+    let mut vm = VirtualMachine::new();
+    // 0x200: JP 0x200
+    vm.load_rom(&[0x12, 0x00]);
+    let mut progress = Progress::new(&vm);
+    let mut settings = Settings::default();
+    fn load(progress: &Progress, vm: &VirtualMachine, settings: &Settings) -> bool {
+        Action::Load(0).needs_confirmation(settings, progress.is_unsaved(vm), true)
+    }
+    assert!(!load(&progress, &vm, &settings));
+    vm.do_cycle();
+    assert!(load(&progress, &vm, &settings));
+    progress.mark_saved(&vm);
+    assert!(!load(&progress, &vm, &settings));
+    vm.do_cycle();
+    settings.confirm_load = false;
+    assert!(!load(&progress, &vm, &settings));
+    settings.confirm_load = true;
+    assert!(!Action::Load(0).needs_confirmation(&settings, true, false));
+    // Saving only asks when there's a state to lose
+    assert!(Action::Save(0).needs_confirmation(&settings, true, true));
+    assert!(!Action::Save(0).needs_confirmation(&settings, true, false));
+    assert!(Action::Reset.needs_confirmation(&settings, false, false));
+}
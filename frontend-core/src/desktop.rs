@@ -0,0 +1,187 @@
+//! The window icon, and integration with freedesktop.org desktops, so double-clicking a
+//! ROM in a file manager opens it.
+//!
+//! The icon is "C8" in the VM's own font, drawn in Octo's colors. `--install-desktop`
+//! installs it along with a `.desktop` file and a MIME type for `.ch8` files into the
+//! user's data directory.
+
+use {
+    crusty_chip::{FONT_ADDR, Palette, VirtualMachine},
+    std::{
+        env,
+        fmt::Write,
+        fs, io,
+        path::{Path, PathBuf},
+        process::Command,
+    },
+};
+
+/// The name the desktop files are installed under.
+pub const APP_ID: &str = "crusty-chip";
+/// The MIME type of CHIP-8 ROMs.
+pub const MIME_TYPE: &str = "application/x-chip8";
+// The icon in CHIP-8 pixels, with the two digits in the middle
+const ICON_PIXELS: usize = 11;
+// How many icon pixels a CHIP-8 pixel takes up in the window icon
+const ICON_SCALE: usize = 4;
+
+// Returns the pixels of the icon, row by row, as 0 or 1
+fn icon_bitmap() -> [[u8; ICON_PIXELS]; ICON_PIXELS] {
+    let vm = VirtualMachine::new();
+    let mut bitmap = [[0; ICON_PIXELS]; ICON_PIXELS];
+    for (i, digit) in [0xCu16, 0x8].into_iter().enumerate() {
+        let sprite = &vm.memory()[usize::from(FONT_ADDR + digit * 5)..][..5];
+        for (y, row) in sprite.iter().enumerate() {
+            for x in 0..4 {
+                bitmap[3 + y][1 + i * 5 + x] = row >> (7 - x) & 1;
+            }
+        }
+    }
+    bitmap
+}
+
+/// Returns the window icon as its width, height, and RGBA pixels.
+pub fn icon_rgba() -> (u32, u32, Vec<u8>) {
+    let size = ICON_PIXELS * ICON_SCALE;
+    let bitmap = icon_bitmap();
+    let mut pixels = Vec::with_capacity(size * size * 4);
+    for y in 0..size {
+        for x in 0..size {
+            let [r, g, b] = Palette::OCTO.color(bitmap[y / ICON_SCALE][x / ICON_SCALE]);
+            pixels.extend_from_slice(&[r, g, b, 255]);
+        }
+    }
+    (size as u32, size as u32, pixels)
+}
+
+/// Returns the icon as an SVG image, for desktops.
+pub fn icon_svg() -> String {
+    let hex = |[r, g, b]: [u8; 3]| format!("#{:02X}{:02X}{:02X}", r, g, b);
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 {0} {0}\" \
+         shape-rendering=\"crispEdges\">\n<rect width=\"{0}\" height=\"{0}\" fill=\"{1}\"/>\n",
+        ICON_PIXELS,
+        hex(Palette::OCTO.color(0))
+    );
+    for (y, row) in icon_bitmap().iter().enumerate() {
+        for (x, &px) in row.iter().enumerate() {
+            if px != 0 {
+                writeln!(
+                    svg,
+                    "<rect x=\"{}\" y=\"{}\" width=\"1\" height=\"1\" fill=\"{}\"/>",
+                    x,
+                    y,
+                    hex(Palette::OCTO.color(1))
+                )
+                .unwrap();
+            }
+        }
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// Turns a `file://` URI, which some file managers pass instead of a path, into a path.
+/// Other arguments are returned as they are.
+pub fn rom_argument(arg: &str) -> String {
+    let Some(path) = arg.strip_prefix("file://") else {
+        return arg.to_owned();
+    };
+    // Skip the host, which is empty or localhost for local files
+    let path = &path[path.find('/').unwrap_or(path.len())..];
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut rest = path.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = (byte == b'%')
+            .then(|| tail.get(..2))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(decoded) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            None => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+// $XDG_DATA_HOME, or its default of ~/.local/share
+fn data_home() -> io::Result<PathBuf> {
+    if let Some(dir) = env::var_os("XDG_DATA_HOME").filter(|dir| !dir.is_empty()) {
+        return Ok(PathBuf::from(dir));
+    }
+    env::var_os("HOME")
+        .map(|home| Path::new(&home).join(".local/share"))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "HOME isn't set"))
+}
+
+fn write_file(path: &Path, contents: &str) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, contents)
+}
+
+/// Installs the icon, a `.desktop` file starting the running executable, and the
+/// [`MIME_TYPE`] of `.ch8` files, so ROMs open with the emulator. Returns the path of the
+/// `.desktop` file.
+///
+/// The desktop's caches are refreshed if its tools are installed.
+pub fn install() -> io::Result<PathBuf> {
+    let data = data_home()?;
+    let exe = env::current_exe()?;
+    write_file(
+        &data.join(format!("icons/hicolor/scalable/apps/{}.svg", APP_ID)),
+        &icon_svg(),
+    )?;
+    write_file(
+        &data.join(format!("mime/packages/{}.xml", APP_ID)),
+        &format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <mime-info xmlns=\"http://www.freedesktop.org/standards/shared-mime-info\">\n  \
+             <mime-type type=\"{}\">\n    \
+             <comment>CHIP-8 ROM</comment>\n    \
+             <icon name=\"{}\"/>\n    \
+             <glob pattern=\"*.ch8\"/>\n  \
+             </mime-type>\n\
+             </mime-info>\n",
+            MIME_TYPE, APP_ID
+        ),
+    )?;
+    let desktop = data.join(format!("applications/{}.desktop", APP_ID));
+    // Exec arguments are quoted, with backslashes and quotes escaped
+    let exec = exe
+        .to_string_lossy()
+        .replace('\\', "\\\\\\\\")
+        .replace('"', "\\\\\"")
+        .replace('$', "\\\\$")
+        .replace('`', "\\\\`");
+    write_file(
+        &desktop,
+        &format!(
+            "[Desktop Entry]\n\
+             Type=Application\n\
+             Name=CrustyChip\n\
+             Comment=CHIP-8 interpreter\n\
+             Exec=\"{}\" %f\n\
+             Icon={}\n\
+             Terminal=false\n\
+             Categories=Game;Emulator;\n\
+             MimeType={};\n",
+            exec, APP_ID, MIME_TYPE
+        ),
+    )?;
+    // Not every desktop has these, and they only speed up picking up the new files
+    let _ = Command::new("update-mime-database")
+        .arg(data.join("mime"))
+        .status();
+    let _ = Command::new("update-desktop-database")
+        .arg(data.join("applications"))
+        .status();
+    Ok(desktop)
+}
@@ -0,0 +1,62 @@
+//! Downloading ROMs over HTTP(S).
+
+/// Returns whether `arg` looks like a URL rather than a file path.
+pub fn is_url(arg: &str) -> bool {
+    arg.starts_with("http://") || arg.starts_with("https://")
+}
+
+/// Returns the name of the file at `url`, to name the files kept for it, like its states.
+///
+/// The query and the fragment are left out, as is a trailing slash. URLs without a path are
+/// named after the host.
+pub fn file_name(url: &str) -> String {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let path = rest.split(['?', '#']).next().unwrap_or_default();
+    let name = path
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or_default();
+    if name.is_empty() {
+        "download".to_owned()
+    } else {
+        // A port would make the host an invalid file name on Windows
+        name.replace(':', "_")
+    }
+}
+
+/// Downloads the file at `url`, refusing anything larger than the memory of the VM.
+#[cfg(feature = "url")]
+pub fn download(url: &str) -> Result<Vec<u8>, String> {
+    use {crusty_chip::MEM_SIZE, std::io::Read};
+
+    let response = ureq::get(url).call().map_err(|e| e.to_string())?;
+    let mut data = Vec::new();
+    response
+        .into_reader()
+        .take(MEM_SIZE as u64 + 1)
+        .read_to_end(&mut data)
+        .map_err(|e| e.to_string())?;
+    if data.len() > MEM_SIZE {
+        return Err(format!("file is larger than {} bytes", MEM_SIZE));
+    }
+    Ok(data)
+}
+
+/// Downloading isn't supported without the `url` feature.
+#[cfg(not(feature = "url"))]
+pub fn download(_url: &str) -> Result<Vec<u8>, String> {
+    Err("this build doesn't support loading ROMs from URLs".into())
+}
+
+#[test]
+fn test_file_name() {
+    assert_eq!(file_name("https://example.com/roms/pong.ch8"), "pong.ch8");
+    assert_eq!(
+        file_name("https://example.com/roms/pong.ch8?v=2#top"),
+        "pong.ch8"
+    );
+    assert_eq!(file_name("https://example.com/games/pong/"), "pong");
+    assert_eq!(file_name("http://example.com:8080"), "example.com_8080");
+    assert_eq!(file_name("https:///?q"), "download");
+}
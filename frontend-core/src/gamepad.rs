@@ -0,0 +1,280 @@
+//! Gamepads, and merging the input of several devices onto the keypad.
+//!
+//! Bindings map gamepad buttons and stick directions to keys of the keypad, either for any
+//! gamepad or for a particular one. They're saved per ROM, in a `<rom>.pad` file with one
+//! binding per line:
+//!
+//! ```text
+//! * button 0 5
+//! * axis 0 + 6
+//! 1 axis 1 - 2
+//! ```
+//!
+//! The first column is the gamepad the binding is for, or `*` for any, and the last one is
+//! the key of the keypad. Without a file, [`default_bindings`] are used.
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// How many gamepads can be connected at once.
+pub const MAX_GAMEPADS: usize = 8;
+
+// How far a stick has to be pushed, out of 100, to count as pressed
+const AXIS_THRESHOLD: f32 = 50.0;
+
+// The axes of the directional pad, as numbered by SFML
+const POV_X: u32 = 6;
+const POV_Y: u32 = 7;
+
+/// An input of a gamepad.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Input {
+    /// A button.
+    Button(u32),
+    /// An axis pushed in the positive or the negative direction.
+    Axis {
+        /// The axis.
+        axis: u32,
+        /// Whether it's pushed towards the positive end.
+        positive: bool,
+    },
+}
+
+/// An input bound to a key of the keypad.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Binding {
+    /// The gamepad the binding is for, or `None` for any.
+    pub gamepad: Option<u32>,
+    /// The input.
+    pub input: Input,
+    /// The key of the keypad.
+    pub key: u8,
+}
+
+impl Binding {
+    /// Describes the binding, like "Any gamepad, axis 0+".
+    pub fn describe(&self) -> String {
+        let device = match self.gamepad {
+            Some(id) => format!("Gamepad {}", id),
+            None => "Any gamepad".to_owned(),
+        };
+        let input = match self.input {
+            Input::Button(button) => format!("button {}", button),
+            Input::Axis { axis, positive } => {
+                format!("axis {}{}", axis, if positive { '+' } else { '-' })
+            }
+        };
+        format!("{}, {}", device, input)
+    }
+}
+
+/// Directions on the left stick and the directional pad as `2`, `4`, `6` and `8`,
+/// and the first four buttons as `5`, `0`, `A` and `B`.
+pub fn default_bindings() -> Vec<Binding> {
+    let any = |input, key| Binding {
+        gamepad: None,
+        input,
+        key,
+    };
+    let axis = |axis, positive| Input::Axis { axis, positive };
+    let mut bindings = Vec::new();
+    for (x, y) in [(0, 1), (POV_X, POV_Y)] {
+        bindings.extend([
+            any(axis(x, false), 0x4),
+            any(axis(x, true), 0x6),
+            any(axis(y, false), 0x2),
+            any(axis(y, true), 0x8),
+        ]);
+    }
+    for (button, key) in [(0, 0x5), (1, 0x0), (2, 0xA), (3, 0xB)] {
+        bindings.push(any(Input::Button(button), key));
+    }
+    bindings
+}
+
+/// The bindings file of a ROM.
+pub fn bindings_path(rom_path: &Path) -> PathBuf {
+    let mut name = rom_path.file_name().unwrap_or_default().to_owned();
+    name.push(".pad");
+    rom_path.with_file_name(name)
+}
+
+/// Loads the bindings saved for a ROM, or the defaults if there's no file.
+/// Malformed lines are skipped.
+pub fn load(path: &Path) -> Vec<Binding> {
+    let Ok(text) = fs::read_to_string(path) else {
+        return default_bindings();
+    };
+    text.lines().filter_map(parse_binding).collect()
+}
+
+fn parse_binding(line: &str) -> Option<Binding> {
+    let mut words = line.split_whitespace();
+    let gamepad = match words.next()? {
+        "*" => None,
+        id => Some(id.parse().ok()?),
+    };
+    let input = match words.next()? {
+        "button" => Input::Button(words.next()?.parse().ok()?),
+        "axis" => Input::Axis {
+            axis: words.next()?.parse().ok()?,
+            positive: match words.next()? {
+                "+" => true,
+                "-" => false,
+                _ => return None,
+            },
+        },
+        _ => return None,
+    };
+    let key = u8::from_str_radix(words.next()?, 16)
+        .ok()
+        .filter(|&k| k < 16)?;
+    Some(Binding {
+        gamepad,
+        input,
+        key,
+    })
+}
+
+/// Saves the bindings for a ROM.
+pub fn save(path: &Path, bindings: &[Binding]) -> io::Result<()> {
+    let text: String = bindings
+        .iter()
+        .map(|binding| {
+            let gamepad = binding.gamepad.map_or("*".to_owned(), |id| id.to_string());
+            let input = match binding.input {
+                Input::Button(button) => format!("button {}", button),
+                Input::Axis { axis, positive } => {
+                    format!("axis {} {}", axis, if positive { '+' } else { '-' })
+                }
+            };
+            format!("{} {} {:X}\n", gamepad, input, binding.key)
+        })
+        .collect();
+    fs::write(path, text)
+}
+
+/// A device that can hold keys of the keypad.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Device {
+    /// The keyboard.
+    Keyboard,
+    /// A gamepad.
+    Gamepad(u32),
+}
+
+/// The keys held by each device.
+///
+/// A key of the keypad stays pressed as long as anything holds it, so releasing a key on the
+/// keyboard doesn't release it while a gamepad holds it too. Gamepad inputs are tracked one
+/// by one, so letting go of a button doesn't release a key the stick still holds.
+#[derive(Default)]
+pub struct Held {
+    // Bitmask of the keys held on the keyboard
+    keyboard: u16,
+    // The keys held by each pressed gamepad input, as a bitmask
+    inputs: HashMap<(u32, Input), u16>,
+}
+
+impl Held {
+    /// Returns the keys held on any device, as a bitmask.
+    pub fn keys(&self) -> u16 {
+        self.inputs
+            .values()
+            .fold(self.keyboard, |keys, &held| keys | held)
+    }
+
+    /// Presses or releases `key` on the keyboard.
+    pub fn set_keyboard(&mut self, key: u8, down: bool) {
+        if down {
+            self.keyboard |= 1 << key;
+        } else {
+            self.keyboard &= !(1 << key);
+        }
+    }
+
+    /// Releases everything held on `device`, like when a gamepad is unplugged.
+    pub fn release_all(&mut self, device: Device) {
+        match device {
+            Device::Keyboard => self.keyboard = 0,
+            Device::Gamepad(id) => self.inputs.retain(|&(gamepad, _), _| gamepad != id),
+        }
+    }
+
+    // Presses or releases `input` of gamepad `id`, holding the keys it's bound to
+    fn set_input(&mut self, bindings: &[Binding], id: u32, input: Input, down: bool) {
+        let keys = bindings
+            .iter()
+            .filter(|binding| binding.input == input && binding.gamepad.is_none_or(|g| g == id))
+            .fold(0, |keys, binding| keys | 1 << binding.key);
+        if down && keys != 0 {
+            self.inputs.insert((id, input), keys);
+        } else {
+            self.inputs.remove(&(id, input));
+        }
+    }
+
+    /// Applies a button press or release on gamepad `id`.
+    pub fn button(&mut self, bindings: &[Binding], id: u32, button: u32, down: bool) {
+        self.set_input(bindings, id, Input::Button(button), down);
+    }
+
+    /// Applies an axis of gamepad `id` moving to `position`, from -100 to 100.
+    pub fn axis(&mut self, bindings: &[Binding], id: u32, axis: u32, position: f32) {
+        for positive in [false, true] {
+            let pushed = if positive {
+                position > AXIS_THRESHOLD
+            } else {
+                position < -AXIS_THRESHOLD
+            };
+            self.set_input(bindings, id, Input::Axis { axis, positive }, pushed);
+        }
+    }
+}
+
+/// Returns the input an axis movement to `position` would bind, if it's pushed far enough.
+pub fn axis_input(axis: u32, position: f32) -> Option<Input> {
+    (position.abs() > AXIS_THRESHOLD).then_some(Input::Axis {
+        axis,
+        positive: position > 0.0,
+    })
+}
+
+#[test]
+fn test_held_inputs() {
+    let bindings = [
+        Binding {
+            gamepad: None,
+            input: Input::Button(0),
+            key: 5,
+        },
+        Binding {
+            gamepad: None,
+            input: Input::Axis {
+                axis: 0,
+                positive: true,
+            },
+            key: 5,
+        },
+    ];
+    let mut held = Held::default();
+    held.button(&bindings, 0, 0, true);
+    held.axis(&bindings, 0, 0, 100.0);
+    held.set_keyboard(5, true);
+    // The key stays held until every input lets go of it
+    held.button(&bindings, 0, 0, false);
+    held.set_keyboard(5, false);
+    assert_eq!(held.keys(), 1 << 5);
+    held.axis(&bindings, 0, 0, -100.0);
+    assert_eq!(held.keys(), 0);
+    // Another gamepad holds keys of its own
+    held.button(&bindings, 1, 0, true);
+    held.button(&bindings, 0, 0, true);
+    held.release_all(Device::Gamepad(0));
+    assert_eq!(held.keys(), 1 << 5);
+    held.release_all(Device::Gamepad(1));
+    assert_eq!(held.keys(), 0);
+}
@@ -0,0 +1,159 @@
+//! Kiosk mode, cycling through a playlist of ROMs for demo kiosks and museum displays.
+//!
+//! Each ROM runs for a while, or until it has sat idle for [`IDLE_LIMIT`], before the next
+//! one starts. While nobody plays, a banner invites passers-by to. Any input interrupts the
+//! rotation, so a visitor can keep playing as long as they like; once they've left the
+//! controls alone for [`HANDS_OFF`], the rotation goes on.
+
+use {
+    crusty_chip::rom,
+    std::{
+        fs, io,
+        path::{Path, PathBuf},
+        time::{Duration, Instant},
+    },
+};
+
+/// How long a ROM may sit halted or waiting for a key before the next one starts.
+pub const IDLE_LIMIT: Duration = Duration::from_secs(15);
+/// How long after the last input the rotation goes on.
+pub const HANDS_OFF: Duration = Duration::from_secs(60);
+
+/// Reads a playlist: the ROMs in a directory, by name, or the ROMs listed in a text file.
+///
+/// A list has a path per line, relative to the list itself. Empty lines and lines starting
+/// with `#` are skipped.
+pub fn read_playlist(path: &Path) -> io::Result<Vec<PathBuf>> {
+    if path.is_dir() {
+        let mut roms = Vec::new();
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            if entry.file_type()?.is_file() && rom::has_rom_extension(&name.to_string_lossy()) {
+                roms.push(entry.path());
+            }
+        }
+        roms.sort();
+        return Ok(roms);
+    }
+    let dir = path.parent().unwrap_or(Path::new(""));
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| dir.join(line))
+        .collect())
+}
+
+/// Reads a ROM of the playlist. Archives have to hold a single ROM, as nobody is around to
+/// pick one.
+pub fn read_rom(path: &Path) -> Result<Vec<u8>, String> {
+    let file = rom::read_file(path).map_err(|e| e.to_string())?;
+    unpack_rom(file, &path.to_string_lossy())
+}
+
+/// Returns the ROM held by a file called `name`, like a hex dump or an archive of a single
+/// ROM.
+pub fn unpack_rom(file: Vec<u8>, name: &str) -> Result<Vec<u8>, String> {
+    let name = name.to_ascii_lowercase();
+    if rom::is_zip(&file) {
+        match rom::read_single_zip_rom(&file) {
+            Ok(Some(data)) => Ok(data),
+            Ok(None) => Err("the archive holds several ROMs".to_owned()),
+            Err(e) => Err(e.to_string()),
+        }
+    } else if name.ends_with(".hex") || name.ends_with(".txt") {
+        rom::load_hex(&String::from_utf8_lossy(&file)).map_err(|e| e.to_string())
+    } else {
+        Ok(file)
+    }
+}
+
+/// The rotation through the playlist.
+pub struct Kiosk {
+    roms: Vec<PathBuf>,
+    current: usize,
+    per_rom: Duration,
+    started: Instant,
+    idle_since: Option<Instant>,
+    last_input: Option<Instant>,
+}
+
+impl Kiosk {
+    /// Starts the rotation with the first of `roms`, running each for `per_rom`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `roms` is empty.
+    pub fn new(roms: Vec<PathBuf>, per_rom: Duration) -> Self {
+        assert!(!roms.is_empty(), "Empty playlist");
+        Self {
+            roms,
+            current: 0,
+            per_rom,
+            started: Instant::now(),
+            idle_since: None,
+            last_input: None,
+        }
+    }
+
+    /// Returns the path of the ROM running now.
+    pub fn current(&self) -> &Path {
+        &self.roms[self.current]
+    }
+
+    /// Returns the name the ROM running now is shown with.
+    pub fn title(&self) -> String {
+        let path = self.current();
+        path.file_stem()
+            .unwrap_or(path.as_os_str())
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// Notes input, which stops the rotation until the controls are left alone.
+    pub fn interrupt(&mut self) {
+        self.last_input = Some(Instant::now());
+    }
+
+    /// Returns whether nobody is playing, so the attract-mode banner is up.
+    pub fn is_attracting(&self) -> bool {
+        self.last_input
+            .is_none_or(|input| input.elapsed() >= HANDS_OFF)
+    }
+
+    /// Returns how long the ROM running now has left.
+    pub fn time_left(&self) -> Duration {
+        self.per_rom.saturating_sub(self.started.elapsed())
+    }
+
+    /// Moves the rotation along, given whether the ROM running now is `idle`, and returns
+    /// the ROM to start if it's time for the next one.
+    ///
+    /// While someone plays, the time of the ROM starts over, so it gets its full turn once
+    /// they leave.
+    pub fn update(&mut self, idle: bool) -> Option<&Path> {
+        let now = Instant::now();
+        if !self.is_attracting() {
+            self.started = now;
+            self.idle_since = None;
+            return None;
+        }
+        self.idle_since = if idle {
+            Some(self.idle_since.unwrap_or(now))
+        } else {
+            None
+        };
+        let idle_too_long = self
+            .idle_since
+            .is_some_and(|since| now.duration_since(since) >= IDLE_LIMIT);
+        if now.duration_since(self.started) < self.per_rom && !idle_too_long {
+            return None;
+        }
+        self.current = (self.current + 1) % self.roms.len();
+        self.started = now;
+        self.idle_since = None;
+        self.last_input = None;
+        Some(self.current())
+    }
+}
@@ -0,0 +1,26 @@
+//! The parts of a CHIP-8 frontend that don't depend on how it's presented.
+//!
+//! Pacing the VM, replays, savestate slots, settings, the log, and the files kept per ROM
+//! work the same whether the frontend draws with SFML, SDL, a terminal or a browser canvas.
+//! They're kept here, so a new frontend only has to draw the display and the windows, and
+//! turn its input into keypad presses.
+
+#![warn(missing_docs)]
+
+pub mod colorize;
+pub mod confirm;
+pub mod desktop;
+pub mod download;
+pub mod gamepad;
+pub mod kiosk;
+pub mod logview;
+pub mod pacing;
+pub mod players;
+pub mod portable;
+pub mod replay;
+pub mod screenshot;
+pub mod settings;
+pub mod startup;
+pub mod states;
+pub mod stats;
+pub mod toasts;
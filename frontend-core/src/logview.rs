@@ -0,0 +1,162 @@
+//! The messages shown in the log window, from the VM and the frontend.
+//!
+//! Unlike a plain text log, every message keeps its severity and the address it's about,
+//! so the log window can filter by severity and link to the code. Messages of the VM come
+//! from its [`EventKind::Log`] events, and the frontend writes its own with [`fmt::Write`].
+
+use {
+    crusty_chip::{EventKind, Severity},
+    std::{
+        cell::RefCell,
+        collections::VecDeque,
+        fmt,
+        fs::File,
+        io::{self, BufWriter, Write},
+        path::Path,
+        rc::Rc,
+    },
+};
+
+/// A message in the log.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    /// How serious the message is.
+    pub severity: Severity,
+    /// The address the message is about, if any.
+    pub addr: Option<u16>,
+    /// The message.
+    pub text: String,
+}
+
+impl Entry {
+    /// Returns whether the message contains every word of `query`, ignoring case.
+    pub fn matches(&self, query: &str) -> bool {
+        let text = self.text.to_lowercase();
+        query
+            .split_whitespace()
+            .all(|word| text.contains(&word.to_lowercase()))
+    }
+}
+
+struct Inner {
+    entries: VecDeque<Entry>,
+    capacity: usize,
+    // The line being written, which becomes an entry once it's complete
+    partial: String,
+    partial_severity: Severity,
+}
+
+/// The log. Clones share the same messages.
+#[derive(Clone)]
+pub struct Log {
+    inner: Rc<RefCell<Inner>>,
+}
+
+impl Log {
+    /// Creates an empty log keeping the last `capacity` messages.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Rc::new(RefCell::new(Inner {
+                entries: VecDeque::new(),
+                capacity,
+                partial: String::new(),
+                partial_severity: Severity::Info,
+            })),
+        }
+    }
+
+    /// Adds a message.
+    pub fn add(&self, severity: Severity, addr: Option<u16>, text: impl Into<String>) {
+        let mut inner = self.inner.borrow_mut();
+        if inner.entries.len() == inner.capacity {
+            inner.entries.pop_front();
+        }
+        inner.entries.push_back(Entry {
+            severity,
+            addr,
+            text: text.into(),
+        });
+    }
+
+    /// Adds the message of a VM event, if it's a log event.
+    pub fn add_event(&self, event: &EventKind) {
+        if let EventKind::Log {
+            severity,
+            addr,
+            message,
+        } = event
+        {
+            self.add(*severity, *addr, message.as_str());
+        }
+    }
+
+    /// Returns a writer adding lines of the given severity. Writing to the log directly
+    /// adds informational lines.
+    pub fn at(&self, severity: Severity) -> Writer<'_> {
+        Writer {
+            log: self,
+            severity,
+        }
+    }
+
+    /// Returns the messages, oldest first.
+    pub fn entries(&self) -> Vec<Entry> {
+        self.inner.borrow().entries.iter().cloned().collect()
+    }
+
+    /// Removes all messages.
+    pub fn clear(&self) {
+        self.inner.borrow_mut().entries.clear();
+    }
+
+    /// Writes the messages to a text file, one per line.
+    pub fn export(&self, path: &Path) -> io::Result<()> {
+        let mut f = BufWriter::new(File::create(path)?);
+        for entry in &self.inner.borrow().entries {
+            match entry.addr {
+                Some(addr) => writeln!(f, "[{}] {:#05x}: {}", entry.severity, addr, entry.text)?,
+                None => writeln!(f, "[{}] {}", entry.severity, entry.text)?,
+            }
+        }
+        f.flush()
+    }
+
+    fn write(&self, severity: Severity, s: &str) {
+        let mut lines = Vec::new();
+        {
+            let mut inner = self.inner.borrow_mut();
+            if inner.partial.is_empty() {
+                inner.partial_severity = severity;
+            }
+            inner.partial.push_str(s);
+            while let Some(end) = inner.partial.find('\n') {
+                let line: String = inner.partial.drain(..=end).collect();
+                lines.push((inner.partial_severity, line.trim_end().to_owned()));
+                inner.partial_severity = severity;
+            }
+        }
+        for (severity, line) in lines {
+            self.add(severity, None, line);
+        }
+    }
+}
+
+impl fmt::Write for Log {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write(Severity::Info, s);
+        Ok(())
+    }
+}
+
+/// Writes lines of one severity to a [`Log`].
+pub struct Writer<'a> {
+    log: &'a Log,
+    severity: Severity,
+}
+
+impl fmt::Write for Writer<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.log.write(self.severity, s);
+        Ok(())
+    }
+}
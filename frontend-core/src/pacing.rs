@@ -0,0 +1,258 @@
+//! Running the VM in step with the display.
+//!
+//! The VM paces itself: [`VirtualMachine::step_frame`] runs a 60 Hz frame's worth of
+//! instructions at the speed set with [`VirtualMachine::set_speed`], and ticks the timers.
+//! The frontend only has to run as many frames as have passed in real time, and press the
+//! keys between them.
+
+use {
+    crusty_chip::VirtualMachine,
+    std::{
+        collections::VecDeque,
+        time::{Duration, Instant},
+    },
+};
+
+/// The length of a 60 Hz frame.
+pub const FRAME: Duration = Duration::from_nanos(1_000_000_000 / 60);
+// After a stall, like while the window is dragged, only this many frames are caught up on,
+// so the program doesn't race ahead
+const MAX_FRAMES_BEHIND: u32 = 4;
+
+/// Runs the VM frame by frame, in real time.
+pub struct Pacer {
+    last: Instant,
+    // Real time not yet run, less than a frame unless frames are due
+    lag: Duration,
+    traced: bool,
+}
+
+impl Default for Pacer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Pacer {
+    /// Creates a pacer, with the first frame due a frame from now.
+    pub fn new() -> Self {
+        Self {
+            last: Instant::now(),
+            lag: Duration::ZERO,
+            traced: false,
+        }
+    }
+
+    /// Runs the frames of `vm` that are due since the last call. Returns the number of
+    /// instructions run.
+    ///
+    /// `input` is called with the VM before each frame, to press and release keys, like those
+    /// of a [replay](crate::replay).
+    ///
+    /// While `paused`, no frames run, and time stands still for the program, unless
+    /// `advance` runs the VM by a single instruction. `trace` is called with the VM before
+    /// each of those, and once for the instruction the VM is paused at.
+    pub fn run_frames(
+        &mut self,
+        vm: &mut VirtualMachine,
+        paused: bool,
+        advance: bool,
+        input: impl FnMut(&mut VirtualMachine),
+        trace: impl FnMut(&VirtualMachine),
+    ) -> u64 {
+        let now = Instant::now();
+        self.lag = (self.lag + (now - self.last)).min(FRAME * MAX_FRAMES_BEHIND);
+        self.last = now;
+        let mut frames = 0;
+        if !paused {
+            while self.lag >= FRAME {
+                self.lag -= FRAME;
+                frames += 1;
+            }
+        }
+        self.run(vm, frames, paused, advance, input, trace)
+    }
+
+    fn run(
+        &mut self,
+        vm: &mut VirtualMachine,
+        frames: u32,
+        paused: bool,
+        advance: bool,
+        mut input: impl FnMut(&mut VirtualMachine),
+        mut trace: impl FnMut(&VirtualMachine),
+    ) -> u64 {
+        let before = vm.cycle_count();
+        if paused {
+            self.lag = Duration::ZERO;
+            if !self.traced {
+                trace(vm);
+                self.traced = true;
+            }
+            if advance {
+                vm.do_cycle();
+                self.traced = false;
+            }
+        } else {
+            self.traced = false;
+            for _ in 0..frames {
+                input(vm);
+                vm.step_frame();
+            }
+        }
+        vm.cycle_count() - before
+    }
+}
+
+/// Sleeps out the rest of the frame that started at `start`.
+///
+/// Vsync usually limits the frame rate, but not with every driver, and a frontend with
+/// nothing to run shouldn't keep the CPU busy drawing the same frame.
+pub fn finish_frame(start: Instant) {
+    std::thread::sleep(FRAME.saturating_sub(start.elapsed()));
+}
+
+/// The frames [`FrameTimes`] keeps, four seconds' worth.
+pub const FRAME_HISTORY: usize = 240;
+
+/// How long a frame took, for the frame-time graph.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameTime {
+    /// From the start of the frame to the start of the next.
+    pub total: Duration,
+    /// The part of it spent running the VM.
+    pub emulation: Duration,
+}
+
+/// The times of the last [`FRAME_HISTORY`] frames, oldest first.
+///
+/// Frames well over [`FRAME`] show up as stutter, and frames whose emulation time alone is
+/// over it mean the host can't keep up with the program.
+#[derive(Debug, Default)]
+pub struct FrameTimes {
+    frames: VecDeque<FrameTime>,
+    frame_start: Option<Instant>,
+    emulation: Duration,
+}
+
+impl FrameTimes {
+    /// Starts timing a frame, finishing the one before it.
+    pub fn start_frame(&mut self) {
+        let now = Instant::now();
+        if let Some(start) = self.frame_start.replace(now) {
+            let emulation = std::mem::take(&mut self.emulation);
+            self.push(FrameTime {
+                total: now - start,
+                emulation,
+            });
+        }
+    }
+
+    /// Runs `f`, counting the time it takes as emulation time of the current frame.
+    pub fn emulate<T>(&mut self, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.emulation += start.elapsed();
+        result
+    }
+
+    /// Adds a frame, forgetting the oldest once [`FRAME_HISTORY`] are kept.
+    pub fn push(&mut self, frame: FrameTime) {
+        if self.frames.len() == FRAME_HISTORY {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(frame);
+    }
+
+    /// Returns the frames kept, oldest first.
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = &FrameTime> {
+        self.frames.iter()
+    }
+
+    /// Returns the longest frame kept.
+    pub fn worst(&self) -> Duration {
+        self.frames
+            .iter()
+            .map(|frame| frame.total)
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// Returns the average frame time and emulation time of the frames kept.
+    pub fn average(&self) -> FrameTime {
+        let n = self.frames.len().max(1) as u32;
+        FrameTime {
+            total: self
+                .frames
+                .iter()
+                .map(|frame| frame.total)
+                .sum::<Duration>()
+                / n,
+            emulation: self
+                .frames
+                .iter()
+                .map(|frame| frame.emulation)
+                .sum::<Duration>()
+                / n,
+        }
+    }
+}
+
+#[test]
+fn test_run_frames() {
+    let mut vm = VirtualMachine::new();
+    vm.set_speed(60 * 2);
+    // 0x200: LD V0, 1
+    // 0x202: ADD V1, V0
+    // 0x204: JP 0x202
+    vm.load_rom(&[0x60, 0x01, 0x81, 0x04, 0x12, 0x02]);
+    let mut pacer = Pacer::new();
+    let mut traced = Vec::new();
+    // Paused, the instruction about to run is traced once, and nothing runs
+    for _ in 0..2 {
+        let cycles = pacer.run(&mut vm, 1, true, false, |_| {}, |vm| traced.push(vm.pc()));
+        assert_eq!(cycles, 0);
+    }
+    assert_eq!(traced, [0x200]);
+    // Advancing runs and traces one instruction at a time
+    assert_eq!(
+        pacer.run(&mut vm, 1, true, true, |_| {}, |vm| traced.push(vm.pc())),
+        1
+    );
+    assert_eq!(
+        pacer.run(&mut vm, 1, true, false, |_| {}, |vm| traced.push(vm.pc())),
+        0
+    );
+    assert_eq!(traced, [0x200, 0x202]);
+    // Running, each frame runs at the speed of the VM, with the input pressed before it
+    let mut presses = 0;
+    let cycles = pacer.run(&mut vm, 3, false, false, |_| presses += 1, |_| {});
+    assert_eq!((cycles, presses), (6, 3));
+    assert_eq!(vm.frame_count(), 3);
+}
+
+#[test]
+fn test_frame_times() {
+    let mut times = FrameTimes::default();
+    assert_eq!(times.worst(), Duration::ZERO);
+    for ms in 0..FRAME_HISTORY as u64 + 10 {
+        times.push(FrameTime {
+            total: Duration::from_millis(ms % 20),
+            emulation: Duration::from_millis(ms % 4),
+        });
+    }
+    assert_eq!(times.iter().len(), FRAME_HISTORY);
+    // The first ten frames are forgotten
+    assert_eq!(
+        times.iter().next().unwrap().total,
+        Duration::from_millis(10)
+    );
+    assert_eq!(times.worst(), Duration::from_millis(19));
+    assert_eq!(
+        times.average(),
+        FrameTime {
+            total: Duration::from_micros(9500),
+            emulation: Duration::from_micros(1500),
+        }
+    );
+}
@@ -0,0 +1,131 @@
+//! Two-player key mapping.
+//!
+//! Two-player ROMs like Pong expect both players on the one keypad, often on keys that are
+//! awkward to share, like `1` and `4` against `C` and `D`. A profile gives each player a
+//! cluster of their own, WASD and left Shift for the first and the arrow keys and right Shift
+//! for the second, and maps it onto the keys the ROM reads. Keys not in a cluster keep
+//! working as usual.
+//!
+//! Profiles are saved per ROM, in a `<rom>.players` file with one `<player> <control> <key>`
+//! line per mapped control, like `2 up C`. ROMs without the file are single player.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// A control of a player's cluster.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Control {
+    /// Up.
+    Up,
+    /// Down.
+    Down,
+    /// Left.
+    Left,
+    /// Right.
+    Right,
+    /// The action button.
+    Action,
+}
+
+impl Control {
+    /// All controls.
+    pub const ALL: [Control; 5] = [
+        Control::Up,
+        Control::Down,
+        Control::Left,
+        Control::Right,
+        Control::Action,
+    ];
+
+    /// The name of the control, as written in profiles.
+    pub fn name(self) -> &'static str {
+        match self {
+            Control::Up => "up",
+            Control::Down => "down",
+            Control::Left => "left",
+            Control::Right => "right",
+            Control::Action => "action",
+        }
+    }
+}
+
+/// The keypad keys the controls of each player press.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Profile {
+    /// The key of each control, by player and in the order of [`Control::ALL`].
+    pub keys: [[Option<u8>; 5]; 2],
+}
+
+impl Default for Profile {
+    /// The Pong layout: `1` and `4` for the first player, `C` and `D` for the second.
+    fn default() -> Self {
+        Self {
+            keys: [
+                [Some(0x1), Some(0x4), None, None, None],
+                [Some(0xC), Some(0xD), None, None, None],
+            ],
+        }
+    }
+}
+
+impl Profile {
+    /// Returns the key `player` presses with `control`, if it's mapped.
+    pub fn key(&self, player: usize, control: Control) -> Option<u8> {
+        self.keys[player][control as usize]
+    }
+}
+
+/// The profile file of a ROM.
+pub fn profile_path(rom_path: &Path) -> PathBuf {
+    let mut name = rom_path.file_name().unwrap_or_default().to_owned();
+    name.push(".players");
+    rom_path.with_file_name(name)
+}
+
+/// Loads the profile saved for a ROM, if it has one. Malformed lines are skipped.
+pub fn load(path: &Path) -> Option<Profile> {
+    let text = fs::read_to_string(path).ok()?;
+    let mut profile = Profile {
+        keys: [[None; 5]; 2],
+    };
+    for line in text.lines() {
+        let mut words = line.split_whitespace();
+        let (Some(player), Some(control), Some(key)) = (words.next(), words.next(), words.next())
+        else {
+            continue;
+        };
+        let player = match player {
+            "1" => 0,
+            "2" => 1,
+            _ => continue,
+        };
+        let Some(control) = Control::ALL.into_iter().find(|c| c.name() == control) else {
+            continue;
+        };
+        if let Ok(key @ 0..16) = u8::from_str_radix(key, 16) {
+            profile.keys[player][control as usize] = Some(key);
+        }
+    }
+    Some(profile)
+}
+
+/// Saves the profile for a ROM, or removes it if `profile` is `None`.
+pub fn save(path: &Path, profile: Option<&Profile>) -> io::Result<()> {
+    let Some(profile) = profile else {
+        return match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        };
+    };
+    let mut text = String::new();
+    for (player, keys) in profile.keys.iter().enumerate() {
+        for (control, key) in Control::ALL.into_iter().zip(keys) {
+            if let Some(key) = key {
+                text += &format!("{} {} {:X}\n", player + 1, control.name(), key);
+            }
+        }
+    }
+    fs::write(path, text)
+}
@@ -0,0 +1,45 @@
+//! Portable mode, for running from a USB stick.
+//!
+//! Normally the files kept for a ROM, like its states and sprite colors, are saved next to
+//! the ROM. In portable mode they're saved in a `data` directory next to the executable
+//! instead, so they go wherever the executable goes. Portable mode is turned on with
+//! `--portable`, or by putting a [`MARKER`] file next to the executable.
+//!
+//! Only these per-ROM files move. The states, sprite colors, gamepad bindings and player
+//! profiles are all the frontend saves, as it has no config file of its own and doesn't save
+//! screenshots. The exception is `--install-desktop`, which still installs into the user's
+//! data directory, as that's where desktops look.
+
+use std::{
+    env, io,
+    path::{Path, PathBuf},
+};
+
+/// The file that turns on portable mode when it's next to the executable.
+pub const MARKER: &str = "portable.txt";
+
+fn exe_dir() -> io::Result<PathBuf> {
+    let exe = env::current_exe()?;
+    Ok(exe.parent().map(Path::to_path_buf).unwrap_or_default())
+}
+
+/// Returns whether there's a [`MARKER`] file next to the executable.
+pub fn is_marked() -> bool {
+    exe_dir().is_ok_and(|dir| dir.join(MARKER).is_file())
+}
+
+/// Returns the directory portable mode keeps its files in, creating it if needed.
+pub fn data_dir() -> io::Result<PathBuf> {
+    let dir = exe_dir()?.join("data");
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+/// Moves the base path the files of a ROM are named after into `data_dir`.
+///
+/// Only the file name of the ROM is kept, so ROMs with the same name in different
+/// directories share their files.
+pub fn rebase(data_dir: &Path, base: &str) -> String {
+    let name = Path::new(base).file_name().unwrap_or_default();
+    data_dir.join(name).to_string_lossy().into_owned()
+}
@@ -0,0 +1,153 @@
+//! Recording a play session, to watch it again.
+//!
+//! A replay is the VM as it was when recording started, along with the keypad presses of
+//! every frame after it. The VM is deterministic when it's run frame by frame, so pressing
+//! the same keys on the same frames plays the session out exactly as it went.
+
+use crusty_chip::VirtualMachine;
+
+fn pressed_keys(vm: &VirtualMachine) -> u16 {
+    (0..16)
+        .filter(|&key| vm.key_pressed(key))
+        .fold(0, |keys, key| keys | 1 << key)
+}
+
+/// Records the keys pressed on every frame.
+#[derive(Clone)]
+pub struct Recorder {
+    start: VirtualMachine,
+    changes: Vec<(u64, u16)>,
+    frame: u64,
+    keys: u16,
+}
+
+impl Recorder {
+    /// Starts recording at the current state of `vm`.
+    pub fn new(vm: &VirtualMachine) -> Self {
+        Self {
+            start: vm.clone(),
+            changes: Vec::new(),
+            frame: 0,
+            keys: pressed_keys(vm),
+        }
+    }
+
+    /// Notes the keys pressed on the frame `vm` is about to run. Call this before every
+    /// frame.
+    pub fn record(&mut self, vm: &VirtualMachine) {
+        let keys = pressed_keys(vm);
+        if keys != self.keys {
+            self.changes.push((self.frame, keys));
+            self.keys = keys;
+        }
+        self.frame += 1;
+    }
+
+    /// Stops recording, returning the replay of the frames recorded.
+    pub fn finish(self) -> Replay {
+        Replay {
+            start: self.start,
+            changes: self.changes,
+            frames: self.frame,
+        }
+    }
+}
+
+/// A recorded session, made by [`Recorder`].
+#[derive(Clone)]
+pub struct Replay {
+    start: VirtualMachine,
+    changes: Vec<(u64, u16)>,
+    frames: u64,
+}
+
+impl Replay {
+    /// Returns the number of frames recorded.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Starts playing the replay back, with `vm` put back to the state it was recorded
+    /// from.
+    pub fn play(&self, vm: &mut VirtualMachine) -> Playback {
+        *vm = self.start.clone();
+        Playback {
+            replay: self.clone(),
+            frame: 0,
+            next: 0,
+        }
+    }
+}
+
+/// Presses the keys of a [`Replay`] frame by frame.
+pub struct Playback {
+    replay: Replay,
+    frame: u64,
+    next: usize,
+}
+
+impl Playback {
+    /// Presses the keys recorded for the frame `vm` is about to run. Call this before every
+    /// frame. Returns `false` once all frames were played back.
+    pub fn apply(&mut self, vm: &mut VirtualMachine) -> bool {
+        if self.frame >= self.replay.frames {
+            return false;
+        }
+        if let Some(&(frame, keys)) = self.replay.changes.get(self.next)
+            && frame == self.frame
+        {
+            let changed = keys ^ pressed_keys(vm);
+            for key in (0..16).filter(|key| changed & 1 << key != 0) {
+                if keys & 1 << key != 0 {
+                    vm.press_key(key);
+                } else {
+                    vm.release_key(key);
+                }
+            }
+            self.next += 1;
+        }
+        self.frame += 1;
+        true
+    }
+}
+
+#[test]
+fn test_replay() {
+    // 0x200: LD V0, K
+    // 0x202: ADD V1, V0
+    // 0x204: RND V2, 0xFF
+    // 0x206: JP 0x200
+    let mut vm = VirtualMachine::new();
+    vm.load_rom(&[0xF0, 0x0A, 0x81, 0x04, 0xC2, 0xFF, 0x12, 0x00]);
+    vm.step_frame();
+    let mut recorder = Recorder::new(&vm);
+    for held in [
+        Some(3),
+        Some(3),
+        None,
+        Some(7),
+        Some(7),
+        Some(7),
+        None,
+        Some(3),
+    ] {
+        for key in 0..16 {
+            if held != Some(key) {
+                vm.release_key(key);
+            } else if !vm.key_pressed(key) {
+                vm.press_key(key);
+            }
+        }
+        recorder.record(&vm);
+        vm.step_frame();
+    }
+    let expected = (vm.v(1), vm.v(2), vm.cycle_count());
+    let replay = recorder.finish();
+    assert_eq!(replay.frames(), 8);
+    let mut played = VirtualMachine::new();
+    let mut playback = replay.play(&mut played);
+    while playback.apply(&mut played) {
+        played.step_frame();
+    }
+    assert_eq!((played.v(1), played.v(2), played.cycle_count()), expected);
+}
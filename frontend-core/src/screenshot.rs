@@ -0,0 +1,174 @@
+//! Screenshots of the display as the frontend shows it, for golden image tests.
+//!
+//! The core tests check the pixels of the display, but not how a frontend colors, rotates
+//! and scales them. A screenshot of a known ROM state, compared against a PNG checked in
+//! when it last looked right, catches mistakes in those steps too.
+
+use {
+    crate::{colorize::Overlay, settings::Settings},
+    crusty_chip::{VirtualMachine, megachip},
+    std::{
+        fs::File,
+        io::{self, BufReader, BufWriter},
+        path::{Path, PathBuf},
+    },
+};
+
+/// An RGBA image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Screenshot {
+    /// The width in pixels.
+    pub width: usize,
+    /// The height in pixels.
+    pub height: usize,
+    /// The pixels, row by row, 4 bytes each.
+    pub rgba: Vec<u8>,
+}
+
+impl Screenshot {
+    /// Colors the display of `ch8` the way the frontend shows it, before scaling: rotated,
+    /// colored by `overlay`, the CHIP-8X colors or the palette, and as seen with the color
+    /// blindness being simulated, as set in `settings`.
+    ///
+    /// While the program shows the 256-color MegaChip screen, that's shown instead, in its
+    /// own colors.
+    pub fn of_display(ch8: &VirtualMachine, overlay: &Overlay, settings: &Settings) -> Self {
+        if let Some(screen) = ch8.megachip_screen() {
+            return Self::of_megachip(screen, settings);
+        }
+        let (width, height) = ch8.display_size();
+        let (view_w, view_h) = settings.rotation.size(width, height);
+        let mut rgba = vec![255u8; width * height * 4];
+        for (i, &b) in ch8.display().iter().enumerate() {
+            let (src_x, src_y) = (i % width, i / width);
+            let (x, y) = settings.rotation.point(src_x, src_y, width, height);
+            let idx = (y * view_w + x) * 4;
+            let mut color = match (overlay.color(src_x, src_y), ch8.chip8x_colors()) {
+                (Some(color), _) if b != 0 => color,
+                (_, Some(colors)) if b != 0 => colors.foreground(src_x, src_y),
+                (_, Some(colors)) => colors.background(),
+                _ => settings.palette.color(b),
+            };
+            if let Some(blindness) = settings.simulate {
+                color = blindness.simulate(color);
+            }
+            rgba[idx..idx + 3].copy_from_slice(&color);
+        }
+        Self {
+            width: view_w,
+            height: view_h,
+            rgba,
+        }
+    }
+
+    fn of_megachip(screen: &megachip::Screen, settings: &Settings) -> Self {
+        let (width, height) = (megachip::WIDTH, megachip::HEIGHT);
+        let (view_w, view_h) = settings.rotation.size(width, height);
+        let mut rgba = vec![255u8; width * height * 4];
+        for (i, &index) in screen.pixels().iter().enumerate() {
+            let (x, y) = settings.rotation.point(i % width, i / width, width, height);
+            let idx = (y * view_w + x) * 4;
+            let mut color = screen.color(index);
+            if let Some(blindness) = settings.simulate {
+                color = blindness.simulate(color);
+            }
+            rgba[idx..idx + 3].copy_from_slice(&color);
+        }
+        Self {
+            width: view_w,
+            height: view_h,
+            rgba,
+        }
+    }
+
+    /// Reads an 8-bit RGBA PNG, like the ones [`Screenshot::write_png`] writes.
+    pub fn read_png(path: &Path) -> io::Result<Self> {
+        let decoder = png::Decoder::new(BufReader::new(File::open(path)?));
+        let mut reader = decoder.read_info()?;
+        let mut rgba = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut rgba)?;
+        if (info.color_type, info.bit_depth) != (png::ColorType::Rgba, png::BitDepth::Eight) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Not an 8-bit RGBA image",
+            ));
+        }
+        rgba.truncate(info.buffer_size());
+        Ok(Self {
+            width: info.width as usize,
+            height: info.height as usize,
+            rgba,
+        })
+    }
+
+    /// Writes the screenshot as an 8-bit RGBA PNG.
+    pub fn write_png(&self, path: &Path) -> io::Result<()> {
+        let mut encoder = png::Encoder::new(
+            BufWriter::new(File::create(path)?),
+            self.width as u32,
+            self.height as u32,
+        );
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.rgba)?;
+        writer.finish()?;
+        Ok(())
+    }
+
+    /// Returns the number of pixels that differ from `other`, or `None` if the sizes differ.
+    pub fn differing_pixels(&self, other: &Screenshot) -> Option<usize> {
+        if (self.width, self.height) != (other.width, other.height) {
+            return None;
+        }
+        Some(
+            self.rgba
+                .chunks(4)
+                .zip(other.rgba.chunks(4))
+                .filter(|(a, b)| a != b)
+                .count(),
+        )
+    }
+}
+
+/// Where [`check_golden`] writes the screenshot that didn't match `golden`, next to it.
+pub fn actual_path(golden: &Path) -> PathBuf {
+    golden.with_extension("actual.png")
+}
+
+/// Compares `actual` against the golden image at `golden`.
+///
+/// If they differ, `actual` is written to [`actual_path`], to look at side by side with the
+/// golden image, or to replace it with once the change turns out to be right. With `bless`,
+/// `actual` replaces the golden image right away.
+pub fn check_golden(actual: &Screenshot, golden: &Path, bless: bool) -> Result<(), String> {
+    if bless {
+        return actual
+            .write_png(golden)
+            .map_err(|e| format!("Failed to write {}: {}", golden.display(), e));
+    }
+    let expected = Screenshot::read_png(golden)
+        .map_err(|e| format!("Failed to read {}: {}", golden.display(), e))?;
+    let problem = match actual.differing_pixels(&expected) {
+        Some(0) => return Ok(()),
+        Some(n) => format!("{} pixels differ from {}", n, golden.display()),
+        None => format!(
+            "{}x{} doesn't match the {}x{} of {}",
+            actual.width,
+            actual.height,
+            expected.width,
+            expected.height,
+            golden.display()
+        ),
+    };
+    let path = actual_path(golden);
+    match actual.write_png(&path) {
+        Ok(()) => Err(format!("{}, see {}", problem, path.display())),
+        Err(e) => Err(format!(
+            "{} (and failed to write {}: {})",
+            problem,
+            path.display(),
+            e
+        )),
+    }
+}
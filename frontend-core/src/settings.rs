@@ -0,0 +1,165 @@
+//! Settings that can be changed while running, grouped into sections.
+//!
+//! Every section can be reset to its defaults on its own, leaving the others alone.
+//! Settings are labelled for the settings window, which can be searched by label.
+
+use crusty_chip::{
+    Palette, Quirks, Rotation, keymap::Layout, palette::ColorBlindness, quirks::QUIRKS,
+};
+
+/// A group of related settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Section {
+    /// How the display is shown.
+    Display,
+    /// The colors of the display.
+    Colors,
+    /// Keyboard input.
+    Keys,
+    /// Interpreter quirks.
+    Quirks,
+    /// Making the emulator usable with impaired vision, hearing or motor control.
+    Accessibility,
+    /// Which actions that lose progress are confirmed first.
+    Confirmations,
+}
+
+impl Section {
+    /// All sections, in the order they're shown.
+    pub const ALL: [Section; 6] = [
+        Section::Display,
+        Section::Colors,
+        Section::Keys,
+        Section::Quirks,
+        Section::Accessibility,
+        Section::Confirmations,
+    ];
+
+    /// The name of the section.
+    pub fn name(self) -> &'static str {
+        match self {
+            Section::Display => "Display",
+            Section::Colors => "Colors",
+            Section::Keys => "Keys",
+            Section::Quirks => "Quirks",
+            Section::Accessibility => "Accessibility",
+            Section::Confirmations => "Confirmations",
+        }
+    }
+
+    /// The labels of the settings in the section, in order.
+    pub fn labels(self) -> Vec<&'static str> {
+        match self {
+            Section::Display => vec!["Rotation", "Pixel aspect"],
+            Section::Colors => vec![
+                "Preset",
+                "Background",
+                "Plane 1",
+                "Plane 2",
+                "Both planes",
+                "Preview color blindness",
+            ],
+            Section::Keys => vec!["Keyboard layout"],
+            Section::Quirks => QUIRKS.iter().map(|quirk| quirk.name).collect(),
+            Section::Accessibility => vec!["Interface scale", "Visual beep", "Key repeat"],
+            Section::Confirmations => vec![
+                "Before resetting",
+                "Before loading over unsaved progress",
+                "Before overwriting a state",
+            ],
+        }
+    }
+}
+
+/// The settings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    /// The rotation of the display.
+    pub rotation: Rotation,
+    /// The width of a pixel relative to its height.
+    pub pixel_aspect: f32,
+    /// The colors of the display.
+    pub palette: Palette,
+    /// The color blindness the display is shown as seen with, to check the colors.
+    pub simulate: Option<ColorBlindness>,
+    /// The keyboard layout the keypad is mapped onto.
+    pub layout: Layout,
+    /// The quirks the VM runs with.
+    pub quirks: Quirks,
+    /// How much larger than normal the windows and text are drawn.
+    pub ui_scale: f32,
+    /// Whether the border of the window flashes while the VM beeps.
+    pub visual_beep: bool,
+    /// Whether holding down a key repeats it, like for cycle advance.
+    pub key_repeat: bool,
+    /// Whether resetting the VM asks first.
+    pub confirm_reset: bool,
+    /// Whether loading a state asks first, if the VM ran since the last save or load.
+    pub confirm_load: bool,
+    /// Whether saving to a slot that holds a state asks first.
+    pub confirm_overwrite: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            rotation: Rotation::default(),
+            pixel_aspect: 1.0,
+            palette: Palette::default(),
+            simulate: None,
+            layout: Layout::default(),
+            quirks: Quirks::default(),
+            ui_scale: 1.0,
+            visual_beep: false,
+            key_repeat: true,
+            confirm_reset: true,
+            confirm_load: true,
+            confirm_overwrite: true,
+        }
+    }
+}
+
+impl Settings {
+    /// Resets the settings of `section` to their defaults.
+    pub fn reset(&mut self, section: Section) {
+        let defaults = Settings::default();
+        match section {
+            Section::Display => {
+                self.rotation = defaults.rotation;
+                self.pixel_aspect = defaults.pixel_aspect;
+            }
+            Section::Colors => {
+                self.palette = defaults.palette;
+                self.simulate = defaults.simulate;
+            }
+            Section::Keys => self.layout = defaults.layout,
+            Section::Quirks => self.quirks = defaults.quirks,
+            Section::Accessibility => {
+                self.ui_scale = defaults.ui_scale;
+                self.visual_beep = defaults.visual_beep;
+                self.key_repeat = defaults.key_repeat;
+            }
+            Section::Confirmations => {
+                self.confirm_reset = defaults.confirm_reset;
+                self.confirm_load = defaults.confirm_load;
+                self.confirm_overwrite = defaults.confirm_overwrite;
+            }
+        }
+    }
+
+    /// Returns whether the settings of `section` are at their defaults.
+    pub fn is_default(&self, section: Section) -> bool {
+        let mut reset = self.clone();
+        reset.reset(section);
+        reset == *self
+    }
+}
+
+/// Returns whether a setting matches a search. Every word of the query has to appear in the
+/// label or the name of the section, ignoring case. An empty query matches everything.
+pub fn matches(query: &str, section: Section, label: &str) -> bool {
+    let text = format!("{} {}", section.name(), label).to_lowercase();
+    query
+        .split_whitespace()
+        .all(|word| text.contains(&word.to_lowercase()))
+}
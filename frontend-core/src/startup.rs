@@ -0,0 +1,163 @@
+//! Commands run once the ROM is loaded, for reproducible demo and debugging setups.
+//!
+//! Commands are separated by `;` or newlines, and `#` starts a comment, so the same syntax
+//! works on the command line and in a script file:
+//!
+//! ```text
+//! load-state slot1   # or autosave, or a path to a state file
+//! quirk shift_uses_vy off
+//! speed 200%         # or a number of instructions per second, like 1400
+//! hold 5
+//! pause
+//! ```
+
+use {
+    crate::states,
+    crusty_chip::{DEFAULT_IPS, quirks::QUIRKS},
+    std::path::PathBuf,
+};
+
+/// Where to load a state from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateSource {
+    /// One of the save slots of the ROM, counting from 0, or [`states::AUTOSAVE_SLOT`].
+    Slot(usize),
+    /// A state file.
+    File(PathBuf),
+}
+
+/// A startup command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Loads a state.
+    LoadState(StateSource),
+    /// Pauses the emulation.
+    Pause,
+    /// Holds down a key of the keypad, until it's pressed and released on the keyboard.
+    Hold(u8),
+    /// Turns the quirk at this index of [`QUIRKS`] on or off.
+    Quirk(usize, bool),
+    /// Sets the speed, in instructions per second.
+    Speed(u32),
+}
+
+// Slots are named slot1 to slot10, like the F1-F10 keys they're on, and autosave
+fn parse_slot(arg: &str) -> Option<usize> {
+    if arg == "autosave" {
+        return Some(states::AUTOSAVE_SLOT);
+    }
+    let n: usize = arg.strip_prefix("slot")?.parse().ok()?;
+    (1..=states::SLOTS).contains(&n).then(|| n - 1)
+}
+
+// Speeds are in instructions per second, or a percentage of the default speed
+fn parse_speed(arg: &str) -> Option<u32> {
+    match arg.strip_suffix('%') {
+        Some(percent) => {
+            let percent: u64 = percent.parse().ok()?;
+            u32::try_from(u64::from(DEFAULT_IPS) * percent / 100).ok()
+        }
+        None => arg.parse().ok(),
+    }
+}
+
+fn parse_command(words: &[&str]) -> Result<Command, String> {
+    match words {
+        ["load-state", arg] => Ok(Command::LoadState(match parse_slot(arg) {
+            Some(slot) => StateSource::Slot(slot),
+            None => StateSource::File(PathBuf::from(arg)),
+        })),
+        ["pause"] => Ok(Command::Pause),
+        ["hold", key] => match u8::from_str_radix(key, 16) {
+            Ok(key) if key < 16 => Ok(Command::Hold(key)),
+            _ => Err(format!("Invalid key: {} (expected 0-F)", key)),
+        },
+        ["quirk", name, state] => {
+            let index = QUIRKS
+                .iter()
+                .position(|quirk| quirk.name == *name)
+                .ok_or_else(|| format!("Unknown quirk: {}", name))?;
+            match *state {
+                "on" => Ok(Command::Quirk(index, true)),
+                "off" => Ok(Command::Quirk(index, false)),
+                _ => Err(format!(
+                    "Invalid quirk state: {} (expected on or off)",
+                    state
+                )),
+            }
+        }
+        ["speed", speed] => parse_speed(speed).map(Command::Speed).ok_or_else(|| {
+            format!(
+                "Invalid speed: {} (expected instructions per second, or a percentage)",
+                speed
+            )
+        }),
+        _ => Err(format!("Unknown command: {}", words.join(" "))),
+    }
+}
+
+/// Parses a list of commands.
+pub fn parse(script: &str) -> Result<Vec<Command>, String> {
+    script
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default())
+        .flat_map(|line| line.split(';'))
+        .map(|command| command.split_whitespace().collect::<Vec<_>>())
+        .filter(|words| !words.is_empty())
+        .map(|words| parse_command(&words))
+        .collect()
+}
+
+#[test]
+fn test_parse() {
+    let script = "load-state slot1; speed 200%\n# a comment\nhold a # holds A\n\npause";
+    assert_eq!(
+        parse(script),
+        Ok(vec![
+            Command::LoadState(StateSource::Slot(0)),
+            Command::Speed(DEFAULT_IPS * 2),
+            Command::Hold(0xA),
+            Command::Pause,
+        ])
+    );
+    assert_eq!(
+        parse("load-state autosave; load-state my.ccst; speed 1400"),
+        Ok(vec![
+            Command::LoadState(StateSource::Slot(states::AUTOSAVE_SLOT)),
+            Command::LoadState(StateSource::File(PathBuf::from("my.ccst"))),
+            Command::Speed(1400),
+        ])
+    );
+    let index = QUIRKS
+        .iter()
+        .position(|q| q.name == "shift_uses_vy")
+        .unwrap();
+    assert_eq!(
+        parse("quirk shift_uses_vy off"),
+        Ok(vec![Command::Quirk(index, false)])
+    );
+    assert_eq!(parse(" ; # nothing"), Ok(vec![]));
+}
+
+#[test]
+fn test_parse_errors() {
+    for script in [
+        "jump",
+        "pause now",
+        "hold 10",
+        "quirk no_such_quirk on",
+        "quirk shift_uses_vy maybe",
+        "speed fast",
+        "speed -5%",
+        "speed 99999999999%",
+    ] {
+        assert!(parse(script).is_err(), "{}", script);
+    }
+    // slot11 isn't a slot, so it's taken for a file name
+    assert_eq!(
+        parse("load-state slot11"),
+        Ok(vec![Command::LoadState(StateSource::File(PathBuf::from(
+            "slot11"
+        )))])
+    );
+}
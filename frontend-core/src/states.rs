@@ -0,0 +1,184 @@
+//! Savestates on disk.
+//!
+//! Each ROM gets a directory next to it, holding one file per slot plus a backup of the
+//! previous save to that slot. States are stored compressed, and uncompressed states saved by
+//! older versions still load.
+//!
+//! Besides the slots the user saves to, there's an autosave slot, written every so often
+//! and on exit, to continue where the user left off.
+
+use {
+    crusty_chip::{StateError, VirtualMachine, savestate},
+    std::{
+        fs::{self, File},
+        io::{self, Write},
+        path::{Path, PathBuf},
+        time::{Duration, Instant},
+    },
+};
+
+/// The number of slots the user saves to.
+pub const SLOTS: usize = 10;
+/// The slot written by [`Autosave`], after the ones the user saves to.
+pub const AUTOSAVE_SLOT: usize = SLOTS;
+
+/// The savestate directory of a ROM.
+pub fn state_dir(rom_path: &Path) -> PathBuf {
+    let mut name = rom_path.file_name().unwrap_or_default().to_owned();
+    name.push(".states");
+    rom_path.with_file_name(name)
+}
+
+fn slot_name(slot: usize) -> String {
+    if slot == AUTOSAVE_SLOT {
+        "autosave".to_owned()
+    } else {
+        format!("slot{}", slot + 1)
+    }
+}
+
+fn slot_path(dir: &Path, slot: usize) -> PathBuf {
+    dir.join(format!("{}.ccst", slot_name(slot)))
+}
+
+fn backup_path(dir: &Path, slot: usize) -> PathBuf {
+    dir.join(format!("{}.ccst.bak", slot_name(slot)))
+}
+
+/// Saves the state of `vm` to `slot`, keeping the previous save as a backup.
+///
+/// The state is written to a temporary file first, so a crash while saving
+/// can't leave a half-written state behind.
+pub fn save(dir: &Path, slot: usize, vm: &VirtualMachine) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let path = slot_path(dir, slot);
+    let tmp = dir.join(format!("{}.ccst.tmp", slot_name(slot)));
+    let mut f = File::create(&tmp)?;
+    f.write_all(&savestate::compress(&vm.save_state()))?;
+    f.sync_all()?;
+    drop(f);
+    if path.exists() {
+        fs::rename(&path, backup_path(dir, slot))?;
+    }
+    fs::rename(&tmp, &path)
+}
+
+/// Returns whether a state was saved to `slot`, even if only its backup is left.
+pub fn is_saved(dir: &Path, slot: usize) -> bool {
+    slot_path(dir, slot).exists() || backup_path(dir, slot).exists()
+}
+
+/// Deletes the state saved to `slot`, along with its backup.
+pub fn clear(dir: &Path, slot: usize) -> io::Result<()> {
+    for path in [slot_path(dir, slot), backup_path(dir, slot)] {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// How loading a slot went.
+pub enum Loaded {
+    /// The state was loaded.
+    Ok,
+    /// The state was corrupt, so the backup was loaded instead.
+    RestoredBackup(StateError),
+}
+
+/// An error while loading a slot.
+pub enum LoadError {
+    /// Nothing was saved to the slot.
+    Empty,
+    /// The state couldn't be read.
+    Io(io::Error),
+    /// The state is invalid, and there is no valid backup either.
+    Corrupt(StateError),
+}
+
+/// Loads the state saved to `slot` into `vm`, falling back to the backup if it's corrupt.
+///
+/// A crash between the two renames of [`save`] leaves only the backup, which is loaded then.
+pub fn load(dir: &Path, slot: usize, vm: &mut VirtualMachine) -> Result<Loaded, LoadError> {
+    let data = match fs::read(slot_path(dir, slot)) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return match fs::read(backup_path(dir, slot)) {
+                Ok(backup) => vm
+                    .load_state(&backup)
+                    .map(|()| Loaded::Ok)
+                    .map_err(LoadError::Corrupt),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Err(LoadError::Empty),
+                Err(e) => Err(LoadError::Io(e)),
+            };
+        }
+        Err(e) => return Err(LoadError::Io(e)),
+    };
+    let err = match vm.load_state(&data) {
+        Ok(()) => return Ok(Loaded::Ok),
+        Err(e) => e,
+    };
+    match fs::read(backup_path(dir, slot)) {
+        Ok(backup) if vm.load_state(&backup).is_ok() => Ok(Loaded::RestoredBackup(err)),
+        _ => Err(LoadError::Corrupt(err)),
+    }
+}
+
+/// Keeps time for writing the autosave slot.
+pub struct Autosave {
+    interval: Duration,
+    last: Instant,
+}
+
+impl Autosave {
+    /// Creates a timer that's due every `interval`, starting from now.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last: Instant::now(),
+        }
+    }
+
+    /// Returns whether it's time to autosave, starting the next interval if it is.
+    pub fn due(&mut self) -> bool {
+        let due = self.last.elapsed() >= self.interval;
+        if due {
+            self.last = Instant::now();
+        }
+        due
+    }
+}
+
+#[test]
+fn test_autosave_slot() {
+    let dir = std::env::temp_dir().join(format!("crusty-chip-states-{}", std::process::id()));
+    let mut vm = VirtualMachine::new();
+    // 0x200: LD V0, 7
+    vm.load_rom(&[0x60, 0x07]);
+    vm.do_cycle();
+    save(&dir, AUTOSAVE_SLOT, &vm).unwrap();
+    assert!(dir.join("autosave.ccst").exists());
+    assert!(is_saved(&dir, AUTOSAVE_SLOT));
+    assert!(!is_saved(&dir, 0));
+    let mut loaded = VirtualMachine::new();
+    assert!(matches!(load(&dir, 0, &mut loaded), Err(LoadError::Empty)));
+    assert!(matches!(
+        load(&dir, AUTOSAVE_SLOT, &mut loaded),
+        Ok(Loaded::Ok)
+    ));
+    assert_eq!(loaded.v(0), 7);
+    save(&dir, AUTOSAVE_SLOT, &vm).unwrap();
+    clear(&dir, AUTOSAVE_SLOT).unwrap();
+    assert!(!is_saved(&dir, AUTOSAVE_SLOT));
+    assert!(!dir.join("autosave.ccst.bak").exists());
+    clear(&dir, AUTOSAVE_SLOT).unwrap();
+    // A save interrupted after moving the old state to the backup leaves only the backup
+    save(&dir, 0, &vm).unwrap();
+    fs::rename(dir.join("slot1.ccst"), dir.join("slot1.ccst.bak")).unwrap();
+    assert!(is_saved(&dir, 0));
+    let mut loaded = VirtualMachine::new();
+    assert!(matches!(load(&dir, 0, &mut loaded), Ok(Loaded::Ok)));
+    assert_eq!(loaded.v(0), 7);
+    fs::remove_dir_all(&dir).unwrap();
+}
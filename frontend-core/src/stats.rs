@@ -0,0 +1,93 @@
+//! Statistics of the current session, for the session window.
+
+use {
+    crusty_chip::VirtualMachine,
+    std::time::{Duration, Instant},
+};
+
+/// What happened since the frontend was started.
+pub struct Session {
+    started: Instant,
+    last_frame: Instant,
+    /// The time the VM was running, leaving out time paused or halted.
+    pub played: Duration,
+    /// The instructions executed.
+    pub instructions: u64,
+    /// The 60 Hz timer ticks the VM went through.
+    pub ticks: u64,
+    /// The frames drawn to the window.
+    pub frames_rendered: u64,
+    /// The states saved.
+    pub states_saved: u32,
+    /// The states loaded.
+    pub states_loaded: u32,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Session {
+    /// Starts a session.
+    pub fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            started: now,
+            last_frame: now,
+            played: Duration::ZERO,
+            instructions: 0,
+            ticks: 0,
+            frames_rendered: 0,
+            states_saved: 0,
+            states_loaded: 0,
+        }
+    }
+
+    /// Returns how long the session has been going on.
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Returns the average number of instructions executed per second of playing.
+    pub fn average_ips(&self) -> f64 {
+        let secs = self.played.as_secs_f64();
+        if secs > 0.0 {
+            self.instructions as f64 / secs
+        } else {
+            0.0
+        }
+    }
+
+    /// Counts the instructions and ticks `vm` went through since its counters were `before`,
+    /// as returned by [`counters`].
+    ///
+    /// Counters going backwards, like after loading a state, count as nothing.
+    pub fn add_run(&mut self, vm: &VirtualMachine, before: (u64, u64)) {
+        let (cycles, frames) = counters(vm);
+        self.instructions += cycles.saturating_sub(before.0);
+        self.ticks += frames.saturating_sub(before.1);
+    }
+
+    /// Counts a rendered frame, and the time since the last one as played if `running`.
+    pub fn add_frame(&mut self, running: bool) {
+        let now = Instant::now();
+        if running {
+            self.played += now.duration_since(self.last_frame);
+        }
+        self.last_frame = now;
+        self.frames_rendered += 1;
+    }
+}
+
+/// Returns the cycle and frame counters of `vm`.
+pub fn counters(vm: &VirtualMachine) -> (u64, u64) {
+    (vm.cycle_count(), vm.frame_count())
+}
+
+/// Formats a duration as hours, minutes and seconds.
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
@@ -0,0 +1,110 @@
+//! Short notifications shown on top of the display, which fade out after a while.
+//!
+//! Feedback like "Saved state 3." is easy to miss in the log window, so it's shown as a
+//! toast as well. Writing to [`Toasts`] adds a toast per line and copies the line to the log.
+
+use {
+    crate::logview::Log,
+    crusty_chip::EventKind,
+    std::{
+        collections::VecDeque,
+        fmt,
+        time::{Duration, Instant},
+    },
+};
+
+/// How long a toast stays up, including fading out.
+pub const DURATION: Duration = Duration::from_secs(3);
+// How long the fading out takes, at the end of DURATION
+const FADE: Duration = Duration::from_millis(500);
+// Older toasts are dropped to make room for new ones
+const MAX_TOASTS: usize = 5;
+
+struct Toast {
+    text: String,
+    // How many times the same text was shown in a row
+    repeats: u32,
+    shown: Instant,
+}
+
+/// The toasts currently up.
+pub struct Toasts {
+    toasts: VecDeque<Toast>,
+    log: Log,
+    line: String,
+}
+
+impl Toasts {
+    /// Creates an empty list of toasts, which copies written lines to `log`.
+    pub fn new(log: Log) -> Self {
+        Self {
+            toasts: VecDeque::new(),
+            log,
+            line: String::new(),
+        }
+    }
+
+    /// Shows a toast, without copying it to the log.
+    ///
+    /// Showing the same text as the newest toast restarts it with a count instead,
+    /// so repeated messages don't pile up.
+    pub fn push(&mut self, text: impl Into<String>) {
+        let text = text.into();
+        let now = Instant::now();
+        if let Some(last) = self.toasts.back_mut()
+            && last.text == text
+        {
+            last.repeats += 1;
+            last.shown = now;
+            return;
+        }
+        if self.toasts.len() == MAX_TOASTS {
+            self.toasts.pop_front();
+        }
+        self.toasts.push_back(Toast {
+            text,
+            repeats: 1,
+            shown: now,
+        });
+    }
+
+    /// Shows a toast for the events of the VM that are worth noticing, like its warnings.
+    pub fn route(&mut self, event: &EventKind) {
+        if let EventKind::Log { message, .. } = event {
+            self.push(message.as_str());
+        }
+    }
+
+    /// Drops the toasts that have run out, and returns the rest, oldest first, with their
+    /// opacity between 0 and 1.
+    pub fn visible(&mut self) -> Vec<(String, f32)> {
+        let now = Instant::now();
+        self.toasts
+            .retain(|toast| now.duration_since(toast.shown) < DURATION);
+        self.toasts
+            .iter()
+            .map(|toast| {
+                let left = DURATION.saturating_sub(now.duration_since(toast.shown));
+                let opacity = (left.as_secs_f32() / FADE.as_secs_f32()).min(1.0);
+                let text = if toast.repeats > 1 {
+                    format!("{} (\u{d7}{})", toast.text, toast.repeats)
+                } else {
+                    toast.text.clone()
+                };
+                (text, opacity)
+            })
+            .collect()
+    }
+}
+
+impl fmt::Write for Toasts {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.log.write_str(s)?;
+        self.line.push_str(s);
+        while let Some(end) = self.line.find('\n') {
+            let line: String = self.line.drain(..=end).collect();
+            self.push(line.trim_end());
+        }
+        Ok(())
+    }
+}
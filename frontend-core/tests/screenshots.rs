@@ -0,0 +1,53 @@
+//! Compares screenshots of a known ROM state against the golden images in
+//! `tests/screenshots`. Run with `BLESS=1` to replace the golden images after a change
+//! that's meant to alter them.
+
+use {
+    crusty_chip::{Palette, Rotation, VirtualMachine, palette::ColorBlindness},
+    crusty_chip_frontend_core::{
+        colorize::Overlay,
+        screenshot::{self, Screenshot},
+        settings::Settings,
+    },
+    std::path::Path,
+};
+
+#[test]
+fn golden_screenshots() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/screenshots");
+    let bless = std::env::var_os("BLESS").is_some();
+    // Draws the digits 0-7 and 8-F in two rows, then loops
+    let rom = std::fs::read(dir.join("digits.ch8")).unwrap();
+    let mut vm = VirtualMachine::new();
+    vm.set_rng_seed(0);
+    vm.load_rom(&rom);
+    for _ in 0..60 {
+        vm.step_frame();
+    }
+    let cases = [
+        ("digits", Settings::default()),
+        (
+            "digits_rotated",
+            Settings {
+                rotation: Rotation::Cw90,
+                palette: Palette::HIGH_CONTRAST,
+                ..Settings::default()
+            },
+        ),
+        (
+            "digits_tritanopia",
+            Settings {
+                palette: Palette::OCTO,
+                simulate: Some(ColorBlindness::Tritanopia),
+                ..Settings::default()
+            },
+        ),
+    ];
+    for (name, settings) in cases {
+        let shot = Screenshot::of_display(&vm, &Overlay::default(), &settings);
+        let golden = dir.join(name).with_extension("png");
+        if let Err(e) = screenshot::check_golden(&shot, &golden, bless) {
+            panic!("{}", e);
+        }
+    }
+}
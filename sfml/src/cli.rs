@@ -0,0 +1,284 @@
+//! The command line options
+
+use {
+    crusty_chip::{Palette, Rotation, Variant, keymap::Layout},
+    crusty_chip_frontend_core::{desktop, kiosk, settings::Settings, startup, states},
+    getopts::{Matches, Options},
+    std::{path::Path, time::Duration},
+};
+
+// The options the frontend runs with, from the command line
+pub struct Args {
+    pub paused: bool,
+    pub settings: Settings,
+    pub forced_variant: Option<Variant>,
+    pub commands: Vec<startup::Command>,
+    pub kiosk: Option<kiosk::Kiosk>,
+    // Without a ROM, the boot program runs and asks for one
+    pub filename: Option<String>,
+    pub autosave: Option<states::Autosave>,
+    pub portable: bool,
+    // A savestate to resume from
+    pub state: Option<String>,
+    pub fresh: bool,
+    pub screenshot_test: Option<ScreenshotTest>,
+}
+
+// A screenshot test to run instead of opening a window
+pub struct ScreenshotTest {
+    pub golden: String,
+    pub frames: u32,
+    pub bless: bool,
+}
+
+pub fn options() -> Options {
+    let mut opts = Options::new();
+    opts.optflag("", "pause", "Start in a paused state");
+    opts.optflag(
+        "",
+        "portable",
+        "Keep states and other files in a directory next to the executable",
+    );
+    opts.optflag(
+        "",
+        "install-desktop",
+        "Install a desktop entry opening .ch8 files with this executable, and exit",
+    );
+    opts.optopt(
+        "",
+        "layout",
+        "Keyboard layout used for the keypad (qwerty, qwertz, azerty)",
+        "LAYOUT",
+    );
+    opts.optopt(
+        "",
+        "rotate",
+        "Rotate the display clockwise by 90, 180 or 270 degrees",
+        "DEGREES",
+    );
+    opts.optopt(
+        "",
+        "pixel-aspect",
+        "Width of a pixel relative to its height, for the look of non-square pixels",
+        "RATIO",
+    );
+    opts.optopt(
+        "",
+        "ui-scale",
+        "Draw the windows and their text larger, by a factor between 1 and 3",
+        "SCALE",
+    );
+    opts.optopt(
+        "",
+        "variant",
+        "Run ROMs as a platform (vip, hires, chip48, schip, megachip, xochip, chip8x) instead of \
+         guessing it",
+        "NAME",
+    );
+    opts.optflag(
+        "",
+        "high-contrast",
+        "Show the display in high contrast colors",
+    );
+    opts.optflag(
+        "",
+        "visual-beep",
+        "Flash the border of the window while the program beeps",
+    );
+    opts.optflag(
+        "",
+        "no-key-repeat",
+        "Don't repeat keys while they're held down",
+    );
+    opts.optopt(
+        "",
+        "state",
+        "Resume from a savestate of the ROM, like one from a bug report",
+        "FILE",
+    );
+    opts.optopt(
+        "",
+        "do",
+        "Commands to run after loading the ROM, like \"load-state slot1; pause\"",
+        "COMMANDS",
+    );
+    opts.optopt(
+        "",
+        "do-file",
+        "Run the commands in a file after loading the ROM, before those of --do",
+        "FILE",
+    );
+    opts.optopt(
+        "",
+        "kiosk",
+        "Cycle through the ROMs in a directory or listed in a file, for demo kiosks",
+        "PLAYLIST",
+    );
+    opts.optopt(
+        "",
+        "kiosk-minutes",
+        "How long each ROM runs in kiosk mode, 3 by default",
+        "MINUTES",
+    );
+    opts.optopt(
+        "",
+        "url",
+        "Download the ROM from a URL instead of loading a file",
+        "URL",
+    );
+    opts.optopt(
+        "",
+        "autosave",
+        "Autosave this often, and on exit, 60 seconds by default, 0 to turn autosaving off",
+        "SECONDS",
+    );
+    opts.optflag(
+        "",
+        "fresh",
+        "Start the ROM from the beginning, instead of where the autosave left off",
+    );
+    opts.optopt(
+        "",
+        "screenshot-test",
+        "Render the display offscreen after running the ROM, compare it against a golden \
+         PNG, and exit",
+        "GOLDEN",
+    );
+    opts.optopt(
+        "",
+        "screenshot-frames",
+        "How many frames to run the ROM for before the screenshot test, 60 by default",
+        "FRAMES",
+    );
+    opts.optflag(
+        "",
+        "bless",
+        "Replace the golden PNG of --screenshot-test instead of comparing against it",
+    );
+    opts
+}
+
+pub fn usage(progname: &str, opts: &Options) -> String {
+    let brief = format!("{} [rom_file]", progname);
+    format!("Usage: {}", opts.usage(&brief))
+}
+
+impl Args {
+    // Reads the options out of `matches`, or says what's wrong with them
+    pub fn parse(matches: &Matches) -> Result<Self, String> {
+        let layout = matches
+            .opt_get_default("layout", Layout::default())
+            .map_err(|e| e.to_string())?;
+        let rotation = matches
+            .opt_get_default("rotate", Rotation::default())
+            .map_err(|e| e.to_string())?;
+        let forced_variant = matches
+            .opt_get::<Variant>("variant")
+            .map_err(|e| e.to_string())?;
+        let pixel_aspect = match matches.opt_get_default("pixel-aspect", 1.0f32) {
+            Ok(aspect) if aspect > 0.0 => aspect,
+            Ok(aspect) => return Err(format!("Pixel aspect must be positive, got {}", aspect)),
+            Err(e) => return Err(format!("Invalid pixel aspect: {}", e)),
+        };
+        let ui_scale = match matches.opt_get_default("ui-scale", 1.0f32) {
+            Ok(scale) if (1.0..=3.0).contains(&scale) => scale,
+            Ok(scale) => {
+                return Err(format!(
+                    "Interface scale must be between 1 and 3, got {}",
+                    scale
+                ));
+            }
+            Err(e) => return Err(format!("Invalid interface scale: {}", e)),
+        };
+        let mut settings = Settings {
+            rotation,
+            pixel_aspect,
+            layout,
+            ui_scale,
+            visual_beep: matches.opt_present("visual-beep"),
+            key_repeat: !matches.opt_present("no-key-repeat"),
+            ..Settings::default()
+        };
+        if matches.opt_present("high-contrast") {
+            settings.palette = Palette::HIGH_CONTRAST;
+        }
+
+        let mut script = String::new();
+        if let Some(path) = matches.opt_str("do-file") {
+            script = std::fs::read_to_string(&path)
+                .map_err(|e| format!("Failed to read \"{}\": {}", path, e))?;
+        }
+        script.push('\n');
+        script.push_str(&matches.opt_str("do").unwrap_or_default());
+        let commands = startup::parse(&script)?;
+
+        let kiosk = match matches.opt_str("kiosk") {
+            None => None,
+            Some(_) if matches.opt_present("url") || !matches.free.is_empty() => {
+                return Err(
+                    "Kiosk mode runs the ROMs of the playlist, so it takes no other ROM".to_owned(),
+                );
+            }
+            Some(path) => Some(read_kiosk(matches, &path)?),
+        };
+
+        let filename = matches
+            .opt_str("url")
+            .or_else(|| {
+                kiosk
+                    .as_ref()
+                    .map(|kiosk| kiosk.current().to_string_lossy().into_owned())
+            })
+            .or_else(|| matches.free.first().map(|arg| desktop::rom_argument(arg)));
+        // Kiosk visitors start every game from the beginning, and the boot program has nothing
+        // worth continuing
+        let autosave = match matches.opt_get_default("autosave", 60u64) {
+            Ok(_) if kiosk.is_some() || filename.is_none() => None,
+            Ok(0) => None,
+            Ok(secs) => Some(states::Autosave::new(Duration::from_secs(secs))),
+            Err(e) => return Err(format!("Invalid autosave interval: {}", e)),
+        };
+
+        let screenshot_test = match matches.opt_str("screenshot-test") {
+            None => None,
+            Some(golden) => Some(ScreenshotTest {
+                golden,
+                frames: match matches.opt_str("screenshot-frames").map(|s| s.parse()) {
+                    None => 60,
+                    Some(Ok(frames)) => frames,
+                    Some(Err(e)) => return Err(format!("Invalid frame count: {}", e)),
+                },
+                bless: matches.opt_present("bless"),
+            }),
+        };
+
+        Ok(Self {
+            paused: matches.opt_present("pause"),
+            settings,
+            forced_variant,
+            commands,
+            kiosk,
+            filename,
+            autosave,
+            portable: matches.opt_present("portable"),
+            state: matches.opt_str("state"),
+            fresh: matches.opt_present("fresh"),
+            screenshot_test,
+        })
+    }
+}
+
+// Reads the playlist at `path`, giving each ROM the minutes of --kiosk-minutes
+fn read_kiosk(matches: &Matches, path: &str) -> Result<kiosk::Kiosk, String> {
+    let turn = match matches.opt_get_default("kiosk-minutes", 3.0f32) {
+        Ok(minutes) if minutes > 0.0 => Duration::try_from_secs_f32(minutes * 60.)
+            .map_err(|e| format!("Invalid kiosk minutes {}: {}", minutes, e))?,
+        Ok(minutes) => return Err(format!("Kiosk minutes must be positive, got {}", minutes)),
+        Err(e) => return Err(format!("Invalid kiosk minutes: {}", e)),
+    };
+    match kiosk::read_playlist(Path::new(path)) {
+        Ok(roms) if roms.is_empty() => Err(format!("No ROMs in \"{}\"", path)),
+        Ok(roms) => Ok(kiosk::Kiosk::new(roms, turn)),
+        Err(e) => Err(format!("Failed to read playlist \"{}\": {}", path, e)),
+    }
+}
@@ -1,261 +1,1382 @@
+mod cli;
+mod ui;
+
 use {
-    crusty_chip::{DISPLAY_HEIGHT, DISPLAY_WIDTH, VirtualMachine, decode},
-    egui_sfml::{
-        egui,
-        sfml::{
-            graphics::{RenderTarget, RenderWindow, Sprite, Texture, Transformable},
-            system::Clock,
-            window::{ContextSettings, Event, Key, Style, VideoMode},
+    crusty_chip::{
+        DISPLAY_HEIGHT, DISPLAY_WIDTH, EventKind, Rotation, Severity, Variant, VirtualMachine,
+        analysis, decode,
+        keymap::{self, Layout},
+        octo, palette, present, rom, twopage,
+    },
+    crusty_chip_frontend_core::{
+        colorize, confirm, desktop, download,
+        gamepad::{self, Device, Held},
+        kiosk,
+        logview::Log,
+        pacing,
+        players::{self, Control, Profile},
+        portable, replay,
+        screenshot::{self, Screenshot},
+        settings::Settings,
+        startup, states, stats, toasts,
+    },
+    egui_sfml::sfml::{
+        graphics::{
+            Color, FloatRect, RectangleShape, RenderTarget, RenderTexture, RenderWindow, Shape,
+            Sprite, Texture, Transformable, View,
         },
+        window::{ContextSettings, Event, Key, Style, VideoMode, joystick},
+    },
+    std::{
+        fmt::Write,
+        path::{Path, PathBuf},
+        process::ExitCode,
+        time::Instant,
     },
-    getopts::Options,
-    std::{fmt::Write, fs::File, io::Read, process::ExitCode},
 };
 
-fn sfml_key_to_ch8(code: Key) -> Option<u8> {
+// How many frames a key stays highlighted after the program read it
+const POLL_FLASH_FRAMES: u8 = 15;
+// How many frames the border flashes for at least, so even the shortest beep is seen
+const BEEP_FLASH_FRAMES: u8 = 6;
+// The width of the flashing border in pixels
+const BEEP_BORDER: f32 = 8.;
+// The keys loading the states, and saving them with Shift
+const SLOT_KEYS: [Key; states::SLOTS] = [
+    Key::F1,
+    Key::F2,
+    Key::F3,
+    Key::F4,
+    Key::F5,
+    Key::F6,
+    Key::F7,
+    Key::F8,
+    Key::F9,
+    Key::F10,
+];
+
+fn sfml_key_char(code: Key) -> Option<char> {
     Some(match code {
-        Key::Num1 => 1,
-        Key::Num2 => 2,
-        Key::Num3 => 3,
-        Key::Num4 => 0xC,
-        Key::Q => 4,
-        Key::W => 5,
-        Key::E => 6,
-        Key::R => 0xD,
-        Key::A => 7,
-        Key::S => 8,
-        Key::D => 9,
-        Key::F => 0xE,
-        Key::Z => 0xA,
-        Key::X => 0,
-        Key::C => 0xB,
-        Key::V => 0xF,
+        Key::Num0 => '0',
+        Key::Num1 => '1',
+        Key::Num2 => '2',
+        Key::Num3 => '3',
+        Key::Num4 => '4',
+        Key::Num5 => '5',
+        Key::Num6 => '6',
+        Key::Num7 => '7',
+        Key::Num8 => '8',
+        Key::Num9 => '9',
+        Key::A => 'a',
+        Key::B => 'b',
+        Key::C => 'c',
+        Key::D => 'd',
+        Key::E => 'e',
+        Key::F => 'f',
+        Key::G => 'g',
+        Key::H => 'h',
+        Key::I => 'i',
+        Key::J => 'j',
+        Key::K => 'k',
+        Key::L => 'l',
+        Key::M => 'm',
+        Key::N => 'n',
+        Key::O => 'o',
+        Key::P => 'p',
+        Key::Q => 'q',
+        Key::R => 'r',
+        Key::S => 's',
+        Key::T => 't',
+        Key::U => 'u',
+        Key::V => 'v',
+        Key::W => 'w',
+        Key::X => 'x',
+        Key::Y => 'y',
+        Key::Z => 'z',
         _ => return None,
     })
 }
 
-fn usage(progname: &str, opts: &Options) -> String {
-    let brief = format!("{} rom_file", progname);
-    format!("Usage: {}", opts.usage(&brief))
+// The key clusters of the two players, when a two-player profile is active
+fn player_control(code: Key) -> Option<(usize, Control)> {
+    Some(match code {
+        Key::W => (0, Control::Up),
+        Key::S => (0, Control::Down),
+        Key::A => (0, Control::Left),
+        Key::D => (0, Control::Right),
+        Key::LShift => (0, Control::Action),
+        Key::Up => (1, Control::Up),
+        Key::Down => (1, Control::Down),
+        Key::Left => (1, Control::Left),
+        Key::Right => (1, Control::Right),
+        Key::RShift => (1, Control::Action),
+        _ => return None,
+    })
 }
 
-fn main() -> ExitCode {
-    let mut args = std::env::args();
-    let progname = args.next().expect("Missing program name?");
-    let mut opts = Options::new();
-    opts.optflag("", "pause", "Start in a paused state");
+fn sfml_key_to_ch8(
+    code: Key,
+    layout: Layout,
+    rotation: Rotation,
+    players: Option<&Profile>,
+) -> Option<u8> {
+    // Controls the profile leaves unmapped keep their key on the keypad
+    if let Some(profile) = players
+        && let Some((player, control)) = player_control(code)
+        && let Some(key) = profile.key(player, control)
+    {
+        return Some(key);
+    }
+    sfml_key_char(code)
+        .and_then(|c| layout.hex_key(c))
+        .map(|key| keymap::rotate_key(key, rotation))
+}
 
-    let matches = match opts.parse(args) {
-        Ok(matches) => matches,
-        Err(e) => {
-            eprintln!("{}\n\n{}", e, usage(&progname, &opts));
-            return ExitCode::FAILURE;
-        }
-    };
+// Which of the windows are open
+#[derive(Default)]
+struct Windows {
+    settings: bool,
+    log: bool,
+    session: bool,
+    bookmarks: bool,
+    url: bool,
+    keypad: bool,
+    pads: bool,
+    players: bool,
+    colors: bool,
+}
+
+// What the user asked for during a frame, handled once the events and windows are through
+#[derive(Default)]
+struct Requests {
+    // Run a single frame while paused
+    advance: bool,
+    // The action asked for, and the answer to the pending one
+    action: Option<confirm::Action>,
+    answer: Option<bool>,
+    // The ROM picked from the archive
+    zip_rom: Option<String>,
+    // A URL to load a ROM from, entered in the URL window
+    url: Option<String>,
+}
 
-    let mut paused = matches.opt_present("pause");
+// The files kept for each ROM, named after the path given as their base
+struct RomFiles {
+    state_dir: PathBuf,
+    rules_path: PathBuf,
+    pad_path: PathBuf,
+    players_path: PathBuf,
+}
 
-    let filename = match matches.free.first() {
-        Some(filename) => filename,
-        None => {
-            eprintln!("Required filename as first positional argument.\n");
-            eprintln!("{}", usage(&progname, &opts));
-            return ExitCode::FAILURE;
+impl RomFiles {
+    fn new(base: &str) -> Self {
+        let base = Path::new(base);
+        Self {
+            state_dir: states::state_dir(base),
+            rules_path: colorize::rules_path(base),
+            pad_path: gamepad::bindings_path(base),
+            players_path: players::profile_path(base),
         }
-    };
+    }
+}
 
-    let mut log_open = false;
+// A ROM as read from the file given on the command line
+struct LoadedRom {
+    // The file itself, which only archives need later, to read the ROM the user picks
+    file: Vec<u8>,
+    data: Vec<u8>,
+    // If the archive holds several ROMs, the user has to pick one before starting
+    zip_choice: Option<Vec<String>>,
+    // The options of an Octo cartridge, applied whenever the ROM starts
+    cartridge: Option<octo::CartridgeOptions>,
+}
 
-    let mut clock = Clock::start().unwrap();
+// The state of the frontend, kept across frames
+struct App {
+    settings: Settings,
+    windows: Windows,
+    settings_query: String,
+    session: stats::Session,
+    // Which severities the log window shows, in the order of Severity::ALL
+    log_shown: [bool; 3],
+    log_query: String,
+    // The address the code window shows, after clicking one in the log
+    code_addr: Option<u16>,
+    perf_shown: bool,
+    // The slot Ctrl+S and Ctrl+O save to and load from, following the F keys
+    active_slot: usize,
+    // An action that loses progress, waiting for the user to confirm it
+    pending: Option<confirm::Action>,
+    frame_times: pacing::FrameTimes,
+    url_text: String,
+    bookmark_name: String,
+    poll_flash: [u8; 16],
+    beep_flash: u8,
+    held: Held,
+    // The key the next gamepad input gets bound to, from the gamepads window
+    binding_key: Option<u8>,
+    bind_any: bool,
+    connected: Vec<u32>,
+    overlay: colorize::Overlay,
+    // Sprite addresses drawn from recently, to make rules for
+    recent_sprites: Vec<u16>,
+    pacer: pacing::Pacer,
+    // The session being recorded, the last one recorded, and the one being played back
+    recorder: Option<replay::Recorder>,
+    last_replay: Option<replay::Replay>,
+    playback: Option<replay::Playback>,
+    paused: bool,
+    kiosk: Option<kiosk::Kiosk>,
+    autosave: Option<states::Autosave>,
+    // Whether --state or the startup commands load a state, which the autosave would undo
+    state_given: bool,
+    fresh: bool,
+    // The file given on the command line, and the path its files are named after
+    file: Vec<u8>,
+    state_base: String,
+    portable_dir: Option<PathBuf>,
+    forced_variant: Option<Variant>,
+    variant: Option<Variant>,
+    zip_choice: Option<Vec<String>>,
+    cartridge: Option<octo::CartridgeOptions>,
+    data: Vec<u8>,
+    // The messages of the VM and the frontend, for the log window
+    log: Log,
+    ch8: VirtualMachine,
+    // The keys the ROM was found to read, outlined on the keypad
+    rom_keys: u16,
+    // Feedback worth noticing, shown on top of the display as well as in the log
+    toasts: toasts::Toasts,
+    files: RomFiles,
+    rules: Vec<colorize::Rule>,
+    bindings: Vec<gamepad::Binding>,
+    two_players: Option<Profile>,
+    // How keyboard keys map to the keypad, to let go of held keys when it changes
+    key_mapping: (Layout, Rotation, Option<Profile>),
+    progress: confirm::Progress,
+    // The keys last pressed on the VM for the devices. A loaded state brings its own.
+    held_keys: u16,
+}
 
-    let file = match File::open(filename) {
-        Ok(f) => f,
+fn main() -> ExitCode {
+    let mut args = std::env::args();
+    let progname = args.next().expect("Missing program name?");
+    let opts = cli::options();
+    let matches = match opts.parse(args) {
+        Ok(matches) => matches,
         Err(e) => {
-            eprintln!("Failed to open \"{}\": {}", filename, e);
+            eprintln!("{}\n\n{}", e, cli::usage(&progname, &opts));
             return ExitCode::FAILURE;
         }
     };
 
-    let mut data = Vec::new();
-    file.take(crusty_chip::MEM_SIZE as u64)
-        .read_to_end(&mut data)
-        .unwrap_or_else(|e| panic!("Failed to read rom: {}", e));
+    if matches.opt_present("install-desktop") {
+        return match desktop::install() {
+            Ok(path) => {
+                println!("Installed {}", path.display());
+                ExitCode::SUCCESS
+            }
+            Err(e) => {
+                eprintln!("Failed to install the desktop entry: {}", e);
+                ExitCode::FAILURE
+            }
+        };
+    }
 
-    let scale = 10;
+    match cli::Args::parse(&matches).and_then(run) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
 
-    let mut ch8 = VirtualMachine::new();
-    ch8.load_rom(&data);
+// Runs the frontend until the window is closed, or the screenshot test is done
+fn run(args: cli::Args) -> Result<(), String> {
+    let Some(mut app) = App::new(args)? else {
+        return Ok(());
+    };
 
+    let (win_w, win_h) = window_size(&app.settings);
     let ctx = ContextSettings::default();
     let mut win = RenderWindow::new(
-        VideoMode::new(
-            DISPLAY_WIDTH as u32 * scale,
-            DISPLAY_HEIGHT as u32 * scale,
-            32,
-        ),
+        VideoMode::new(win_w, win_h, 32),
         "CrustyChip",
-        Style::CLOSE,
+        Style::DEFAULT,
         &ctx,
     )
     .unwrap();
     win.set_vertical_sync_enabled(true);
+    win.set_key_repeat_enabled(app.settings.key_repeat);
+    let mut key_repeat = app.settings.key_repeat;
+    let (icon_w, icon_h, icon) = desktop::icon_rgba();
+    // SAFETY: The icon has icon_w * icon_h RGBA pixels
+    unsafe {
+        win.set_icon(icon_w, icon_h, &icon);
+    }
 
     let mut sf_egui = egui_sfml::SfEgui::new(&win);
 
     let mut tex = Texture::new().unwrap();
-    if tex
-        .create(DISPLAY_WIDTH as u32, DISPLAY_HEIGHT as u32)
-        .is_err()
-    {
+    let (view_w, view_h) = app.settings.rotation.size(DISPLAY_WIDTH, DISPLAY_HEIGHT);
+    if tex.create(view_w as u32, view_h as u32).is_err() {
         panic!("Couldn't create texture");
     }
-    let mut saved_states: [Option<VirtualMachine>; 10] = std::array::from_fn(|_idx| None);
-    let mut printed_info = false;
-    let mut cycles_made: u64 = 0;
 
     loop {
-        let mut advance = false;
+        let frame_start = Instant::now();
+        app.frame_times.start_frame();
+        if let Some(autosave) = &mut app.autosave
+            && app.zip_choice.is_none()
+            && autosave.due()
+        {
+            write_autosave(&app.files.state_dir, &app.ch8, &mut app.log);
+        }
+        let mut requests = Requests::default();
         while let Some(event) = win.poll_event() {
             sf_egui.add_event(&event);
             match event {
-                Event::Closed => return ExitCode::SUCCESS,
-                Event::KeyPressed {
-                    code, ctrl, shift, ..
-                } => {
-                    if code == Key::P {
-                        paused = !paused;
-                    } else if code == Key::R && ctrl {
-                        ch8 = VirtualMachine::new();
-                        ch8.load_rom(&data);
-                    } else if code == Key::Period {
-                        advance = true;
-                    } else if code == Key::F11 {
-                        log_open ^= true;
-                    } else if let Some(key) = sfml_key_to_ch8(code) {
-                        ch8.press_key(key);
+                Event::Closed => {
+                    app.autosave_now();
+                    return Ok(());
+                }
+                // Keep drawing in pixels, the display is scaled to fit by render_screen
+                Event::Resized { width, height } => {
+                    let area = FloatRect::new(0., 0., width as f32, height as f32);
+                    win.set_view(&View::from_rect(area).unwrap());
+                }
+                event => app.handle_event(event, &mut requests),
+            }
+        }
+        app.sync_keys();
+        app.emulate(requests.advance);
+        app.handle_vm_events();
+        app.next_kiosk_rom();
+        let di = sf_egui
+            .run(&mut win, |_rw, ctx| app.show_ui(ctx, &mut requests))
+            .unwrap();
+        if let Some(url) = requests.url.take() {
+            app.load_url(url);
+        }
+        if let Some(name) = requests.zip_rom.take() {
+            app.load_zip_rom(name);
+        }
+        app.handle_actions(&requests);
+
+        let shot = Screenshot::of_display(&app.ch8, &app.overlay, &app.settings);
+        let (view_w, view_h) = (shot.width as u32, shot.height as u32);
+        let tex_size = tex.size();
+        if (tex_size.x, tex_size.y) != (view_w, view_h) && tex.create(view_w, view_h).is_err() {
+            return Err("Couldn't create texture".to_owned());
+        }
+        if app.settings.key_repeat != key_repeat {
+            key_repeat = app.settings.key_repeat;
+            win.set_key_repeat_enabled(key_repeat);
+        }
+        let beeping = app.settings.visual_beep && app.beep_flash > 0;
+        render_screen(&mut *win, &mut tex, &shot, &app.settings, beeping);
+        app.session
+            .add_frame(!app.is_waiting() && app.ch8.halt_reason().is_none());
+        app.ch8.clear_du_flag();
+        sf_egui.draw(di, &mut win, None);
+        win.display();
+        if app.is_waiting() || app.ch8.is_idle() {
+            pacing::finish_frame(frame_start);
+        }
+    }
+}
+
+// The size of the window at the start, 10 times the low resolution display, which a 128x64
+// display fills at half the scale
+fn window_size(settings: &Settings) -> (u32, u32) {
+    let scale = 10.;
+    let (view_w, view_h) = settings.rotation.size(DISPLAY_WIDTH, DISPLAY_HEIGHT);
+    (
+        (view_w as f32 * scale * settings.pixel_aspect.max(1.0)) as u32,
+        (view_h as f32 * scale / settings.pixel_aspect.min(1.0)) as u32,
+    )
+}
+
+// Reads the ROM in `filename`, which can be a URL, an archive, an Octo cartridge or a hex
+// listing. Without a file, it's the boot program.
+fn read_rom(filename: Option<&str>) -> Result<LoadedRom, String> {
+    let mut file = match filename {
+        None => crusty_chip::boot::ROM.to_vec(),
+        Some(filename) if download::is_url(filename) => download::download(filename)
+            .map_err(|e| format!("Failed to download \"{}\": {}", filename, e))?,
+        Some(filename) => rom::read_file(Path::new(filename))
+            .map_err(|e| format!("Failed to open \"{}\": {}", filename, e))?,
+    };
+    let filename = filename.unwrap_or_default();
+    let mut zip_choice = None;
+    let mut cartridge = None;
+    let data = if rom::is_zip(&file) {
+        match rom::read_single_zip_rom(&file) {
+            Ok(Some(data)) => data,
+            Ok(None) => {
+                zip_choice = Some(rom::zip_rom_names(&file).unwrap_or_default());
+                Vec::new()
+            }
+            Err(e) => return Err(format!("Failed to load ROM from \"{}\": {}", filename, e)),
+        }
+    } else if filename.to_ascii_lowercase().ends_with(".gif") {
+        // Octo cartridges hold the source of the program, along with the quirks and speed
+        let (rom, options) = octo::read_cartridge(&file[..])
+            .map_err(|e| e.to_string())
+            .and_then(|cartridge| match octo::assemble(&cartridge.program) {
+                Ok(rom) => Ok((rom, cartridge.options)),
+                Err(e) => Err(e.to_string()),
+            })
+            .map_err(|e| format!("Failed to load Octo cartridge \"{}\": {}", filename, e))?;
+        cartridge = Some(options);
+        rom
+    } else if [".hex", ".txt"]
+        .iter()
+        .any(|ext| filename.to_ascii_lowercase().ends_with(ext))
+    {
+        rom::load_hex(&String::from_utf8_lossy(&file))
+            .map_err(|e| format!("Failed to load hex ROM \"{}\": {}", filename, e))?
+    } else {
+        std::mem::take(&mut file)
+    };
+    Ok(LoadedRom {
+        file,
+        data,
+        zip_choice,
+        cartridge,
+    })
+}
+
+impl App {
+    // Loads the ROM and runs the startup commands of `args`. Returns None after running the
+    // screenshot test instead.
+    fn new(args: cli::Args) -> Result<Option<Self>, String> {
+        let cli::Args {
+            paused,
+            settings,
+            forced_variant,
+            commands,
+            kiosk,
+            filename,
+            autosave,
+            portable,
+            state,
+            fresh,
+            screenshot_test: test,
+        } = args;
+        let LoadedRom {
+            file,
+            data,
+            zip_choice,
+            cartridge,
+        } = read_rom(filename.as_deref())?;
+        let filename = filename.unwrap_or_default();
+        // Downloaded ROMs and the boot program keep their states in the current directory
+        let state_base = if download::is_url(&filename) {
+            download::file_name(&filename)
+        } else if filename.is_empty() {
+            "boot".to_owned()
+        } else {
+            filename.clone()
+        };
+        let portable_dir = if portable || portable::is_marked() {
+            Some(
+                portable::data_dir()
+                    .map_err(|e| format!("Failed to create the portable data directory: {}", e))?,
+            )
+        } else {
+            None
+        };
+        let state_base = match &portable_dir {
+            Some(dir) => portable::rebase(dir, &state_base),
+            None => state_base,
+        };
+
+        // CHIP-8X ROMs only run as such, and only the file name tells them apart
+        let variant = forced_variant.or_else(|| rom::required_variant(&filename));
+        let mut log = Log::new(1000);
+        let mut ch8 = start(&data, variant, &mut log);
+        if let Some(options) = &cartridge {
+            apply_cartridge(&mut ch8, options);
+        }
+        let rom_keys = discover_keys(&data, variant, &mut log);
+        let toasts = toasts::Toasts::new(log.clone());
+        if let Some(path) = &state {
+            if zip_choice.is_some() {
+                return Err(
+                    "The archive holds several ROMs, so it's unclear which the state is of"
+                        .to_owned(),
+                );
+            }
+            std::fs::read(path)
+                .map_err(|e| e.to_string())
+                .and_then(|state| ch8.load_state(&state).map_err(|e| e.to_string()))
+                .map_err(|e| format!("Failed to load state \"{}\": {}", path, e))?;
+        }
+
+        if let Some(test) = test {
+            let golden = Path::new(&test.golden);
+            screenshot_test(&mut ch8, &settings, test.frames, window_size(&settings))
+                .and_then(|shot| screenshot::check_golden(&shot, golden, test.bless))?;
+            println!("{} matches", golden.display());
+            return Ok(None);
+        }
+
+        let files = RomFiles::new(&state_base);
+        let two_players = players::load(&files.players_path);
+        let mut app = Self {
+            key_mapping: (settings.layout, settings.rotation, two_players),
+            settings,
+            windows: Windows::default(),
+            settings_query: String::new(),
+            session: stats::Session::new(),
+            log_shown: [true; 3],
+            log_query: String::new(),
+            code_addr: None,
+            perf_shown: false,
+            active_slot: 0,
+            pending: None,
+            frame_times: pacing::FrameTimes::default(),
+            url_text: String::new(),
+            bookmark_name: String::new(),
+            poll_flash: [0; 16],
+            beep_flash: 0,
+            held: Held::default(),
+            binding_key: None,
+            bind_any: true,
+            connected: (0..gamepad::MAX_GAMEPADS as u32)
+                .filter(|&id| joystick::is_connected(id))
+                .collect(),
+            overlay: colorize::Overlay::default(),
+            recent_sprites: Vec::new(),
+            pacer: pacing::Pacer::new(),
+            recorder: None,
+            last_replay: None,
+            playback: None,
+            paused,
+            kiosk,
+            autosave,
+            state_given: state.is_some()
+                || commands
+                    .iter()
+                    .any(|command| matches!(command, startup::Command::LoadState(_))),
+            fresh,
+            file,
+            state_base,
+            portable_dir,
+            forced_variant,
+            variant,
+            zip_choice,
+            cartridge,
+            data,
+            log,
+            progress: confirm::Progress::new(&ch8),
+            held_keys: 0,
+            ch8,
+            rom_keys,
+            toasts,
+            rules: colorize::load(&files.rules_path),
+            bindings: gamepad::load(&files.pad_path),
+            two_players,
+            files,
+        };
+        if app.autosave.is_some() && app.zip_choice.is_none() && !app.state_given && !app.fresh {
+            restore_autosave(
+                &app.files.state_dir,
+                &mut app.ch8,
+                &mut app.log,
+                &mut app.toasts,
+            );
+        }
+        for command in commands {
+            app.run_command(command)?;
+        }
+        app.progress.mark_saved(&app.ch8);
+        app.held_keys = pressed_keys(&app.ch8);
+        Ok(Some(app))
+    }
+
+    fn run_command(&mut self, command: startup::Command) -> Result<(), String> {
+        match command {
+            startup::Command::LoadState(startup::StateSource::Slot(slot)) => {
+                match states::load(&self.files.state_dir, slot, &mut self.ch8) {
+                    Ok(_) => Ok(()),
+                    Err(states::LoadError::Empty) => Err("nothing saved".to_owned()),
+                    Err(states::LoadError::Io(e)) => Err(e.to_string()),
+                    Err(states::LoadError::Corrupt(e)) => Err(e.to_string()),
+                }
+                .map_err(|e| format!("Failed to load state {}: {}", slot + 1, e))
+            }
+            startup::Command::LoadState(startup::StateSource::File(path)) => std::fs::read(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|state| self.ch8.load_state(&state).map_err(|e| e.to_string()))
+                .map_err(|e| format!("Failed to load state \"{}\": {}", path.display(), e)),
+            startup::Command::Pause => {
+                self.paused = true;
+                Ok(())
+            }
+            startup::Command::Hold(key) => {
+                self.held.set_keyboard(key, true);
+                Ok(())
+            }
+            startup::Command::Quirk(index, on) => {
+                let mut quirks = self.ch8.quirks();
+                *quirks.flags_mut()[index] = on;
+                self.ch8.set_quirks(quirks);
+                Ok(())
+            }
+            startup::Command::Speed(ips) => {
+                self.ch8.set_speed(ips);
+                Ok(())
+            }
+        }
+    }
+
+    // Whether the program waits, for the user to unpause, answer a prompt or pick a ROM
+    fn is_waiting(&self) -> bool {
+        self.paused || self.pending.is_some() || self.zip_choice.is_some()
+    }
+
+    // Saves to the autosave slot, before leaving the ROM that's running
+    fn autosave_now(&mut self) {
+        if self.autosave.is_some() && self.zip_choice.is_none() {
+            write_autosave(&self.files.state_dir, &self.ch8, &mut self.log);
+        }
+    }
+
+    // Handles the input of the keyboard and the gamepads
+    fn handle_event(&mut self, event: Event, requests: &mut Requests) {
+        if let Some(kiosk) = &mut self.kiosk
+            && match event {
+                Event::KeyPressed { .. } | Event::JoystickButtonPressed { .. } => true,
+                Event::JoystickMoved { axis, position, .. } => {
+                    gamepad::axis_input(axis as u32, position).is_some()
+                }
+                _ => false,
+            }
+        {
+            kiosk.interrupt();
+        }
+        match event {
+            Event::KeyPressed {
+                code, ctrl, shift, ..
+            } => self.handle_key(code, ctrl, shift, requests),
+            Event::KeyReleased { code, .. } => {
+                if let Some(key) = sfml_key_to_ch8(
+                    code,
+                    self.settings.layout,
+                    self.settings.rotation,
+                    self.two_players.as_ref(),
+                ) {
+                    self.held.set_keyboard(key, false);
+                }
+            }
+            Event::JoystickConnected { joystickid } => {
+                if !self.connected.contains(&joystickid) {
+                    self.connected.push(joystickid);
+                }
+                writeln!(self.toasts, "Gamepad {} connected.", joystickid).unwrap();
+            }
+            Event::JoystickDisconnected { joystickid } => {
+                self.connected.retain(|&id| id != joystickid);
+                self.held.release_all(Device::Gamepad(joystickid));
+                writeln!(self.toasts, "Gamepad {} disconnected.", joystickid).unwrap();
+            }
+            Event::JoystickButtonPressed { joystickid, button } => match self.binding_key.take() {
+                Some(key) => self.bindings.push(gamepad::Binding {
+                    gamepad: (!self.bind_any).then_some(joystickid),
+                    input: gamepad::Input::Button(button),
+                    key,
+                }),
+                None => self.held.button(&self.bindings, joystickid, button, true),
+            },
+            Event::JoystickButtonReleased { joystickid, button } => {
+                self.held.button(&self.bindings, joystickid, button, false);
+            }
+            Event::JoystickMoved {
+                joystickid,
+                axis,
+                position,
+            } => {
+                let input = gamepad::axis_input(axis as u32, position);
+                if let Some(key) = self.binding_key
+                    && let Some(input) = input
+                {
+                    self.bindings.push(gamepad::Binding {
+                        gamepad: (!self.bind_any).then_some(joystickid),
+                        input,
+                        key,
+                    });
+                    self.binding_key = None;
+                } else {
+                    self.held
+                        .axis(&self.bindings, joystickid, axis as u32, position);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_key(&mut self, code: Key, ctrl: bool, shift: bool, requests: &mut Requests) {
+        // The prompt takes the keyboard until it's answered
+        if self.pending.is_some() {
+            if code == Key::Enter {
+                requests.answer = Some(true);
+            } else if code == Key::Escape {
+                requests.answer = Some(false);
+            }
+            return;
+        }
+        if code == Key::P {
+            self.paused = !self.paused;
+        } else if code == Key::R && ctrl {
+            requests.action = Some(confirm::Action::Reset);
+        } else if code == Key::K && ctrl {
+            self.windows.keypad ^= true;
+        } else if code == Key::T && ctrl {
+            self.windows.players ^= true;
+        } else if code == Key::I && ctrl {
+            self.windows.session ^= true;
+        } else if code == Key::J && ctrl {
+            self.windows.pads ^= true;
+        } else if code == Key::U && ctrl {
+            self.windows.url ^= true;
+        } else if code == Key::E && ctrl && shift {
+            self.toggle_playback();
+        } else if code == Key::E && ctrl {
+            self.toggle_recording();
+        } else if code == Key::L && ctrl {
+            self.windows.colors ^= true;
+        } else if code == Key::Period {
+            requests.advance = true;
+        } else if code == Key::Comma && ctrl {
+            self.windows.settings ^= true;
+        } else if code == Key::F10 {
+            self.perf_shown ^= true;
+        } else if code == Key::F11 {
+            self.windows.log ^= true;
+        } else if code == Key::F12 {
+            self.windows.bookmarks ^= true;
+        } else if code == Key::LBracket || code == Key::RBracket {
+            let step = if code == Key::RBracket {
+                1
+            } else {
+                states::SLOTS - 1
+            };
+            self.active_slot = (self.active_slot + step) % states::SLOTS;
+            writeln!(
+                self.toasts,
+                "State {} selected, Ctrl+S saves and Ctrl+O loads it.",
+                self.active_slot + 1
+            )
+            .unwrap();
+        } else if ctrl && (code == Key::S || code == Key::O) {
+            // Saving and loading the selected slot is handled with the F keys
+        } else if let Some(key) = sfml_key_to_ch8(
+            code,
+            self.settings.layout,
+            self.settings.rotation,
+            self.two_players.as_ref(),
+        ) {
+            self.held.set_keyboard(key, true);
+        }
+        // Which slot to save to or load from
+        let slot_action = if code == Key::S && ctrl {
+            Some((self.active_slot, true))
+        } else if code == Key::O && ctrl {
+            Some((self.active_slot, false))
+        } else {
+            SLOT_KEYS
+                .iter()
+                .position(|&key| key == code)
+                .map(|slot| (slot, shift))
+        };
+        if let Some((slot, save)) = slot_action {
+            self.active_slot = slot;
+            requests.action = Some(if save {
+                confirm::Action::Save(slot)
+            } else {
+                confirm::Action::Load(slot)
+            });
+        }
+    }
+
+    // Plays back the last replay, or stops the one playing
+    fn toggle_playback(&mut self) {
+        if self.playback.take().is_some() {
+            self.held_keys = pressed_keys(&self.ch8);
+            writeln!(self.toasts, "Replay stopped.").unwrap();
+        } else if let Some(replay) = &self.last_replay {
+            self.recorder = None;
+            self.playback = Some(replay.play(&mut self.ch8));
+            writeln!(
+                self.toasts,
+                "Playing back the replay, Ctrl+Shift+E stops it."
+            )
+            .unwrap();
+        } else {
+            writeln!(
+                self.toasts,
+                "Nothing recorded yet, Ctrl+E starts recording."
+            )
+            .unwrap();
+        }
+    }
+
+    // Starts recording a replay, or finishes the one being recorded
+    fn toggle_recording(&mut self) {
+        if let Some(recording) = self.recorder.take() {
+            let replay = recording.finish();
+            writeln!(
+                self.toasts,
+                "Recorded {} frames, Ctrl+Shift+E plays them back.",
+                replay.frames()
+            )
+            .unwrap();
+            self.last_replay = Some(replay);
+        } else {
+            if self.playback.take().is_some() {
+                self.held_keys = pressed_keys(&self.ch8);
+            }
+            self.recorder = Some(replay::Recorder::new(&self.ch8));
+            writeln!(self.toasts, "Recording, Ctrl+E stops.").unwrap();
+        }
+    }
+
+    // Presses and releases the keys on the VM that the devices did
+    fn sync_keys(&mut self) {
+        let mapping = (
+            self.settings.layout,
+            self.settings.rotation,
+            self.two_players,
+        );
+        // Releasing a key under a new mapping would release another one, so let go of them all
+        if self.key_mapping != mapping {
+            self.key_mapping = mapping;
+            self.held.release_all(Device::Keyboard);
+        }
+        // A key stays pressed as long as any device holds it. A replay presses its own keys.
+        let keys = self.held.keys();
+        if self.playback.is_none() {
+            for key in 0..16 {
+                if (keys ^ self.held_keys) & (1 << key) != 0 {
+                    if keys & (1 << key) != 0 {
+                        self.ch8.press_key(key);
+                    } else {
+                        self.ch8.release_key(key);
                     }
-                    macro_rules! state_key (
-                        ($s: expr, $k: ident) => (
-                            if code == Key::$k {
-                                if shift {
-                                    saved_states[$s] = Some(ch8.clone());
-                                    writeln!(ch8.log, "Saved state {}.", $s).unwrap();
-                                } else if let Some(state) = saved_states[$s].clone() {
-                                    ch8 = state;
-                                    writeln!(ch8.log, "Loaded state {}.", $s).unwrap();
-                                }
-                            }
+                }
+            }
+            self.held_keys = keys;
+        }
+    }
+
+    // Runs the frames that are due, recording or playing back a replay along the way
+    fn emulate(&mut self, advance: bool) {
+        let before = stats::counters(&self.ch8);
+        if self.zip_choice.is_none() {
+            // The program waits along with the user while a prompt is open
+            let paused = self.paused || self.pending.is_some();
+            let mut replay_ended = false;
+            let Self {
+                frame_times,
+                pacer,
+                ch8,
+                playback,
+                recorder,
+                log,
+                ..
+            } = self;
+            frame_times.emulate(|| {
+                pacer.run_frames(
+                    ch8,
+                    paused,
+                    advance,
+                    |ch8| {
+                        if let Some(playback) = playback {
+                            replay_ended |= !playback.apply(ch8);
+                        } else if let Some(recorder) = recorder {
+                            recorder.record(ch8);
+                        }
+                    },
+                    |ch8| {
+                        let pc = usize::from(ch8.pc());
+                        let mem = ch8.memory();
+                        let raw_ins = u16::from_be_bytes([
+                            mem.get(pc).copied().unwrap_or(0),
+                            mem.get(pc + 1).copied().unwrap_or(0),
+                        ]);
+                        writeln!(
+                            log,
+                            "Cycle {}, pc @ {:#x}, ins: {:#x?} raw: {:#x}",
+                            ch8.cycle_count(),
+                            ch8.pc(),
+                            decode(raw_ins),
+                            raw_ins
                         )
-                    );
-                    state_key!(0, F1);
-                    state_key!(1, F2);
-                    state_key!(2, F3);
-                    state_key!(3, F4);
-                    state_key!(4, F5);
-                    state_key!(5, F6);
-                    state_key!(6, F7);
-                    state_key!(7, F8);
-                    state_key!(8, F9);
-                    state_key!(9, F10);
+                        .unwrap();
+                    },
+                )
+            });
+            if replay_ended {
+                self.playback = None;
+                self.held_keys = pressed_keys(&self.ch8);
+                writeln!(self.toasts, "The replay is over.").unwrap();
+            }
+        }
+        self.session.add_run(&self.ch8, before);
+    }
+
+    // Passes the events of the VM on to the log, the toasts, the keypad and the overlay
+    fn handle_vm_events(&mut self) {
+        for flash in &mut self.poll_flash {
+            *flash = flash.saturating_sub(1);
+        }
+        self.beep_flash = self.beep_flash.saturating_sub(1);
+        for event in self.ch8.take_events() {
+            self.toasts.route(&event.kind);
+            self.log.add_event(&event.kind);
+            match event.kind {
+                EventKind::KeyPolled { key, .. } => {
+                    self.poll_flash[usize::from(key)] = POLL_FLASH_FRAMES;
                 }
-                Event::KeyReleased { code, .. } => {
-                    if let Some(key) = sfml_key_to_ch8(code) {
-                        ch8.release_key(key);
+                EventKind::SoundStarted => self.beep_flash = BEEP_FLASH_FRAMES,
+                // Sprites drawn at the old resolution don't line up with the new one
+                EventKind::ResolutionChanged(resolution) => {
+                    self.overlay = colorize::Overlay::new(resolution);
+                }
+                EventKind::SpriteDrawn(draw) => {
+                    self.overlay.draw(&draw, self.ch8.memory(), &self.rules);
+                    if !self.recent_sprites.contains(&draw.addr) {
+                        self.recent_sprites.insert(0, draw.addr);
+                        self.recent_sprites.truncate(8);
                     }
                 }
                 _ => {}
             }
         }
-        let mut cycles = 0;
-        while !(ch8.display_updated() || ch8.waiting_for_key()) {
-            do_emulation_cycle(
-                &mut clock,
-                &mut ch8,
-                paused,
-                &mut printed_info,
-                &mut cycles_made,
-                advance,
+        // Waiting for a key reads all of them, for as long as it waits
+        if self.ch8.waiting_for_key() {
+            self.poll_flash = [POLL_FLASH_FRAMES; 16];
+        }
+        if self.ch8.sound_playing() {
+            self.beep_flash = BEEP_FLASH_FRAMES;
+        }
+    }
+
+    // Moves on to the next ROM of the kiosk playlist, once the turn of this one is over
+    fn next_kiosk_rom(&mut self) {
+        let idle = self.ch8.halt_reason().is_some() || self.ch8.waiting_for_key();
+        let Some(path) = self
+            .kiosk
+            .as_mut()
+            .and_then(|kiosk| kiosk.update(idle))
+            .map(Path::to_path_buf)
+        else {
+            return;
+        };
+        // A ROM that fails to load leaves the last one running until its turn is over
+        match kiosk::read_rom(&path) {
+            Ok(rom) => {
+                let base = self.rebase(&path.to_string_lossy());
+                self.switch_rom(rom, &path.to_string_lossy(), &base, false);
+            }
+            Err(e) => {
+                writeln!(
+                    self.log.at(Severity::Error),
+                    "Failed to load \"{}\": {}",
+                    path.display(),
+                    e
+                )
+                .unwrap();
+            }
+        }
+    }
+
+    // Downloads the ROM at `url` and runs it in place of the one running
+    fn load_url(&mut self, url: String) {
+        let name = download::file_name(&url);
+        let result = if download::is_url(&url) {
+            download::download(&url).and_then(|file| kiosk::unpack_rom(file, &name))
+        } else {
+            Err("not an http:// or https:// URL".to_owned())
+        };
+        match result {
+            Ok(rom) => {
+                self.autosave_now();
+                // Downloaded ROMs keep their states in the current directory
+                let base = self.rebase(&name);
+                self.switch_rom(rom, &name, &base, true);
+                self.windows.url = false;
+                writeln!(self.toasts, "Loaded {}.", name).unwrap();
+            }
+            Err(e) => {
+                self.windows.log = true;
+                writeln!(
+                    self.log.at(Severity::Error),
+                    "Failed to load {}: {}",
+                    url,
+                    e
+                )
+                .unwrap();
+            }
+        }
+    }
+
+    // Runs the ROM called `name` in the archive, as picked by the user
+    fn load_zip_rom(&mut self, name: String) {
+        match rom::read_zip_rom(&self.file, &name) {
+            Ok(rom) => {
+                // Members in folders would otherwise get a state directory in a folder
+                // that doesn't exist
+                let base = format!("{}#{}", self.state_base, name.replace(['/', '\\'], "_"));
+                // Only now is it known which ROM of the archive to continue
+                let restore = !self.state_given && !self.fresh;
+                self.switch_rom(rom, &name, &base, restore);
+            }
+            Err(e) => {
+                self.windows.log = true;
+                writeln!(
+                    self.log.at(Severity::Error),
+                    "Failed to load {}: {}",
+                    name,
+                    e
+                )
+                .unwrap();
+            }
+        }
+    }
+
+    // Where the files of a ROM named after `base` go, with --portable in the portable directory
+    fn rebase(&self, base: &str) -> String {
+        match &self.portable_dir {
+            Some(dir) => portable::rebase(dir, base),
+            None => base.to_owned(),
+        }
+    }
+
+    // Starts `data`, the ROM called `name`, from scratch, with the files named after `base`.
+    // With `restore`, it continues from the autosave of the ROM, if autosaving.
+    fn switch_rom(&mut self, data: Vec<u8>, name: &str, base: &str, restore: bool) {
+        self.data = data;
+        self.variant = self.forced_variant.or_else(|| rom::required_variant(name));
+        self.cartridge = None;
+        self.zip_choice = None;
+        self.ch8 = start(&self.data, self.variant, &mut self.log);
+        self.rom_keys = discover_keys(&self.data, self.variant, &mut self.log);
+        (self.recorder, self.last_replay, self.playback) = (None, None, None);
+        self.overlay = colorize::Overlay::default();
+        self.files = RomFiles::new(base);
+        self.rules = colorize::load(&self.files.rules_path);
+        self.bindings = gamepad::load(&self.files.pad_path);
+        self.two_players = players::load(&self.files.players_path);
+        if restore && self.autosave.is_some() {
+            restore_autosave(
+                &self.files.state_dir,
+                &mut self.ch8,
+                &mut self.log,
+                &mut self.toasts,
             );
-            cycles += 1;
-            // Take a little break and render if the machine takes too long
-            // to respond
-            if cycles > 50_000 {
-                break;
+        }
+        self.progress.mark_saved(&self.ch8);
+        self.held_keys = pressed_keys(&self.ch8);
+    }
+
+    // Carries out the actions asked for this frame, once they're confirmed if they need to be
+    fn handle_actions(&mut self, requests: &Requests) {
+        // Actions that lose progress wait for confirmation, if the settings ask for it
+        let mut confirmed = requests
+            .answer
+            .and_then(|yes| self.pending.take().filter(|_| yes));
+        if let Some(action) = requests.action {
+            let slot_taken = match action {
+                confirm::Action::Save(slot) | confirm::Action::Load(slot) => {
+                    states::is_saved(&self.files.state_dir, slot)
+                }
+                confirm::Action::Reset => false,
+            };
+            let unsaved = self.progress.is_unsaved(&self.ch8);
+            if action.needs_confirmation(&self.settings, unsaved, slot_taken) {
+                self.pending = Some(action);
+            } else {
+                confirmed = Some(action);
             }
         }
-        let di = sf_egui
-            .run(&mut win, |_rw, ctx| {
-                egui::Window::new("Log (F11)")
-                    .open(&mut log_open)
-                    .show(ctx, |ui| {
-                        egui::ScrollArea::vertical()
-                            .stick_to_bottom(true)
-                            .max_height(200.)
-                            .show(ui, |ui| {
-                                let log_size = 5000;
-                                if ch8.log.len() > log_size {
-                                    ch8.log = ch8.log[ch8.log.len() - log_size..].to_owned();
-                                }
-                                ui.label(&ch8.log);
-                            });
-                    });
-            })
-            .unwrap();
-        render_screen(&mut win, &mut tex, &ch8, scale as f32);
-        ch8.clear_du_flag();
-        sf_egui.draw(di, &mut win, None);
-        win.display();
+        // A replay only follows the program from where it was recorded, so resetting or
+        // loading a state ends recording and playback
+        if matches!(
+            confirmed,
+            Some(confirm::Action::Reset | confirm::Action::Load(_))
+        ) {
+            if let Some(recording) = self.recorder.take() {
+                self.last_replay = Some(recording.finish());
+            }
+            self.playback = None;
+        }
+        match confirmed {
+            Some(confirm::Action::Reset) => self.reset(),
+            Some(confirm::Action::Save(slot)) => self.save_state(slot),
+            Some(confirm::Action::Load(slot)) => self.load_state(slot),
+            None => {}
+        }
+    }
+
+    fn reset(&mut self) {
+        self.ch8 = start(&self.data, self.variant, &mut self.log);
+        if let Some(options) = &self.cartridge {
+            apply_cartridge(&mut self.ch8, options);
+        }
+        self.overlay = colorize::Overlay::default();
+        self.progress.mark_saved(&self.ch8);
+        self.held_keys = pressed_keys(&self.ch8);
     }
+
+    fn save_state(&mut self, slot: usize) {
+        match states::save(&self.files.state_dir, slot, &self.ch8) {
+            Ok(()) => {
+                self.session.states_saved += 1;
+                self.progress.mark_saved(&self.ch8);
+                writeln!(self.toasts, "Saved state {}.", slot + 1)
+            }
+            Err(e) => {
+                self.windows.log = true;
+                writeln!(
+                    self.log.at(Severity::Error),
+                    "Failed to save state {}: {}",
+                    slot + 1,
+                    e
+                )
+            }
+        }
+        .unwrap();
+    }
+
+    fn load_state(&mut self, slot: usize) {
+        match states::load(&self.files.state_dir, slot, &mut self.ch8) {
+            Ok(states::Loaded::Ok) => {
+                self.session.states_loaded += 1;
+                self.progress.mark_saved(&self.ch8);
+                self.held_keys = pressed_keys(&self.ch8);
+                writeln!(self.toasts, "Loaded state {}.", slot + 1)
+            }
+            Ok(states::Loaded::RestoredBackup(e)) => {
+                self.session.states_loaded += 1;
+                self.progress.mark_saved(&self.ch8);
+                self.held_keys = pressed_keys(&self.ch8);
+                self.windows.log = true;
+                writeln!(
+                    self.log.at(Severity::Warning),
+                    "State {} corrupt ({}), restored backup.",
+                    slot + 1,
+                    e
+                )
+            }
+            Err(states::LoadError::Empty) => {
+                writeln!(self.toasts, "State {} is empty.", slot + 1)
+            }
+            Err(states::LoadError::Io(e)) => {
+                self.windows.log = true;
+                writeln!(
+                    self.log.at(Severity::Error),
+                    "Failed to load state {}: {}",
+                    slot + 1,
+                    e
+                )
+            }
+            Err(states::LoadError::Corrupt(e)) => {
+                self.windows.log = true;
+                writeln!(
+                    self.log.at(Severity::Error),
+                    "State {} corrupt ({}), and no usable backup.",
+                    slot + 1,
+                    e
+                )
+            }
+        }
+        .unwrap();
+    }
+}
+
+// The keys pressed on `ch8`, as a bitmask like Held::keys. The keys held on the devices are
+// compared against them, so after the VM is replaced or loaded, they're pressed on it again.
+fn pressed_keys(ch8: &VirtualMachine) -> u16 {
+    (0..16)
+        .filter(|&key| ch8.key_pressed(key))
+        .fold(0, |keys, key| keys | 1 << key)
 }
 
-fn do_emulation_cycle(
-    clock: &mut Clock,
+// Saves `ch8` to the autosave slot. Once it halted, there's nothing to continue, so the
+// autosave is removed instead.
+fn write_autosave(state_dir: &Path, ch8: &VirtualMachine, log: &mut Log) {
+    let result = if ch8.halt_reason().is_some() {
+        states::clear(state_dir, states::AUTOSAVE_SLOT)
+    } else {
+        states::save(state_dir, states::AUTOSAVE_SLOT, ch8)
+    };
+    if let Err(e) = result {
+        writeln!(log.at(Severity::Error), "Failed to autosave: {}", e).unwrap();
+    }
+}
+
+// Continues from the autosave in `state_dir`, if there is one
+fn restore_autosave(
+    state_dir: &Path,
     ch8: &mut VirtualMachine,
-    paused: bool,
-    printed_info: &mut bool,
-    cycles_made: &mut u64,
-    advance: bool,
+    log: &mut Log,
+    toasts: &mut toasts::Toasts,
 ) {
-    if clock.elapsed_time().as_seconds() >= 1.0 / 60.0 {
-        ch8.decrement_timers();
-        clock.restart();
+    match states::load(state_dir, states::AUTOSAVE_SLOT, ch8) {
+        Ok(_) => writeln!(toasts, "Continuing where you left off, Ctrl+R starts over.").unwrap(),
+        Err(states::LoadError::Empty) => {}
+        Err(states::LoadError::Io(e)) => writeln!(
+            log.at(Severity::Warning),
+            "Failed to read the autosave: {}",
+            e
+        )
+        .unwrap(),
+        Err(states::LoadError::Corrupt(e)) => {
+            writeln!(log.at(Severity::Warning), "The autosave is corrupt: {}", e).unwrap()
+        }
     }
+}
 
-    if paused && !*printed_info {
-        let raw_ins = ch8.get_ins();
+// Creates a VM running `data` as `variant` and logging to `log`, warning about anything the
+// ROM needs that the VM doesn't support
+fn start(data: &[u8], variant: Option<Variant>, log: &mut Log) -> VirtualMachine {
+    let mut ch8 = match variant {
+        Some(variant) => {
+            writeln!(log.at(Severity::Info), "Running the ROM as {}.", variant).unwrap();
+            VirtualMachine::with_variant(variant)
+        }
+        None => VirtualMachine::new(),
+    };
+    ch8.set_perf_counters(true);
+    // The messages of the VM reach the log as events, including the warnings about the ROM
+    ch8.record_events(true);
+    for warning in ch8.load_rom_lenient(data) {
+        eprintln!("Warning: {}", warning);
+    }
+    // A variant set on the command line knows better than the heuristic
+    if variant.is_none() && twopage::is_two_page_rom(data) {
+        ch8.set_two_page(true);
         writeln!(
-            ch8.log,
-            "Cycle {}, pc @ {:#x}, ins: {:#x?} raw: {:#x}",
-            cycles_made,
-            ch8.pc(),
-            decode(raw_ins),
-            raw_ins
+            log.at(Severity::Info),
+            "This looks like a HI-RES CHIP-8 ROM, showing its 64x64 display."
         )
         .unwrap();
-        *printed_info = true;
     }
+    let report = VirtualMachine::compatibility_report(data, variant);
+    if !report.is_supported_by(&ch8.capabilities()) {
+        let msg = format!(
+            "This ROM might not run correctly. Extensions: {:?}, unknown opcodes: {:04X?}",
+            report.extensions, report.unknown_opcodes
+        );
+        eprintln!("{}", msg);
+        writeln!(log.at(Severity::Warning), "{}", msg).unwrap();
+    }
+    ch8
+}
 
-    if !paused || advance {
-        ch8.do_cycle();
-        *cycles_made += 1;
-        *printed_info = false;
+// Runs the ROM headless to find the keys it reads, and notes them in the log
+fn discover_keys(data: &[u8], variant: Option<Variant>, log: &mut Log) -> u16 {
+    let report = analysis::discover_controls(data, variant);
+    if !report.keys.is_empty() {
+        let keys: Vec<_> = report.keys.iter().map(|k| format!("{:X}", k.key)).collect();
+        writeln!(
+            log.at(Severity::Info),
+            "The ROM reads the keys {}.",
+            keys.join(", ")
+        )
+        .unwrap();
+    } else if !report.any_key_frames.is_empty() {
+        writeln!(log.at(Severity::Info), "The ROM waits for any key.").unwrap();
     }
+    report.keys.iter().fold(0, |keys, k| keys | 1 << k.key)
 }
 
-fn render_screen(win: &mut RenderWindow, tex: &mut Texture, ch8: &VirtualMachine, scale: f32) {
-    let mut pixels = [255u8; DISPLAY_WIDTH * DISPLAY_HEIGHT * 4];
+// Runs `ch8` with the quirks and speed an Octo cartridge was saved with
+fn apply_cartridge(ch8: &mut VirtualMachine, options: &octo::CartridgeOptions) {
+    let mut quirks = ch8.quirks();
+    options.apply_quirks(&mut quirks);
+    ch8.set_quirks(quirks);
+    if let Some(ips) = options.ips() {
+        ch8.set_speed(ips);
+    }
+}
 
-    for (i, b) in ch8.display().iter().enumerate() {
-        let idx = i * 4;
-        if *b == 0u8 {
-            for p in pixels[idx..idx + 3].iter_mut() {
-                *p = 0;
-            }
-        }
+// Runs `ch8` for `frames` frames with a fixed random seed, and renders its display offscreen
+// the way it's shown in a `width` by `height` window
+fn screenshot_test(
+    ch8: &mut VirtualMachine,
+    settings: &Settings,
+    frames: u32,
+    (width, height): (u32, u32),
+) -> Result<Screenshot, String> {
+    ch8.set_rng_seed(0);
+    for _ in 0..frames {
+        ch8.step_frame();
     }
+    let shot = Screenshot::of_display(ch8, &colorize::Overlay::default(), settings);
+    let mut target = RenderTexture::new(width, height)
+        .map_err(|e| format!("Couldn't create the offscreen target: {}", e))?;
+    let mut tex = Texture::new().map_err(|e| format!("Couldn't create texture: {}", e))?;
+    tex.create(shot.width as u32, shot.height as u32)
+        .map_err(|e| format!("Couldn't create texture: {}", e))?;
+    render_screen(&mut *target, &mut tex, &shot, settings, false);
+    target.display();
+    let image = target
+        .texture()
+        .copy_to_image()
+        .map_err(|e| format!("Couldn't read back the screenshot: {}", e))?;
+    Ok(Screenshot {
+        width: width as usize,
+        height: height as usize,
+        rgba: image.pixel_data().to_vec(),
+    })
+}
 
-    tex.update_from_pixels(&pixels, DISPLAY_WIDTH as u32, DISPLAY_HEIGHT as u32, 0, 0);
+// Draws `shot`, the display as colored by Screenshot::of_display, scaled to fit `win`
+fn render_screen(
+    win: &mut impl RenderTarget,
+    tex: &mut Texture,
+    shot: &Screenshot,
+    settings: &Settings,
+    beeping: bool,
+) {
+    let Settings {
+        pixel_aspect,
+        palette,
+        ..
+    } = *settings;
+    let (view_w, view_h) = (shot.width as u32, shot.height as u32);
+
+    tex.update_from_pixels(&shot.rgba, view_w, view_h, 0, 0);
+    let size = win.size();
+    let place = present::fit_with_aspect(view_w, view_h, size.x, size.y, pixel_aspect);
     let mut sprite = Sprite::with_texture(tex);
-    sprite.set_scale((scale, scale));
+    sprite.set_position((place.offset_x, place.offset_y));
+    sprite.set_scale((place.scale_x, place.scale_y));
+    win.clear(Color::BLACK);
     win.draw(&sprite);
+    if beeping {
+        // The brightest color stands out the most against the black around the display
+        let [r, g, b] = palette
+            .colors
+            .into_iter()
+            .max_by(|&a, &b| {
+                palette::contrast_ratio(a, [0; 3]).total_cmp(&palette::contrast_ratio(b, [0; 3]))
+            })
+            .unwrap_or([255; 3]);
+        let mut border = RectangleShape::new();
+        border.set_position((BEEP_BORDER, BEEP_BORDER));
+        border.set_size((
+            size.x as f32 - 2. * BEEP_BORDER,
+            size.y as f32 - 2. * BEEP_BORDER,
+        ));
+        border.set_fill_color(Color::TRANSPARENT);
+        border.set_outline_color(Color::rgb(r, g, b));
+        border.set_outline_thickness(BEEP_BORDER);
+        win.draw(&border);
+    }
 }
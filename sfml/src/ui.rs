@@ -0,0 +1,717 @@
+//! The egui windows and the overlays on top of the display
+
+use {
+    crate::{App, POLL_FLASH_FRAMES, Requests},
+    crusty_chip::{
+        HaltReason, Palette, Rotation, Severity, VirtualMachine, decode,
+        keymap::{self, Layout},
+        palette::ColorBlindness,
+        quirks::QUIRKS,
+    },
+    crusty_chip_frontend_core::{
+        colorize, gamepad, pacing,
+        players::{self, Control, Profile},
+        settings::{self, Section, Settings},
+        stats,
+    },
+    egui_sfml::egui,
+    std::{fmt::Write, path::Path, time::Duration},
+};
+
+impl App {
+    // Shows everything drawn with egui for a frame, noting what the user asked for in `requests`
+    pub fn show_ui(&mut self, ctx: &egui::Context, requests: &mut Requests) {
+        if ctx.zoom_factor() != self.settings.ui_scale {
+            ctx.set_zoom_factor(self.settings.ui_scale);
+        }
+        self.confirm_prompt(ctx, &mut requests.answer);
+        if let Some(names) = &self.zip_choice {
+            egui::Window::new("Choose a ROM")
+                .collapsible(false)
+                .show(ctx, |ui| {
+                    for name in names {
+                        if ui.button(name).clicked() {
+                            requests.zip_rom = Some(name.clone());
+                        }
+                    }
+                });
+        }
+        self.overlays(ctx);
+        self.log_window(ctx);
+        if let Some(addr) = self.code_addr {
+            let mut open = true;
+            egui::Window::new("Code").open(&mut open).show(ctx, |ui| {
+                ui.monospace(code_listing(&self.ch8, addr));
+            });
+            if !open {
+                self.code_addr = None;
+            }
+        }
+        self.session_window(ctx);
+        self.keypad_window(ctx);
+        self.colors_window(ctx);
+        self.pads_window(ctx);
+        self.players_window(ctx);
+        self.bookmarks_window(ctx);
+        egui::Window::new("Open URL (Ctrl+U)")
+            .open(&mut self.windows.url)
+            .show(ctx, |ui| {
+                ui.label("Paste the URL of a ROM:");
+                ui.horizontal(|ui| {
+                    let field = ui.text_edit_singleline(&mut self.url_text);
+                    let entered =
+                        field.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                    if (ui.button("Load").clicked() || entered) && !self.url_text.is_empty() {
+                        requests.url = Some(self.url_text.trim().to_owned());
+                    }
+                });
+            });
+        self.settings_window(ctx);
+    }
+
+    // Asks about the pending action, if there is one, putting the answer in `answer`
+    fn confirm_prompt(&self, ctx: &egui::Context, answer: &mut Option<bool>) {
+        let Some(action) = self.pending else {
+            return;
+        };
+        // Dim the display, to make clear the question needs an answer first
+        ctx.layer_painter(egui::LayerId::new(
+            egui::Order::Background,
+            egui::Id::new("confirm_dim"),
+        ))
+        .rect_filled(ctx.screen_rect(), 0., egui::Color32::from_black_alpha(160));
+        egui::Window::new("Are you sure?")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0., 0.])
+            .show(ctx, |ui| {
+                ui.label(action.question());
+                ui.horizontal(|ui| {
+                    if ui.button("Yes (Enter)").clicked() {
+                        *answer = Some(true);
+                    }
+                    if ui.button("No (Esc)").clicked() {
+                        *answer = Some(false);
+                    }
+                });
+                ui.label("These questions can be turned off in the settings (Ctrl+,).");
+            });
+    }
+
+    // The text shown over the display: why the program halted, the performance numbers, the
+    // toasts, and the title of the kiosk game waiting for a player
+    fn overlays(&mut self, ctx: &egui::Context) {
+        if let Some(reason) = self.ch8.halt_reason() {
+            let what = match reason {
+                HaltReason::ProgramEnded => "Program finished",
+                HaltReason::Exited => "Program exited",
+                HaltReason::OutOfBounds
+                | HaltReason::MemoryOutOfBounds
+                | HaltReason::StackUnderflow => "Program crashed",
+            };
+            egui::Area::new(egui::Id::new("halted"))
+                .anchor(egui::Align2::CENTER_BOTTOM, egui::vec2(0., -8.))
+                .show(ctx, |ui| {
+                    ui.label(
+                        egui::RichText::new(format!("{} \u{2014} press Ctrl+R to restart", what))
+                            .background_color(egui::Color32::from_black_alpha(200))
+                            .color(egui::Color32::WHITE),
+                    );
+                });
+        }
+        if self.perf_shown {
+            egui::Area::new(egui::Id::new("perf"))
+                .anchor(egui::Align2::LEFT_TOP, egui::vec2(8., 8.))
+                .show(ctx, |ui| {
+                    if let Some(perf) = self.ch8.perf_counters() {
+                        ui.label(
+                            egui::RichText::new(format!(
+                                "{:.0} instructions/s, {} draws: {:.1} ms drawing, {:.1} ms other",
+                                perf.cycles_per_second,
+                                perf.draws,
+                                perf.draw_time.as_secs_f64() * 1e3,
+                                perf.other_time.as_secs_f64() * 1e3
+                            ))
+                            .background_color(egui::Color32::from_black_alpha(200))
+                            .color(egui::Color32::WHITE),
+                        );
+                    }
+                    frame_graph(ui, &self.frame_times);
+                });
+        }
+        egui::Area::new(egui::Id::new("toasts"))
+            .anchor(egui::Align2::RIGHT_BOTTOM, egui::vec2(-8., -8.))
+            .show(ctx, |ui| {
+                for (text, opacity) in self.toasts.visible() {
+                    ui.label(
+                        egui::RichText::new(text)
+                            .background_color(egui::Color32::from_black_alpha(
+                                (200. * opacity) as u8,
+                            ))
+                            .color(egui::Color32::WHITE.gamma_multiply(opacity)),
+                    );
+                }
+            });
+        if let Some(kiosk) = &self.kiosk
+            && kiosk.is_attracting()
+        {
+            egui::Area::new(egui::Id::new("attract"))
+                .anchor(egui::Align2::CENTER_TOP, egui::vec2(0., 8.))
+                .show(ctx, |ui| {
+                    ui.label(
+                        egui::RichText::new(format!(
+                            "{} \u{2014} press any key to play",
+                            kiosk.title()
+                        ))
+                        .heading()
+                        .background_color(egui::Color32::from_black_alpha(200))
+                        .color(egui::Color32::WHITE),
+                    );
+                    ui.label(
+                        egui::RichText::new(format!(
+                            "Next game in {}",
+                            stats::format_duration(kiosk.time_left())
+                        ))
+                        .background_color(egui::Color32::from_black_alpha(200))
+                        .color(egui::Color32::WHITE),
+                    );
+                });
+        }
+    }
+
+    fn log_window(&mut self, ctx: &egui::Context) {
+        egui::Window::new("Log (F11)")
+            .open(&mut self.windows.log)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    for (severity, shown) in Severity::ALL.iter().zip(&mut self.log_shown) {
+                        ui.checkbox(shown, severity.to_string());
+                    }
+                    ui.label("Search:");
+                    ui.text_edit_singleline(&mut self.log_query);
+                });
+                ui.horizontal(|ui| {
+                    if ui.button("Export").clicked() {
+                        let path = format!("{}.log", self.state_base);
+                        match self.log.export(Path::new(&path)) {
+                            Ok(()) => writeln!(self.toasts, "Exported the log to {}.", path),
+                            Err(e) => writeln!(
+                                self.log.at(Severity::Error),
+                                "Failed to export the log: {}",
+                                e
+                            ),
+                        }
+                        .unwrap();
+                    }
+                    if ui.button("Clear").clicked() {
+                        self.log.clear();
+                    }
+                });
+                ui.separator();
+                egui::ScrollArea::vertical()
+                    .stick_to_bottom(true)
+                    .max_height(200.)
+                    .show(ui, |ui| {
+                        for entry in self.log.entries() {
+                            let shown = self.log_shown[entry.severity as usize];
+                            if !shown || !entry.matches(&self.log_query) {
+                                continue;
+                            }
+                            ui.horizontal(|ui| {
+                                let color = match entry.severity {
+                                    Severity::Info => ui.visuals().text_color(),
+                                    Severity::Warning => ui.visuals().warn_fg_color,
+                                    Severity::Error => ui.visuals().error_fg_color,
+                                };
+                                if let Some(addr) = entry.addr
+                                    && ui.link(format!("{:#05x}", addr)).clicked()
+                                {
+                                    self.code_addr = Some(addr);
+                                }
+                                ui.label(egui::RichText::new(&entry.text).color(color));
+                            });
+                        }
+                    });
+            });
+    }
+
+    fn session_window(&mut self, ctx: &egui::Context) {
+        let session = &self.session;
+        egui::Window::new("Session (Ctrl+I)")
+            .open(&mut self.windows.session)
+            .show(ctx, |ui| {
+                egui::Grid::new("session").show(ui, |ui| {
+                    let rows = [
+                        ("Session time", stats::format_duration(session.elapsed())),
+                        ("Time played", stats::format_duration(session.played)),
+                        ("Instructions", session.instructions.to_string()),
+                        ("Average speed", format!("{:.0} IPS", session.average_ips())),
+                        ("Timer ticks", session.ticks.to_string()),
+                        ("Frames rendered", session.frames_rendered.to_string()),
+                        ("States saved", session.states_saved.to_string()),
+                        ("States loaded", session.states_loaded.to_string()),
+                    ];
+                    for (name, value) in rows {
+                        ui.label(name);
+                        ui.label(value);
+                        ui.end_row();
+                    }
+                });
+            });
+    }
+
+    fn keypad_window(&mut self, ctx: &egui::Context) {
+        egui::Window::new("Keypad (Ctrl+K)")
+            .open(&mut self.windows.keypad)
+            .show(ctx, |ui| {
+                egui::Grid::new("keypad").show(ui, |ui| {
+                    for row in keymap::KEYPAD {
+                        for key in row {
+                            let flash = self.poll_flash[usize::from(key)];
+                            let fill = if self.ch8.key_pressed(key) {
+                                egui::Color32::from_rgb(60, 160, 60)
+                            } else {
+                                let alpha = flash * (255 / POLL_FLASH_FRAMES);
+                                egui::Color32::from_rgba_unmultiplied(220, 180, 0, alpha)
+                            };
+                            let mut button = egui::Button::new(format!("{:X}", key))
+                                .fill(fill)
+                                .min_size(egui::vec2(32., 32.));
+                            if self.rom_keys & 1 << key != 0 {
+                                button = button.stroke(egui::Stroke::new(
+                                    2.,
+                                    egui::Color32::from_rgb(220, 180, 0),
+                                ));
+                            }
+                            ui.add(button);
+                        }
+                        ui.end_row();
+                    }
+                });
+                ui.label(
+                    "Green: pressed. Yellow: read by the program. \
+                     Outlined: used by the ROM.",
+                );
+            });
+    }
+
+    fn colors_window(&mut self, ctx: &egui::Context) {
+        egui::Window::new("Sprite colors (Ctrl+L)")
+            .open(&mut self.windows.colors)
+            .show(ctx, |ui| {
+                let rules = &mut self.rules;
+                let mut remove = None;
+                for (i, rule) in rules.iter_mut().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.label(format!("Sprite at {:#05X}", rule.addr));
+                        ui.color_edit_button_srgb(&mut rule.color);
+                        if ui.button("Delete").clicked() {
+                            remove = Some(i);
+                        }
+                    });
+                }
+                if let Some(i) = remove {
+                    rules.remove(i);
+                }
+                ui.separator();
+                ui.label("Recently drawn sprites:");
+                ui.horizontal_wrapped(|ui| {
+                    for &addr in &self.recent_sprites {
+                        let exists = rules.iter().any(|rule| rule.addr == addr);
+                        if ui
+                            .add_enabled(!exists, egui::Button::new(format!("{:#05X}", addr)))
+                            .clicked()
+                        {
+                            rules.push(colorize::Rule {
+                                addr,
+                                color: [255, 0, 0],
+                            });
+                        }
+                    }
+                });
+                if ui.button("Save").clicked()
+                    && let Err(e) = colorize::save(&self.files.rules_path, rules)
+                {
+                    writeln!(
+                        self.log.at(Severity::Error),
+                        "Failed to save sprite colors: {}",
+                        e
+                    )
+                    .unwrap();
+                }
+            });
+    }
+
+    fn pads_window(&mut self, ctx: &egui::Context) {
+        egui::Window::new("Gamepads (Ctrl+J)")
+            .open(&mut self.windows.pads)
+            .show(ctx, |ui| {
+                if self.connected.is_empty() {
+                    ui.label("No gamepads connected.");
+                }
+                for id in &self.connected {
+                    ui.label(format!("Gamepad {} connected.", id));
+                }
+                ui.separator();
+                let mut remove = None;
+                for (i, binding) in self.bindings.iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.label(format!(
+                            "{} \u{2192} key {:X}",
+                            binding.describe(),
+                            binding.key
+                        ));
+                        if ui.button("Delete").clicked() {
+                            remove = Some(i);
+                        }
+                    });
+                }
+                if let Some(i) = remove {
+                    self.bindings.remove(i);
+                }
+                ui.separator();
+                match self.binding_key {
+                    Some(key) => {
+                        ui.horizontal(|ui| {
+                            ui.label(format!(
+                                "Press a button or push a stick for key {:X}...",
+                                key
+                            ));
+                            if ui.button("Cancel").clicked() {
+                                self.binding_key = None;
+                            }
+                        });
+                    }
+                    None => {
+                        ui.label("Bind a key:");
+                        ui.horizontal_wrapped(|ui| {
+                            for key in 0..16 {
+                                if ui.button(format!("{:X}", key)).clicked() {
+                                    self.binding_key = Some(key);
+                                }
+                            }
+                        });
+                    }
+                }
+                ui.checkbox(&mut self.bind_any, "Bind for any gamepad");
+                ui.horizontal(|ui| {
+                    if ui.button("Defaults").clicked() {
+                        self.bindings = gamepad::default_bindings();
+                    }
+                    if ui.button("Save").clicked()
+                        && let Err(e) = gamepad::save(&self.files.pad_path, &self.bindings)
+                    {
+                        writeln!(
+                            self.log.at(Severity::Error),
+                            "Failed to save gamepad bindings: {}",
+                            e
+                        )
+                        .unwrap();
+                    }
+                });
+            });
+    }
+
+    fn players_window(&mut self, ctx: &egui::Context) {
+        egui::Window::new("Two players (Ctrl+T)")
+            .open(&mut self.windows.players)
+            .show(ctx, |ui| {
+                let mut enabled = self.two_players.is_some();
+                if ui.checkbox(&mut enabled, "Two-player keys").changed() {
+                    self.two_players = enabled.then(Profile::default);
+                }
+                if let Some(profile) = &mut self.two_players {
+                    egui::Grid::new("players").show(ui, |ui| {
+                        ui.label("");
+                        ui.label("Player 1 (WASD, left Shift)");
+                        ui.label("Player 2 (arrows, right Shift)");
+                        ui.end_row();
+                        for (i, control) in Control::ALL.into_iter().enumerate() {
+                            ui.label(control.name());
+                            for (player, keys) in profile.keys.iter_mut().enumerate() {
+                                let text =
+                                    keys[i].map_or("-".to_owned(), |key| format!("{:X}", key));
+                                egui::ComboBox::from_id_salt((player, i))
+                                    .selected_text(text)
+                                    .show_ui(ui, |ui| {
+                                        ui.selectable_value(&mut keys[i], None, "-");
+                                        for key in 0..16 {
+                                            ui.selectable_value(
+                                                &mut keys[i],
+                                                Some(key),
+                                                format!("{:X}", key),
+                                            );
+                                        }
+                                    });
+                            }
+                            ui.end_row();
+                        }
+                    });
+                }
+                if ui.button("Save").clicked()
+                    && let Err(e) =
+                        players::save(&self.files.players_path, self.two_players.as_ref())
+                {
+                    writeln!(
+                        self.log.at(Severity::Error),
+                        "Failed to save two-player keys: {}",
+                        e
+                    )
+                    .unwrap();
+                }
+            });
+    }
+
+    fn bookmarks_window(&mut self, ctx: &egui::Context) {
+        egui::Window::new("Bookmarks (F12)")
+            .open(&mut self.windows.bookmarks)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.text_edit_singleline(&mut self.bookmark_name);
+                    if ui.button("Add").clicked() && !self.bookmark_name.is_empty() {
+                        self.ch8.bookmark(std::mem::take(&mut self.bookmark_name));
+                    }
+                });
+                let mut jump = None;
+                let mut remove = None;
+                for (name, cycle) in self.ch8.bookmarks() {
+                    ui.horizontal(|ui| {
+                        ui.label(format!("{} (cycle {})", name, cycle));
+                        if ui.button("Jump").clicked() {
+                            jump = Some(name.to_owned());
+                        }
+                        if ui.button("Delete").clicked() {
+                            remove = Some(name.to_owned());
+                        }
+                    });
+                }
+                if let Some(name) = jump {
+                    self.ch8.jump_to_bookmark(&name);
+                }
+                if let Some(name) = remove {
+                    self.ch8.remove_bookmark(&name);
+                }
+            });
+    }
+
+    fn settings_window(&mut self, ctx: &egui::Context) {
+        // The quirks can also change by loading a state, so they're taken from the VM
+        self.settings.quirks = self.ch8.quirks();
+        egui::Window::new("Settings (Ctrl+,)")
+            .open(&mut self.windows.settings)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Search:");
+                    ui.text_edit_singleline(&mut self.settings_query);
+                });
+                let capabilities = self.ch8.capabilities();
+                for section in Section::ALL {
+                    let labels: Vec<_> = section
+                        .labels()
+                        .into_iter()
+                        .enumerate()
+                        .filter(|(_, label)| {
+                            settings::matches(&self.settings_query, section, label)
+                        })
+                        .collect();
+                    if labels.is_empty() {
+                        continue;
+                    }
+                    ui.separator();
+                    ui.horizontal(|ui| {
+                        ui.heading(section.name());
+                        let changed = !self.settings.is_default(section);
+                        if ui
+                            .add_enabled(changed, egui::Button::new("Reset to defaults"))
+                            .clicked()
+                        {
+                            self.settings.reset(section);
+                        }
+                    });
+                    for (i, label) in labels {
+                        // Quirks of instructions the VM doesn't understand change nothing
+                        let enabled = section != Section::Quirks
+                            || capabilities.supports_quirk(QUIRKS[i].name);
+                        ui.add_enabled_ui(enabled, |ui| {
+                            settings_row(ui, &mut self.settings, section, i, label);
+                        });
+                    }
+                }
+            });
+        if self.settings.quirks != self.ch8.quirks() {
+            self.ch8.set_quirks(self.settings.quirks);
+        }
+    }
+}
+
+// Disassembles the instructions around `addr`, marking the one at `addr`
+fn code_listing(ch8: &VirtualMachine, addr: u16) -> String {
+    let mem = ch8.memory();
+    let mut listing = String::new();
+    let start = addr.saturating_sub(8);
+    for at in (start..start.saturating_add(24)).step_by(2) {
+        let (Some(&hi), Some(&lo)) = (mem.get(usize::from(at)), mem.get(usize::from(at) + 1))
+        else {
+            break;
+        };
+        let ins = u16::from_be_bytes([hi, lo]);
+        let marker = if at == addr { '>' } else { ' ' };
+        writeln!(
+            listing,
+            "{} {:03X}  {:04X}  {:?}",
+            marker,
+            at,
+            ins,
+            decode(ins)
+        )
+        .unwrap();
+    }
+    listing
+}
+
+// Shows the control of the `i`th setting of `section`
+fn settings_row(
+    ui: &mut egui::Ui,
+    settings: &mut Settings,
+    section: Section,
+    i: usize,
+    label: &str,
+) {
+    ui.horizontal(|ui| {
+        ui.label(label);
+        match section {
+            Section::Display if i == 0 => {
+                egui::ComboBox::from_id_salt("rotation")
+                    .selected_text(format!("{}°", settings.rotation))
+                    .show_ui(ui, |ui| {
+                        for rotation in Rotation::ALL {
+                            ui.selectable_value(
+                                &mut settings.rotation,
+                                rotation,
+                                format!("{}°", rotation),
+                            );
+                        }
+                    });
+            }
+            Section::Display => {
+                ui.add(
+                    egui::DragValue::new(&mut settings.pixel_aspect)
+                        .range(0.25..=4.0)
+                        .speed(0.01),
+                );
+            }
+            Section::Colors if i == 0 => {
+                let preset = Palette::PRESETS
+                    .iter()
+                    .find(|(_, palette)| *palette == settings.palette)
+                    .map_or("Custom", |(name, _)| name);
+                egui::ComboBox::from_id_salt("palette")
+                    .selected_text(preset)
+                    .show_ui(ui, |ui| {
+                        for (name, palette) in Palette::PRESETS {
+                            ui.selectable_value(&mut settings.palette, palette, name);
+                        }
+                    });
+            }
+            Section::Colors if i == 5 => {
+                let name = |simulate: Option<ColorBlindness>| {
+                    simulate.map_or("None", ColorBlindness::name)
+                };
+                egui::ComboBox::from_id_salt("simulate")
+                    .selected_text(name(settings.simulate))
+                    .show_ui(ui, |ui| {
+                        for simulate in [None].into_iter().chain(ColorBlindness::ALL.map(Some)) {
+                            ui.selectable_value(&mut settings.simulate, simulate, name(simulate));
+                        }
+                    })
+                    .response
+                    .on_hover_text("Show the display as it looks to colorblind players");
+            }
+            Section::Colors => {
+                ui.color_edit_button_srgb(&mut settings.palette.colors[i - 1]);
+            }
+            Section::Keys => {
+                egui::ComboBox::from_id_salt("layout")
+                    .selected_text(settings.layout.to_string())
+                    .show_ui(ui, |ui| {
+                        for layout in Layout::ALL {
+                            ui.selectable_value(&mut settings.layout, layout, layout.to_string());
+                        }
+                    });
+            }
+            Section::Quirks => {
+                ui.checkbox(settings.quirks.flags_mut()[i], "")
+                    .on_hover_text(QUIRKS[i].description);
+            }
+            Section::Accessibility if i == 0 => {
+                ui.add(
+                    egui::DragValue::new(&mut settings.ui_scale)
+                        .range(1.0..=3.0)
+                        .speed(0.01),
+                );
+            }
+            Section::Accessibility if i == 1 => {
+                ui.checkbox(&mut settings.visual_beep, "")
+                    .on_hover_text("Flash the border of the window while the program beeps");
+            }
+            Section::Accessibility => {
+                ui.checkbox(&mut settings.key_repeat, "")
+                    .on_hover_text("Repeat keys while they're held down");
+            }
+            Section::Confirmations => {
+                let confirm = match i {
+                    0 => &mut settings.confirm_reset,
+                    1 => &mut settings.confirm_load,
+                    _ => &mut settings.confirm_overwrite,
+                };
+                ui.checkbox(confirm, "")
+                    .on_hover_text("Ask before losing progress this way");
+            }
+        }
+    });
+}
+
+// Plots the time of each frame kept in `times`, with the part spent emulating on top, against
+// a line at the 60 Hz frame time
+fn frame_graph(ui: &mut egui::Ui, times: &pacing::FrameTimes) {
+    let average = times.average();
+    ui.label(
+        egui::RichText::new(format!(
+            "Frames: {:.1} ms average, {:.1} ms worst, {:.1} ms emulating",
+            average.total.as_secs_f64() * 1e3,
+            times.worst().as_secs_f64() * 1e3,
+            average.emulation.as_secs_f64() * 1e3
+        ))
+        .background_color(egui::Color32::from_black_alpha(200))
+        .color(egui::Color32::WHITE),
+    );
+    let size = egui::vec2(pacing::FRAME_HISTORY as f32, 64.);
+    let (rect, _) = ui.allocate_exact_size(size, egui::Sense::hover());
+    let painter = ui.painter();
+    painter.rect_filled(rect, 0., egui::Color32::from_black_alpha(200));
+    // Two frames' worth fit, unless a frame took even longer
+    let max = times.worst().max(pacing::FRAME * 2).as_secs_f32();
+    let y = |time: Duration| rect.bottom() - time.as_secs_f32() / max * rect.height();
+    for (i, frame) in times.iter().enumerate() {
+        let x = rect.left() + i as f32 + 0.5;
+        let bottom = egui::pos2(x, rect.bottom());
+        let color = if frame.total > pacing::FRAME * 3 / 2 {
+            egui::Color32::RED
+        } else {
+            egui::Color32::GRAY
+        };
+        painter.line_segment([bottom, egui::pos2(x, y(frame.total))], (1., color));
+        painter.line_segment(
+            [bottom, egui::pos2(x, y(frame.emulation))],
+            (1., egui::Color32::YELLOW),
+        );
+    }
+    let target = y(pacing::FRAME);
+    painter.line_segment(
+        [
+            egui::pos2(rect.left(), target),
+            egui::pos2(rect.right(), target),
+        ],
+        (1., egui::Color32::GREEN),
+    );
+}
@@ -0,0 +1,36 @@
+//! Runs `--screenshot-test` against the golden images in `tests/screenshots`, which checks
+//! the scaling done by SFML on top of the coloring tested by frontend-core. Run with `BLESS=1`
+//! to replace the golden images after a change that's meant to alter them.
+//!
+//! Rendering offscreen still needs a graphics context, so these need a display to run on,
+//! and are ignored by default. Run them with `cargo test -p crusty-chip-sfml -- --ignored`.
+//!
+//! The golden image was made by scaling up the frontend-core one, not by a run of SFML. If
+//! the first run fails on a difference in scaling, check the output and bless it.
+
+use std::{path::Path, process::Command};
+
+#[test]
+#[ignore = "needs a display"]
+fn golden_screenshots() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/screenshots");
+    // The ROM of the frontend-core screenshots, which draws the digits 0-F
+    let rom =
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("../frontend-core/tests/screenshots/digits.ch8");
+    // The default 640x320 window is 10 times the display, so the golden image is the
+    // frontend-core one with every pixel a 10x10 block
+    let mut command = Command::new(env!("CARGO_BIN_EXE_crusty-chip-sfml"));
+    command
+        .arg("--screenshot-test")
+        .arg(dir.join("digits.png"))
+        .arg(&rom);
+    if std::env::var_os("BLESS").is_some() {
+        command.arg("--bless");
+    }
+    let output = command.output().unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
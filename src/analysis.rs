@@ -0,0 +1,403 @@
+//! Static and dynamic analysis of ROMs.
+
+use super::{
+    Capabilities, DEFAULT_IPS, EventKind, HaltReason, MEM_SIZE, START_ADDR, Variant,
+    VirtualMachine,
+    opcodes::{self, Extension},
+    quirks::QUIRKS,
+};
+
+/// Extension opcodes, as (pattern, mask, extension).
+///
+/// These are recognized even when support for the extension isn't compiled in,
+/// so a ROM can be diagnosed as needing it.
+#[rustfmt::skip]
+static EXTENSION_SIGNATURES: &[(u16, u16, Extension)] = &[
+    (0x00C0, 0xFFF0, Extension::SuperChip), // SCD n
+    (0x00FB, 0xFFFF, Extension::SuperChip), // SCR
+    (0x00FC, 0xFFFF, Extension::SuperChip), // SCL
+    (0x00FD, 0xFFFF, Extension::SuperChip), // EXIT
+    (0x00FE, 0xFFFF, Extension::SuperChip), // LOW
+    (0x00FF, 0xFFFF, Extension::SuperChip), // HIGH
+    (0xF030, 0xF0FF, Extension::SuperChip), // LD HF, Vx
+    (0xF075, 0xF0FF, Extension::SuperChip), // LD R, Vx
+    (0xF085, 0xF0FF, Extension::SuperChip), // LD Vx, R
+    (0x00D0, 0xFFF0, Extension::XoChip),    // SCU n
+    (0x5002, 0xF00F, Extension::XoChip),    // SAVE Vx - Vy
+    (0x5003, 0xF00F, Extension::XoChip),    // LOAD Vx - Vy
+    (0xF000, 0xFFFF, Extension::XoChip),    // LD I, nnnn
+    (0xF001, 0xF0FF, Extension::XoChip),    // PLANE n
+    (0xF002, 0xFFFF, Extension::XoChip),    // AUDIO
+    (0xF03A, 0xF0FF, Extension::XoChip),    // PITCH Vx
+    (0x02A0, 0xFFFF, Extension::Chip8X),    // BGND
+    (0x5001, 0xF00F, Extension::Chip8X),    // ADD Vx, Vy, nibbles
+    (0xE0F2, 0xF0FF, Extension::Chip8X),    // SKP2 Vx
+    (0xE0F5, 0xF0FF, Extension::Chip8X),    // SKNP2 Vx
+    (0x0011, 0xFFFF, Extension::MegaChip),  // MEGAON
+];
+
+/// Returns the extension an instruction belongs to, if it's not a classic CHIP-8 one.
+pub fn extension_of(ins: u16) -> Option<Extension> {
+    EXTENSION_SIGNATURES
+        .iter()
+        .find(|&&(pattern, mask, _)| ins & mask == pattern)
+        .map(|&(_, _, ext)| ext)
+        .or_else(|| {
+            opcodes::lookup(ins)
+                .map(|spec| spec.extension)
+                .filter(|&ext| ext != Extension::Chip8)
+        })
+}
+
+// The address `rom` is loaded at when running as `variant`, or without one
+fn start_addr(variant: Option<Variant>) -> u16 {
+    variant.map_or(START_ADDR, Variant::start_addr)
+}
+
+// A fresh VM for running ROMs as `variant`
+fn vm_for(variant: Option<Variant>) -> VirtualMachine {
+    variant.map_or_else(VirtualMachine::new, VirtualMachine::with_variant)
+}
+
+/// Returns the addresses of the instructions reachable from the start of `rom`, in order.
+///
+/// The ROM is taken to be loaded where programs of `variant` start, or at `0x200` without
+/// one. Control flow is followed through jumps, calls and skips. Computed jumps (`Bnnn`)
+/// can't be followed, so code only reachable through them is missed.
+pub fn reachable_instructions(rom: &[u8], variant: Option<Variant>) -> Vec<u16> {
+    let start = start_addr(variant);
+    let end = start as usize + rom.len().min(MEM_SIZE - start as usize);
+    let fetch = |addr: u16| {
+        let offset = usize::from(addr - start);
+        u16::from_be_bytes([rom[offset], rom[offset + 1]])
+    };
+    let mut visited = vec![false; MEM_SIZE];
+    let mut work = vec![start];
+    while let Some(addr) = work.pop() {
+        if addr < start || usize::from(addr) + 1 >= end || visited[usize::from(addr)] {
+            continue;
+        }
+        visited[usize::from(addr)] = true;
+        let ins = fetch(addr);
+        let nnn = ins & 0x0FFF;
+        let next = addr + 2;
+        match ins {
+            0x00EE | 0x00FD => {}
+            0xF000 => work.push(addr + 4),
+            _ => match ins >> 12 {
+                0x1 => work.push(nnn),
+                0x2 => work.extend([nnn, next]),
+                0xB => {}
+                0x3 | 0x4 => work.extend([next, next + 2]),
+                0x5 | 0x9 if ins & 0xF == 0 => work.extend([next, next + 2]),
+                0xE if matches!(ins & 0xFF, 0x9E | 0xA1) => work.extend([next, next + 2]),
+                _ => work.push(next),
+            },
+        }
+    }
+    (start..end as u16)
+        .filter(|&addr| visited[usize::from(addr)])
+        .collect()
+}
+
+/// How long [`VirtualMachine::compatibility_report`] runs the ROM, in instructions.
+pub const REPORT_CYCLES: usize = 10 * DEFAULT_IPS as usize;
+
+/// What a ROM needs from the interpreter, as found by
+/// [`VirtualMachine::compatibility_report`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompatibilityReport {
+    /// Extensions the ROM uses instructions of.
+    pub extensions: Vec<Extension>,
+    /// Names of the [`Quirks`](crate::Quirks) fields affecting instructions the ROM executed.
+    pub quirks: Vec<&'static str>,
+    /// Instructions the ROM executed that aren't understood by this build.
+    pub unknown_opcodes: Vec<u16>,
+    /// Whether the ROM drew anything on the display.
+    pub drew: bool,
+    /// Whether the ROM was waiting for a key when the run ended.
+    pub waiting_for_key: bool,
+    /// Why the ROM halted during the run, if it did.
+    pub halt_reason: Option<HaltReason>,
+}
+
+impl CompatibilityReport {
+    /// Returns whether this build can run the ROM.
+    pub fn is_supported(&self) -> bool {
+        self.unknown_opcodes.is_empty() && self.extensions.iter().all(|ext| ext.is_compiled_in())
+    }
+
+    /// Returns whether a VM with `capabilities` can run the ROM, understanding the
+    /// instructions of every extension it uses.
+    ///
+    /// Unlike [`CompatibilityReport::is_supported`], this takes the [`Variant`] set into
+    /// account, and doesn't count extensions with no instructions compiled in.
+    ///
+    /// [`Variant`]: crate::Variant
+    pub fn is_supported_by(&self, capabilities: &Capabilities) -> bool {
+        self.unknown_opcodes.is_empty()
+            && self
+                .extensions
+                .iter()
+                .all(|&ext| capabilities.supports_extension(ext))
+    }
+}
+
+impl VirtualMachine {
+    /// Analyzes `rom` and runs it headless for a short while, reporting what it needs.
+    ///
+    /// The ROM runs as `variant`, or on a VM understanding every extension without one, so
+    /// programs of platforms like CHIP-8X are loaded where they expect.
+    /// The run lasts [`REPORT_CYCLES`] instructions, or until the ROM halts or waits for a key.
+    /// A ROM that goes wrong, like by returning without a call, halts with an error
+    /// [`HaltReason`] rather than taking the caller down.
+    pub fn compatibility_report(rom: &[u8], variant: Option<Variant>) -> CompatibilityReport {
+        let mut report = CompatibilityReport {
+            extensions: Vec::new(),
+            quirks: Vec::new(),
+            unknown_opcodes: Vec::new(),
+            drew: false,
+            waiting_for_key: false,
+            halt_reason: None,
+        };
+        let mut executed = vec![false; MEM_SIZE];
+        let seen = |report: &mut CompatibilityReport, ins: u16| {
+            if let Some(ext) = extension_of(ins)
+                && !report.extensions.contains(&ext)
+            {
+                report.extensions.push(ext);
+            }
+        };
+        for addr in reachable_instructions(rom, variant) {
+            let offset = usize::from(addr - start_addr(variant));
+            seen(
+                &mut report,
+                u16::from_be_bytes([rom[offset], rom[offset + 1]]),
+            );
+        }
+        let mut vm = vm_for(variant);
+        vm.load_rom(rom);
+        let cycles_per_tick = DEFAULT_IPS as usize / 60;
+        for cycle in 0..REPORT_CYCLES {
+            if vm.halt.is_some() || vm.keypress_wait.wait {
+                break;
+            }
+            let pc = usize::from(vm.pc);
+            if pc + 1 < MEM_SIZE && !executed[pc] {
+                executed[pc] = true;
+                let ins = u16::from_be_bytes([vm.ram[pc], vm.ram[pc + 1]]);
+                seen(&mut report, ins);
+                match opcodes::lookup(ins) {
+                    Some(spec) => {
+                        for quirk in QUIRKS {
+                            if quirk.opcodes.contains(&spec.pattern)
+                                && !report.quirks.contains(&quirk.name)
+                            {
+                                report.quirks.push(quirk.name);
+                            }
+                        }
+                    }
+                    None => report.unknown_opcodes.push(ins),
+                }
+            }
+            vm.do_cycle();
+            if vm.display_updated {
+                report.drew |= vm.display.pixels.iter().any(|&px| px != 0);
+                vm.display_updated = false;
+            }
+            if cycle % cycles_per_tick == cycles_per_tick - 1 {
+                vm.decrement_timers();
+            }
+        }
+        report.waiting_for_key = vm.keypress_wait.wait;
+        report.halt_reason = vm.halt;
+        report.unknown_opcodes.sort_unstable();
+        report.unknown_opcodes.dedup();
+        report
+    }
+}
+
+/// How long [`discover_controls`] runs the ROM, in frames.
+pub const DISCOVERY_FRAMES: u64 = 60 * 60;
+
+/// A keypad key a ROM reads, as found by [`discover_controls`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyUse {
+    /// The key.
+    pub key: u8,
+    /// The addresses of the instructions that read it.
+    pub addrs: Vec<u16>,
+    /// The frame it was first read in.
+    pub first_frame: u64,
+    /// The frame it was last read in.
+    pub last_frame: u64,
+}
+
+/// The controls of a ROM, as found by [`discover_controls`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ControlReport {
+    /// The keys the ROM checked with `Ex9E` or `ExA1`, in order.
+    pub keys: Vec<KeyUse>,
+    /// The frames in which the ROM waited for any key with `Fx0A`, like on a title screen.
+    pub any_key_frames: Vec<u64>,
+    /// The addresses of all reachable key instructions.
+    pub poll_sites: Vec<u16>,
+    /// The poll sites that weren't executed during the run, so the keys they read are unknown.
+    pub unexplored_sites: Vec<u16>,
+}
+
+/// Finds the keys `rom` reads, and when.
+///
+/// Key instructions are found statically, but the keys they check are only known once they
+/// run, so the ROM is run headless for [`DISCOVERY_FRAMES`] frames. Whenever it waits for a
+/// key, the keys are pressed in turn to get it going, which can also pick menu entries.
+/// The ROM runs as `variant`, like in [`VirtualMachine::compatibility_report`].
+pub fn discover_controls(rom: &[u8], variant: Option<Variant>) -> ControlReport {
+    let mut report = ControlReport::default();
+    for addr in reachable_instructions(rom, variant) {
+        let offset = usize::from(addr - start_addr(variant));
+        let ins = u16::from_be_bytes([rom[offset], rom[offset + 1]]);
+        if matches!(ins & 0xF0FF, 0xE09E | 0xE0A1 | 0xF00A) {
+            report.poll_sites.push(addr);
+        }
+    }
+    let mut vm = vm_for(variant);
+    vm.set_rng_seed(0);
+    vm.load_rom(rom);
+    vm.record_events(true);
+    let mut executed = vec![false; MEM_SIZE];
+    let mut next_key = 0;
+    let mut held = None;
+    for _ in 0..DISCOVERY_FRAMES {
+        if let Some(key) = held.take() {
+            vm.release_key(key);
+        } else if vm.waiting_for_key() {
+            vm.press_key(next_key);
+            held = Some(next_key);
+            next_key = (next_key + 1) % 16;
+        }
+        vm.step_frame();
+        for event in vm.take_events() {
+            match event.kind {
+                EventKind::KeyPolled { key, addr } => {
+                    executed[usize::from(addr)] = true;
+                    let pos = report.keys.iter().position(|k| k.key == key);
+                    let key_use = match pos {
+                        Some(pos) => &mut report.keys[pos],
+                        None => {
+                            report.keys.push(KeyUse {
+                                key,
+                                addrs: Vec::new(),
+                                first_frame: event.frame,
+                                last_frame: event.frame,
+                            });
+                            report.keys.last_mut().unwrap()
+                        }
+                    };
+                    if !key_use.addrs.contains(&addr) {
+                        key_use.addrs.push(addr);
+                    }
+                    key_use.last_frame = event.frame;
+                }
+                EventKind::KeyWait { addr } => {
+                    executed[usize::from(addr)] = true;
+                    if report.any_key_frames.last() != Some(&event.frame) {
+                        report.any_key_frames.push(event.frame);
+                    }
+                }
+                _ => {}
+            }
+        }
+        if vm.halt_reason().is_some() {
+            break;
+        }
+    }
+    report.keys.sort_by_key(|k| k.key);
+    report.unexplored_sites = report
+        .poll_sites
+        .iter()
+        .copied()
+        .filter(|&addr| !executed[usize::from(addr)])
+        .collect();
+    report
+}
+
+#[test]
+fn test_reachable_instructions() {
+    let rom = [
+        0x30, 0x01, // 0x200: SE V0, 1
+        0x12, 0x0A, // 0x202: JP 0x20A
+        0x22, 0x0A, // 0x204: CALL 0x20A
+        0x12, 0x06, // 0x206: JP 0x206
+        0xFF, 0xFF, // 0x208: data
+        0x00, 0xEE, // 0x20A: RET
+        0xFF, 0xFF, // 0x20C: data
+    ];
+    assert_eq!(
+        reachable_instructions(&rom, None),
+        [0x200, 0x202, 0x204, 0x206, 0x20A]
+    );
+}
+
+#[test]
+fn test_compatibility_report() {
+    let rom = [
+        0xA0, 0x00, // 0x200: LD I, 0
+        0xD0, 0x05, // 0x202: DRW V0, V0, 5
+        0x81, 0x26, // 0x204: SHR V1, V2
+        0x12, 0x0A, // 0x206: JP 0x20A
+        0x00, 0xFF, // 0x208: data
+        0x12, 0x0A, // 0x20A: JP 0x20A
+    ];
+    let report = VirtualMachine::compatibility_report(&rom, None);
+    assert!(report.extensions.is_empty());
+    assert_eq!(report.quirks, ["shift_uses_vy"]);
+    assert!(report.unknown_opcodes.is_empty());
+    assert!(report.drew);
+    assert_eq!(report.halt_reason, Some(HaltReason::ProgramEnded));
+    assert!(report.is_supported());
+
+    let report = VirtualMachine::compatibility_report(&[0x00, 0xFF, 0xF0, 0x02, 0x12, 0x04], None);
+    assert_eq!(report.extensions, [Extension::SuperChip, Extension::XoChip]);
+    assert!(!report.drew);
+
+    // 0x200: LD I, 0xFFF
+    // 0x202: LD V3, [I]
+    let report = VirtualMachine::compatibility_report(&[0xAF, 0xFF, 0xF3, 0x65], None);
+    assert_eq!(report.halt_reason, Some(HaltReason::MemoryOutOfBounds));
+    // 0x200: RET
+    let report = VirtualMachine::compatibility_report(&[0x00, 0xEE], None);
+    assert_eq!(report.halt_reason, Some(HaltReason::StackUnderflow));
+
+    // CHIP-8X programs start at 0x300, so the jump stays within the ROM
+    // 0x300: ADD V0, 1
+    // 0x302: JP 0x300
+    let rom = [0x70, 0x01, 0x13, 0x00];
+    let report = VirtualMachine::compatibility_report(&rom, Some(Variant::Chip8X));
+    assert_eq!(report.halt_reason, None);
+    assert!(report.unknown_opcodes.is_empty());
+    assert_eq!(
+        reachable_instructions(&rom, Some(Variant::Chip8X)),
+        [0x300, 0x302]
+    );
+}
+
+#[test]
+fn test_discover_controls() {
+    let rom = [
+        0xF1, 0x0A, // 0x200: LD V1, K
+        0x60, 0x05, // 0x202: LD V0, 5
+        0xE0, 0xA1, // 0x204: SKNP V0
+        0x71, 0x01, // 0x206: ADD V1, 1
+        0x12, 0x02, // 0x208: JP 0x202
+        0xE0, 0x9E, // 0x20A: SKP V0, never reached
+    ];
+    let report = discover_controls(&rom, None);
+    assert_eq!(report.poll_sites, [0x200, 0x204]);
+    assert!(report.unexplored_sites.is_empty());
+    assert_eq!(report.any_key_frames, [0]);
+    assert_eq!(report.keys.len(), 1);
+    assert_eq!(report.keys[0].key, 5);
+    assert_eq!(report.keys[0].addrs, [0x204]);
+    assert_eq!(report.keys[0].last_frame, DISCOVERY_FRAMES - 1);
+}
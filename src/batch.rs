@@ -0,0 +1,140 @@
+//! Running many headless VMs in parallel.
+
+use {
+    super::{
+        FrameView, HaltReason, VirtualMachine, savestate,
+        scenario::{Scenario, ScenarioError},
+    },
+    std::{
+        fs,
+        sync::{
+            Arc, Mutex,
+            atomic::{AtomicU64, AtomicUsize, Ordering},
+        },
+        thread,
+    },
+};
+
+/// Applies `f` to every item on as many threads as the host has cores,
+/// returning the results in the order of the items.
+pub fn parallel_map<T: Sync, R: Send>(items: &[T], f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new((0..items.len()).map(|_| None).collect::<Vec<_>>());
+    thread::scope(|scope| {
+        for _ in 0..threads.min(items.len()) {
+            scope.spawn(|| {
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(item) = items.get(i) else {
+                        break;
+                    };
+                    let result = f(item);
+                    results.lock().unwrap()[i] = Some(result);
+                }
+            });
+        }
+    });
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|r| r.expect("every item was processed"))
+        .collect()
+}
+
+/// How a scenario run by [`run_batch`] went.
+#[derive(Debug)]
+pub struct Outcome {
+    /// Why the scenario failed, if it did. A ROM that halts with an error fails with
+    /// [`ScenarioError::Crashed`].
+    pub error: Option<ScenarioError>,
+    /// A hash of the final state, as in [`SmokeResult`](crate::smoke::SmokeResult).
+    ///
+    /// `None` if the ROM couldn't be read.
+    pub state_hash: Option<u64>,
+    /// The number of frames that were run.
+    pub frames: u64,
+    /// The number of frames in which the display changed.
+    pub frames_drawn: u64,
+    /// Why the VM halted, if it did.
+    pub halt_reason: Option<HaltReason>,
+}
+
+fn run_one(scenario: &Scenario) -> Outcome {
+    let rom = match fs::read(&scenario.rom) {
+        Ok(rom) => rom,
+        Err(e) => {
+            return Outcome {
+                error: Some(ScenarioError::Io(e)),
+                state_hash: None,
+                frames: 0,
+                frames_drawn: 0,
+                halt_reason: None,
+            };
+        }
+    };
+    let mut vm = VirtualMachine::new();
+    vm.set_rng_seed(0);
+    vm.load_rom(&rom);
+    let drawn = Arc::new(AtomicU64::new(0));
+    let counter = drawn.clone();
+    vm.on_frame(move |view: &FrameView| {
+        if view.display_updated {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    });
+    let error = scenario.run_on(&mut vm).err();
+    Outcome {
+        error,
+        state_hash: Some(savestate::checksum(&vm.save_state())),
+        frames: vm.frame_count(),
+        frames_drawn: drawn.load(Ordering::Relaxed),
+        halt_reason: vm.halt_reason(),
+    }
+}
+
+/// Runs the scenarios in parallel, with a fixed random seed so the outcomes are reproducible.
+///
+/// The outcomes are in the order of the scenarios.
+pub fn run_batch(scenarios: &[Scenario]) -> Vec<Outcome> {
+    parallel_map(scenarios, run_one)
+}
+
+#[test]
+fn test_run_batch() {
+    use {
+        crate::scenario::{Action, Step},
+        std::path::Path,
+    };
+
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/scenarios");
+    let scenario = Scenario::load(&dir.join("draw_after_key.scenario")).unwrap();
+    let missing = Scenario {
+        rom: dir.join("missing.ch8"),
+        steps: Vec::new(),
+    };
+    // Returns without a call, which halts before the step
+    let crash = Scenario {
+        rom: dir.join("crash.ch8"),
+        steps: vec![Step {
+            frame: 10,
+            action: Action::Press(0),
+        }],
+    };
+    let outcomes = run_batch(&[scenario.clone(), missing, scenario, crash]);
+    assert!(outcomes[0].error.is_none());
+    assert!(outcomes[0].frames_drawn > 0);
+    assert!(matches!(outcomes[1].error, Some(ScenarioError::Io(_))));
+    assert_eq!(outcomes[1].state_hash, None);
+    assert_eq!(outcomes[0].state_hash, outcomes[2].state_hash);
+    assert!(matches!(
+        outcomes[3].error,
+        Some(ScenarioError::Crashed {
+            frame: 0,
+            reason: HaltReason::StackUnderflow
+        })
+    ));
+    assert_eq!(outcomes[3].halt_reason, Some(HaltReason::StackUnderflow));
+    assert_eq!(parallel_map(&[1, 2, 3], |n| n * 2), [2, 4, 6]);
+}
@@ -0,0 +1,72 @@
+//! Named snapshots to jump back to.
+
+use super::VirtualMachine;
+
+#[derive(Clone)]
+pub(super) struct Bookmark {
+    name: String,
+    cycle: u64,
+    state: Vec<u8>,
+}
+
+impl VirtualMachine {
+    /// Bookmarks the current state under `name`, replacing any bookmark of the same name.
+    pub fn bookmark(&mut self, name: impl Into<String>) {
+        let name = name.into();
+        self.remove_bookmark(&name);
+        self.bookmarks.push(Bookmark {
+            name,
+            cycle: self.cycles,
+            state: self.save_state(),
+        });
+    }
+
+    /// Returns to the state bookmarked under `name`.
+    ///
+    /// Returns `false` if there is no such bookmark. The bookmarks themselves are kept,
+    /// so it's possible to jump forward again.
+    pub fn jump_to_bookmark(&mut self, name: &str) -> bool {
+        let Some(bookmark) = self.bookmarks.iter().find(|b| b.name == name) else {
+            return false;
+        };
+        let state = bookmark.state.clone();
+        self.load_state(&state)
+            .expect("bookmarked states are always valid");
+        true
+    }
+
+    /// Removes the bookmark called `name`, if there is one.
+    pub fn remove_bookmark(&mut self, name: &str) {
+        self.bookmarks.retain(|b| b.name != name);
+    }
+
+    /// Returns the names of the bookmarks and the cycles they were made at,
+    /// in the order they were made.
+    pub fn bookmarks(&self) -> impl Iterator<Item = (&str, u64)> {
+        self.bookmarks.iter().map(|b| (b.name.as_str(), b.cycle))
+    }
+}
+
+#[test]
+fn test_bookmarks() {
+    let mut vm = VirtualMachine::new();
+    // 0x200: ADD V0, 1
+    // 0x202: JP 0x200
+    vm.load_rom(&[0x70, 0x01, 0x12, 0x00]);
+    vm.do_cycle();
+    vm.bookmark("one");
+    for _ in 0..4 {
+        vm.do_cycle();
+    }
+    vm.bookmark("three");
+    assert!(vm.jump_to_bookmark("one"));
+    assert_eq!(vm.v[0].0, 1);
+    assert!(vm.jump_to_bookmark("three"));
+    assert_eq!(vm.v[0].0, 3);
+    assert_eq!(
+        vm.bookmarks().collect::<Vec<_>>(),
+        [("one", 1), ("three", 5)]
+    );
+    vm.remove_bookmark("one");
+    assert!(!vm.jump_to_bookmark("one"));
+}
@@ -0,0 +1,80 @@
+//! A built-in program for frontends to run when no ROM was given.
+//!
+//! It shows the name of the interpreter and asks for a ROM, rendered by the interpreter itself
+//! like any other program, then waits for keypresses forever.
+//!
+//! The program is listed below in assembly. The text is drawn with a small font of its own,
+//! from a table of `x, y, glyph offset` triples ending with an `x` of `0xFF`.
+
+/// The boot program.
+#[rustfmt::skip]
+pub static ROM: &[u8] = &[
+    0x00, 0xE0, // 0x200: CLS
+    0x63, 0x00, // 0x202: LD V3, 0          ; offset into the text table
+    0xA2, 0x76, // 0x204: LD I, 0x276       ; the text table
+    0xF3, 0x1E, // 0x206: ADD I, V3
+    0xF2, 0x65, // 0x208: LD V2, [I]        ; V0 = x, V1 = y, V2 = glyph offset
+    0x30, 0xFF, // 0x20A: SE V0, 0xFF
+    0x12, 0x12, // 0x20C: JP 0x212
+    0xF4, 0x0A, // 0x20E: LD V4, K          ; done, idle without halting
+    0x12, 0x0E, // 0x210: JP 0x20E
+    0xA2, 0x1C, // 0x212: LD I, 0x21C       ; the font
+    0xF2, 0x1E, // 0x214: ADD I, V2
+    0xD0, 0x15, // 0x216: DRW V0, V1, 5
+    0x73, 0x03, // 0x218: ADD V3, 3
+    0x12, 0x04, // 0x21A: JP 0x204
+    // 0x21C: font
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0xE0, 0xA0, 0x90, // R
+    0x90, 0x90, 0x90, 0x90, 0xF0, // U
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // S
+    0xE0, 0x40, 0x40, 0x40, 0x40, // T
+    0xA0, 0xA0, 0x40, 0x40, 0x40, // Y
+    0x90, 0x90, 0xF0, 0x90, 0x90, // H
+    0xE0, 0x40, 0x40, 0x40, 0xE0, // I
+    0xE0, 0x90, 0xE0, 0x80, 0x80, // P
+    0x00, 0x00, 0xE0, 0x00, 0x00, // -
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0x90, 0xD0, 0xB0, 0x90, 0x90, // N
+    0x60, 0x90, 0x90, 0x90, 0x60, // O
+    0x88, 0xD8, 0xA8, 0x88, 0x88, // M
+    0x60, 0x90, 0xF0, 0x90, 0x90, // A
+    0xF0, 0x80, 0xE0, 0x80, 0x80, // F
+    0x80, 0x80, 0x80, 0x80, 0xF0, // L
+    0xF0, 0x80, 0xE0, 0x80, 0xF0, // E
+    // 0x276: text
+    // CRUSTY
+    18, 1, 0, 23, 1, 5, 28, 1, 10, 33, 1, 15, 38, 1, 20, 42, 1, 25,
+    // CHIP-8
+    18, 7, 0, 23, 7, 30, 28, 7, 35, 32, 7, 40, 37, 7, 45, 41, 7, 50,
+    // NO ROM
+    18, 15, 55, 23, 15, 60, 30, 15, 5, 35, 15, 60, 40, 15, 65,
+    // PASS A ROM
+    10, 21, 40, 15, 21, 70, 20, 21, 15, 25, 21, 15, 32, 21, 70, 39, 21, 5, 44, 21, 60, 49, 21, 65,
+    // FILE OR URL
+    8, 27, 75, 13, 27, 35, 17, 27, 80, 22, 27, 85, 29, 27, 60, 34, 27, 5,
+    41, 27, 10, 46, 27, 5, 51, 27, 80,
+    0xFF,
+];
+
+#[test]
+fn test_boot_rom_shows_text() {
+    let mut vm = crate::VirtualMachine::new();
+    vm.load_rom(ROM);
+    vm.record_events(true);
+    for _ in 0..60 {
+        vm.step_frame();
+    }
+    assert!(vm.waiting_for_key());
+    assert_eq!(vm.halt_reason(), None);
+    assert!(
+        !vm.take_events()
+            .iter()
+            .any(|e| matches!(e.kind, crate::EventKind::UnknownInstruction(_)))
+    );
+    // The top left corner of the C of CRUSTY
+    let pixel = |x: usize, y: usize| vm.display()[y * crate::DISPLAY_WIDTH + x];
+    assert_eq!(pixel(18, 1), 1);
+    assert_eq!(pixel(17, 1), 0);
+    assert_eq!(vm.display().iter().filter(|&&px| px != 0).count(), 372);
+}
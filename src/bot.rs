@@ -0,0 +1,79 @@
+//! Programs playing CHIP-8 games.
+//!
+//! A bot looks at the VM at the start of every frame and decides which keys to hold during it.
+//! Runs are headless and go as fast as the host allows.
+
+use super::VirtualMachine;
+
+/// The keys a bot holds during a frame, indexed by key.
+pub type KeyState = [bool; 16];
+
+/// How a bot run ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BotRun {
+    /// The number of frames that were run.
+    pub frames: u64,
+    /// Whether the run ended because the VM halted.
+    pub halted: bool,
+}
+
+/// Runs `vm` for up to `frames` frames, with `bot` choosing the input of every frame.
+///
+/// The run ends early if the VM halts. Keys the bot holds are left pressed when it ends.
+pub fn run_bot(
+    vm: &mut VirtualMachine,
+    frames: u64,
+    mut bot: impl FnMut(&VirtualMachine) -> KeyState,
+) -> BotRun {
+    for frame in 0..frames {
+        if vm.halt_reason().is_some() {
+            return BotRun {
+                frames: frame,
+                halted: true,
+            };
+        }
+        let keys = bot(vm);
+        for (key, &pressed) in (0..16).zip(&keys) {
+            if pressed != vm.key_pressed(key) {
+                if pressed {
+                    vm.press_key(key);
+                } else {
+                    vm.release_key(key);
+                }
+            }
+        }
+        vm.step_frame();
+    }
+    BotRun {
+        frames,
+        halted: vm.halt_reason().is_some(),
+    }
+}
+
+#[test]
+fn test_run_bot() {
+    let mut vm = VirtualMachine::new();
+    // 0x200: LD V0, 5
+    // 0x202: SKNP V0
+    // 0x204: ADD V1, 1
+    // 0x206: SE V1, 2
+    // 0x208: JP 0x202
+    // 0x20A: JP 0x20A
+    vm.load_rom(&[
+        0x60, 0x05, 0xE0, 0xA1, 0x71, 0x01, 0x31, 0x02, 0x12, 0x02, 0x12, 0x0A,
+    ]);
+    let run = run_bot(&mut vm, 100, |vm| {
+        let mut keys = KeyState::default();
+        keys[5] = vm.frame_count() == 3;
+        keys
+    });
+    assert_eq!(
+        run,
+        BotRun {
+            frames: 4,
+            halted: true
+        }
+    );
+    assert_eq!(vm.v(1), 2);
+    assert!(vm.key_pressed(5));
+}
@@ -0,0 +1,88 @@
+//! What the VM understands in its current configuration.
+//!
+//! Which instructions run depends on the extensions compiled in and the [`Variant`] set, so
+//! some quirks end up affecting nothing. [`Capabilities`] are worked out from the opcode
+//! table, so frontends can grey out what makes no difference, and a
+//! [`CompatibilityReport`](crate::CompatibilityReport) can be checked against them.
+//!
+//! [`Variant`]: crate::Variant
+
+use super::{
+    VirtualMachine,
+    opcodes::{self, Extension},
+    quirks::QUIRKS,
+};
+
+/// The extensions and quirks the VM supports, as found by [`VirtualMachine::capabilities`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    /// The extensions with instructions that are understood, oldest first.
+    pub extensions: Vec<Extension>,
+    /// Names of the [`Quirks`](crate::Quirks) fields affecting an instruction that's
+    /// understood, in the order of [`QUIRKS`]. Setting the others changes nothing.
+    pub quirks: Vec<&'static str>,
+}
+
+impl Capabilities {
+    /// Returns whether instructions of `ext` are understood.
+    pub fn supports_extension(&self, ext: Extension) -> bool {
+        self.extensions.contains(&ext)
+    }
+
+    /// Returns whether the quirk named `name` affects anything.
+    pub fn supports_quirk(&self, name: &str) -> bool {
+        self.quirks.contains(&name)
+    }
+}
+
+impl VirtualMachine {
+    /// Returns the extensions and quirks supported with the current extension setting.
+    ///
+    /// An extension only counts if instructions of it were compiled in, so an extension
+    /// whose opcode table is empty isn't supported.
+    pub fn capabilities(&self) -> Capabilities {
+        let mut extensions: Vec<_> = opcodes::all()
+            .map(|spec| spec.extension)
+            .filter(|&ext| self.extension.includes(ext))
+            .collect();
+        extensions.sort();
+        extensions.dedup();
+        let quirks = QUIRKS
+            .iter()
+            .filter(|quirk| {
+                // Patterns of instructions that aren't understood can fall through to more
+                // general ones, like 00FE to SYS nnn
+                quirk.opcodes.iter().any(|&pattern| {
+                    opcodes::lookup_up_to(pattern, self.extension)
+                        .is_some_and(|spec| spec.pattern == pattern)
+                })
+            })
+            .map(|quirk| quirk.name)
+            .collect();
+        Capabilities { extensions, quirks }
+    }
+}
+
+#[test]
+fn test_capabilities() {
+    let caps = VirtualMachine::with_variant(crate::Variant::CosmacVip).capabilities();
+    assert_eq!(caps.extensions, [Extension::Chip8]);
+    assert!(caps.supports_quirk("shift_uses_vy"));
+    // Only the SUPER-CHIP resolution switches clear the display
+    assert!(!caps.supports_quirk("resolution_switch_clears"));
+    // 0x200: HIGH
+    let report = VirtualMachine::compatibility_report(&[0x00, 0xFF], None);
+    assert!(!report.is_supported_by(&caps));
+    let caps = VirtualMachine::new().capabilities();
+    assert_eq!(
+        caps.supports_extension(Extension::SuperChip),
+        cfg!(feature = "schip")
+    );
+    assert_eq!(
+        caps.supports_quirk("resolution_switch_clears"),
+        cfg!(feature = "schip")
+    );
+    assert_eq!(report.is_supported_by(&caps), cfg!(feature = "schip"));
+    // The VM doesn't run CHIP-8X programs unless asked to
+    assert!(!caps.supports_extension(Extension::Chip8X));
+}
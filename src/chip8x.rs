@@ -0,0 +1,238 @@
+//! CHIP-8X, the VIP interpreter for the VP-590 color board and the VP-580 second keypad.
+//!
+//! The display stays monochrome, and a color map alongside it colors the lit pixels. The
+//! map divides the 64x32 display into zones 8 pixels wide and 1 pixel tall, each with one
+//! of 8 foreground colors, behind which the whole display has one of 4 background colors.
+//! Frontends get the map with [`VirtualMachine::chip8x_colors`].
+//!
+//! The interpreter takes up more memory, so programs start at [`START_ADDR`]. CHIP-8X
+//! replaces `BNNN` with a color instruction, so it doesn't mix with the other extensions.
+//!
+//! Only compiled in with the `chip8x` feature. The port I/O instructions, `FXF8` and `FXFB`,
+//! aren't supported.
+
+use {
+    super::{Instruction::*, VirtualMachine},
+    crate::{
+        opcodes::{OpcodeSpec, op},
+        palette::Rgb,
+    },
+};
+
+/// The address CHIP-8X programs start at.
+pub const START_ADDR: u16 = crate::Variant::Chip8X.start_addr();
+
+/// The foreground colors, indexed by the color number of `BXYN` and `BXY0`.
+pub const COLORS: [Rgb; 8] = [
+    [0x00, 0x00, 0x00], // Black
+    [0xFF, 0x00, 0x00], // Red
+    [0x00, 0x00, 0xFF], // Blue
+    [0xFF, 0x00, 0xFF], // Violet
+    [0x00, 0xFF, 0x00], // Green
+    [0xFF, 0xFF, 0x00], // Yellow
+    [0x00, 0xFF, 0xFF], // Aqua
+    [0xFF, 0xFF, 0xFF], // White
+];
+
+/// The background colors, in the order `02A0` cycles through them.
+pub const BACKGROUNDS: [Rgb; 4] = [
+    [0x00, 0x00, 0x80], // Dark blue
+    [0x00, 0x00, 0x00], // Black
+    [0x00, 0x80, 0x00], // Dark green
+    [0x80, 0x00, 0x00], // Dark red
+];
+
+const ZONE_COLUMNS: usize = 8;
+const ZONE_WIDTH: usize = 8;
+const ZONE_ROWS: usize = 32;
+// The rows colored at once by BXY0
+const BLOCK_HEIGHT: usize = 4;
+
+/// The colors of the CHIP-8X display.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColorMap {
+    pub(super) background: u8,
+    pub(super) zones: [u8; ZONE_COLUMNS * ZONE_ROWS],
+}
+
+impl Default for ColorMap {
+    /// Red on dark blue.
+    fn default() -> Self {
+        Self {
+            background: 0,
+            zones: [1; ZONE_COLUMNS * ZONE_ROWS],
+        }
+    }
+}
+
+impl ColorMap {
+    /// Returns the color of unlit pixels.
+    pub fn background(&self) -> Rgb {
+        BACKGROUNDS[usize::from(self.background)]
+    }
+
+    /// Returns the color of the pixel at `x`, `y` when it's lit.
+    pub fn foreground(&self, x: usize, y: usize) -> Rgb {
+        let zone = (y % ZONE_ROWS) * ZONE_COLUMNS + (x / ZONE_WIDTH) % ZONE_COLUMNS;
+        COLORS[usize::from(self.zones[zone])]
+    }
+
+    // Colors `rows` rows of the zones from `column` to `column + columns`, clipped to the
+    // display
+    fn fill(&mut self, column: usize, columns: usize, rows: std::ops::Range<usize>, color: u8) {
+        for row in rows.start.min(ZONE_ROWS)..rows.end.min(ZONE_ROWS) {
+            let start = row * ZONE_COLUMNS;
+            let zones = &mut self.zones[start..start + ZONE_COLUMNS];
+            for zone in zones.iter_mut().skip(column).take(columns) {
+                *zone = color;
+            }
+        }
+    }
+}
+
+#[rustfmt::skip]
+pub(crate) static OPCODES: &[OpcodeSpec] = &[
+    op!(Chip8X, 0x02A0, 0xFFFF, "BGND", "Switch to the next background color.",
+        |_| CycleBackground, |vm, _| vm.cycle_background()),
+    op!(Chip8X, 0x5001, 0xF00F, "ADD Vx, Vy, nibbles",
+        "Add the nibbles of Vy to those of Vx, each without carry, ignoring their top bits.",
+        |o| AddVxVyNibbles { x: o.x, y: o.y },
+        |vm, o| vm.add_vx_vy_nibbles(o.x.into(), o.y.into())),
+    op!(Chip8X, 0xB000, 0xF00F, "COL Vx, Vy",
+        "Color the zones given by Vx and V(x+1), 8x4 pixels each, with color Vy.",
+        |o| SetColorZones { x: o.x, y: o.y },
+        |vm, o| vm.set_color_zones(o.x.into(), o.y.into())),
+    op!(Chip8X, 0xB000, 0xF000, "COL Vx, Vy, n",
+        "Color n rows from V(x+1) of the zone column holding Vx with color Vy.",
+        |o| SetColorRows { x: o.x, y: o.y, n: o.n },
+        |vm, o| vm.set_color_rows(o.x.into(), o.y.into(), o.n.into())),
+    op!(Chip8X, 0xE0F2, 0xF0FF, "SKP2 Vx",
+        "Skip next instruction if key Vx of the second keypad is pressed.",
+        |o| SkipNextKey2VxPressed { x: o.x },
+        |vm, o| vm.skip_next_key2_vx(o.x.into(), true)),
+    op!(Chip8X, 0xE0F5, 0xF0FF, "SKNP2 Vx",
+        "Skip next instruction if key Vx of the second keypad is not pressed.",
+        |o| SkipNextKey2VxNotPressed { x: o.x },
+        |vm, o| vm.skip_next_key2_vx(o.x.into(), false)),
+];
+
+impl VirtualMachine {
+    /// Returns the colors of the display, while [CHIP-8X](crate::chip8x) instructions are
+    /// understood.
+    pub fn chip8x_colors(&self) -> Option<&ColorMap> {
+        (self.extension == crate::opcodes::Extension::Chip8X).then_some(&self.colors)
+    }
+
+    /// Presses a key on the second hexadecimal keypad of CHIP-8X.
+    ///
+    /// `key` should be in the range `0..15`.
+    pub fn press_key2(&mut self, key: u8) {
+        assert!(key <= 15);
+        self.keys2[usize::from(key)] = true;
+    }
+
+    /// Releases a key on the second hexadecimal keypad of CHIP-8X.
+    ///
+    /// `key` should be in the range `0..15`.
+    pub fn release_key2(&mut self, key: u8) {
+        assert!(key <= 15);
+        self.keys2[usize::from(key)] = false;
+    }
+
+    fn cycle_background(&mut self) {
+        self.colors.background = (self.colors.background + 1) % BACKGROUNDS.len() as u8;
+        self.display_changed();
+    }
+
+    fn add_vx_vy_nibbles(&mut self, x: usize, y: usize) {
+        self.v[x].0 = ((self.v[x].0 & 0x77) + (self.v[y].0 & 0x77)) & 0x77;
+    }
+
+    fn set_color_zones(&mut self, x: usize, y: usize) {
+        let (h, v) = (self.v[x].0, self.v[(x + 1) & 0xF].0);
+        let row = usize::from(v & 0xF) * BLOCK_HEIGHT;
+        let rows = usize::from(v >> 4) * BLOCK_HEIGHT + BLOCK_HEIGHT;
+        let color = self.v[y].0 & 7;
+        self.colors.fill(
+            usize::from(h & 0xF),
+            usize::from(h >> 4) + 1,
+            row..row + rows,
+            color,
+        );
+        self.display_changed();
+    }
+
+    fn set_color_rows(&mut self, x: usize, y: usize, n: usize) {
+        let column = usize::from(self.v[x].0) / ZONE_WIDTH % ZONE_COLUMNS;
+        let row = usize::from(self.v[(x + 1) & 0xF].0);
+        let color = self.v[y].0 & 7;
+        self.colors.fill(column, 1, row..row + n, color);
+        self.display_changed();
+    }
+
+    fn skip_next_key2_vx(&mut self, x: usize, pressed: bool) {
+        if self.keys2[usize::from(self.v[x].0 & 0xF)] == pressed {
+            self.pc += 2;
+        }
+    }
+}
+
+#[test]
+fn test_chip8x() {
+    let mut vm = VirtualMachine::with_variant(crate::Variant::Chip8X);
+    // 0x300: LD V0, 0x1A
+    // 0x302: LD V1, 0x11
+    // 0x304: LD V2, 0x14
+    // 0x306: LD V3, 0x66
+    // 0x308: ADD V0, V3, nibbles
+    // 0x30A: COL V1, V3: zone columns 1 and 2, rows 16 to 23, aqua
+    // 0x30C: BGND
+    // 0x30E: COL V0, V3, 2: rows 17 and 18 of zone column 6, aqua
+    // 0x310: SKP2 V3
+    // 0x312: JP 0x312
+    // 0x314: JP 0x314
+    vm.load_rom(&[
+        0x60, 0x1A, 0x61, 0x11, 0x62, 0x14, 0x63, 0x66, 0x50, 0x31, 0xB1, 0x30, 0x02, 0xA0, 0xB0,
+        0x32, 0xE3, 0xF2, 0x13, 0x12, 0x13, 0x14,
+    ]);
+    assert_eq!(vm.pc(), START_ADDR);
+    for _ in 0..5 {
+        vm.do_cycle();
+    }
+    // The top bits of the nibbles are ignored: 0x12 + 0x66
+    assert_eq!(vm.v(0), 0x70);
+    let colors = vm.chip8x_colors().unwrap();
+    assert_eq!(colors.background(), BACKGROUNDS[0]);
+    assert_eq!(colors.foreground(8, 16), COLORS[1]);
+    vm.do_cycle();
+    vm.do_cycle();
+    let colors = vm.chip8x_colors().unwrap();
+    assert_eq!(colors.background(), BACKGROUNDS[1]);
+    for (x, y, color) in [
+        (7, 16, 1),
+        (8, 16, 6),
+        (23, 23, 6),
+        (24, 23, 1),
+        (8, 15, 1),
+        (8, 24, 1),
+    ] {
+        assert_eq!(colors.foreground(x, y), COLORS[color], "{}, {}", x, y);
+    }
+    vm.do_cycle();
+    let colors = vm.chip8x_colors().unwrap();
+    for (y, color) in [(16, 1), (17, 6), (18, 6), (19, 1)] {
+        assert_eq!(colors.foreground(48, y), COLORS[color], "{}", y);
+    }
+    // Saving and loading keeps the colors
+    let mut loaded = VirtualMachine::new();
+    loaded.load_state(&vm.save_state()).unwrap();
+    assert_eq!(loaded.chip8x_colors(), vm.chip8x_colors());
+    // The second keypad is separate from the first
+    vm.press_key(6);
+    vm.do_cycle();
+    assert_eq!(vm.pc(), 0x312);
+    vm.press_key2(6);
+    vm.pc = 0x310;
+    vm.do_cycle();
+    assert_eq!(vm.pc(), 0x314);
+}
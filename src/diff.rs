@@ -0,0 +1,91 @@
+use {super::VirtualMachine, std::ops::Range};
+
+/// A part of the VM state that differs between two VMs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Difference {
+    /// Register Vx.
+    V(u8),
+    /// The I register.
+    I,
+    /// The program counter.
+    Pc,
+    /// The stack pointer.
+    Sp,
+    /// A stack slot.
+    Stack(u8),
+    /// The delay timer.
+    DelayTimer,
+    /// The sound timer.
+    SoundTimer,
+    /// A contiguous range of RAM.
+    Ram(Range<usize>),
+    /// Pixels of the display, as (x, y) positions.
+    Display(Vec<(usize, usize)>),
+}
+
+impl VirtualMachine {
+    /// Lists which parts of the state differ between `self` and `other`.
+    pub fn diff(&self, other: &VirtualMachine) -> Vec<Difference> {
+        let mut diffs = Vec::new();
+        for (x, (a, b)) in self.v.iter().zip(other.v.iter()).enumerate() {
+            if a != b {
+                diffs.push(Difference::V(x as u8));
+            }
+        }
+        if self.i != other.i {
+            diffs.push(Difference::I);
+        }
+        if self.pc != other.pc {
+            diffs.push(Difference::Pc);
+        }
+        if self.sp != other.sp {
+            diffs.push(Difference::Sp);
+        }
+        for (slot, (a, b)) in self.stack.iter().zip(other.stack.iter()).enumerate() {
+            if a != b {
+                diffs.push(Difference::Stack(slot as u8));
+            }
+        }
+        if self.delay_timer != other.delay_timer {
+            diffs.push(Difference::DelayTimer);
+        }
+        if self.sound_timer != other.sound_timer {
+            diffs.push(Difference::SoundTimer);
+        }
+        let mut run_start = None;
+        for addr in 0..=self.ram.len() {
+            let differs = addr < self.ram.len() && self.ram[addr] != other.ram[addr];
+            match (differs, run_start) {
+                (true, None) => run_start = Some(addr),
+                (false, Some(start)) => {
+                    diffs.push(Difference::Ram(start..addr));
+                    run_start = None;
+                }
+                _ => {}
+            }
+        }
+        let pixels = self.display.diff(&other.display);
+        if !pixels.is_empty() {
+            diffs.push(Difference::Display(pixels));
+        }
+        diffs
+    }
+}
+
+#[test]
+fn test_diff_coalesces_ram_ranges() {
+    let a = VirtualMachine::new();
+    let mut b = a.clone();
+    b.ram[0x300] = 1;
+    b.ram[0x301] = 2;
+    b.ram[0x400] = 3;
+    b.v[3].0 = 7;
+    assert_eq!(
+        a.diff(&b),
+        [
+            Difference::V(3),
+            Difference::Ram(0x300..0x302),
+            Difference::Ram(0x400..0x401)
+        ]
+    );
+}
@@ -0,0 +1,395 @@
+use {
+    super::{DISPLAY_HEIGHT, DISPLAY_WIDTH, VirtualMachine},
+    std::{
+        fmt,
+        io::{self, Write},
+        str::FromStr,
+    },
+};
+
+/// A display resolution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    /// 64x32, the only resolution of the original CHIP-8.
+    Low,
+    /// 128x64, the SUPER-CHIP high resolution mode.
+    High,
+    /// 64x64, the two-page display of [HI-RES CHIP-8](crate::twopage).
+    TwoPage,
+}
+
+impl Resolution {
+    /// All resolutions.
+    pub const ALL: [Resolution; 3] = [Resolution::Low, Resolution::High, Resolution::TwoPage];
+
+    /// The width in pixels.
+    pub fn width(self) -> usize {
+        match self {
+            Resolution::Low | Resolution::TwoPage => DISPLAY_WIDTH,
+            Resolution::High => 128,
+        }
+    }
+    /// The height in pixels.
+    pub fn height(self) -> usize {
+        match self {
+            Resolution::Low => DISPLAY_HEIGHT,
+            Resolution::High | Resolution::TwoPage => 64,
+        }
+    }
+}
+
+/// A clockwise rotation of the display, for portrait games and screens mounted sideways.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Rotation {
+    /// Upright.
+    #[default]
+    None,
+    /// A quarter turn clockwise.
+    Cw90,
+    /// Upside down.
+    Cw180,
+    /// A quarter turn counterclockwise.
+    Cw270,
+}
+
+impl Rotation {
+    /// All rotations, in clockwise order.
+    pub const ALL: [Rotation; 4] = [
+        Rotation::None,
+        Rotation::Cw90,
+        Rotation::Cw180,
+        Rotation::Cw270,
+    ];
+
+    /// The rotation in degrees.
+    pub fn degrees(self) -> u16 {
+        match self {
+            Rotation::None => 0,
+            Rotation::Cw90 => 90,
+            Rotation::Cw180 => 180,
+            Rotation::Cw270 => 270,
+        }
+    }
+
+    /// Returns the width and height of a `width` by `height` image after rotation.
+    pub fn size(self, width: usize, height: usize) -> (usize, usize) {
+        match self {
+            Rotation::None | Rotation::Cw180 => (width, height),
+            Rotation::Cw90 | Rotation::Cw270 => (height, width),
+        }
+    }
+
+    /// Returns where the pixel at (`x`, `y`) of a `width` by `height` image ends up
+    /// after rotation.
+    pub fn point(self, x: usize, y: usize, width: usize, height: usize) -> (usize, usize) {
+        match self {
+            Rotation::None => (x, y),
+            Rotation::Cw90 => (height - 1 - y, x),
+            Rotation::Cw180 => (width - 1 - x, height - 1 - y),
+            Rotation::Cw270 => (y, width - 1 - x),
+        }
+    }
+}
+
+impl fmt::Display for Rotation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.degrees())
+    }
+}
+
+impl FromStr for Rotation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Rotation::ALL
+            .into_iter()
+            .find(|rotation| rotation.to_string() == s)
+            .ok_or_else(|| format!("Unknown rotation: {} (expected 0, 90, 180 or 270)", s))
+    }
+}
+
+/// The contents of the display, one byte per pixel.
+///
+/// A pixel holds the bitmask of the XO-CHIP planes it's set in, so it's either 0 (off) or 1
+/// (on) unless the program draws to the second plane. Frontends can map pixel values to
+/// colors with a [`Palette`](crate::Palette). The size follows the [`Resolution`] of the
+/// display, so it's 64x32, 128x64 or 64x64.
+#[derive(Clone, PartialEq, Eq)]
+pub struct FrameBuffer {
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+    pub(crate) pixels: Vec<u8>,
+}
+
+impl Default for FrameBuffer {
+    fn default() -> Self {
+        Self::new(Resolution::Low)
+    }
+}
+
+impl FrameBuffer {
+    /// Creates an empty display of the given resolution.
+    pub fn new(resolution: Resolution) -> Self {
+        let (width, height) = (resolution.width(), resolution.height());
+        Self {
+            width,
+            height,
+            pixels: vec![0; width * height],
+        }
+    }
+    /// The width of the display in pixels.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+    /// The height of the display in pixels.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+    /// The resolution of the display.
+    pub fn resolution(&self) -> Resolution {
+        Resolution::ALL
+            .into_iter()
+            .find(|res| (res.width(), res.height()) == (self.width, self.height))
+            .expect("Display of unknown resolution")
+    }
+    /// Returns the display scaled to another resolution, like on the SUPER-CHIP, where
+    /// low resolution pixels are 2x2 blocks of the high resolution screen.
+    pub fn resized(&self, resolution: Resolution) -> FrameBuffer {
+        let mut out = FrameBuffer::new(resolution);
+        for y in 0..out.height {
+            for x in 0..out.width {
+                let (src_x, src_y) = (x * self.width / out.width, y * self.height / out.height);
+                out.pixels[y * out.width + x] = self.pixels[src_y * self.width + src_x];
+            }
+        }
+        out
+    }
+    /// The pixels of the display, row by row.
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+    /// Returns whether the pixel at (`x`, `y`) is on.
+    ///
+    /// Panics if the position is out of bounds.
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        assert!(x < self.width() && y < self.height());
+        self.pixels[y * self.width() + x] != 0
+    }
+    /// Returns the pixels rotated by `rotation`, row by row.
+    ///
+    /// The dimensions of the result are given by [`Rotation::size`].
+    pub fn rotated(&self, rotation: Rotation) -> Vec<u8> {
+        let (w, h) = (self.width(), self.height());
+        let (rotated_w, _) = rotation.size(w, h);
+        let mut out = vec![0; self.pixels.len()];
+        for (i, &px) in self.pixels.iter().enumerate() {
+            let (x, y) = rotation.point(i % w, i / w, w, h);
+            out[y * rotated_w + x] = px;
+        }
+        out
+    }
+    /// Returns the positions of the pixels that differ between `self` and `other`.
+    ///
+    /// If the resolutions differ, every pixel of `self` counts as changed.
+    pub fn diff(&self, other: &FrameBuffer) -> Vec<(usize, usize)> {
+        if (self.width, self.height) != (other.width, other.height) {
+            return (0..self.pixels.len())
+                .map(|i| (i % self.width, i / self.width))
+                .collect();
+        }
+        self.pixels
+            .iter()
+            .zip(other.pixels.iter())
+            .enumerate()
+            .filter(|(_, (a, b))| a != b)
+            .map(|(i, _)| (i % self.width(), i / self.width()))
+            .collect()
+    }
+
+    /// Renders the display as text, `#` for pixels that are on and a space for those that are off.
+    pub fn to_ascii(&self) -> String {
+        self.to_ascii_with('#', ' ')
+    }
+    /// Renders the display as text, using the given characters for on and off pixels.
+    ///
+    /// Each row ends with a newline.
+    pub fn to_ascii_with(&self, on: char, off: char) -> String {
+        let mut out = String::with_capacity((self.width() + 1) * self.height());
+        for row in self.pixels.chunks(self.width()) {
+            out.extend(row.iter().map(|&px| if px != 0 { on } else { off }));
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Writes the display as a binary PBM image, with on pixels white like in
+    /// [`FrameBuffer::write_pgm`].
+    pub fn write_pbm<W: Write>(&self, mut w: W) -> io::Result<()> {
+        write!(w, "P4\n{} {}\n", self.width(), self.height())?;
+        for row in self.pixels.chunks(self.width()) {
+            // A set bit is black in PBM
+            let row: Vec<u8> = pack_row(row).iter().map(|byte| !byte).collect();
+            w.write_all(&row)?;
+        }
+        Ok(())
+    }
+    /// Writes the display as a binary PGM image, with on pixels white.
+    pub fn write_pgm<W: Write>(&self, mut w: W) -> io::Result<()> {
+        write!(w, "P5\n{} {}\n255\n", self.width(), self.height())?;
+        let bytes: Vec<u8> = self
+            .pixels
+            .iter()
+            .map(|&px| if px != 0 { 255 } else { 0 })
+            .collect();
+        w.write_all(&bytes)
+    }
+    /// Writes the display as a PNG image.
+    ///
+    /// `palette` gives the colors of the off and on pixels, in that order.
+    #[cfg(feature = "image")]
+    pub fn write_png<W: Write>(&self, w: W, palette: [[u8; 3]; 2]) -> io::Result<()> {
+        let mut encoder = png::Encoder::new(w, self.width() as u32, self.height() as u32);
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_depth(png::BitDepth::One);
+        encoder.set_palette(palette.concat());
+        let mut writer = encoder.write_header()?;
+        let data: Vec<u8> = self
+            .pixels
+            .chunks(self.width())
+            .flat_map(pack_row)
+            .collect();
+        writer.write_image_data(&data)?;
+        Ok(())
+    }
+}
+
+impl VirtualMachine {
+    /// Returns the resolution the program selected, which is also the size of the
+    /// [`FrameBuffer`].
+    ///
+    /// Changes are also reported as [`EventKind::ResolutionChanged`](crate::EventKind)
+    /// events.
+    pub fn current_resolution(&self) -> Resolution {
+        if self.high_res {
+            return Resolution::High;
+        }
+        #[cfg(feature = "hires")]
+        if self.two_page {
+            return Resolution::TwoPage;
+        }
+        Resolution::Low
+    }
+
+    /// Returns the display as it was at the end of the most recent frame.
+    ///
+    /// Frames end whenever the timers are decremented.
+    pub fn last_frame(&self) -> &FrameBuffer {
+        &self.last_frame
+    }
+    /// Returns the display as it was at the end of the frame before the most recent one.
+    ///
+    /// Frontends refreshing faster than 60 Hz can interpolate or fade between this and
+    /// [`VirtualMachine::last_frame`], instead of showing the same frame several times.
+    pub fn previous_frame(&self) -> &FrameBuffer {
+        &self.prev_frame
+    }
+    /// Returns the pixels that changed between the previous and the most recent frame.
+    pub fn frame_changes(&self) -> Vec<(usize, usize)> {
+        self.prev_frame.diff(&self.last_frame)
+    }
+}
+
+// Pack a row of pixels into bytes, most significant bit first
+pub(crate) fn pack_row(row: &[u8]) -> Vec<u8> {
+    row.chunks(8)
+        .map(|px| {
+            px.iter()
+                .enumerate()
+                .fold(0, |byte, (i, &p)| byte | (u8::from(p != 0) << (7 - i)))
+        })
+        .collect()
+}
+
+#[test]
+fn test_write_pbm() {
+    let mut fb = FrameBuffer::default();
+    fb.pixels[0] = 1;
+    fb.pixels[DISPLAY_WIDTH + 9] = 1;
+    let mut out = Vec::new();
+    fb.write_pbm(&mut out).unwrap();
+    let header = b"P4\n64 32\n";
+    assert_eq!(&out[..header.len()], header);
+    let data = &out[header.len()..];
+    assert_eq!(data.len(), DISPLAY_WIDTH / 8 * DISPLAY_HEIGHT);
+    assert_eq!(data[0], 0b0111_1111);
+    assert_eq!(data[9], 0b1011_1111);
+    assert_eq!(data[1], 0xFF);
+}
+
+#[test]
+fn test_write_pgm() {
+    let mut fb = FrameBuffer::default();
+    // Any nonzero value is on
+    fb.pixels[0] = 1;
+    fb.pixels[1] = 3;
+    let mut out = Vec::new();
+    fb.write_pgm(&mut out).unwrap();
+    let header = b"P5\n64 32\n255\n";
+    assert_eq!(&out[..header.len()], header);
+    assert_eq!(out[header.len()..][..3], [255, 255, 0]);
+}
+
+#[test]
+fn test_to_ascii() {
+    let mut fb = FrameBuffer::default();
+    fb.pixels[1] = 1;
+    let text = fb.to_ascii_with('X', '.');
+    let first = text.lines().next().unwrap();
+    assert_eq!(&first[..3], ".X.");
+    assert_eq!(text.lines().count(), DISPLAY_HEIGHT);
+}
+
+#[test]
+fn test_rotated() {
+    let mut fb = FrameBuffer::default();
+    // The top left corner, and the pixel right of it
+    fb.pixels[0] = 1;
+    fb.pixels[1] = 1;
+    let at = |pixels: &[u8], w: usize| -> Vec<(usize, usize)> {
+        (0..pixels.len())
+            .filter(|&i| pixels[i] != 0)
+            .map(|i| (i % w, i / w))
+            .collect()
+    };
+    let (w, h) = (DISPLAY_WIDTH, DISPLAY_HEIGHT);
+    assert_eq!(at(&fb.rotated(Rotation::None), w), [(0, 0), (1, 0)]);
+    assert_eq!(at(&fb.rotated(Rotation::Cw90), h), [(h - 1, 0), (h - 1, 1)]);
+    assert_eq!(
+        at(&fb.rotated(Rotation::Cw180), w),
+        [(w - 2, h - 1), (w - 1, h - 1)]
+    );
+    assert_eq!(
+        at(&fb.rotated(Rotation::Cw270), h),
+        [(0, w - 2), (0, w - 1)]
+    );
+    assert_eq!("270".parse(), Ok(Rotation::Cw270));
+    assert!("45".parse::<Rotation>().is_err());
+}
+
+#[test]
+fn test_previous_frame() {
+    let mut vm = VirtualMachine::new();
+    // 0x200: LD I, 0
+    // 0x202: DRW V0, V0, 1
+    // 0x204: CLS
+    vm.load_rom(&[0xA0, 0x00, 0xD0, 0x01, 0x00, 0xE0]);
+    vm.do_cycle();
+    vm.do_cycle();
+    vm.decrement_timers();
+    assert!(vm.last_frame().get(0, 0));
+    vm.do_cycle();
+    vm.decrement_timers();
+    assert!(vm.previous_frame().get(0, 0));
+    assert!(!vm.last_frame().get(0, 0));
+    assert_eq!(vm.frame_changes(), [(0, 0), (1, 0), (2, 0), (3, 0)]);
+}
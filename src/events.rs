@@ -0,0 +1,301 @@
+//! Timestamped events emitted by the VM.
+
+use {
+    super::{HaltReason, Resolution, Severity, VirtualMachine},
+    std::fmt,
+};
+
+/// What happened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EventKind {
+    /// A message was written to the log.
+    Log {
+        /// How serious the message is.
+        severity: Severity,
+        /// The address the message is about, like that of an unknown instruction.
+        addr: Option<u16>,
+        /// The message, without a trailing newline.
+        message: String,
+    },
+    /// The display was changed.
+    DisplayUpdated,
+    /// The sound started playing.
+    SoundStarted,
+    /// The sound stopped playing.
+    SoundStopped,
+    /// The VM halted.
+    Halted(HaltReason),
+    /// An instruction that isn't understood was skipped.
+    UnknownInstruction(u16),
+    /// The program checked whether a key is pressed, with `Ex9E` or `ExA1`.
+    KeyPolled {
+        /// The key that was checked.
+        key: u8,
+        /// The address of the instruction.
+        addr: u16,
+    },
+    /// The program started waiting for any key, with `Fx0A`.
+    KeyWait {
+        /// The address of the instruction.
+        addr: u16,
+    },
+    /// The program switched to another resolution, with `00FE` or `00FF`.
+    ResolutionChanged(Resolution),
+    /// The program drew a sprite.
+    SpriteDrawn(SpriteDraw),
+}
+
+/// A sprite drawn with `Dxyn`, for renderers treating sprites differently
+/// and for debugging sprites that don't show up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpriteDraw {
+    /// The address of the instruction.
+    pub pc: u16,
+    /// The column the sprite starts at, after wrapping around the display.
+    pub x: u8,
+    /// The row the sprite starts at, after wrapping around the display.
+    pub y: u8,
+    /// The number of columns, 16 for the big sprites of SUPER-CHIP and 8 otherwise.
+    pub width: u8,
+    /// The number of rows.
+    pub height: u8,
+    /// The address the rows were read from, the value of I.
+    pub addr: u16,
+    /// Whether a pixel was turned off, setting VF.
+    pub collision: bool,
+}
+
+/// Something that happened in the VM, and when.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    /// The value of [`VirtualMachine::cycle_count`] when the event occurred.
+    pub cycle: u64,
+    /// The value of [`VirtualMachine::frame_count`] when the event occurred.
+    pub frame: u64,
+    /// What happened.
+    pub kind: EventKind,
+}
+
+/// A beep, as the frames it started and stopped at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Beep {
+    /// The value of [`VirtualMachine::frame_count`] when the sound started.
+    pub start: u64,
+    /// The value of [`VirtualMachine::frame_count`] when the sound stopped,
+    /// or `None` if it was still playing.
+    pub end: Option<u64>,
+}
+
+impl Beep {
+    /// Pairs up the sound events in `events` into beeps, for checking the timing of audio
+    /// like the timing of the display is checked.
+    pub fn collect(events: &[Event]) -> Vec<Beep> {
+        let mut beeps: Vec<Beep> = Vec::new();
+        for event in events {
+            match event.kind {
+                EventKind::SoundStarted => beeps.push(Beep {
+                    start: event.frame,
+                    end: None,
+                }),
+                EventKind::SoundStopped => {
+                    if let Some(beep) = beeps.last_mut().filter(|beep| beep.end.is_none()) {
+                        beep.end = Some(event.frame);
+                    }
+                }
+                _ => {}
+            }
+        }
+        beeps
+    }
+}
+
+impl VirtualMachine {
+    /// Returns the number of instructions executed since the VM was created,
+    /// including the one being executed.
+    pub fn cycle_count(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Returns the number of 60 Hz timer ticks since the VM was created.
+    pub fn frame_count(&self) -> u64 {
+        self.frames
+    }
+
+    /// Starts or stops recording [`Event`]s.
+    ///
+    /// Recording is off by default. Stopping discards the events that weren't taken yet.
+    pub fn record_events(&mut self, record: bool) {
+        self.events = record.then(Vec::new);
+    }
+
+    /// Takes the events recorded since the last call.
+    pub fn take_events(&mut self) -> Vec<Event> {
+        self.events.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    pub(super) fn emit(&mut self, kind: EventKind) {
+        if let Some(events) = &mut self.events {
+            events.push(Event {
+                cycle: self.cycles,
+                frame: self.frames,
+                kind,
+            });
+        }
+    }
+
+    // Sends a message to the log sink and the `log` crate, and emits it as an event
+    pub(super) fn log_line(&mut self, severity: Severity, addr: Option<u16>, args: fmt::Arguments) {
+        if self.events.is_some() {
+            self.emit(EventKind::Log {
+                severity,
+                addr,
+                message: args.to_string(),
+            });
+        }
+        self.send_log(severity, args);
+    }
+
+    // Only the first reason is kept, as nothing runs after it
+    pub(super) fn halt(&mut self, reason: HaltReason) {
+        if self.halt.is_some() {
+            return;
+        }
+        self.halt = Some(reason);
+        self.emit(EventKind::Halted(reason));
+    }
+}
+
+#[test]
+fn test_events_are_timestamped() {
+    let mut vm = VirtualMachine::new();
+    // 0x200: LD V0, 2
+    // 0x202: LD ST, V0
+    // 0x204: CLS
+    // 0x206: JP 0x206
+    vm.load_rom(&[0x60, 0x02, 0xF0, 0x18, 0x00, 0xE0, 0x12, 0x06]);
+    vm.record_events(true);
+    for _ in 0..4 {
+        vm.do_cycle();
+    }
+    vm.decrement_timers();
+    vm.decrement_timers();
+    let events = vm.take_events();
+    let summary: Vec<_> = events.iter().map(|e| (e.cycle, e.frame)).collect();
+    assert_eq!(summary, [(2, 0), (3, 0), (4, 0), (4, 0), (4, 2)]);
+    assert_eq!(events[0].kind, EventKind::SoundStarted);
+    assert_eq!(events[1].kind, EventKind::DisplayUpdated);
+    assert_eq!(
+        events[2].kind,
+        EventKind::Log {
+            severity: Severity::Info,
+            addr: Some(0x206),
+            message: "Program ended at 0x206. Halted.".into(),
+        }
+    );
+    assert_eq!(events[3].kind, EventKind::Halted(HaltReason::ProgramEnded));
+    assert_eq!(events[4].kind, EventKind::SoundStopped);
+    assert!(vm.take_events().is_empty());
+}
+
+#[test]
+fn test_key_poll_events() {
+    let mut vm = VirtualMachine::new();
+    // 0x200: LD V0, 0x15
+    // 0x202: SKNP V0
+    // 0x204: SKP V0
+    // 0x206: (skipped)
+    // 0x208: LD V1, K
+    vm.load_rom(&[0x60, 0x15, 0xE0, 0xA1, 0xE0, 0x9E, 0x00, 0x00, 0xF1, 0x0A]);
+    vm.record_events(true);
+    vm.press_key(5);
+    for _ in 0..4 {
+        vm.do_cycle();
+    }
+    let kinds: Vec<_> = vm.take_events().into_iter().map(|e| e.kind).collect();
+    assert_eq!(
+        kinds,
+        [
+            EventKind::KeyPolled {
+                key: 5,
+                addr: 0x202
+            },
+            EventKind::KeyPolled {
+                key: 5,
+                addr: 0x204
+            },
+            EventKind::KeyWait { addr: 0x208 },
+        ]
+    );
+    assert!(vm.key_pressed(5));
+    assert!(vm.waiting_for_key());
+}
+
+#[test]
+fn test_sprite_draw_events() {
+    let mut vm = VirtualMachine::new();
+    // 0x200: LD V0, 70
+    // 0x202: DRW V0, V0, 5
+    // 0x204: DRW V0, V0, 5
+    vm.load_rom(&[0x60, 70, 0xD0, 0x05, 0xD0, 0x05]);
+    vm.record_events(true);
+    for _ in 0..3 {
+        vm.do_cycle();
+    }
+    let draws: Vec<_> = vm
+        .take_events()
+        .into_iter()
+        .filter_map(|e| match e.kind {
+            EventKind::SpriteDrawn(draw) => Some(draw),
+            _ => None,
+        })
+        .collect();
+    let first = SpriteDraw {
+        pc: 0x202,
+        x: 6,
+        y: 6,
+        width: 8,
+        height: 5,
+        addr: 0,
+        collision: false,
+    };
+    let second = SpriteDraw {
+        pc: 0x204,
+        collision: true,
+        ..first
+    };
+    assert_eq!(draws, [first, second]);
+}
+
+#[test]
+fn test_beeps() {
+    let mut vm = VirtualMachine::new();
+    // 0x200: LD V0, 3
+    // 0x202: LD ST, V0
+    // 0x204: LD DT, V0
+    // 0x206: LD V1, DT
+    // 0x208: SE V1, 0
+    // 0x20A: JP 0x206
+    // 0x20C: LD ST, V0
+    // 0x20E: JP 0x20E
+    vm.load_rom(&[
+        0x60, 0x03, 0xF0, 0x18, 0xF0, 0x15, 0xF1, 0x07, 0x31, 0x00, 0x12, 0x06, 0xF0, 0x18, 0x12,
+        0x0E,
+    ]);
+    vm.record_events(true);
+    for _ in 0..5 {
+        vm.step_frame();
+    }
+    assert_eq!(
+        Beep::collect(&vm.take_events()),
+        [
+            Beep {
+                start: 0,
+                end: Some(3)
+            },
+            Beep {
+                start: 3,
+                end: None
+            }
+        ]
+    );
+}
@@ -0,0 +1,401 @@
+//! Expressions over the VM state, for watches, conditional breakpoints and cheats.
+//!
+//! The syntax is a small subset of C:
+//!
+//! ```text
+//! v3 == 2 && [i + 1] != 0
+//! (pc >= 0x300) || !dt
+//! ```
+//!
+//! Operands are numbers (decimal, `0x` hexadecimal or `0b` binary), the registers `v0` to `vf`,
+//! `i`, `pc`, `sp`, `dt` and `st`, the counters `cycle` and `frame`, and memory bytes written
+//! as `[addr]`. Operators are the C ones with the C precedence: unary `-`, `!` and `~`,
+//! `*`, `/`, `%`, `+`, `-`, `<<`, `>>`, comparisons, `&`, `^`, `|`, `&&` and `||`.
+//! Comparisons and logical operators give 1 or 0, and any nonzero value counts as true.
+//! Arithmetic wraps around on overflow, and dividing by zero is an error.
+//! Expressions can be nested up to [`MAX_DEPTH`] deep.
+
+use {super::VirtualMachine, std::fmt};
+
+/// The value of an expression.
+pub type Value = i64;
+
+/// An error parsing or evaluating an expression.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExprError {
+    /// The byte offset in the expression the error is at.
+    pub pos: usize,
+    /// What's wrong.
+    pub message: String,
+}
+
+impl fmt::Display for ExprError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "at {}: {}", self.pos, self.message)
+    }
+}
+
+impl std::error::Error for ExprError {}
+
+/// Something an expression can read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Var {
+    V(usize),
+    I,
+    Pc,
+    Sp,
+    Dt,
+    St,
+    Cycle,
+    Frame,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Unary {
+    Neg,
+    Not,
+    BitNot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Binary {
+    Mul,
+    Div,
+    Rem,
+    Add,
+    Sub,
+    Shl,
+    Shr,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    BitAnd,
+    BitXor,
+    BitOr,
+    And,
+    Or,
+}
+
+/// How deeply expressions can be nested, counting operators and brackets, so parsing and
+/// evaluating them can't run out of stack.
+pub const MAX_DEPTH: usize = 64;
+
+// Binary operators from the loosest binding to the tightest
+const LEVELS: &[&[(&str, Binary)]] = &[
+    &[("||", Binary::Or)],
+    &[("&&", Binary::And)],
+    &[("|", Binary::BitOr)],
+    &[("^", Binary::BitXor)],
+    &[("&", Binary::BitAnd)],
+    &[("==", Binary::Eq), ("!=", Binary::Ne)],
+    &[
+        ("<=", Binary::Le),
+        (">=", Binary::Ge),
+        ("<", Binary::Lt),
+        (">", Binary::Gt),
+    ],
+    &[("<<", Binary::Shl), (">>", Binary::Shr)],
+    &[("+", Binary::Add), ("-", Binary::Sub)],
+    &[("*", Binary::Mul), ("/", Binary::Div), ("%", Binary::Rem)],
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Num(Value),
+    Var(Var),
+    Mem(Box<Node>),
+    Unary(Unary, Box<Node>),
+    // The position is that of the operator, for reporting division by zero
+    Binary(Binary, Box<Node>, Box<Node>, usize),
+}
+
+/// A parsed expression, which can be evaluated repeatedly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expr {
+    root: Node,
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+    // How many unary operators and brackets the parser is inside
+    nesting: usize,
+}
+
+impl Parser<'_> {
+    fn err(&self, message: impl Into<String>) -> ExprError {
+        ExprError {
+            pos: self.pos,
+            message: message.into(),
+        }
+    }
+
+    fn skip_space(&mut self) {
+        let rest = &self.src[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_space();
+        let rest = &self.src[self.pos..];
+        // Don't take `<` out of `<<`, or `&` out of `&&`
+        let longer = LEVELS
+            .iter()
+            .flat_map(|level| level.iter())
+            .any(|(op, _)| op.len() > token.len() && op.starts_with(token) && rest.starts_with(op));
+        if rest.starts_with(token) && !longer {
+            self.pos += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), ExprError> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(self.err(format!("expected `{}`", token)))
+        }
+    }
+
+    // Checks the depth of a node, or the nesting of the parser
+    fn check_depth(&self, depth: usize) -> Result<usize, ExprError> {
+        if depth > MAX_DEPTH {
+            Err(self.err(format!("nested more than {} deep", MAX_DEPTH)))
+        } else {
+            Ok(depth)
+        }
+    }
+
+    // Parses what follows an opening unary operator or bracket
+    fn nested<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> Result<T, ExprError> {
+        self.nesting = self.check_depth(self.nesting + 1)?;
+        let result = f(self);
+        self.nesting -= 1;
+        Ok(result)
+    }
+
+    // These return the parsed node along with its depth
+    fn binary(&mut self, level: usize) -> Result<(Node, usize), ExprError> {
+        let Some(ops) = LEVELS.get(level) else {
+            return self.unary();
+        };
+        let (mut lhs, mut depth) = self.binary(level + 1)?;
+        'outer: loop {
+            for &(token, op) in *ops {
+                let pos = self.pos;
+                if self.eat(token) {
+                    let (rhs, rhs_depth) = self.binary(level + 1)?;
+                    lhs = Node::Binary(op, Box::new(lhs), Box::new(rhs), pos);
+                    depth = self.check_depth(depth.max(rhs_depth) + 1)?;
+                    continue 'outer;
+                }
+            }
+            return Ok((lhs, depth));
+        }
+    }
+
+    fn unary(&mut self) -> Result<(Node, usize), ExprError> {
+        for (token, op) in [("-", Unary::Neg), ("!", Unary::Not), ("~", Unary::BitNot)] {
+            if self.eat(token) {
+                let (node, depth) = self.nested(Self::unary)??;
+                return Ok((Node::Unary(op, Box::new(node)), depth + 1));
+            }
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<(Node, usize), ExprError> {
+        if self.eat("(") {
+            let node = self.nested(|p| p.binary(0))??;
+            self.expect(")")?;
+            return Ok(node);
+        }
+        if self.eat("[") {
+            let (node, depth) = self.nested(|p| p.binary(0))??;
+            self.expect("]")?;
+            return Ok((Node::Mem(Box::new(node)), depth + 1));
+        }
+        let start = self.pos;
+        let rest = &self.src[start..];
+        let len = rest
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(rest.len());
+        if len == 0 {
+            return Err(self.err("expected a value"));
+        }
+        let word = rest[..len].to_ascii_lowercase();
+        self.pos += len;
+        let num = |digits: &str, radix| {
+            Value::from_str_radix(digits, radix).map_err(|_| ExprError {
+                pos: start,
+                message: format!("invalid number `{}`", &rest[..len]),
+            })
+        };
+        if word.starts_with(|c: char| c.is_ascii_digit()) {
+            let n = if let Some(hex) = word.strip_prefix("0x") {
+                num(hex, 16)?
+            } else if let Some(bin) = word.strip_prefix("0b") {
+                num(bin, 2)?
+            } else {
+                num(&word, 10)?
+            };
+            return Ok((Node::Num(n), 1));
+        }
+        let var = match word.as_str() {
+            "i" => Var::I,
+            "pc" => Var::Pc,
+            "sp" => Var::Sp,
+            "dt" => Var::Dt,
+            "st" => Var::St,
+            "cycle" => Var::Cycle,
+            "frame" => Var::Frame,
+            _ => match word.strip_prefix('v').map(|x| usize::from_str_radix(x, 16)) {
+                Some(Ok(x)) if x < 16 && word.len() == 2 => Var::V(x),
+                _ => {
+                    return Err(ExprError {
+                        pos: start,
+                        message: format!("unknown name `{}`", &rest[..len]),
+                    });
+                }
+            },
+        };
+        Ok((Node::Var(var), 1))
+    }
+}
+
+/// Parses an expression.
+pub fn parse(src: &str) -> Result<Expr, ExprError> {
+    let mut parser = Parser {
+        src,
+        pos: 0,
+        nesting: 0,
+    };
+    let (root, _) = parser.binary(0)?;
+    parser.skip_space();
+    if parser.pos != src.len() {
+        return Err(parser.err("unexpected input"));
+    }
+    Ok(Expr { root })
+}
+
+/// Parses and evaluates an expression against the state of `vm`.
+pub fn eval(src: &str, vm: &VirtualMachine) -> Result<Value, ExprError> {
+    parse(src)?.eval(vm)
+}
+
+fn eval_node(node: &Node, vm: &VirtualMachine) -> Result<Value, ExprError> {
+    Ok(match node {
+        Node::Num(n) => *n,
+        Node::Var(var) => match *var {
+            Var::V(x) => vm.v[x].0.into(),
+            Var::I => vm.i.into(),
+            Var::Pc => vm.pc.into(),
+            Var::Sp => vm.sp.0.into(),
+            Var::Dt => vm.delay_timer.into(),
+            Var::St => vm.sound_timer.into(),
+            Var::Cycle => vm.cycles as Value,
+            Var::Frame => vm.frames as Value,
+        },
+        // Addresses wrap around memory, like they do for the VM
+        Node::Mem(addr) => {
+            let addr = eval_node(addr, vm)?.rem_euclid(vm.ram.len() as Value);
+            vm.ram[addr as usize].into()
+        }
+        Node::Unary(op, operand) => {
+            let x = eval_node(operand, vm)?;
+            match op {
+                Unary::Neg => x.wrapping_neg(),
+                Unary::Not => Value::from(x == 0),
+                Unary::BitNot => !x,
+            }
+        }
+        Node::Binary(op, lhs, rhs, pos) => {
+            let a = eval_node(lhs, vm)?;
+            // Short-circuit, so `i < 0x1000 && [i]` only reads memory when it makes sense
+            match op {
+                Binary::And if a == 0 => return Ok(0),
+                Binary::Or if a != 0 => return Ok(1),
+                _ => {}
+            }
+            let b = eval_node(rhs, vm)?;
+            match op {
+                Binary::Mul => a.wrapping_mul(b),
+                Binary::Div | Binary::Rem if b == 0 => {
+                    return Err(ExprError {
+                        pos: *pos,
+                        message: "division by zero".into(),
+                    });
+                }
+                Binary::Div => a.wrapping_div(b),
+                Binary::Rem => a.wrapping_rem(b),
+                Binary::Add => a.wrapping_add(b),
+                Binary::Sub => a.wrapping_sub(b),
+                Binary::Shl => a.wrapping_shl(b as u32),
+                Binary::Shr => a.wrapping_shr(b as u32),
+                Binary::Lt => Value::from(a < b),
+                Binary::Le => Value::from(a <= b),
+                Binary::Gt => Value::from(a > b),
+                Binary::Ge => Value::from(a >= b),
+                Binary::Eq => Value::from(a == b),
+                Binary::Ne => Value::from(a != b),
+                Binary::BitAnd => a & b,
+                Binary::BitXor => a ^ b,
+                Binary::BitOr => a | b,
+                Binary::And | Binary::Or => Value::from(b != 0),
+            }
+        }
+    })
+}
+
+impl Expr {
+    /// Evaluates the expression against the state of `vm`.
+    pub fn eval(&self, vm: &VirtualMachine) -> Result<Value, ExprError> {
+        eval_node(&self.root, vm)
+    }
+
+    /// Evaluates the expression as a condition, which holds if the value is nonzero.
+    pub fn holds(&self, vm: &VirtualMachine) -> Result<bool, ExprError> {
+        Ok(self.eval(vm)? != 0)
+    }
+}
+
+#[test]
+fn test_eval() {
+    let mut vm = VirtualMachine::new();
+    // 0x200: LD V3, 2
+    // 0x202: LD I, 0x200
+    vm.load_rom(&[0x63, 0x02, 0xA2, 0x00]);
+    vm.do_cycle();
+    vm.do_cycle();
+    assert_eq!(eval("v3 == 2 && [i + 1] == 0x02", &vm), Ok(1));
+    assert_eq!(eval("1 + 2 * 3 << 1", &vm), Ok(14));
+    assert_eq!(eval("-(pc - 0x204) | ~0b0 & 4", &vm), Ok(4));
+    assert_eq!(eval("V3 <= 1 || !dt", &vm), Ok(1));
+    assert_eq!(eval("cycle % 2", &vm), Ok(0));
+    let err = |src| eval(src, &vm).unwrap_err().to_string();
+    assert_eq!(err("v3 / (i - 0x200)"), "at 3: division by zero");
+    assert_eq!(err("v3 % 0"), "at 3: division by zero");
+    // The one division that overflows wraps like the rest of the arithmetic
+    let min = "(-0x7FFFFFFFFFFFFFFF - 1)";
+    assert_eq!(eval(&format!("{} / -1", min), &vm), Ok(Value::MIN));
+    assert_eq!(eval(&format!("{} % -1", min), &vm), Ok(0));
+    assert_eq!(err("vg + 1"), "at 0: unknown name `vg`");
+    assert_eq!(err("(v1 + 2"), "at 7: expected `)`");
+    assert_eq!(err("v1 2"), "at 3: unexpected input");
+    // Deep nesting is an error rather than a stack overflow
+    let deep = |prefix: &str, infix: &str| {
+        let src = format!("{}1{}", prefix.repeat(100_000), infix.repeat(100_000));
+        parse(&src).unwrap_err().message
+    };
+    for (prefix, infix) in [("-", ""), ("(", ""), ("[", ""), ("", "+1"), ("1-", "")] {
+        assert_eq!(deep(prefix, infix), "nested more than 64 deep");
+    }
+    assert!(parse(&format!("{}1{}", "(".repeat(30), ")".repeat(30))).is_ok());
+    let cond = parse("pc == 0x202").unwrap();
+    assert!(!cond.holds(&vm).unwrap());
+}
@@ -0,0 +1,55 @@
+//! Structured fuzzing support.
+//!
+//! Only compiled in with the `arbitrary` feature.
+//! Everything generated here decodes to a known instruction, so fuzzers spend their time
+//! exercising execution paths rather than the `Unknown` instruction handler.
+
+use {
+    super::{Instruction, MAX_ROM_LEN, decode, opcodes},
+    arbitrary::{Arbitrary, Result, Unstructured},
+};
+
+/// Generates a raw instruction that decodes to a known opcode.
+pub fn arbitrary_opcode(u: &mut Unstructured) -> Result<u16> {
+    let n_specs = opcodes::all().count();
+    let idx = u.int_in_range(0..=n_specs - 1)?;
+    let spec = opcodes::all().nth(idx).unwrap();
+    let operands: u16 = u.arbitrary()?;
+    Ok(spec.pattern | (operands & !spec.mask))
+}
+
+/// Generates a ROM consisting only of known instructions.
+pub fn arbitrary_rom(u: &mut Unstructured) -> Result<Vec<u8>> {
+    let len = u.int_in_range(1..=MAX_ROM_LEN / 2)?;
+    let mut rom = Vec::with_capacity(len * 2);
+    for _ in 0..len {
+        if u.is_empty() {
+            break;
+        }
+        rom.extend_from_slice(&arbitrary_opcode(u)?.to_be_bytes());
+    }
+    Ok(rom)
+}
+
+impl<'a> Arbitrary<'a> for Instruction {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(decode(arbitrary_opcode(u)?))
+    }
+}
+
+#[test]
+fn test_arbitrary_rom_is_known_instructions() {
+    let data: Vec<u8> = (0..4096u32)
+        .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+        .collect();
+    let rom = arbitrary_rom(&mut Unstructured::new(&data)).unwrap();
+    assert!(!rom.is_empty());
+    for word in rom.chunks(2) {
+        let ins = u16::from_be_bytes([word[0], word[1]]);
+        assert!(
+            !matches!(decode(ins), Instruction::Unknown(_)),
+            "{:#x}",
+            ins
+        );
+    }
+}
@@ -0,0 +1,134 @@
+//! Host calls, an opt-in extension that lets programs call into the host.
+//!
+//! When a handler is set, the `0Fnn` instructions, which would otherwise be `SYS` calls into
+//! the machine code of the COSMAC VIP, call the handler with function number `nn` instead.
+//! This lets test programs print to the host console, make assertions or report that they
+//! are done, without the host having to look at the display.
+
+use {
+    super::{HaltReason, VirtualMachine},
+    std::{
+        num::Wrapping,
+        sync::{Arc, Mutex},
+    },
+};
+
+const HOST_CALL_PATTERN: u16 = 0x0F00;
+const HOST_CALL_MASK: u16 = 0xFF00;
+
+/// The context of a host call.
+///
+/// Changes to the registers are written back to the VM when the handler returns.
+#[derive(Debug)]
+pub struct HostCall<'a> {
+    /// The function number, the low byte of the instruction.
+    pub function: u8,
+    /// The address of the instruction.
+    pub pc: u16,
+    /// The general purpose registers.
+    pub v: [u8; 16],
+    /// The I register.
+    pub i: u16,
+    /// The memory of the VM.
+    pub ram: &'a [u8],
+}
+
+impl HostCall<'_> {
+    /// Returns the zero-terminated string that I points to.
+    ///
+    /// Invalid UTF-8 is replaced, and the string ends at the end of memory
+    /// if there is no terminator.
+    pub fn string_at_i(&self) -> String {
+        let bytes = self.ram.get(usize::from(self.i)..).unwrap_or_default();
+        let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        String::from_utf8_lossy(&bytes[..end]).into_owned()
+    }
+}
+
+/// What the VM should do after a host call or a [`SYS` call](crate::SysCall).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostCallResult {
+    /// Continue with the next instruction.
+    Continue,
+    /// Halt with [`HaltReason::Exited`].
+    Exit,
+}
+
+pub(super) type Handler = Arc<Mutex<dyn FnMut(&mut HostCall) -> HostCallResult + Send>>;
+
+impl VirtualMachine {
+    /// Enables host calls, making `0Fnn` instructions call `handler`.
+    ///
+    /// The handler is shared with clones of this VM.
+    pub fn set_host_call_handler(
+        &mut self,
+        handler: impl FnMut(&mut HostCall) -> HostCallResult + Send + 'static,
+    ) {
+        self.host_calls = Some(Arc::new(Mutex::new(handler)));
+    }
+
+    /// Disables host calls, so `0Fnn` instructions are `SYS` calls again.
+    pub fn clear_host_call_handler(&mut self) {
+        self.host_calls = None;
+    }
+
+    // Makes a host call if they are enabled, returning whether it did
+    pub(super) fn host_call(&mut self, ins: u16) -> bool {
+        let Some(handler) = self.host_calls.clone() else {
+            return false;
+        };
+        if ins & HOST_CALL_MASK != HOST_CALL_PATTERN {
+            return false;
+        }
+        let mut call = HostCall {
+            function: ins as u8,
+            pc: self.pc.wrapping_sub(2),
+            v: self.v.map(|v| v.0),
+            i: self.i,
+            ram: &self.ram,
+        };
+        let result = (handler.lock().unwrap())(&mut call);
+        let (v, i) = (call.v, call.i);
+        self.v = v.map(Wrapping);
+        self.i = i;
+        if result == HostCallResult::Exit {
+            self.halt(HaltReason::Exited);
+        }
+        true
+    }
+}
+
+#[test]
+fn test_host_calls() {
+    let mut vm = VirtualMachine::new();
+    // 0x200: LD I, 0x20A
+    // 0x202: (print)
+    // 0x204: LD V0, 7
+    // 0x206: (assert V0 == 7)
+    // 0x208: (exit)
+    // 0x20A: "ok"
+    vm.load_rom(&[
+        0xA2, 0x0A, 0x0F, 0x01, 0x60, 0x07, 0x0F, 0x02, 0x0F, 0x00, b'o', b'k', 0,
+    ]);
+    let output = Arc::new(Mutex::new(Vec::new()));
+    let out = output.clone();
+    vm.set_host_call_handler(move |call| {
+        match call.function {
+            0x00 => return HostCallResult::Exit,
+            0x01 => out.lock().unwrap().push(call.string_at_i()),
+            0x02 => {
+                assert_eq!(call.v[0], 7, "assertion at {:#X}", call.pc);
+                call.v[0xF] = 1;
+            }
+            _ => {}
+        }
+        HostCallResult::Continue
+    });
+    for _ in 0..6 {
+        vm.do_cycle();
+    }
+    assert_eq!(*output.lock().unwrap(), ["ok"]);
+    assert_eq!(vm.v[0xF].0, 1);
+    assert_eq!(vm.halt_reason(), Some(HaltReason::Exited));
+    assert_eq!(vm.pc(), 0x20A);
+}
@@ -0,0 +1,124 @@
+//! Telling when running the VM would change nothing, so frontends can stop running it.
+//!
+//! Programs commonly wait for the delay timer in a loop like
+//!
+//! ```text
+//! loop: LD V0, DT
+//!       SE V0, 0
+//!       JP loop
+//! ```
+//!
+//! Until the next timer tick, every pass through the loop reads the same value. Once the
+//! same `Fx07` read the same nonzero value twice in a row, with nothing but the program
+//! counter changed in between, the program is taken to be waiting for the timer. Every pass
+//! until the next tick then does exactly the same, so running it any further is wasted work.
+
+use {
+    super::VirtualMachine,
+    std::hash::{DefaultHasher, Hash, Hasher},
+};
+
+// The last read of the delay timer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct DelayRead {
+    addr: u16,
+    value: u8,
+    // A hash of the state besides the program counter, after the read
+    state: u64,
+}
+
+impl VirtualMachine {
+    /// Returns whether running the VM before the next call to
+    /// [`VirtualMachine::decrement_timers`] or the next key press would change nothing
+    /// visible: it's halted, waiting for a key, or spinning on the delay timer.
+    ///
+    /// Frontends can stop running the VM while it's idle, instead of burning CPU time.
+    pub fn is_idle(&self) -> bool {
+        self.halt.is_some() || self.waiting_for_key() || self.delay_spin
+    }
+
+    // Called when the delay timer is read by the instruction before pc
+    pub(super) fn note_delay_read(&mut self) {
+        let read = DelayRead {
+            addr: self.pc.wrapping_sub(2),
+            value: self.delay_timer,
+            state: self.spin_state(),
+        };
+        self.delay_spin = read.value != 0 && self.last_delay_read == Some(read);
+        self.last_delay_read = Some(read);
+    }
+
+    // Hashes what a pass through a loop could change, besides the program counter. The
+    // register read is the same after both reads, having been set to the same value.
+    fn spin_state(&self) -> u64 {
+        let mut h = DefaultHasher::new();
+        self.ram.hash(&mut h);
+        self.v.hash(&mut h);
+        (self.i, self.sound_timer, self.sp, self.stack).hash(&mut h);
+        self.display.pixels.hash(&mut h);
+        (self.high_res, self.rng.state).hash(&mut h);
+        #[cfg(feature = "hires")]
+        self.two_page.hash(&mut h);
+        #[cfg(feature = "chip8x")]
+        (self.colors.background, self.colors.zones).hash(&mut h);
+        h.finish()
+    }
+
+    // Called whenever the delay timer changes
+    pub(super) fn reset_delay_spin(&mut self) {
+        self.delay_spin = false;
+        self.last_delay_read = None;
+    }
+}
+
+#[test]
+fn test_delay_loop_is_idle() {
+    let mut vm = VirtualMachine::new();
+    // 0x200: LD V0, 3
+    // 0x202: LD DT, V0
+    // 0x204: LD V1, DT
+    // 0x206: SE V1, 0
+    // 0x208: JP 0x204
+    // 0x20A: JP 0x20A
+    vm.load_rom(&[
+        0x60, 0x03, 0xF0, 0x15, 0xF1, 0x07, 0x31, 0x00, 0x12, 0x04, 0x12, 0x0A,
+    ]);
+    for _ in 0..5 {
+        vm.do_cycle();
+    }
+    assert!(!vm.is_idle());
+    // The second read of the same value
+    vm.do_cycle();
+    assert!(vm.is_idle());
+    let mut ticks = 0;
+    while vm.halt_reason().is_none() {
+        if vm.is_idle() {
+            vm.decrement_timers();
+            ticks += 1;
+            assert!(!vm.is_idle());
+        }
+        vm.do_cycle();
+    }
+    assert_eq!(ticks, 3);
+    assert!(vm.is_idle());
+}
+
+#[test]
+fn test_busy_loop_is_not_idle() {
+    let mut vm = VirtualMachine::new();
+    // 0x200: LD V0, 3
+    // 0x202: LD DT, V0
+    // 0x204: LD V1, DT
+    // 0x206: ADD V2, 1
+    // 0x208: SE V1, 0
+    // 0x20A: JP 0x204
+    // 0x20C: JP 0x20C
+    vm.load_rom(&[
+        0x60, 0x03, 0xF0, 0x15, 0xF1, 0x07, 0x72, 0x01, 0x31, 0x00, 0x12, 0x04, 0x12, 0x0C,
+    ]);
+    // Counting in V2 between the reads does something every pass
+    for _ in 0..50 {
+        vm.do_cycle();
+        assert!(!vm.is_idle());
+    }
+}
@@ -0,0 +1,195 @@
+//! Exchange of memory contents in the Intel HEX format.
+//!
+//! Intel HEX is a text format understood by most hex editors, assemblers and EPROM
+//! programmers. Every line is a record of the form `:LLAAAATT<data>CC`, with the data length,
+//! the address, the record type, the data and a checksum. Only 16-bit addresses are
+//! meaningful here, so extended address records must be zero.
+
+use {
+    super::{MEM_SIZE, VirtualMachine},
+    std::{fmt, ops::Range},
+};
+
+const DATA: u8 = 0x00;
+const END_OF_FILE: u8 = 0x01;
+const EXTENDED_SEGMENT_ADDRESS: u8 = 0x02;
+const START_SEGMENT_ADDRESS: u8 = 0x03;
+const EXTENDED_LINEAR_ADDRESS: u8 = 0x04;
+const START_LINEAR_ADDRESS: u8 = 0x05;
+
+/// Data bytes per record written by [`encode`].
+const RECORD_LEN: usize = 16;
+
+/// An error in Intel HEX text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IntelHexError {
+    /// The line the error is on, starting from 1.
+    pub line: usize,
+    /// What's wrong with it.
+    pub message: String,
+}
+
+impl fmt::Display for IntelHexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for IntelHexError {}
+
+fn record(out: &mut String, addr: u16, kind: u8, data: &[u8]) {
+    let mut bytes = vec![data.len() as u8];
+    bytes.extend_from_slice(&addr.to_be_bytes());
+    bytes.push(kind);
+    bytes.extend_from_slice(data);
+    let sum = bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+    bytes.push(sum.wrapping_neg());
+    out.push(':');
+    for b in bytes {
+        out.push_str(&format!("{:02X}", b));
+    }
+    out.push('\n');
+}
+
+/// Encodes `data`, which starts at address `start`, as Intel HEX.
+pub fn encode(data: &[u8], start: u16) -> String {
+    let mut out = String::new();
+    for (i, chunk) in data.chunks(RECORD_LEN).enumerate() {
+        record(
+            &mut out,
+            start.wrapping_add((i * RECORD_LEN) as u16),
+            DATA,
+            chunk,
+        );
+    }
+    record(&mut out, 0, END_OF_FILE, &[]);
+    out
+}
+
+/// Decodes Intel HEX text into blocks of data and their addresses.
+///
+/// Blank lines are skipped, and decoding stops at the end of file record.
+pub fn decode(text: &str) -> Result<Vec<(u16, Vec<u8>)>, IntelHexError> {
+    Ok(data_records(text)?
+        .into_iter()
+        .map(|(_, addr, data)| (addr, data))
+        .collect())
+}
+
+// Decodes the data records, along with the lines they're on
+fn data_records(text: &str) -> Result<Vec<(usize, u16, Vec<u8>)>, IntelHexError> {
+    let mut blocks = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let err = |message: &str| IntelHexError {
+            line: i + 1,
+            message: message.to_owned(),
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let digits = line
+            .strip_prefix(':')
+            .ok_or_else(|| err("record doesn't start with `:`"))?;
+        if digits.len() % 2 != 0 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(err("invalid hex digits"));
+        }
+        let bytes: Vec<u8> = (0..digits.len())
+            .step_by(2)
+            .map(|at| u8::from_str_radix(&digits[at..at + 2], 16).unwrap())
+            .collect();
+        if bytes.len() < 5 || bytes.len() != usize::from(bytes[0]) + 5 {
+            return Err(err("record length doesn't match its contents"));
+        }
+        if bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) != 0 {
+            return Err(err("checksum mismatch"));
+        }
+        let addr = u16::from_be_bytes([bytes[1], bytes[2]]);
+        let data = &bytes[4..bytes.len() - 1];
+        match bytes[3] {
+            DATA => blocks.push((i + 1, addr, data.to_vec())),
+            END_OF_FILE => break,
+            EXTENDED_SEGMENT_ADDRESS | EXTENDED_LINEAR_ADDRESS => {
+                if data.iter().any(|&b| b != 0) {
+                    return Err(err("addresses beyond 64K aren't supported"));
+                }
+            }
+            START_SEGMENT_ADDRESS | START_LINEAR_ADDRESS => {}
+            kind => return Err(err(&format!("unknown record type {:02X}", kind))),
+        }
+    }
+    Ok(blocks)
+}
+
+impl VirtualMachine {
+    /// Returns the contents of memory.
+    pub fn memory(&self) -> &[u8] {
+        &self.ram
+    }
+
+    /// Writes `data` to memory starting at `addr`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the data doesn't fit in memory.
+    pub fn write_memory(&mut self, addr: u16, data: &[u8]) {
+        let start = usize::from(addr);
+        self.ram[start..start + data.len()].copy_from_slice(data);
+    }
+
+    /// Exports the memory in `range` as Intel HEX.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range is out of memory bounds.
+    pub fn export_intel_hex(&self, range: Range<u16>) -> String {
+        encode(
+            &self.ram[usize::from(range.start)..usize::from(range.end)],
+            range.start,
+        )
+    }
+
+    /// Imports Intel HEX into memory.
+    ///
+    /// On error, memory is left unchanged.
+    pub fn import_intel_hex(&mut self, text: &str) -> Result<(), IntelHexError> {
+        let blocks = data_records(text)?;
+        for &(line, addr, ref data) in &blocks {
+            if usize::from(addr) + data.len() > MEM_SIZE {
+                return Err(IntelHexError {
+                    line,
+                    message: format!("data at {:#05X} doesn't fit in memory", addr),
+                });
+            }
+        }
+        for (_, addr, data) in blocks {
+            self.write_memory(addr, &data);
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_intel_hex_roundtrip() {
+    let mut vm = VirtualMachine::new();
+    vm.load_rom(&(0..40).collect::<Vec<u8>>());
+    let hex = vm.export_intel_hex(0x200..0x228);
+    assert!(hex.starts_with(":10020000000102030405060708090A0B0C0D0E0F76\n"));
+    assert!(hex.ends_with(":00000001FF\n"));
+    let mut other = VirtualMachine::new();
+    other.import_intel_hex(&hex).unwrap();
+    assert_eq!(other.memory()[0x200..0x228], vm.memory()[0x200..0x228]);
+
+    let corrupt = hex.replacen("0F76", "0F77", 1);
+    assert_eq!(
+        other.import_intel_hex(&corrupt).unwrap_err().to_string(),
+        "line 1: checksum mismatch"
+    );
+    assert_eq!(
+        other
+            .import_intel_hex("\n:02FFFF00AABB9B")
+            .unwrap_err()
+            .line,
+        2
+    );
+}
@@ -0,0 +1,193 @@
+use super::VirtualMachine;
+
+/// Synthetic input that is played back by the VM over several frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputMacro {
+    /// Hold `key` down for `frames` frames, or not at all if `frames` is 0.
+    Hold {
+        /// The key to hold.
+        key: u8,
+        /// For how many frames.
+        frames: u32,
+    },
+    /// Tap `key` every `every` frames, `times` times in total, or forever if `None`.
+    ///
+    /// A tap holds the key down for a single frame, and the key is released for at least one
+    /// frame before the next, so an `every` below 2 counts as 2.
+    Tap {
+        /// The key to tap.
+        key: u8,
+        /// Number of frames between taps.
+        every: u32,
+        /// Number of taps.
+        times: Option<u32>,
+    },
+}
+
+impl InputMacro {
+    fn key(self) -> u8 {
+        let (InputMacro::Hold { key, .. } | InputMacro::Tap { key, .. }) = self;
+        key
+    }
+}
+
+#[derive(Clone)]
+pub(super) struct ActiveMacro {
+    input: InputMacro,
+    elapsed: u32,
+    // Whether the macro pressed the key, rather than finding it held already
+    pressed: bool,
+}
+
+impl VirtualMachine {
+    /// Schedules synthetic input, starting with the current frame.
+    ///
+    /// Macros advance on every frame completed by [`VirtualMachine::step_frame`]
+    /// or [`VirtualMachine::step_micros`]. They only release keys they pressed, so a key
+    /// that was already held stays held.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the key is above 15, like [`VirtualMachine::press_key`].
+    pub fn schedule_input(&mut self, input: InputMacro) {
+        assert!(input.key() <= 15);
+        if let InputMacro::Hold { frames: 0, .. } | InputMacro::Tap { times: Some(0), .. } = input {
+            return;
+        }
+        let mut m = ActiveMacro {
+            input,
+            elapsed: 0,
+            pressed: false,
+        };
+        self.press_macro_key(&mut m);
+        self.input_macros.push(m);
+    }
+
+    /// Cancels all scheduled input, releasing the keys that are held by it.
+    pub fn clear_input_macros(&mut self) {
+        for mut m in std::mem::take(&mut self.input_macros) {
+            self.release_macro_key(&mut m);
+        }
+    }
+
+    fn press_macro_key(&mut self, m: &mut ActiveMacro) {
+        let key = m.input.key();
+        m.pressed = !self.keys[usize::from(key)];
+        if m.pressed {
+            self.press_key(key);
+        }
+    }
+
+    fn release_macro_key(&mut self, m: &mut ActiveMacro) {
+        if std::mem::take(&mut m.pressed) {
+            self.release_key(m.input.key());
+        }
+    }
+
+    pub(super) fn advance_input_macros(&mut self) {
+        let mut macros = std::mem::take(&mut self.input_macros);
+        macros.retain_mut(|m| {
+            m.elapsed += 1;
+            match m.input {
+                InputMacro::Hold { frames, .. } => {
+                    if m.elapsed >= frames {
+                        self.release_macro_key(m);
+                        return false;
+                    }
+                    true
+                }
+                InputMacro::Tap { every, times, .. } => {
+                    // Release after the frame of the tap, press again when the next one is due
+                    self.release_macro_key(m);
+                    let every = every.max(2);
+                    let taps_done = m.elapsed.div_ceil(every);
+                    if times.is_some_and(|times| taps_done >= times) {
+                        return false;
+                    }
+                    if m.elapsed % every == 0 {
+                        self.press_macro_key(m);
+                    }
+                    true
+                }
+            }
+        });
+        // Macros scheduled in the meantime are kept as well
+        macros.append(&mut self.input_macros);
+        self.input_macros = macros;
+    }
+}
+
+#[test]
+fn test_input_macros() {
+    let mut vm = VirtualMachine::new();
+    vm.schedule_input(InputMacro::Hold { key: 5, frames: 2 });
+    vm.schedule_input(InputMacro::Tap {
+        key: 0xA,
+        every: 3,
+        times: Some(2),
+    });
+    let mut history = Vec::new();
+    for _ in 0..8 {
+        history.push((vm.keys[5], vm.keys[0xA]));
+        vm.step_frame();
+    }
+    assert_eq!(
+        history,
+        [
+            (true, true),
+            (true, false),
+            (false, false),
+            (false, true),
+            (false, false),
+            (false, false),
+            (false, false),
+            (false, false),
+        ]
+    );
+}
+
+#[test]
+fn test_tap_edge_cases() {
+    let mut vm = VirtualMachine::new();
+    // Tapping every frame still releases the key in between
+    vm.schedule_input(InputMacro::Tap {
+        key: 1,
+        every: 1,
+        times: None,
+    });
+    // No taps at all
+    vm.schedule_input(InputMacro::Tap {
+        key: 2,
+        every: 3,
+        times: Some(0),
+    });
+    let mut history = Vec::new();
+    for _ in 0..4 {
+        history.push((vm.keys[1], vm.keys[2]));
+        vm.step_frame();
+    }
+    assert_eq!(
+        history,
+        [(true, false), (false, false), (true, false), (false, false)]
+    );
+}
+
+#[test]
+fn test_macros_leave_held_keys_alone() {
+    let mut vm = VirtualMachine::new();
+    // The player holds 5 through a macro holding it too
+    vm.press_key(5);
+    vm.schedule_input(InputMacro::Hold { key: 5, frames: 1 });
+    // Holding for no frames doesn't press the key
+    vm.schedule_input(InputMacro::Hold { key: 6, frames: 0 });
+    assert!(!vm.keys[6]);
+    vm.step_frame();
+    assert!(vm.keys[5]);
+    vm.schedule_input(InputMacro::Tap {
+        key: 5,
+        every: 2,
+        times: None,
+    });
+    vm.clear_input_macros();
+    assert!(vm.keys[5]);
+}
@@ -0,0 +1,165 @@
+//! Mapping host keyboards onto the hexadecimal keypad.
+//!
+//! The keypad is conventionally mapped onto the 4x4 block of keys below and including
+//! `1 2 3 4`, so that the physical arrangement of the keys is kept.
+
+use {
+    super::Rotation,
+    std::{fmt, str::FromStr},
+};
+
+/// The keys of the hex keypad, row by row, as arranged on the COSMAC VIP.
+pub const KEYPAD: [[u8; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+
+/// Returns the key at `row` and `col` of the [`KEYPAD`], counting from the top left.
+pub fn key_at(row: usize, col: usize) -> Option<u8> {
+    KEYPAD.get(row)?.get(col).copied()
+}
+
+/// Returns the row and column of `key` on the [`KEYPAD`].
+pub fn key_position(key: u8) -> Option<(usize, usize)> {
+    (0..4)
+        .flat_map(|row| (0..4).map(move |col| (row, col)))
+        .find(|&(row, col)| KEYPAD[row][col] == key)
+}
+
+/// A host keyboard layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Layout {
+    /// `1234` / `QWER` / `ASDF` / `ZXCV`
+    #[default]
+    Qwerty,
+    /// `1234` / `QWER` / `ASDF` / `YXCV`
+    Qwertz,
+    /// `1234` / `AZER` / `QSDF` / `WXCV`
+    Azerty,
+}
+
+impl Layout {
+    /// All supported layouts.
+    pub const ALL: [Layout; 3] = [Layout::Qwerty, Layout::Qwertz, Layout::Azerty];
+
+    /// The host keys of the 4x4 block, row by row.
+    pub fn keys(self) -> [[char; 4]; 4] {
+        let [row2, row3, row4] = match self {
+            Layout::Qwerty => [
+                ['q', 'w', 'e', 'r'],
+                ['a', 's', 'd', 'f'],
+                ['z', 'x', 'c', 'v'],
+            ],
+            Layout::Qwertz => [
+                ['q', 'w', 'e', 'r'],
+                ['a', 's', 'd', 'f'],
+                ['y', 'x', 'c', 'v'],
+            ],
+            Layout::Azerty => [
+                ['a', 'z', 'e', 'r'],
+                ['q', 's', 'd', 'f'],
+                ['w', 'x', 'c', 'v'],
+            ],
+        };
+        [['1', '2', '3', '4'], row2, row3, row4]
+    }
+
+    /// Maps a host key to a key on the hex keypad.
+    ///
+    /// Letters are matched case-insensitively.
+    pub fn hex_key(self, host_key: char) -> Option<u8> {
+        let host_key = host_key.to_ascii_lowercase();
+        let keys = self.keys();
+        (0..4)
+            .flat_map(|row| (0..4).map(move |col| (row, col)))
+            .find(|&(row, col)| keys[row][col] == host_key)
+            .map(|(row, col)| KEYPAD[row][col])
+    }
+
+    /// Maps a key on the hex keypad to the host key it's bound to.
+    pub fn host_key(self, hex_key: u8) -> Option<char> {
+        let (row, col) = key_position(hex_key)?;
+        Some(self.keys()[row][col])
+    }
+}
+
+impl fmt::Display for Layout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Layout::Qwerty => "qwerty",
+            Layout::Qwertz => "qwertz",
+            Layout::Azerty => "azerty",
+        })
+    }
+}
+
+impl FromStr for Layout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Layout::ALL
+            .into_iter()
+            .find(|layout| layout.to_string().eq_ignore_ascii_case(s))
+            .ok_or_else(|| format!("Unknown keyboard layout: {}", s))
+    }
+}
+
+/// Maps a key pressed while the display is rotated to the key the program expects.
+///
+/// Games conventionally use `2`, `4`, `6` and `8` as directions, around `5`. The 3x3 block
+/// of `1` to `9` is turned against the rotation, so that the key pointing up on screen
+/// is the one the program takes as up. Other keys are left alone.
+pub fn rotate_key(key: u8, rotation: Rotation) -> u8 {
+    if !(1..=9).contains(&key) {
+        return key;
+    }
+    let (dx, dy) = (((key - 1) % 3) as i8 - 1, ((key - 1) / 3) as i8 - 1);
+    let (dx, dy) = match rotation {
+        Rotation::None => (dx, dy),
+        Rotation::Cw90 => (dy, -dx),
+        Rotation::Cw180 => (-dx, -dy),
+        Rotation::Cw270 => (-dy, dx),
+    };
+    ((dy + 1) * 3 + dx + 2) as u8
+}
+
+#[test]
+fn test_rotate_key() {
+    // With the display turned clockwise, up on screen is left for the program
+    assert_eq!(rotate_key(0x2, Rotation::Cw90), 0x4);
+    assert_eq!(rotate_key(0x6, Rotation::Cw90), 0x2);
+    assert_eq!(rotate_key(0x1, Rotation::Cw180), 0x9);
+    assert_eq!(rotate_key(0x8, Rotation::Cw270), 0x4);
+    assert_eq!(rotate_key(0x5, Rotation::Cw90), 0x5);
+    assert_eq!(rotate_key(0xA, Rotation::Cw90), 0xA);
+    for rotation in Rotation::ALL {
+        let mut keys: Vec<u8> = (0..16).map(|key| rotate_key(key, rotation)).collect();
+        keys.sort();
+        assert_eq!(keys, (0..16).collect::<Vec<_>>());
+    }
+}
+
+#[test]
+fn test_qwerty_mapping() {
+    let layout = Layout::Qwerty;
+    assert_eq!(layout.hex_key('4'), Some(0xC));
+    assert_eq!(layout.hex_key('X'), Some(0x0));
+    assert_eq!(layout.hex_key('p'), None);
+    for key in 0..16 {
+        assert_eq!(layout.hex_key(layout.host_key(key).unwrap()), Some(key));
+    }
+}
+
+#[test]
+fn test_keypad_positions() {
+    assert_eq!(key_at(0, 3), Some(0xC));
+    assert_eq!(key_at(3, 1), Some(0x0));
+    assert_eq!(key_at(4, 0), None);
+    for key in 0..16 {
+        let (row, col) = key_position(key).unwrap();
+        assert_eq!(key_at(row, col), Some(key));
+    }
+    assert_eq!(key_position(16), None);
+}
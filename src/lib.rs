@@ -7,9 +7,79 @@
 
 #![warn(missing_docs, trivial_casts, trivial_numeric_casts)]
 
-use std::{fmt::Write, num::Wrapping};
+pub use analysis::CompatibilityReport;
+pub use capabilities::Capabilities;
+pub use diff::Difference;
+pub use display::{FrameBuffer, Resolution, Rotation};
+pub use events::{Beep, Event, EventKind, SpriteDraw};
+pub use hostcall::{HostCall, HostCallResult};
+pub use input::InputMacro;
+pub use mmio::MemoryMappedIo;
+pub use overrides::OverrideAction;
+pub use pacing::{DEFAULT_IPS, FrameEvents, FrameView};
+pub use palette::Palette;
+pub use quirks::Quirks;
+pub use savestate::StateError;
+pub use sink::{AudioEvent, AudioSink, DisplaySink, LogSink, MemoryLog, Severity, StderrLog};
+pub use sys::SysCall;
+pub use variant::Variant;
 
+use {opcodes::Operands, std::num::Wrapping};
+
+pub mod analysis;
+pub mod batch;
+mod bookmarks;
+pub mod boot;
+pub mod bot;
+mod capabilities;
+#[cfg(feature = "chip8x")]
+pub mod chip8x;
+mod diff;
+mod display;
+mod events;
+pub mod expr;
+#[cfg(feature = "arbitrary")]
+pub mod fuzz;
+mod hostcall;
+mod idle;
+pub mod ihex;
+mod input;
+pub mod keymap;
+#[cfg(feature = "megachip")]
+pub mod megachip;
+mod mmio;
+#[cfg(feature = "octo")]
+pub mod octo;
+pub mod opcodes;
 mod ops;
+mod overrides;
+mod pacing;
+pub mod palette;
+pub mod perf;
+pub mod pool;
+pub mod present;
+pub mod profiler;
+pub mod quirks;
+pub mod recorder;
+pub mod reference;
+mod rng;
+pub mod rom;
+pub mod romgen;
+pub mod savestate;
+pub mod scenario;
+#[cfg(feature = "schip")]
+mod schip;
+pub mod shared;
+mod sink;
+pub mod smoke;
+pub mod solver;
+mod sys;
+pub mod testrom;
+#[cfg(feature = "hires")]
+pub mod twopage;
+pub mod variant;
+#[cfg(feature = "xochip")]
+mod xochip;
 
 /// 4 bit value extracted from an instruction.
 pub type Nibble = u8;
@@ -21,6 +91,8 @@ pub type Semiword = u16;
 #[allow(missing_docs)]
 #[derive(Debug)]
 /// A CHIP-8 instruction.
+///
+/// Opcodes that aren't understood decode to `Unknown`, with the raw opcode encountered.
 pub enum Instruction {
     ClearDisplay,
     Return,
@@ -43,6 +115,7 @@ pub enum Instruction {
     SetVxToVyShl1 { x: Nibble, y: Nibble },
     SkipNextVxNeVy { x: Nibble, y: Nibble },
     SetI { to: Semiword },
+    JumpToAddrPlusV0 { addr: Semiword },
     SetVxRandAnd { x: Nibble, and: Byte },
     DisplaySprite { x: Nibble, y: Nibble, n: Nibble },
     SkipNextKeyVxNotPressed { x: Nibble },
@@ -53,83 +126,52 @@ pub enum Instruction {
     SetSoundTimer { x: Nibble },
     AddVxToI { x: Nibble },
     SetIToLocOfDigitVx { x: Nibble },
+    SetIToLocOfBigDigitVx { x: Nibble },
     StoreBcdOfVxToI { x: Nibble },
     CopyV0ThroughVxToMem { x: Nibble },
     ReadV0ThroughVxFromMem { x: Nibble },
-    Unknown,
+    ScrollDown { n: Nibble },
+    ScrollRight,
+    ScrollLeft,
+    Exit,
+    DisableHighRes,
+    EnableHighRes,
+    CycleBackground,
+    AddVxVyNibbles { x: Nibble, y: Nibble },
+    SetColorZones { x: Nibble, y: Nibble },
+    SetColorRows { x: Nibble, y: Nibble, n: Nibble },
+    SkipNextKey2VxPressed { x: Nibble },
+    SkipNextKey2VxNotPressed { x: Nibble },
+    SelectPlanes { n: Nibble },
+    MegaOff,
+    MegaOn,
+    SetILong { hi: Byte },
+    LoadPalette { n: Byte },
+    SetSpriteWidth { width: Byte },
+    SetSpriteHeight { height: Byte },
+    SetScreenAlpha { alpha: Byte },
+    PlayDigitizedSound { n: Nibble },
+    StopDigitizedSound,
+    SetBlendMode { mode: Nibble },
+    SetCollisionColor { index: Byte },
+    ScrollUp { n: Nibble },
+    Unknown(u16),
 }
 
 /// Decode a raw instruction into an Instruction structure.
 pub fn decode(ins: u16) -> Instruction {
-    use self::Instruction::*;
-
-    // A 12-bit value, the lowest 12 bits of the instruction
-    let nnn: Semiword = ins & 0x0FFF;
-    // A 4-bit value, the lowest 4 bits of the instruction
-    let n: Nibble = (ins & 0x000F) as Nibble;
-    // A 4-bit value, the lower 4 bits of the high byte of the instruction
-    let x: Nibble = ((ins & 0x0F00) >> 8) as Nibble;
-    // A 4-bit value, the upper 4 bits of the low byte of the instruction
-    let y: Nibble = ((ins & 0x00F0) >> 4) as Nibble;
-    // kk or byte - An 8-bit value, the lowest 8 bits of the instruction
-    let kk: Byte = (ins & 0x00FF) as Nibble;
-
-    match (ins & 0xF000) >> 12 {
-        0x0 => match nnn {
-            0x0E0 => ClearDisplay,
-            0x0EE => Return,
-            _ => JumpToSysRoutine { addr: nnn },
-        },
-        0x1 => JumpToAddress { addr: nnn },
-        0x2 => CallSubroutine { addr: nnn },
-        0x3 => SkipNextVxEq { x, cmp_with: kk },
-        0x4 => SkipNextVxNe { x, cmp_with: kk },
-        0x5 => match n {
-            0x0 => SkipNextVxEqVy { x, y },
-            _ => Unknown,
-        },
-        0x6 => SetVxByte { x, to: kk },
-        0x7 => AddVxByte { x, rhs: kk },
-        0x8 => match n {
-            0x0 => SetVxToVy { x, y },
-            0x1 => SetVxToVxOrVy { x, y },
-            0x2 => SetVxToVxAndVy { x, y },
-            0x3 => SetVxToVxXorVy { x, y },
-            0x4 => AddVxVy { x, y },
-            0x5 => SubVxVy { x, y },
-            0x6 => SetVxToVyShr1 { x, y },
-            0x7 => SubnVxVy { x, y },
-            0xE => SetVxToVyShl1 { x, y },
-            _ => Unknown,
-        },
-        0x9 => match n {
-            0x0 => SkipNextVxNeVy { x, y },
-            _ => Unknown,
-        },
-        0xA => SetI { to: nnn },
-        0xC => SetVxRandAnd { x, and: kk },
-        0xD => DisplaySprite { x, y, n },
-        0xE => match kk {
-            0xA1 => SkipNextKeyVxNotPressed { x },
-            0x9E => SkipNextKeyVxPressed { x },
-            _ => Unknown,
-        },
-        0xF => match kk {
-            0x07 => SetVxToDelayTimer { x },
-            0x0A => WaitForKeypressStoreInVx { x },
-            0x15 => SetDelayTimer { x },
-            0x18 => SetSoundTimer { x },
-            0x1E => AddVxToI { x },
-            0x29 => SetIToLocOfDigitVx { x },
-            0x33 => StoreBcdOfVxToI { x },
-            0x55 => CopyV0ThroughVxToMem { x },
-            0x65 => ReadV0ThroughVxFromMem { x },
-            _ => Unknown,
-        },
-        _ => Unknown,
+    match opcodes::lookup(ins) {
+        Some(spec) => (spec.decode)(Operands::new(ins)),
+        None => Instruction::Unknown(ins),
     }
 }
 
+#[test]
+fn test_decode_unknown() {
+    // 8XYE is the last of the 8XY_ instructions
+    assert!(matches!(decode(0x812F), Instruction::Unknown(0x812F)));
+}
+
 const START_ADDR: u16 = 0x200;
 /// The memory size of the Chip-8 virtual machine.
 pub const MEM_SIZE: usize = 4096;
@@ -138,11 +180,19 @@ pub const MEM_SIZE: usize = 4096;
 /// It doesn't make sense to feed the VM something larger than this, so you can use this
 /// to .e.g. reject files that are larger than this when loading the ROM.
 pub const MAX_ROM_LEN: usize = MEM_SIZE - START_ADDR as usize;
-/// The width of the Chip8's display in pixels.
+/// The width of the Chip8's display in pixels, in low resolution.
 pub const DISPLAY_WIDTH: usize = 64;
-/// The height of the Chip8's display in pixels.
+/// The height of the Chip8's display in pixels, in low resolution.
 pub const DISPLAY_HEIGHT: usize = 32;
 
+/// The address of the 4x5 pixel hex digits, 5 bytes each, that `LD F, Vx` points I to.
+pub const FONT_ADDR: u16 = 0;
+/// The address of the 8x10 pixel hex digits, 10 bytes each, that `LD HF, Vx` points I to.
+pub const BIG_FONT_ADDR: u16 = FONT_ADDR + FONTSET.len() as u16;
+/// The memory the fonts are kept in. Programs are loaded above it, but nothing stops them
+/// from overwriting it.
+pub const FONT_MEMORY: std::ops::Range<u16> = FONT_ADDR..BIG_FONT_ADDR + BIG_FONTSET.len() as u16;
+
 static FONTSET: [u8; 5 * 0x10] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
     0x20, 0x60, 0x20, 0x20, 0x70, // 1
@@ -162,6 +212,59 @@ static FONTSET: [u8; 5 * 0x10] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
+// The SUPER-CHIP only had digits 0-9, the letters are Octo's
+static BIG_FONTSET: [u8; 10 * 0x10] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+// Puts the fonts into a fresh 4 KiB of RAM
+fn load_fonts(ram: &mut [u8]) {
+    ram[usize::from(FONT_ADDR)..][..FONTSET.len()].copy_from_slice(&FONTSET);
+    ram[usize::from(BIG_FONT_ADDR)..][..BIG_FONTSET.len()].copy_from_slice(&BIG_FONTSET);
+}
+
+/// The reason the virtual machine stopped executing instructions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HaltReason {
+    /// The program jumped to itself, which is how a lot of programs signal that they are done.
+    ProgramEnded,
+    /// The program counter went out of memory bounds.
+    OutOfBounds,
+    /// An instruction read or wrote memory past the end.
+    MemoryOutOfBounds,
+    /// The program returned from a subroutine without calling one.
+    StackUnderflow,
+    /// The program asked to exit, with the SUPER-CHIP `EXIT` instruction or a host call.
+    Exited,
+}
+
+impl HaltReason {
+    /// Returns whether the program went wrong, rather than coming to an end on purpose.
+    pub fn is_error(self) -> bool {
+        match self {
+            HaltReason::ProgramEnded | HaltReason::Exited => false,
+            HaltReason::OutOfBounds
+            | HaltReason::MemoryOutOfBounds
+            | HaltReason::StackUnderflow => true,
+        }
+    }
+}
+
 #[derive(Clone, Copy)]
 struct KeypressWait {
     wait: bool,
@@ -179,13 +282,48 @@ pub struct VirtualMachine {
     pc: u16,
     sp: Wrapping<u8>,
     stack: [u16; 16],
-    display: [u8; DISPLAY_WIDTH * DISPLAY_HEIGHT],
+    display: FrameBuffer,
     display_updated: bool,
     keys: [bool; 16],
+    // The second keypad of CHIP-8X
+    #[cfg(feature = "chip8x")]
+    keys2: [bool; 16],
     keypress_wait: KeypressWait,
-    halt: bool,
-    /// Message log
-    pub log: String,
+    halt: Option<HaltReason>,
+    last_delay_read: Option<idle::DelayRead>,
+    // Whether the program is waiting for the delay timer to change
+    delay_spin: bool,
+    // Whether the SUPER-CHIP high resolution mode is on
+    high_res: bool,
+    // The XO-CHIP planes drawn to, as a bitmask
+    planes: u8,
+    #[cfg(feature = "hires")]
+    two_page: bool,
+    #[cfg(feature = "chip8x")]
+    colors: chip8x::ColorMap,
+    #[cfg(feature = "megachip")]
+    mega: megachip::Screen,
+    sound_on: bool,
+    pacer: pacing::Pacer,
+    input_macros: Vec<input::ActiveMacro>,
+    quirks: Quirks,
+    // The newest extension whose instructions are understood
+    extension: opcodes::Extension,
+    rng: rng::Rng,
+    cycles: u64,
+    frames: u64,
+    events: Option<Vec<Event>>,
+    profile: Option<Box<profiler::Profile>>,
+    perf: Option<Box<perf::Perf>>,
+    sinks: sink::Sinks,
+    // The display at the end of the last two frames, for interpolating between them
+    last_frame: FrameBuffer,
+    prev_frame: FrameBuffer,
+    bookmarks: Vec<bookmarks::Bookmark>,
+    overrides: Vec<overrides::OpcodeOverride>,
+    host_calls: Option<hostcall::Handler>,
+    sys_calls: Option<sys::Handler>,
+    io: Vec<mmio::Mapping>,
 }
 
 impl Default for VirtualMachine {
@@ -206,93 +344,156 @@ impl VirtualMachine {
             pc: START_ADDR,
             sp: Wrapping(0),
             stack: [0; 16],
-            display: [0; DISPLAY_WIDTH * DISPLAY_HEIGHT],
+            display: FrameBuffer::default(),
             display_updated: false,
             keys: [false; 16],
+            #[cfg(feature = "chip8x")]
+            keys2: [false; 16],
             keypress_wait: KeypressWait { wait: false, vx: 0 },
-            halt: false,
-            log: String::new(),
+            halt: None,
+            last_delay_read: None,
+            delay_spin: false,
+            high_res: false,
+            planes: 1,
+            #[cfg(feature = "hires")]
+            two_page: false,
+            #[cfg(feature = "chip8x")]
+            colors: chip8x::ColorMap::default(),
+            #[cfg(feature = "megachip")]
+            mega: megachip::Screen::default(),
+            sound_on: false,
+            pacer: pacing::Pacer::default(),
+            input_macros: Vec::new(),
+            quirks: Quirks::default(),
+            extension: opcodes::Extension::XoChip,
+            rng: rng::Rng::new(rand::random()),
+            cycles: 0,
+            frames: 0,
+            events: None,
+            profile: None,
+            perf: None,
+            sinks: sink::Sinks::default(),
+            last_frame: FrameBuffer::default(),
+            prev_frame: FrameBuffer::default(),
+            bookmarks: Vec::new(),
+            overrides: Vec::new(),
+            host_calls: None,
+            sys_calls: None,
+            io: Vec::new(),
         };
-        ch8.ram[0usize..5 * 0x10].copy_from_slice(&FONTSET);
+        load_fonts(&mut ch8.ram);
         ch8
     }
 
     /// Loads a ROM into the VirtualMachine.
     ///
+    /// CHIP-8X programs are loaded at `0x300`, the others at `0x200`.
+    ///
     /// ## Arguments ##
     /// * rom - ROM to load
     pub fn load_rom(&mut self, rom: &[u8]) {
-        let len = std::cmp::min(rom.len(), MAX_ROM_LEN);
-        self.ram[START_ADDR as usize..START_ADDR as usize + len].copy_from_slice(&rom[..len]);
+        let start = usize::from(self.start_addr());
+        let len = std::cmp::min(rom.len(), MEM_SIZE - start);
+        self.ram[start..start + len].copy_from_slice(&rom[..len]);
+    }
+
+    // The address programs start at
+    fn start_addr(&self) -> u16 {
+        if self.extension == opcodes::Extension::Chip8X {
+            Variant::Chip8X.start_addr()
+        } else {
+            START_ADDR
+        }
+    }
+
+    /// Loads a ROM like [`VirtualMachine::load_rom`], after fixing it up with
+    /// [`rom::lenient`].
+    ///
+    /// The warnings are written to the log, and returned.
+    pub fn load_rom_lenient(&mut self, rom: &[u8]) -> Vec<rom::RomWarning> {
+        let (rom, warnings) = rom::lenient(rom);
+        for warning in &warnings {
+            self.log_line(
+                Severity::Warning,
+                None,
+                format_args!("Warning: {}", warning),
+            );
+        }
+        self.load_rom(&rom);
+        warnings
     }
 
     /// Does an interpretation cycle.
     pub fn do_cycle(&mut self) {
-        if !self.halt {
+        if self.halt.is_none() {
+            self.cycles += 1;
             let ins = self.fetch_ins();
-            self.dispatch(ins);
+            // Fetching past the end of memory halts, leaving nothing to run
+            if self.halt.is_none() {
+                self.dispatch(ins);
+            }
         }
     }
 
     // Decode instruction and execute it
     fn dispatch(&mut self, ins: u16) {
-        use Instruction::*;
-        match decode(ins) {
-            ClearDisplay => self.clear_display(),
-            Return => self.ret_from_subroutine(),
-            JumpToSysRoutine { addr } => self.jump_to_sys_routine(addr as usize),
-            JumpToAddress { addr } => self.jump_addr(addr),
-            CallSubroutine { addr } => self.call_subroutine(addr),
-            SkipNextVxEq { x, cmp_with } => self.skip_next_vx_eq(x as usize, cmp_with),
-            SkipNextVxNe { x, cmp_with } => self.skip_next_vx_ne(x as usize, cmp_with),
-            SkipNextVxEqVy { x, y } => self.skip_next_vx_eq_vy(x as usize, y as usize),
-            SetVxByte { x, to } => self.set_vx_byte(x as usize, to),
-            AddVxByte { x, rhs } => self.add_vx_byte(x as usize, rhs),
-            SetVxToVy { x, y } => self.set_vx_to_vy(x as usize, y as usize),
-            SetVxToVxOrVy { x, y } => self.set_vx_to_vx_or_vy(x as usize, y as usize),
-            SetVxToVxAndVy { x, y } => self.set_vx_to_vx_and_vy(x as usize, y as usize),
-            SetVxToVxXorVy { x, y } => self.set_vx_to_vx_xor_vy(x as usize, y as usize),
-            AddVxVy { x, y } => self.add_vx_vy(x as usize, y as usize),
-            SubVxVy { x, y } => self.sub_vx_vy(x as usize, y as usize),
-            SetVxToVyShr1 { x, y } => self.set_vx_to_vy_shr_1(x as usize, y as usize),
-            SubnVxVy { x, y } => self.subn_vx_vy(x as usize, y as usize),
-            SetVxToVyShl1 { x, y } => self.set_vx_to_vy_shl_1(x as usize, y as usize),
-            SkipNextVxNeVy { x, y } => self.skip_next_vx_ne_vy(x as usize, y as usize),
-            SetI { to } => self.set_i(to),
-            SetVxRandAnd { x, and } => self.set_vx_rand_and(x as usize, and),
-            DisplaySprite { x, y, n } => self.display_sprite(x as usize, y as usize, n as usize),
-            SkipNextKeyVxNotPressed { x } => self.skip_next_key_vx_not_pressed(x as usize),
-            SkipNextKeyVxPressed { x } => self.skip_next_key_vx_pressed(x as usize),
-            SetVxToDelayTimer { x } => self.set_vx_to_delay_timer(x as usize),
-            WaitForKeypressStoreInVx { x } => self.wait_for_keypress_store_in_vx(x as usize),
-            SetDelayTimer { x } => self.set_delay_timer(x as usize),
-            SetSoundTimer { x } => self.set_sound_timer(x as usize),
-            AddVxToI { x } => self.add_vx_to_i(x as usize),
-            SetIToLocOfDigitVx { x } => self.set_i_to_loc_of_digit_vx(x as usize),
-            StoreBcdOfVxToI { x } => self.store_bcd_of_vx_to_i(x as usize),
-            CopyV0ThroughVxToMem { x } => self.copy_v0_through_vx_to_mem(u16::from(x)),
-            ReadV0ThroughVxFromMem { x } => self.read_v0_through_vx_from_mem(u16::from(x)),
-            Unknown => writeln!(self.log, "Unknown instruction: {:X}", ins).unwrap(),
+        if !self.overrides.is_empty() && self.run_override(ins) == OverrideAction::Handled {
+            return;
+        }
+        if self.host_call(ins) {
+            return;
+        }
+        match opcodes::lookup_up_to(ins, self.extension) {
+            Some(spec) if self.profile.is_none() && self.perf.is_none() => {
+                (spec.exec)(self, Operands::new(ins))
+            }
+            Some(spec) => self.exec_measured(spec, ins),
+            None => {
+                self.log_line(
+                    Severity::Warning,
+                    Some(self.pc.wrapping_sub(2)),
+                    format_args!("Unknown instruction: {:X}", ins),
+                );
+                self.emit(EventKind::UnknownInstruction(ins));
+            }
         }
     }
 
     /// Gets the instruction that the program counter is pointing to.
     pub fn get_ins(&mut self) -> u16 {
-        let b1 = self.ram.get(self.pc as usize).cloned().unwrap_or_else(|| {
-            writeln!(self.log, "Out of bounds when getting instruction. Halted.").unwrap();
-            self.halt = true;
-            0
-        });
-        let b2 = self
-            .ram
-            .get((self.pc + 1) as usize)
-            .cloned()
-            .unwrap_or_else(|| {
-                writeln!(self.log, "Out of bounds when getting instruction. Halted.").unwrap();
-                self.halt = true;
+        let pc = usize::from(self.pc);
+        match self.ram.get(pc..pc + 2) {
+            Some(&[b1, b2]) => u16::from_be_bytes([b1, b2]),
+            _ => {
+                self.log_line(
+                    Severity::Error,
+                    Some(self.pc),
+                    format_args!("Out of bounds when getting instruction. Halted."),
+                );
+                self.halt(HaltReason::OutOfBounds);
                 0
-            });
-        (u16::from(b1) << 8) | u16::from(b2)
+            }
+        }
+    }
+
+    /// Returns the quirks the VM runs with.
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    /// Sets the quirks the VM runs with.
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    /// Reseeds the random number generator, making the results of `Cxkk` reproducible.
+    pub fn set_rng_seed(&mut self, seed: u64) {
+        self.rng = rng::Rng::new(seed);
+    }
+
+    /// Returns why the VM halted, or `None` if it's still running.
+    pub fn halt_reason(&self) -> Option<HaltReason> {
+        self.halt
     }
 
     /// Returns the value of the program counter.
@@ -300,6 +501,18 @@ impl VirtualMachine {
         self.pc
     }
 
+    /// Returns the value of register Vx.
+    ///
+    /// `x` should be in the range `0..15`.
+    pub fn v(&self, x: usize) -> u8 {
+        self.v[x].0
+    }
+
+    /// Returns the value of the I register.
+    pub fn i(&self) -> u16 {
+        self.i
+    }
+
     fn fetch_ins(&mut self) -> u16 {
         let ins = self.get_ins();
         self.pc += 2;
@@ -308,9 +521,13 @@ impl VirtualMachine {
 
     /// Presses a key on the hexadecimal keypad.
     ///
-    /// `key` should be in the range `0..15`.
+    /// `key` should be in the range `0..15`. With the
+    /// [`single_key`](Quirks::single_key) quirk, the other keys are released.
     pub fn press_key(&mut self, key: u8) {
         assert!(key <= 15);
+        if self.quirks.single_key {
+            self.keys = [false; 16];
+        }
         self.keys[usize::from(key)] = true;
         if self.keypress_wait.wait {
             self.v[self.keypress_wait.vx].0 = key;
@@ -318,6 +535,13 @@ impl VirtualMachine {
         }
     }
 
+    /// Returns whether a key on the hexadecimal keypad is pressed.
+    ///
+    /// `key` should be in the range `0..15`.
+    pub fn key_pressed(&self, key: u8) -> bool {
+        self.keys[usize::from(key)]
+    }
+
     /// Releases a key on the hexadecimal keypad.
     ///
     /// `key` should be in the range `0..15`.
@@ -330,20 +554,62 @@ impl VirtualMachine {
     ///
     /// They should be decremented at a rate of 60 Hz.
     pub fn decrement_timers(&mut self) {
+        self.frames += 1;
+        self.prev_frame = std::mem::replace(&mut self.last_frame, self.display.clone());
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
+            self.reset_delay_spin();
         }
         if self.sound_timer > 0 {
             self.sound_timer -= 1;
         }
+        self.update_sound();
+    }
+
+    /// Returns whether the sound is currently playing.
+    pub fn sound_playing(&self) -> bool {
+        self.sound_on
+    }
+
+    // Keep track of the sound turning on and off
+    fn update_sound(&mut self) {
+        // With the min_beep quirk, it takes 2 to start the sound, but it plays down to 1
+        let on = if self.sound_on || !self.quirks.min_beep {
+            self.sound_timer > 0
+        } else {
+            self.sound_timer > 1
+        };
+        if on != self.sound_on {
+            self.sound_on = on;
+            if on {
+                self.pacer.events.sound_started = true;
+                self.emit(EventKind::SoundStarted);
+                self.send_audio(AudioEvent::BeepStarted);
+            } else {
+                self.pacer.events.sound_stopped = true;
+                self.emit(EventKind::SoundStopped);
+                self.send_audio(AudioEvent::BeepStopped);
+            }
+        }
     }
 
     /// Returns whether the display has been updated.
     pub fn display_updated(&self) -> bool {
         self.display_updated
     }
-    /// Returns the contents of the display.
-    pub fn display(&self) -> &[u8; DISPLAY_WIDTH * DISPLAY_HEIGHT] {
+    /// Returns the contents of the display, row by row.
+    ///
+    /// The display is [`VirtualMachine::display_size`] pixels large, which changes when the
+    /// program switches resolution.
+    pub fn display(&self) -> &[u8] {
+        &self.display.pixels
+    }
+    /// Returns the width and height of the display in pixels.
+    pub fn display_size(&self) -> (usize, usize) {
+        (self.display.width, self.display.height)
+    }
+    /// Returns the display as a [`FrameBuffer`].
+    pub fn framebuffer(&self) -> &FrameBuffer {
         &self.display
     }
     /// Whether the VM is waiting for a key
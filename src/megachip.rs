@@ -0,0 +1,331 @@
+//! MegaChip, the SUPER-CHIP extension for 256-color graphics.
+//!
+//! `MEGAON` switches to a 256x192 screen kept apart from the monochrome display, with a byte
+//! per pixel holding an index into a palette of 256 colors. Programs load the palette
+//! themselves with `LDPAL`, and until then every color is black. Sprites are a byte per
+//! pixel as well, sized by `SPRW` and `SPRH`, and their pixels of color 0 are transparent.
+//! Drawing goes to a back buffer, which `CLS` shows before clearing it for the next frame,
+//! so frames are never seen half drawn. Frontends get the screen with
+//! [`VirtualMachine::megachip_screen`].
+//!
+//! Memory stays 4 KiB, so `LDHI` can only point I within it, and halts the program
+//! otherwise. Digitized sound, blend modes and the screen alpha aren't supported: their
+//! instructions are understood, but change nothing.
+//!
+//! Only compiled in with the `megachip` feature.
+
+use {
+    super::{Instruction::*, VirtualMachine, opcodes::Extension},
+    crate::{
+        opcodes::{OpcodeSpec, op},
+        palette::Rgb,
+    },
+};
+
+/// The width of the MegaChip screen.
+pub const WIDTH: usize = 256;
+/// The height of the MegaChip screen.
+pub const HEIGHT: usize = 192;
+
+/// The 256-color screen of MegaChip mode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Screen {
+    pub(super) on: bool,
+    // The frame shown and the one being drawn, empty while MegaChip mode is off
+    pub(super) shown: Vec<u8>,
+    pub(super) drawing: Vec<u8>,
+    pub(super) palette: [Rgb; 256],
+    pub(super) sprite_width: u16,
+    pub(super) sprite_height: u16,
+    pub(super) collision: u8,
+}
+
+impl Default for Screen {
+    fn default() -> Self {
+        Self {
+            on: false,
+            shown: Vec::new(),
+            drawing: Vec::new(),
+            palette: [[0; 3]; 256],
+            // Like the big sprites of SUPER-CHIP, until the program sets the size
+            sprite_width: 16,
+            sprite_height: 16,
+            collision: 0,
+        }
+    }
+}
+
+impl Screen {
+    /// Returns the frame shown, a color index per pixel, row by row.
+    pub fn pixels(&self) -> &[u8] {
+        &self.shown
+    }
+
+    /// Returns the color index of the pixel at `x`, `y` of the frame shown.
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        self.shown[y * WIDTH + x]
+    }
+
+    /// Returns the color at `index` of the palette.
+    pub fn color(&self, index: u8) -> Rgb {
+        self.palette[usize::from(index)]
+    }
+
+    fn set_on(&mut self, on: bool) {
+        self.on = on;
+        let len = if on { WIDTH * HEIGHT } else { 0 };
+        self.shown = vec![0; len];
+        self.drawing = vec![0; len];
+    }
+}
+
+#[rustfmt::skip]
+pub(crate) static OPCODES: &[OpcodeSpec] = &[
+    op!(MegaChip, 0x0010, 0xFFFF, "MEGAOFF", "Switch back to the monochrome display.",
+        |_| MegaOff, |vm, _| vm.set_megachip(false)),
+    op!(MegaChip, 0x0011, 0xFFFF, "MEGAON", "Switch to the 256-color screen.",
+        |_| MegaOn, |vm, _| vm.set_megachip(true)),
+    op!(MegaChip, 0x0100, 0xFF00, "LDHI I, nnnnnn",
+        "Set I = the 24-bit address made of kk and the 16 bits after the instruction.",
+        |o| SetILong { hi: o.kk }, |vm, o| vm.set_i_long(o.kk)),
+    op!(MegaChip, 0x0200, 0xFF00, "LDPAL kk",
+        "Load colors 1 to kk of the palette from I, 4 bytes each as ARGB.",
+        |o| LoadPalette { n: o.kk }, |vm, o| vm.load_palette(o.kk)),
+    op!(MegaChip, 0x0300, 0xFF00, "SPRW kk", "Set the sprite width to kk, or 256 if 0.",
+        |o| SetSpriteWidth { width: o.kk },
+        |vm, o| vm.mega.sprite_width = sprite_size(o.kk)),
+    op!(MegaChip, 0x0400, 0xFF00, "SPRH kk", "Set the sprite height to kk, or 256 if 0.",
+        |o| SetSpriteHeight { height: o.kk },
+        |vm, o| vm.mega.sprite_height = sprite_size(o.kk)),
+    op!(MegaChip, 0x0500, 0xFF00, "ALPHA kk", "Set the screen alpha to kk. Ignored.",
+        |o| SetScreenAlpha { alpha: o.kk }, |_, _| {}),
+    op!(MegaChip, 0x0600, 0xFFF0, "DIGISND n", "Play the digitized sound at I. Ignored.",
+        |o| PlayDigitizedSound { n: o.n }, |_, _| {}),
+    op!(MegaChip, 0x0700, 0xFFFF, "STOPSND", "Stop the digitized sound. Ignored.",
+        |_| StopDigitizedSound, |_, _| {}),
+    op!(MegaChip, 0x0800, 0xFFF0, "BMODE n", "Set the sprite blend mode to n. Ignored.",
+        |o| SetBlendMode { mode: o.n }, |_, _| {}),
+    op!(MegaChip, 0x0900, 0xFF00, "CCOL kk", "Set the collision color to kk.",
+        |o| SetCollisionColor { index: o.kk }, |vm, o| vm.mega.collision = o.kk),
+    op!(MegaChip, 0x00B0, 0xFFF0, "SCU n", "Scroll the display up by n pixels.",
+        |o| ScrollUp { n: o.n }, |vm, o| vm.mega_scroll(0, -(o.n as isize))),
+    op!(MegaChip, 0x00C0, 0xFFF0, "SCD n", "Scroll the display down by n pixels.",
+        |o| ScrollDown { n: o.n }, |vm, o| vm.mega_scroll(0, o.n as isize)),
+    op!(MegaChip, 0x00FB, 0xFFFF, "SCR", "Scroll the display right by 4 pixels.",
+        |_| ScrollRight, |vm, _| vm.mega_scroll(4, 0)),
+    op!(MegaChip, 0x00FC, 0xFFFF, "SCL", "Scroll the display left by 4 pixels.",
+        |_| ScrollLeft, |vm, _| vm.mega_scroll(-4, 0)),
+    op!(MegaChip, 0x00E0, 0xFFFF, "CLS",
+        "Clear the display. On the 256-color screen, show the frame drawn and start the next.",
+        |_| ClearDisplay, |vm, _| vm.mega_clear()),
+    op!(MegaChip, 0xD000, 0xF000, "DRW Vx, Vy, n",
+        "Draw a sprite from memory location I at (Vx, Vy), VF = collision. On the 256-color \
+         screen, sprites are SPRW by SPRH pixels, and n is ignored.",
+        |o| DisplaySprite { x: o.x, y: o.y, n: o.n },
+        |vm, o| vm.mega_sprite(o.x as usize, o.y as usize, o.n as usize)),
+];
+
+// SPRW and SPRH take 0 for 256
+fn sprite_size(kk: u8) -> u16 {
+    if kk == 0 { 256 } else { u16::from(kk) }
+}
+
+impl VirtualMachine {
+    /// Returns the 256-color screen, while [MegaChip](crate::megachip) instructions are
+    /// understood and the program switched to it.
+    pub fn megachip_screen(&self) -> Option<&Screen> {
+        (self.extension == Extension::MegaChip && self.mega.on).then_some(&self.mega)
+    }
+
+    fn set_megachip(&mut self, on: bool) {
+        self.mega.set_on(on);
+        self.display_changed();
+    }
+
+    fn set_i_long(&mut self, hi: u8) {
+        let lo = [
+            self.read_mem(usize::from(self.pc)),
+            self.read_mem(usize::from(self.pc) + 1),
+        ];
+        self.pc += 2;
+        let addr = usize::from(hi) << 16 | usize::from(u16::from_be_bytes(lo));
+        if addr < crate::MEM_SIZE {
+            self.i = addr as u16;
+        } else {
+            self.memory_out_of_bounds(addr);
+        }
+    }
+
+    fn load_palette(&mut self, n: u8) {
+        for index in 1..=usize::from(n) {
+            let at = usize::from(self.i) + (index - 1) * 4;
+            // The alpha byte comes first, and is ignored
+            self.mega.palette[index] = [1, 2, 3].map(|offset| self.read_mem(at + offset));
+        }
+    }
+
+    fn mega_clear(&mut self) {
+        if !self.mega.on {
+            return self.clear_display();
+        }
+        std::mem::swap(&mut self.mega.shown, &mut self.mega.drawing);
+        self.mega.drawing.fill(0);
+        self.display_changed();
+    }
+
+    fn mega_scroll(&mut self, dx: isize, dy: isize) {
+        if !self.mega.on {
+            return self.scroll(dx, dy);
+        }
+        let (width, height) = (WIDTH as isize, HEIGHT as isize);
+        let old = self.mega.drawing.clone();
+        for y in 0..height {
+            for x in 0..width {
+                let (src_x, src_y) = (x - dx, y - dy);
+                let inside = (0..width).contains(&src_x) && (0..height).contains(&src_y);
+                self.mega.drawing[(y * width + x) as usize] = if inside {
+                    old[(src_y * width + src_x) as usize]
+                } else {
+                    0
+                };
+            }
+        }
+    }
+
+    fn mega_sprite(&mut self, vx: usize, vy: usize, n: usize) {
+        if !self.mega.on {
+            return if n == 0 {
+                self.draw_sprite(vx, vy, 16, 16)
+            } else {
+                self.display_sprite(vx, vy, n)
+            };
+        }
+        let (x0, y0) = (usize::from(self.v[vx].0), usize::from(self.v[vy].0));
+        let (width, height) = (
+            usize::from(self.mega.sprite_width),
+            usize::from(self.mega.sprite_height),
+        );
+        self.v[0xF].0 = 0;
+        for y in 0..height {
+            for x in 0..width {
+                let color = self.read_mem(usize::from(self.i) + y * width + x);
+                if self.halt.is_some() {
+                    return;
+                }
+                let (xx, yy) = (x0 + x, y0 + y);
+                if color == 0 || xx >= WIDTH || yy >= HEIGHT {
+                    continue;
+                }
+                let pixel = &mut self.mega.drawing[yy * WIDTH + xx];
+                if *pixel == self.mega.collision {
+                    self.v[0xF].0 = 1;
+                }
+                *pixel = color;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_megachip() {
+    let mut vm = VirtualMachine::with_variant(crate::Variant::MegaChip);
+    // 0x200: MEGAON
+    // 0x202: LDHI I, 0x000300
+    // 0x206: LDPAL 2
+    // 0x208: SPRW 2
+    // 0x20A: SPRH 2
+    // 0x20C: CCOL 1
+    // 0x20E: LD I, 0x308
+    // 0x210: DRW V0, V0, 0
+    // 0x212: CLS
+    // 0x214: DRW V0, V0, 0
+    // 0x216: CLS
+    let mut rom = vec![
+        0x00, 0x11, 0x01, 0x00, 0x03, 0x00, 0x02, 0x02, 0x03, 0x02, 0x04, 0x02, 0x09, 0x01, 0xA3,
+        0x08, 0xD0, 0x00, 0x00, 0xE0, 0xD0, 0x00, 0x00, 0xE0,
+    ];
+    rom.resize(0x100, 0);
+    // 0x300: the palette, red and blue
+    rom.extend_from_slice(&[0xFF, 0xFF, 0x00, 0x00, 0xFF, 0x00, 0x00, 0xFF]);
+    // 0x308: a 2x2 sprite with a transparent pixel
+    rom.extend_from_slice(&[1, 2, 0, 1]);
+    vm.load_rom(&rom);
+    assert!(vm.megachip_screen().is_none());
+    vm.do_cycle();
+    assert_eq!(vm.megachip_screen().unwrap().pixels(), [0; WIDTH * HEIGHT]);
+    for _ in 0..7 {
+        vm.do_cycle();
+    }
+    assert_eq!(vm.i(), 0x308);
+    let screen = vm.megachip_screen().unwrap();
+    assert_eq!(screen.color(1), [0xFF, 0x00, 0x00]);
+    assert_eq!(screen.color(2), [0x00, 0x00, 0xFF]);
+    // Sprites are only shown once the frame is
+    assert_eq!(screen.pixel(0, 0), 0);
+    vm.do_cycle();
+    let screen = vm.megachip_screen().unwrap();
+    assert_eq!(
+        [(0, 0), (1, 0), (0, 1), (1, 1)].map(|(x, y)| screen.pixel(x, y)),
+        [1, 2, 0, 1]
+    );
+    assert_eq!(vm.v(0xF), 0);
+    // The next frame starts out empty, so nothing collides
+    vm.do_cycle();
+    vm.do_cycle();
+    assert_eq!(vm.v(0xF), 0);
+    // Saving and loading keeps the screen
+    let mut loaded = VirtualMachine::new();
+    loaded.load_state(&vm.save_state()).unwrap();
+    assert_eq!(loaded.megachip_screen(), vm.megachip_screen());
+    // Pointing I past the end of memory halts
+    vm.pc = 0x202;
+    vm.ram[0x205] = 0x10;
+    vm.ram[0x203] = 0x01;
+    vm.do_cycle();
+    assert_eq!(vm.halt_reason(), Some(crate::HaltReason::MemoryOutOfBounds));
+}
+
+#[test]
+fn test_megachip_collision() {
+    let mut vm = VirtualMachine::with_variant(crate::Variant::MegaChip);
+    // 0x200: MEGAON
+    // 0x202: SPRW 1
+    // 0x204: SPRH 1
+    // 0x206: CCOL 3
+    // 0x208: LD I, 0x210
+    // 0x20A: DRW V0, V0, 1
+    // 0x20C: DRW V0, V0, 1
+    // 0x20E: MEGAOFF
+    // 0x210: color 3
+    vm.load_rom(&[
+        0x00, 0x11, 0x03, 0x01, 0x04, 0x01, 0x09, 0x03, 0xA2, 0x10, 0xD0, 0x01, 0xD0, 0x01, 0x00,
+        0x10, 0x03,
+    ]);
+    for _ in 0..6 {
+        vm.do_cycle();
+    }
+    assert_eq!(vm.v(0xF), 0);
+    vm.do_cycle();
+    assert_eq!(vm.v(0xF), 1);
+    // Switched off, the monochrome display is back
+    vm.do_cycle();
+    assert!(vm.megachip_screen().is_none());
+}
+
+#[test]
+fn test_lookup_prefers_older_versions() {
+    use crate::opcodes::{lookup, lookup_up_to};
+
+    // Disassembly shows the usual CLS and SYS, while MegaChip programs run their own
+    assert_eq!(lookup(0x00E0).unwrap().extension, Extension::Chip8);
+    assert_eq!(lookup(0x0011).unwrap().mnemonic, "SYS nnn");
+    assert_eq!(
+        lookup_up_to(0x0011, Extension::MegaChip).unwrap().mnemonic,
+        "MEGAON"
+    );
+    assert_eq!(
+        lookup_up_to(0x00E0, Extension::MegaChip).unwrap().extension,
+        Extension::MegaChip
+    );
+    assert!(lookup_up_to(0x0011, Extension::XoChip).unwrap().mnemonic != "MEGAON");
+}
@@ -0,0 +1,126 @@
+//! Memory-mapped I/O, an opt-in experiment.
+//!
+//! Ranges of memory can be mapped to host devices, so programs talk to them by reading and
+//! writing memory, like with a virtual serial port. Mapped accesses are the data accesses of
+//! instructions: sprite reads by `Dxyn`, `Fx33`, `Fx55` and `Fx65`. Instructions are still
+//! fetched from RAM, and [`VirtualMachine::memory`] shows RAM rather than the devices.
+
+use {
+    super::{HaltReason, Severity, VirtualMachine},
+    std::{
+        ops::Range,
+        sync::{Arc, Mutex},
+    },
+};
+
+/// A device that memory can be mapped to.
+pub trait MemoryMappedIo: Send {
+    /// Called when the program reads `addr`.
+    fn read(&mut self, addr: u16) -> u8;
+    /// Called when the program writes `value` to `addr`.
+    fn write(&mut self, addr: u16, value: u8);
+}
+
+#[derive(Clone)]
+pub(super) struct Mapping {
+    range: Range<u16>,
+    device: Arc<Mutex<dyn MemoryMappedIo>>,
+}
+
+impl VirtualMachine {
+    /// Maps the addresses in `range` to `device`.
+    ///
+    /// Mappings added later take precedence where ranges overlap.
+    /// The device is shared with clones of this VM.
+    pub fn map_io(&mut self, range: Range<u16>, device: impl MemoryMappedIo + 'static) {
+        self.io.push(Mapping {
+            range,
+            device: Arc::new(Mutex::new(device)),
+        });
+    }
+
+    /// Removes all mappings added with [`VirtualMachine::map_io`].
+    pub fn unmap_io(&mut self) {
+        self.io.clear();
+    }
+
+    fn device_at(&self, addr: usize) -> Option<&Mapping> {
+        if self.io.is_empty() {
+            return None;
+        }
+        let addr = u16::try_from(addr).ok()?;
+        self.io.iter().rev().find(|m| m.range.contains(&addr))
+    }
+
+    // Reads memory for an instruction. Reading past the end halts, reading 0.
+    pub(super) fn read_mem(&mut self, addr: usize) -> u8 {
+        match self.device_at(addr) {
+            Some(m) => m.device.lock().unwrap().read(addr as u16),
+            None => match self.ram.get(addr) {
+                Some(&value) => value,
+                None => {
+                    self.memory_out_of_bounds(addr);
+                    0
+                }
+            },
+        }
+    }
+
+    // Writes memory for an instruction. Writing past the end halts, writing nothing.
+    pub(super) fn write_mem(&mut self, addr: usize, value: u8) {
+        match self.device_at(addr) {
+            Some(m) => m.device.lock().unwrap().write(addr as u16, value),
+            None => match self.ram.get_mut(addr) {
+                Some(byte) => *byte = value,
+                None => self.memory_out_of_bounds(addr),
+            },
+        }
+    }
+
+    pub(super) fn memory_out_of_bounds(&mut self, addr: usize) {
+        // An instruction can go past the end with several bytes, but it only halts once
+        if self.halt.is_none() {
+            self.log_line(
+                Severity::Error,
+                Some(self.pc.wrapping_sub(2)),
+                format_args!("Memory access out of bounds at {:#x}. Halted.", addr),
+            );
+            self.halt(HaltReason::MemoryOutOfBounds);
+        }
+    }
+}
+
+#[test]
+fn test_memory_mapped_io() {
+    struct Serial(Arc<Mutex<Vec<u8>>>);
+
+    impl MemoryMappedIo for Serial {
+        fn read(&mut self, _addr: u16) -> u8 {
+            0x2A
+        }
+        fn write(&mut self, _addr: u16, value: u8) {
+            self.0.lock().unwrap().push(value);
+        }
+    }
+
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let mut vm = VirtualMachine::new();
+    vm.map_io(0xFF0..0xFF2, Serial(sent.clone()));
+    // 0x200: LD V0, 'h'
+    // 0x202: LD V1, 'i'
+    // 0x204: LD V2, '!'
+    // 0x206: LD I, 0xFF0
+    // 0x208: LD [I], V2
+    // 0x20A: LD I, 0xFF0
+    // 0x20C: LD V0, [I]
+    vm.load_rom(&[
+        0x60, b'h', 0x61, b'i', 0x62, b'!', 0xAF, 0xF0, 0xF2, 0x55, 0xAF, 0xF0, 0xF0, 0x65,
+    ]);
+    for _ in 0..7 {
+        vm.do_cycle();
+    }
+    // The third byte lands past the mapped range, in RAM
+    assert_eq!(*sent.lock().unwrap(), b"hi");
+    assert_eq!(vm.memory()[0xFF2], b'!');
+    assert_eq!(vm.v(0), 0x2A);
+}
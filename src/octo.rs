@@ -0,0 +1,884 @@
+//! Import of Octo cartridges.
+//!
+//! Octo can save programs as "cartridges": GIF images showing a label, with the program
+//! hidden in the low bits of the pixels. Every pixel carries 2 bits in the lowest bits of its
+//! palette index, most significant bits first, frame after frame. The decoded bytes start with
+//! a 32-bit big-endian length, followed by that many bytes of JSON with the `program` and the
+//! `options` it was saved with.
+//!
+//! The program is Octo source, which [`assemble`] turns into a ROM. It understands the
+//! common subset of Octo: labels, `:const`, `:alias`, `:org`, `:byte`, `:call`, `:unpack`
+//! and `:next`, all instructions, and the `if`, `loop` and `while` control structures.
+//! Macros and `:calc` aren't supported.
+//!
+//! Only compiled in with the `octo` feature.
+
+use {
+    super::{Quirks, START_ADDR},
+    serde_json::Value,
+    std::{collections::HashMap, fmt, io::Read},
+};
+
+/// An error while importing a cartridge.
+#[derive(Debug)]
+pub enum CartridgeError {
+    /// The GIF couldn't be decoded.
+    Gif(gif::DecodingError),
+    /// The image ends before the payload does.
+    Truncated,
+    /// The payload isn't valid.
+    Payload(String),
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CartridgeError::Gif(e) => write!(f, "{}", e),
+            CartridgeError::Truncated => write!(f, "cartridge payload is truncated"),
+            CartridgeError::Payload(msg) => write!(f, "invalid cartridge payload: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for CartridgeError {}
+
+impl From<gif::DecodingError> for CartridgeError {
+    fn from(e: gif::DecodingError) -> Self {
+        CartridgeError::Gif(e)
+    }
+}
+
+/// The options a cartridge was saved with.
+///
+/// Options that weren't present are `None`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CartridgeOptions {
+    /// Instructions executed per frame.
+    pub tickrate: Option<u32>,
+    /// `8xy6` and `8xyE` shift Vx in place, ignoring Vy.
+    pub shift_quirks: Option<bool>,
+    /// `Fx55` and `Fx65` leave I unchanged.
+    pub load_store_quirks: Option<bool>,
+    /// `8xy1`, `8xy2` and `8xy3` leave VF unchanged.
+    pub logic_quirks: Option<bool>,
+    /// `Bnnn` jumps to nnn + Vx, where x is the highest nibble of nnn.
+    pub jump_quirks: Option<bool>,
+    /// Sprites are clipped at the edges of the screen rather than wrapped around.
+    pub clip_quirks: Option<bool>,
+    /// Drawing waits for the vertical blank.
+    pub vblank_quirks: Option<bool>,
+    /// The color of pixels set in the first plane, like `#FFCC00`.
+    pub fill_color: Option<String>,
+    /// The color of pixels set in the second plane.
+    pub fill_color2: Option<String>,
+    /// The color of pixels set in both planes.
+    pub blend_color: Option<String>,
+    /// The color of pixels set in neither plane.
+    pub background_color: Option<String>,
+}
+
+impl CartridgeOptions {
+    fn from_json(options: &Value) -> Self {
+        let flag = |name: &str| options.get(name).and_then(Value::as_bool);
+        let color = |name: &str| options.get(name).and_then(Value::as_str).map(str::to_owned);
+        Self {
+            tickrate: options
+                .get("tickrate")
+                .and_then(Value::as_u64)
+                .and_then(|rate| u32::try_from(rate).ok()),
+            shift_quirks: flag("shiftQuirks"),
+            load_store_quirks: flag("loadStoreQuirks"),
+            logic_quirks: flag("logicQuirks"),
+            jump_quirks: flag("jumpQuirks"),
+            clip_quirks: flag("clipQuirks"),
+            vblank_quirks: flag("vBlankQuirks"),
+            fill_color: color("fillColor"),
+            fill_color2: color("fillColor2"),
+            blend_color: color("blendColor"),
+            background_color: color("backgroundColor"),
+        }
+    }
+
+    /// Applies the quirk options this interpreter supports to `quirks`.
+    pub fn apply_quirks(&self, quirks: &mut Quirks) {
+        if let Some(shift) = self.shift_quirks {
+            quirks.shift_uses_vy = !shift;
+        }
+        if let Some(jump) = self.jump_quirks {
+            quirks.jump_uses_vx = jump;
+        }
+        if let Some(logic) = self.logic_quirks {
+            quirks.logic_resets_vf = logic;
+        }
+        if let Some(load_store) = self.load_store_quirks {
+            quirks.load_store_keeps_i = load_store;
+        }
+    }
+
+    /// Returns the instructions per second to run at, if the cartridge specifies it.
+    pub fn ips(&self) -> Option<u32> {
+        self.tickrate.and_then(|rate| rate.checked_mul(60))
+    }
+}
+
+/// The contents of an Octo cartridge.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cartridge {
+    /// The program.
+    ///
+    /// Octo stores the Octo assembly source of the program, so it needs to be assembled with
+    /// [`assemble`] before it can be run.
+    pub program: String,
+    /// The options the cartridge was saved with.
+    pub options: CartridgeOptions,
+}
+
+/// Decodes an Octo cartridge.
+pub fn read_cartridge(data: impl Read) -> Result<Cartridge, CartridgeError> {
+    let mut opts = gif::DecodeOptions::new();
+    opts.set_color_output(gif::ColorOutput::Indexed);
+    let mut decoder = opts.read_info(data)?;
+    let mut bits = Vec::new();
+    while let Some(frame) = decoder.read_next_frame()? {
+        bits.extend(frame.buffer.iter().map(|&index| index & 0b11));
+    }
+    let bytes: Vec<u8> = bits
+        .chunks_exact(4)
+        .map(|c| (c[0] << 6) | (c[1] << 4) | (c[2] << 2) | c[3])
+        .collect();
+    let Some((len, payload)) = bytes.split_first_chunk::<4>() else {
+        return Err(CartridgeError::Truncated);
+    };
+    let len = u32::from_be_bytes(*len) as usize;
+    let payload = payload.get(..len).ok_or(CartridgeError::Truncated)?;
+    let json: Value =
+        serde_json::from_slice(payload).map_err(|e| CartridgeError::Payload(e.to_string()))?;
+    let program = json
+        .get("program")
+        .and_then(Value::as_str)
+        .ok_or_else(|| CartridgeError::Payload("missing program".into()))?;
+    Ok(Cartridge {
+        program: program.to_owned(),
+        options: json
+            .get("options")
+            .map(CartridgeOptions::from_json)
+            .unwrap_or_default(),
+    })
+}
+
+/// An error while assembling Octo source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssemblyError {
+    /// The line the error is on, starting from 1.
+    pub line: usize,
+    /// What's wrong.
+    pub message: String,
+}
+
+impl fmt::Display for AssemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AssemblyError {}
+
+// Where an address that wasn't known yet goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Patch {
+    // The low 12 bits of the instruction at the offset
+    Addr,
+    // The 16 bits at the offset, after `i := long`
+    Long,
+    // The low nibble of the `v0 :=` instruction at the offset, and the byte of the `v1 :=`
+    // one after it, for `:unpack`
+    Unpack,
+}
+
+// A structure waiting to be closed
+enum Block {
+    // The offset of the jump past the branch taken when the condition holds
+    If(usize),
+    // The offset of the jump past the else branch
+    Else(usize),
+    // The start of the loop, and the offsets of the jumps out of it
+    Loop(u16, Vec<usize>),
+}
+
+// A condition, as in `if v0 != 5 then`
+struct Cond<'a> {
+    x: u8,
+    op: &'a str,
+    rhs: Operand,
+}
+
+enum Operand {
+    None,
+    Reg(u8),
+    Byte(u8),
+}
+
+struct Assembler<'a> {
+    tokens: Vec<(&'a str, usize)>,
+    pos: usize,
+    rom: Vec<u8>,
+    // The offset in the ROM the next byte goes to
+    here: usize,
+    labels: HashMap<&'a str, u16>,
+    consts: HashMap<&'a str, i64>,
+    aliases: HashMap<&'a str, u8>,
+    fixups: Vec<(usize, &'a str, Patch, usize)>,
+    blocks: Vec<(Block, usize)>,
+}
+
+/// Assembles Octo source into a ROM, starting with a jump to the `main` label.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AssemblyError> {
+    let tokens = source
+        .lines()
+        .enumerate()
+        .flat_map(|(n, line)| {
+            let code = line.split('#').next().unwrap_or_default();
+            code.split_whitespace().map(move |token| (token, n + 1))
+        })
+        .collect();
+    let mut asm = Assembler {
+        tokens,
+        pos: 0,
+        rom: Vec::new(),
+        here: 0,
+        labels: HashMap::new(),
+        consts: HashMap::new(),
+        aliases: HashMap::new(),
+        fixups: Vec::new(),
+        blocks: Vec::new(),
+    };
+    // Room for the jump to main
+    asm.emit(&[0x10, 0x00])?;
+    asm.fixups.push((0, "main", Patch::Addr, 1));
+    while asm.pos < asm.tokens.len() {
+        asm.statement()?;
+    }
+    if let Some(&(_, line)) = asm.blocks.last() {
+        return Err(AssemblyError {
+            line,
+            message: "unclosed block".into(),
+        });
+    }
+    for (offset, name, patch, line) in std::mem::take(&mut asm.fixups) {
+        let err = |message: String| AssemblyError { line, message };
+        let addr = *asm
+            .labels
+            .get(name)
+            .ok_or_else(|| err(format!("undefined label `{}`", name)))?;
+        let rom = &mut asm.rom[offset..];
+        match patch {
+            Patch::Addr if addr > 0xFFF => {
+                return Err(err(format!("`{}` is out of reach at {:#x}", name, addr)));
+            }
+            Patch::Addr => {
+                rom[0] |= (addr >> 8) as u8;
+                rom[1] = addr as u8;
+            }
+            Patch::Long => rom[..2].copy_from_slice(&addr.to_be_bytes()),
+            Patch::Unpack => {
+                rom[1] |= (addr >> 8) as u8;
+                rom[3] = addr as u8;
+            }
+        }
+    }
+    Ok(asm.rom)
+}
+
+impl<'a> Assembler<'a> {
+    fn err(&self, message: impl Into<String>) -> AssemblyError {
+        let last = self.tokens.last().map_or(1, |&(_, line)| line);
+        AssemblyError {
+            line: self
+                .tokens
+                .get(self.pos.saturating_sub(1))
+                .map_or(last, |&(_, line)| line),
+            message: message.into(),
+        }
+    }
+
+    fn next(&mut self) -> Result<&'a str, AssemblyError> {
+        let &(token, _) = self
+            .tokens
+            .get(self.pos)
+            .ok_or_else(|| self.err("unexpected end of source"))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.pos).map(|&(token, _)| token)
+    }
+
+    fn line(&self) -> usize {
+        self.tokens[self.pos - 1].1
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), AssemblyError> {
+        if self.next()? == token {
+            Ok(())
+        } else {
+            Err(self.err(format!("expected `{}`", token)))
+        }
+    }
+
+    fn addr(&self) -> u16 {
+        START_ADDR + self.here as u16
+    }
+
+    fn emit(&mut self, bytes: &[u8]) -> Result<(), AssemblyError> {
+        let end = self.here + bytes.len();
+        if end > usize::from(u16::MAX - START_ADDR) + 1 {
+            return Err(self.err("program doesn't fit in memory"));
+        }
+        if self.rom.len() < end {
+            self.rom.resize(end, 0);
+        }
+        self.rom[self.here..end].copy_from_slice(bytes);
+        self.here = end;
+        Ok(())
+    }
+
+    fn emit_op(&mut self, op: u16) -> Result<(), AssemblyError> {
+        self.emit(&op.to_be_bytes())
+    }
+
+    fn reg(&self, token: &str) -> Option<u8> {
+        if let Some(&x) = self.aliases.get(token) {
+            return Some(x);
+        }
+        let digit = token.strip_prefix(['v', 'V'])?;
+        (digit.len() == 1)
+            .then(|| u8::from_str_radix(digit, 16).ok())
+            .flatten()
+    }
+
+    fn next_reg(&mut self) -> Result<u8, AssemblyError> {
+        let token = self.next()?;
+        self.reg(token)
+            .ok_or_else(|| self.err(format!("expected a register, found `{}`", token)))
+    }
+
+    // The value of a number, constant or label defined before
+    fn value(&self, token: &str) -> Option<i64> {
+        let (neg, digits) = match token.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, token),
+        };
+        let n = if let Some(hex) = digits.strip_prefix("0x") {
+            i64::from_str_radix(hex, 16).ok()
+        } else if let Some(bin) = digits.strip_prefix("0b") {
+            i64::from_str_radix(bin, 2).ok()
+        } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+            digits.parse().ok()
+        } else {
+            None
+        };
+        match n {
+            Some(n) if neg => Some(-n),
+            Some(n) => Some(n),
+            None => (!neg)
+                .then(|| {
+                    self.consts
+                        .get(token)
+                        .copied()
+                        .or_else(|| self.labels.get(token).map(|&addr| i64::from(addr)))
+                })
+                .flatten(),
+        }
+    }
+
+    fn next_value(&mut self, min: i64, max: i64) -> Result<i64, AssemblyError> {
+        let token = self.next()?;
+        match self.value(token) {
+            Some(n) if (min..=max).contains(&n) => Ok(n),
+            Some(_) => Err(self.err(format!("`{}` is out of range", token))),
+            None => Err(self.err(format!("expected a number, found `{}`", token))),
+        }
+    }
+
+    fn next_byte(&mut self) -> Result<u8, AssemblyError> {
+        Ok(self.next_value(-128, 255)? as u8)
+    }
+
+    fn next_nibble(&mut self) -> Result<u16, AssemblyError> {
+        Ok(self.next_value(0, 15)? as u16)
+    }
+
+    // Emits `op` with an address in its low 12 bits, which can be a label defined later
+    fn emit_addr_op(&mut self, op: u16) -> Result<(), AssemblyError> {
+        let token = self.next()?;
+        let offset = self.here;
+        match self.value(token) {
+            Some(addr @ 0..=0xFFF) => self.emit_op(op | addr as u16),
+            Some(_) => Err(self.err(format!("`{}` is out of range", token))),
+            None if self.reg(token).is_some() => Err(self.err("expected an address")),
+            None => {
+                self.fixups.push((offset, token, Patch::Addr, self.line()));
+                self.emit_op(op)
+            }
+        }
+    }
+
+    // Emits a jump whose address is filled in when the block it leaves is closed
+    fn emit_jump(&mut self) -> Result<usize, AssemblyError> {
+        let offset = self.here;
+        self.emit_op(0x1000)?;
+        Ok(offset)
+    }
+
+    fn patch_jump(&mut self, offset: usize) -> Result<(), AssemblyError> {
+        let addr = self.addr();
+        if addr > 0xFFF {
+            return Err(self.err("block ends out of reach"));
+        }
+        self.rom[offset] |= (addr >> 8) as u8;
+        self.rom[offset + 1] = addr as u8;
+        Ok(())
+    }
+
+    fn cond(&mut self) -> Result<Cond<'a>, AssemblyError> {
+        let x = self.next_reg()?;
+        let op = self.next()?;
+        let rhs = match op {
+            "key" | "-key" => Operand::None,
+            "==" | "!=" | "<" | ">" | "<=" | ">=" => {
+                let token = self.peek().unwrap_or_default();
+                match self.reg(token) {
+                    Some(y) => {
+                        self.pos += 1;
+                        Operand::Reg(y)
+                    }
+                    None => Operand::Byte(self.next_byte()?),
+                }
+            }
+            _ => return Err(self.err(format!("unknown comparison `{}`", op))),
+        };
+        Ok(Cond { x, op, rhs })
+    }
+
+    // Emits code skipping the next instruction if the condition doesn't hold, or if it does
+    // when `negate` is set. Comparisons by size go through VF.
+    fn emit_skip(&mut self, cond: &Cond, negate: bool) -> Result<(), AssemblyError> {
+        let op = if negate {
+            match cond.op {
+                "==" => "!=",
+                "!=" => "==",
+                "key" => "-key",
+                "-key" => "key",
+                "<" => ">=",
+                ">" => "<=",
+                "<=" => ">",
+                _ => "<",
+            }
+        } else {
+            cond.op
+        };
+        let x = u16::from(cond.x) << 8;
+        match (op, &cond.rhs) {
+            ("key", _) => return self.emit_op(0xE0A1 | x),
+            ("-key", _) => return self.emit_op(0xE09E | x),
+            ("==", &Operand::Reg(y)) => return self.emit_op(0x9000 | x | u16::from(y) << 4),
+            ("==", &Operand::Byte(n)) => return self.emit_op(0x4000 | x | u16::from(n)),
+            ("!=", &Operand::Reg(y)) => return self.emit_op(0x5000 | x | u16::from(y) << 4),
+            ("!=", &Operand::Byte(n)) => return self.emit_op(0x3000 | x | u16::from(n)),
+            (_, &Operand::Reg(y)) => self.emit_op(0x8F00 | u16::from(y) << 4)?,
+            (_, &Operand::Byte(n)) => self.emit_op(0x6F00 | u16::from(n))?,
+            (_, Operand::None) => unreachable!(),
+        }
+        // VF := rhs, then VF -= Vx sets VF when rhs >= Vx, and VF =- Vx when Vx >= rhs
+        let (sub, skip) = match op {
+            ">" => (0x5, 0x3F01),
+            "<" => (0x7, 0x3F01),
+            ">=" => (0x7, 0x3F00),
+            _ => (0x5, 0x3F00),
+        };
+        self.emit_op(0x8F00 | u16::from(cond.x) << 4 | sub)?;
+        self.emit_op(skip)
+    }
+
+    fn define_label(&mut self, name: &'a str, addr: u16) -> Result<(), AssemblyError> {
+        if self.labels.insert(name, addr).is_some() {
+            return Err(self.err(format!("label `{}` is already defined", name)));
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<(), AssemblyError> {
+        let token = self.next()?;
+        let simple = match token {
+            "clear" => Some(0x00E0),
+            "return" | ";" => Some(0x00EE),
+            "exit" => Some(0x00FD),
+            "lores" => Some(0x00FE),
+            "hires" => Some(0x00FF),
+            "scroll-right" => Some(0x00FB),
+            "scroll-left" => Some(0x00FC),
+            "audio" => Some(0xF002),
+            _ => None,
+        };
+        if let Some(op) = simple {
+            return self.emit_op(op);
+        }
+        match token {
+            ":" => {
+                let name = self.next()?;
+                self.define_label(name, self.addr())?;
+            }
+            ":const" => {
+                let name = self.next()?;
+                let value = self.next_value(i64::MIN, i64::MAX)?;
+                self.consts.insert(name, value);
+            }
+            ":alias" => {
+                let name = self.next()?;
+                let x = self.next_reg()?;
+                self.aliases.insert(name, x);
+            }
+            ":org" => {
+                let addr = self.next_value(i64::from(START_ADDR), 0xFFFF)?;
+                self.here = (addr - i64::from(START_ADDR)) as usize;
+            }
+            ":byte" => {
+                let byte = self.next_byte()?;
+                self.emit(&[byte])?;
+            }
+            ":call" => self.emit_addr_op(0x2000)?,
+            ":next" => {
+                let name = self.next()?;
+                self.define_label(name, self.addr() + 1)?;
+            }
+            ":unpack" => {
+                let high = match self.peek() {
+                    Some("long") => {
+                        self.pos += 1;
+                        0
+                    }
+                    _ => self.next_nibble()? << 4,
+                };
+                let name = self.next()?;
+                match self.value(name) {
+                    Some(addr @ 0..=0xFFF) => {
+                        self.emit_op(0x6000 | high | (addr as u16 >> 8))?;
+                        self.emit_op(0x6100 | (addr as u16 & 0xFF))?;
+                    }
+                    Some(_) => return Err(self.err(format!("`{}` is out of range", name))),
+                    None => {
+                        let line = self.line();
+                        self.fixups.push((self.here, name, Patch::Unpack, line));
+                        self.emit_op(0x6000 | high)?;
+                        self.emit_op(0x6100)?;
+                    }
+                }
+            }
+            ":breakpoint" => {
+                self.next()?;
+            }
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            }
+            "scroll-down" => {
+                let n = self.next_nibble()?;
+                self.emit_op(0x00C0 | n)?;
+            }
+            "scroll-up" => {
+                let n = self.next_nibble()?;
+                self.emit_op(0x00D0 | n)?;
+            }
+            "plane" => {
+                let n = self.next_nibble()?;
+                self.emit_op(0xF001 | n << 8)?;
+            }
+            "jump" => self.emit_addr_op(0x1000)?,
+            "jump0" => self.emit_addr_op(0xB000)?,
+            "native" => self.emit_addr_op(0x0000)?,
+            "bcd" | "saveflags" | "loadflags" => {
+                let x = u16::from(self.next_reg()?) << 8;
+                let op = match token {
+                    "bcd" => 0xF033,
+                    "saveflags" => 0xF075,
+                    _ => 0xF085,
+                };
+                self.emit_op(op | x)?;
+            }
+            "save" | "load" => {
+                let x = u16::from(self.next_reg()?) << 8;
+                if self.peek() == Some("-") {
+                    self.pos += 1;
+                    let y = u16::from(self.next_reg()?) << 4;
+                    self.emit_op(if token == "save" { 0x5002 } else { 0x5003 } | x | y)?;
+                } else {
+                    self.emit_op(if token == "save" { 0xF055 } else { 0xF065 } | x)?;
+                }
+            }
+            "sprite" => {
+                let x = u16::from(self.next_reg()?) << 8;
+                let y = u16::from(self.next_reg()?) << 4;
+                let n = self.next_nibble()?;
+                self.emit_op(0xD000 | x | y | n)?;
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = u16::from(self.next_reg()?) << 8;
+                let op = match token {
+                    "delay" => 0xF015,
+                    "buzzer" => 0xF018,
+                    _ => 0xF03A,
+                };
+                self.emit_op(op | x)?;
+            }
+            "i" => match self.next()? {
+                ":=" => match self.peek() {
+                    Some(kind @ ("hex" | "bighex")) => {
+                        self.pos += 1;
+                        let x = u16::from(self.next_reg()?) << 8;
+                        self.emit_op(if kind == "hex" { 0xF029 } else { 0xF030 } | x)?;
+                    }
+                    Some("long") => {
+                        self.pos += 1;
+                        self.emit_op(0xF000)?;
+                        let name = self.next()?;
+                        match self.value(name) {
+                            Some(addr @ 0..=0xFFFF) => self.emit_op(addr as u16)?,
+                            Some(_) => {
+                                return Err(self.err(format!("`{}` is out of range", name)));
+                            }
+                            None => {
+                                let line = self.line();
+                                self.fixups.push((self.here, name, Patch::Long, line));
+                                self.emit_op(0)?;
+                            }
+                        }
+                    }
+                    _ => self.emit_addr_op(0xA000)?,
+                },
+                "+=" => {
+                    let x = u16::from(self.next_reg()?) << 8;
+                    self.emit_op(0xF01E | x)?;
+                }
+                op => return Err(self.err(format!("unknown operator `{}` for i", op))),
+            },
+            "if" => {
+                let cond = self.cond()?;
+                match self.next()? {
+                    "then" => self.emit_skip(&cond, false)?,
+                    "begin" => {
+                        self.emit_skip(&cond, true)?;
+                        let jump = self.emit_jump()?;
+                        self.blocks.push((Block::If(jump), self.line()));
+                    }
+                    _ => return Err(self.err("expected `then` or `begin`")),
+                }
+            }
+            "else" => match self.blocks.pop() {
+                Some((Block::If(jump), line)) => {
+                    let past_else = self.emit_jump()?;
+                    self.patch_jump(jump)?;
+                    self.blocks.push((Block::Else(past_else), line));
+                }
+                _ => return Err(self.err("`else` without `if ... begin`")),
+            },
+            "end" => match self.blocks.pop() {
+                Some((Block::If(jump) | Block::Else(jump), _)) => self.patch_jump(jump)?,
+                _ => return Err(self.err("`end` without `if ... begin`")),
+            },
+            "loop" => {
+                let start = self.addr();
+                self.blocks
+                    .push((Block::Loop(start, Vec::new()), self.line()));
+            }
+            "while" => {
+                let cond = self.cond()?;
+                self.emit_skip(&cond, true)?;
+                let jump = self.emit_jump()?;
+                match self
+                    .blocks
+                    .iter_mut()
+                    .rev()
+                    .find_map(|(block, _)| match block {
+                        Block::Loop(_, exits) => Some(exits),
+                        _ => None,
+                    }) {
+                    Some(exits) => exits.push(jump),
+                    None => return Err(self.err("`while` outside of a loop")),
+                }
+            }
+            "again" => match self.blocks.pop() {
+                Some((Block::Loop(start, exits), _)) => {
+                    self.emit_op(0x1000 | start)?;
+                    for jump in exits {
+                        self.patch_jump(jump)?;
+                    }
+                }
+                _ => return Err(self.err("`again` without `loop`")),
+            },
+            _ if token.starts_with(':') => {
+                return Err(self.err(format!("unsupported directive `{}`", token)));
+            }
+            _ if self.reg(token).is_some() => self.register_statement(token)?,
+            _ if self.value(token).is_some() && !self.labels.contains_key(token) => {
+                self.pos -= 1;
+                let byte = self.next_byte()?;
+                self.emit(&[byte])?;
+            }
+            // A bare label is a call
+            _ => {
+                self.pos -= 1;
+                self.emit_addr_op(0x2000)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn register_statement(&mut self, token: &str) -> Result<(), AssemblyError> {
+        let x = u16::from(self.reg(token).unwrap()) << 8;
+        let op = self.next()?;
+        let rhs = self.peek().unwrap_or_default();
+        if let Some(y) = self.reg(rhs) {
+            self.pos += 1;
+            let y = u16::from(y) << 4;
+            let n = match op {
+                ":=" => 0x0,
+                "|=" => 0x1,
+                "&=" => 0x2,
+                "^=" => 0x3,
+                "+=" => 0x4,
+                "-=" => 0x5,
+                ">>=" => 0x6,
+                "=-" => 0x7,
+                "<<=" => 0xE,
+                _ => return Err(self.err(format!("unknown operator `{}`", op))),
+            };
+            return self.emit_op(0x8000 | x | y | n);
+        }
+        match (op, rhs) {
+            (":=", "key") => {
+                self.pos += 1;
+                self.emit_op(0xF00A | x)
+            }
+            (":=", "delay") => {
+                self.pos += 1;
+                self.emit_op(0xF007 | x)
+            }
+            (":=", "random") => {
+                self.pos += 1;
+                let mask = self.next_byte()?;
+                self.emit_op(0xC000 | x | u16::from(mask))
+            }
+            (":=", _) => {
+                let n = self.next_byte()?;
+                self.emit_op(0x6000 | x | u16::from(n))
+            }
+            ("+=", _) => {
+                let n = self.next_byte()?;
+                self.emit_op(0x7000 | x | u16::from(n))
+            }
+            ("-=", _) => {
+                let n = self.next_byte()?;
+                self.emit_op(0x7000 | x | u16::from(n.wrapping_neg()))
+            }
+            _ => Err(self.err(format!("unknown operator `{}`", op))),
+        }
+    }
+}
+
+#[test]
+fn test_read_cartridge() {
+    let json = br##"{"program": ": main\n  loop again", "options": {"tickrate": 20,
+        "shiftQuirks": true, "loadStoreQuirks": true, "fillColor": "#FFCC00"}}"##;
+    let mut payload = (json.len() as u32).to_be_bytes().to_vec();
+    payload.extend_from_slice(json);
+    // Hide the payload under a label made of palette entries 4, 8 and 12
+    let mut pixels: Vec<u8> = payload
+        .iter()
+        .flat_map(|&b| [b >> 6, (b >> 4) & 3, (b >> 2) & 3, b & 3])
+        .enumerate()
+        .map(|(i, bits)| ((i % 3 + 1) as u8 * 4) | bits)
+        .collect();
+    let width = 32;
+    pixels.resize(pixels.len().next_multiple_of(width), 0);
+    let palette: Vec<u8> = (0..=255).flat_map(|i| [i, i, i]).collect();
+    let mut gif = Vec::new();
+    {
+        let height = (pixels.len() / width) as u16;
+        let mut encoder = gif::Encoder::new(&mut gif, width as u16, height, &palette).unwrap();
+        let frame = gif::Frame::from_indexed_pixels(width as u16, height, pixels, None);
+        encoder.write_frame(&frame).unwrap();
+    }
+    let cartridge = read_cartridge(&gif[..]).unwrap();
+    assert_eq!(cartridge.program, ": main\n  loop again");
+    assert_eq!(cartridge.options.ips(), Some(1200));
+    assert_eq!(cartridge.options.fill_color.as_deref(), Some("#FFCC00"));
+    let mut quirks = Quirks::default();
+    cartridge.options.apply_quirks(&mut quirks);
+    assert!(!quirks.shift_uses_vy);
+    assert!(quirks.load_store_keeps_i);
+}
+
+#[test]
+fn test_assemble() {
+    let source = "
+        :const SPEED 2
+        :alias x v3
+        : main
+            x := 0
+            loop
+                x += SPEED
+                if x == 10 then x := 0
+                while x != 8
+                draw
+            again
+            if x > 4 begin
+                v0 := key
+            else
+                i := long sprite
+            end
+        : draw
+            i := sprite
+            sprite x x 1
+        ; # return
+        : sprite 0x80 0b1
+    ";
+    #[rustfmt::skip]
+    let rom = [
+        0x12, 0x02, // 0x200: JP main
+        0x63, 0x00, // 0x202: LD V3, 0
+        0x73, 0x02, // 0x204: ADD V3, 2
+        0x43, 0x0A, // 0x206: SNE V3, 10
+        0x63, 0x00, // 0x208: LD V3, 0
+        0x43, 0x08, // 0x20A: SNE V3, 8
+        0x12, 0x12, // 0x20C: JP 0x212
+        0x22, 0x22, // 0x20E: CALL draw
+        0x12, 0x04, // 0x210: JP 0x204
+        0x6F, 0x04, // 0x212: LD VF, 4
+        0x8F, 0x35, // 0x214: SUB VF, V3
+        0x3F, 0x00, // 0x216: SE VF, 0
+        0x12, 0x1E, // 0x218: JP 0x21E
+        0xF0, 0x0A, // 0x21A: LD V0, K
+        0x12, 0x22, // 0x21C: JP 0x222
+        0xF0, 0x00, // 0x21E: LD I, long sprite
+        0x02, 0x28,
+        0xA2, 0x28, // 0x222: LD I, sprite
+        0xD3, 0x31, // 0x224: DRW V3, V3, 1
+        0x00, 0xEE, // 0x226: RET
+        0x80, 0x01, // 0x228: sprite
+    ];
+    assert_eq!(assemble(source).unwrap(), rom);
+
+    let err = |source| assemble(source).unwrap_err().to_string();
+    assert_eq!(err(": main\n  v0 := 256"), "line 2: `256` is out of range");
+    assert_eq!(err(": main\n  loop\n  v0 := 1"), "line 2: unclosed block");
+    assert_eq!(
+        err(": start\n  jump start"),
+        "line 1: undefined label `main`"
+    );
+    assert_eq!(
+        err(": main\n  :macro foo"),
+        "line 2: unsupported directive `:macro`"
+    );
+}
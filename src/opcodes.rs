@@ -0,0 +1,384 @@
+//! Declarative opcode specification.
+//!
+//! Every instruction the interpreter understands is described exactly once in [`OPCODES`].
+//! Decoding, execution, and any tooling that needs to know about instructions
+//! (documentation, disassembly, assembly) should be driven by this table.
+
+use super::{Byte, Instruction, Nibble, Semiword, VirtualMachine, quirks::QUIRKS};
+
+/// The CHIP-8 extension an opcode belongs to.
+///
+/// Extensions are ordered by age. SUPER-CHIP and XO-CHIP each build on the ones before
+/// them, while CHIP-8X branched off the original instruction set and replaces some of it, so
+/// it doesn't mix with the others. MegaChip builds on SUPER-CHIP alone, and redefines some of
+/// its instructions for the 256-color screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Extension {
+    /// The original CHIP-8 instruction set.
+    Chip8,
+    /// SUPER-CHIP extensions.
+    SuperChip,
+    /// XO-CHIP extensions.
+    XoChip,
+    /// [CHIP-8X](crate::chip8x) extensions.
+    Chip8X,
+    /// [MegaChip](crate::megachip) extensions.
+    MegaChip,
+}
+
+impl Extension {
+    /// Returns whether support for this extension was compiled in.
+    ///
+    /// Extensions are gated behind the cargo feature of the same name.
+    pub fn is_compiled_in(self) -> bool {
+        match self {
+            Extension::Chip8 => true,
+            Extension::SuperChip => cfg!(feature = "schip"),
+            Extension::XoChip => cfg!(feature = "xochip"),
+            Extension::Chip8X => cfg!(feature = "chip8x"),
+            Extension::MegaChip => cfg!(feature = "megachip"),
+        }
+    }
+
+    /// Returns whether a platform understanding this extension also understands the
+    /// instructions of `ext`.
+    pub fn includes(self, ext: Extension) -> bool {
+        match (self, ext) {
+            (Extension::Chip8X, ext) => matches!(ext, Extension::Chip8 | Extension::Chip8X),
+            (Extension::MegaChip, ext) => matches!(
+                ext,
+                Extension::Chip8 | Extension::SuperChip | Extension::MegaChip
+            ),
+            (_, Extension::Chip8X | Extension::MegaChip) => false,
+            _ => ext <= self,
+        }
+    }
+
+    /// Returns the name of the extension, as used in the JSON export.
+    pub fn name(self) -> &'static str {
+        match self {
+            Extension::Chip8 => "chip8",
+            Extension::SuperChip => "schip",
+            Extension::XoChip => "xochip",
+            Extension::Chip8X => "chip8x",
+            Extension::MegaChip => "megachip",
+        }
+    }
+}
+
+/// The operand fields of a raw instruction.
+#[derive(Debug, Clone, Copy)]
+pub struct Operands {
+    /// A 12-bit value, the lowest 12 bits of the instruction
+    pub nnn: Semiword,
+    /// A 4-bit value, the lowest 4 bits of the instruction
+    pub n: Nibble,
+    /// A 4-bit value, the lower 4 bits of the high byte of the instruction
+    pub x: Nibble,
+    /// A 4-bit value, the upper 4 bits of the low byte of the instruction
+    pub y: Nibble,
+    /// An 8-bit value, the lowest 8 bits of the instruction
+    pub kk: Byte,
+}
+
+impl Operands {
+    /// Extracts the operand fields of a raw instruction.
+    pub fn new(ins: u16) -> Self {
+        Self {
+            nnn: ins & 0x0FFF,
+            n: (ins & 0x000F) as Nibble,
+            x: ((ins & 0x0F00) >> 8) as Nibble,
+            y: ((ins & 0x00F0) >> 4) as Nibble,
+            kk: (ins & 0x00FF) as Byte,
+        }
+    }
+}
+
+/// Specification of a single opcode.
+pub struct OpcodeSpec {
+    /// The fixed bits of the opcode.
+    pub pattern: u16,
+    /// Which bits of the instruction are fixed by `pattern`.
+    pub mask: u16,
+    /// Assembly mnemonic, with operand placeholders.
+    pub mnemonic: &'static str,
+    /// Short description of what the instruction does.
+    pub description: &'static str,
+    /// The extension that introduced this opcode.
+    pub extension: Extension,
+    pub(crate) decode: fn(Operands) -> Instruction,
+    pub(crate) exec: fn(&mut VirtualMachine, Operands),
+}
+
+impl OpcodeSpec {
+    /// Returns whether `ins` is an instance of this opcode.
+    pub fn matches(&self, ins: u16) -> bool {
+        ins & self.mask == self.pattern
+    }
+
+    /// Returns the opcode in the usual notation, like `8xy4`, `Fx0A` or `1nnn`.
+    pub fn template(&self) -> String {
+        let nnn = self.mnemonic.contains("nnn");
+        let kk = self.mnemonic.contains("kk");
+        (0..4)
+            .rev()
+            .map(|pos| {
+                let nibble = (self.pattern >> (pos * 4)) & 0xF;
+                if (self.mask >> (pos * 4)) & 0xF != 0 {
+                    return char::from_digit(nibble.into(), 16)
+                        .unwrap()
+                        .to_ascii_uppercase();
+                }
+                match pos {
+                    _ if nnn => 'n',
+                    0 | 1 if kk => 'k',
+                    2 => 'x',
+                    1 => 'y',
+                    _ => 'n',
+                }
+            })
+            .collect()
+    }
+}
+
+macro_rules! op {
+    ($ext:ident, $pattern:expr, $mask:expr, $mnemonic:expr, $description:expr,
+     $decode:expr, $exec:expr) => {
+        $crate::opcodes::OpcodeSpec {
+            pattern: $pattern,
+            mask: $mask,
+            mnemonic: $mnemonic,
+            description: $description,
+            extension: $crate::opcodes::Extension::$ext,
+            decode: $decode,
+            exec: $exec,
+        }
+    };
+}
+
+#[cfg(any(feature = "schip", feature = "xochip", feature = "chip8x"))]
+pub(crate) use op;
+
+use Instruction::*;
+
+/// The classic CHIP-8 opcodes.
+///
+/// Opcodes are matched in order, so more specific patterns come before more general ones.
+#[rustfmt::skip]
+pub static OPCODES: &[OpcodeSpec] = &[
+    op!(Chip8, 0x00E0, 0xFFFF, "CLS", "Clear the display.",
+        |_| ClearDisplay, |vm, _| vm.clear_display()),
+    op!(Chip8, 0x00EE, 0xFFFF, "RET", "Return from a subroutine.",
+        |_| Return, |vm, _| vm.ret_from_subroutine()),
+    op!(Chip8, 0x0000, 0xF000, "SYS nnn",
+        "Jump to a machine code routine at nnn. Ignored unless a SYS handler is set.",
+        |o| JumpToSysRoutine { addr: o.nnn },
+        |vm, o| vm.jump_to_sys_routine(o.nnn)),
+    op!(Chip8, 0x1000, 0xF000, "JP nnn", "Jump to nnn.",
+        |o| JumpToAddress { addr: o.nnn }, |vm, o| vm.jump_addr(o.nnn)),
+    op!(Chip8, 0x2000, 0xF000, "CALL nnn", "Call subroutine at nnn.",
+        |o| CallSubroutine { addr: o.nnn }, |vm, o| vm.call_subroutine(o.nnn)),
+    op!(Chip8, 0x3000, 0xF000, "SE Vx, kk", "Skip next instruction if Vx == kk.",
+        |o| SkipNextVxEq { x: o.x, cmp_with: o.kk },
+        |vm, o| vm.skip_next_vx_eq(o.x as usize, o.kk)),
+    op!(Chip8, 0x4000, 0xF000, "SNE Vx, kk", "Skip next instruction if Vx != kk.",
+        |o| SkipNextVxNe { x: o.x, cmp_with: o.kk },
+        |vm, o| vm.skip_next_vx_ne(o.x as usize, o.kk)),
+    op!(Chip8, 0x5000, 0xF00F, "SE Vx, Vy", "Skip next instruction if Vx == Vy.",
+        |o| SkipNextVxEqVy { x: o.x, y: o.y },
+        |vm, o| vm.skip_next_vx_eq_vy(o.x as usize, o.y as usize)),
+    op!(Chip8, 0x6000, 0xF000, "LD Vx, kk", "Set Vx = kk.",
+        |o| SetVxByte { x: o.x, to: o.kk },
+        |vm, o| vm.set_vx_byte(o.x as usize, o.kk)),
+    op!(Chip8, 0x7000, 0xF000, "ADD Vx, kk", "Set Vx = Vx + kk.",
+        |o| AddVxByte { x: o.x, rhs: o.kk },
+        |vm, o| vm.add_vx_byte(o.x as usize, o.kk)),
+    op!(Chip8, 0x8000, 0xF00F, "LD Vx, Vy", "Set Vx = Vy.",
+        |o| SetVxToVy { x: o.x, y: o.y },
+        |vm, o| vm.set_vx_to_vy(o.x as usize, o.y as usize)),
+    op!(Chip8, 0x8001, 0xF00F, "OR Vx, Vy", "Set Vx = Vx OR Vy.",
+        |o| SetVxToVxOrVy { x: o.x, y: o.y },
+        |vm, o| vm.set_vx_to_vx_or_vy(o.x as usize, o.y as usize)),
+    op!(Chip8, 0x8002, 0xF00F, "AND Vx, Vy", "Set Vx = Vx AND Vy.",
+        |o| SetVxToVxAndVy { x: o.x, y: o.y },
+        |vm, o| vm.set_vx_to_vx_and_vy(o.x as usize, o.y as usize)),
+    op!(Chip8, 0x8003, 0xF00F, "XOR Vx, Vy", "Set Vx = Vx XOR Vy.",
+        |o| SetVxToVxXorVy { x: o.x, y: o.y },
+        |vm, o| vm.set_vx_to_vx_xor_vy(o.x as usize, o.y as usize)),
+    op!(Chip8, 0x8004, 0xF00F, "ADD Vx, Vy", "Set Vx = Vx + Vy, VF = carry.",
+        |o| AddVxVy { x: o.x, y: o.y },
+        |vm, o| vm.add_vx_vy(o.x as usize, o.y as usize)),
+    op!(Chip8, 0x8005, 0xF00F, "SUB Vx, Vy", "Set Vx = Vx - Vy, VF = NOT borrow.",
+        |o| SubVxVy { x: o.x, y: o.y },
+        |vm, o| vm.sub_vx_vy(o.x as usize, o.y as usize)),
+    op!(Chip8, 0x8006, 0xF00F, "SHR Vx, Vy", "Set Vx = Vy SHR 1, VF = shifted out bit.",
+        |o| SetVxToVyShr1 { x: o.x, y: o.y },
+        |vm, o| vm.set_vx_to_vy_shr_1(o.x as usize, o.y as usize)),
+    op!(Chip8, 0x8007, 0xF00F, "SUBN Vx, Vy", "Set Vx = Vy - Vx, VF = NOT borrow.",
+        |o| SubnVxVy { x: o.x, y: o.y },
+        |vm, o| vm.subn_vx_vy(o.x as usize, o.y as usize)),
+    op!(Chip8, 0x800E, 0xF00F, "SHL Vx, Vy", "Set Vx = Vy SHL 1, VF = shifted out bit.",
+        |o| SetVxToVyShl1 { x: o.x, y: o.y },
+        |vm, o| vm.set_vx_to_vy_shl_1(o.x as usize, o.y as usize)),
+    op!(Chip8, 0x9000, 0xF00F, "SNE Vx, Vy", "Skip next instruction if Vx != Vy.",
+        |o| SkipNextVxNeVy { x: o.x, y: o.y },
+        |vm, o| vm.skip_next_vx_ne_vy(o.x as usize, o.y as usize)),
+    op!(Chip8, 0xA000, 0xF000, "LD I, nnn", "Set I = nnn.",
+        |o| SetI { to: o.nnn }, |vm, o| vm.set_i(o.nnn)),
+    op!(Chip8, 0xB000, 0xF000, "JP V0, nnn", "Jump to nnn + V0.",
+        |o| JumpToAddrPlusV0 { addr: o.nnn }, |vm, o| vm.jump_addr_plus_v0(o.x as usize, o.nnn)),
+    op!(Chip8, 0xC000, 0xF000, "RND Vx, kk", "Set Vx = random byte AND kk.",
+        |o| SetVxRandAnd { x: o.x, and: o.kk },
+        |vm, o| vm.set_vx_rand_and(o.x as usize, o.kk)),
+    op!(Chip8, 0xD000, 0xF000, "DRW Vx, Vy, n",
+        "Draw n-byte sprite from memory location I at (Vx, Vy), VF = collision.",
+        |o| DisplaySprite { x: o.x, y: o.y, n: o.n },
+        |vm, o| vm.display_sprite(o.x as usize, o.y as usize, o.n as usize)),
+    op!(Chip8, 0xE09E, 0xF0FF, "SKP Vx", "Skip next instruction if key Vx is pressed.",
+        |o| SkipNextKeyVxPressed { x: o.x },
+        |vm, o| vm.skip_next_key_vx_pressed(o.x as usize)),
+    op!(Chip8, 0xE0A1, 0xF0FF, "SKNP Vx", "Skip next instruction if key Vx is not pressed.",
+        |o| SkipNextKeyVxNotPressed { x: o.x },
+        |vm, o| vm.skip_next_key_vx_not_pressed(o.x as usize)),
+    op!(Chip8, 0xF007, 0xF0FF, "LD Vx, DT", "Set Vx = delay timer.",
+        |o| SetVxToDelayTimer { x: o.x },
+        |vm, o| vm.set_vx_to_delay_timer(o.x as usize)),
+    op!(Chip8, 0xF00A, 0xF0FF, "LD Vx, K", "Wait for a key press, store the key in Vx.",
+        |o| WaitForKeypressStoreInVx { x: o.x },
+        |vm, o| vm.wait_for_keypress_store_in_vx(o.x as usize)),
+    op!(Chip8, 0xF015, 0xF0FF, "LD DT, Vx", "Set delay timer = Vx.",
+        |o| SetDelayTimer { x: o.x }, |vm, o| vm.set_delay_timer(o.x as usize)),
+    op!(Chip8, 0xF018, 0xF0FF, "LD ST, Vx", "Set sound timer = Vx.",
+        |o| SetSoundTimer { x: o.x }, |vm, o| vm.set_sound_timer(o.x as usize)),
+    op!(Chip8, 0xF01E, 0xF0FF, "ADD I, Vx", "Set I = I + Vx.",
+        |o| AddVxToI { x: o.x }, |vm, o| vm.add_vx_to_i(o.x as usize)),
+    op!(Chip8, 0xF029, 0xF0FF, "LD F, Vx", "Set I = location of sprite for digit Vx.",
+        |o| SetIToLocOfDigitVx { x: o.x },
+        |vm, o| vm.set_i_to_loc_of_digit_vx(o.x as usize)),
+    op!(Chip8, 0xF033, 0xF0FF, "LD B, Vx",
+        "Store BCD representation of Vx in memory locations I, I+1, and I+2.",
+        |o| StoreBcdOfVxToI { x: o.x },
+        |vm, o| vm.store_bcd_of_vx_to_i(o.x as usize)),
+    op!(Chip8, 0xF055, 0xF0FF, "LD [I], Vx",
+        "Store registers V0 through Vx in memory starting at location I.",
+        |o| CopyV0ThroughVxToMem { x: o.x },
+        |vm, o| vm.copy_v0_through_vx_to_mem(u16::from(o.x))),
+    op!(Chip8, 0xF065, 0xF0FF, "LD Vx, [I]",
+        "Read registers V0 through Vx from memory starting at location I.",
+        |o| ReadV0ThroughVxFromMem { x: o.x },
+        |vm, o| vm.read_v0_through_vx_from_mem(u16::from(o.x))),
+];
+
+// MegaChip comes first, so its versions of older instructions take over where it's understood
+static EXTENSIONS: &[&[OpcodeSpec]] = &[
+    #[cfg(feature = "megachip")]
+    crate::megachip::OPCODES,
+    #[cfg(feature = "schip")]
+    crate::schip::OPCODES,
+    #[cfg(feature = "xochip")]
+    crate::xochip::OPCODES,
+    #[cfg(feature = "chip8x")]
+    crate::chip8x::OPCODES,
+];
+
+/// Returns all opcodes that were compiled in, extensions first.
+pub fn all() -> impl Iterator<Item = &'static OpcodeSpec> {
+    EXTENSIONS
+        .iter()
+        .flat_map(|table| table.iter())
+        .chain(OPCODES)
+}
+
+/// Looks up the specification of a raw instruction.
+///
+/// CHIP-8X and MegaChip opcodes only match instructions that no other extension understands,
+/// as some of them replace older ones. That keeps the result the same whether or not they
+/// were compiled in, for all but the instructions only they understand.
+pub fn lookup(ins: u16) -> Option<&'static OpcodeSpec> {
+    all()
+        .find(|spec| {
+            spec.matches(ins) && !matches!(spec.extension, Extension::Chip8X | Extension::MegaChip)
+        })
+        .or_else(|| all().find(|spec| spec.matches(ins)))
+}
+
+/// Looks up the specification of a raw instruction, leaving out extensions that `newest`
+/// doesn't [include](Extension::includes).
+pub fn lookup_up_to(ins: u16, newest: Extension) -> Option<&'static OpcodeSpec> {
+    all().find(|spec| newest.includes(spec.extension) && spec.matches(ins))
+}
+
+/// Exports the opcode table and the quirks affecting each opcode as JSON.
+///
+/// The output is an array of objects with the fields `opcode` (like `"8xy4"`), `pattern`,
+/// `mask`, `mnemonic`, `description`, `extension` and `quirks`, the latter listing the names
+/// of the [`Quirks`](crate::Quirks) fields that change the behavior of the opcode.
+pub fn to_json() -> String {
+    let mut out = String::from("[\n");
+    for (i, spec) in all().enumerate() {
+        let quirks: Vec<_> = QUIRKS
+            .iter()
+            .filter(|quirk| quirk.opcodes.contains(&spec.pattern))
+            .map(|quirk| json_string(quirk.name))
+            .collect();
+        if i != 0 {
+            out.push_str(",\n");
+        }
+        out.push_str(&format!(
+            "  {{\"opcode\": {}, \"pattern\": {}, \"mask\": {}, \"mnemonic\": {}, \
+             \"description\": {}, \"extension\": {}, \"quirks\": [{}]}}",
+            json_string(&spec.template()),
+            spec.pattern,
+            spec.mask,
+            json_string(spec.mnemonic),
+            json_string(spec.description),
+            json_string(spec.extension.name()),
+            quirks.join(", ")
+        ));
+    }
+    out.push_str("\n]\n");
+    out
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[test]
+fn test_template() {
+    let template = |ins| lookup(ins).unwrap().template();
+    assert_eq!(template(0x8124), "8xy4");
+    assert_eq!(template(0xF10A), "Fx0A");
+    assert_eq!(template(0x1234), "1nnn");
+    assert_eq!(template(0x7123), "7xkk");
+    assert_eq!(template(0xD123), "Dxyn");
+    assert_eq!(template(0x00E0), "00E0");
+}
+
+#[test]
+fn test_to_json() {
+    let json = to_json();
+    assert_eq!(json.lines().count(), all().count() + 2);
+    assert!(json.contains(
+        "{\"opcode\": \"8xy6\", \"pattern\": 32774, \"mask\": 61455, \"mnemonic\": \"SHR Vx, Vy\", "
+    ));
+    assert!(json.contains("\"quirks\": [\"shift_uses_vy\"]"));
+    assert_eq!(json_string("a\"b\\c\n"), r#""a\"b\\c\u000a""#);
+}
+
+#[test]
+fn test_patterns_are_within_masks() {
+    for spec in all() {
+        assert_eq!(spec.pattern & !spec.mask, 0, "{}", spec.mnemonic);
+    }
+}
@@ -1,25 +1,54 @@
 use {
-    super::VirtualMachine,
-    std::{fmt::Write, num::Wrapping},
+    super::{AudioEvent, EventKind, HaltReason, Severity, VirtualMachine},
+    std::num::Wrapping,
 };
 
 impl VirtualMachine {
-    pub(super) fn jump_to_sys_routine(&mut self, _addr: usize) {
-        // Do nothing
+    pub(super) fn display_changed(&mut self) {
+        self.display_updated = true;
+        self.emit(EventKind::DisplayUpdated);
+        self.present_display();
     }
 
+    // Clears the selected planes
     pub(super) fn clear_display(&mut self) {
-        for px in self.display.iter_mut() {
-            *px = 0;
+        for px in self.display.pixels.iter_mut() {
+            *px &= !self.planes;
         }
+        self.display_changed();
     }
 
     pub(super) fn ret_from_subroutine(&mut self) {
-        self.pc = self.stack[self.sp.0 as usize];
-        self.sp -= 1;
+        // Slot 0 is never written, the first call goes to slot 1
+        match self.stack.get(self.sp.0 as usize) {
+            Some(&addr) if self.sp.0 != 0 => {
+                self.pc = addr;
+                self.sp -= 1;
+            }
+            _ => {
+                self.log_line(
+                    Severity::Error,
+                    Some(self.pc.wrapping_sub(2)),
+                    format_args!("Return without a call. Halted."),
+                );
+                self.halt(HaltReason::StackUnderflow);
+            }
+        }
     }
 
     pub(super) fn jump_addr(&mut self, addr: u16) {
+        #[cfg(feature = "hires")]
+        let addr = self.two_page_jump(addr);
+        // The jump instruction was fetched from pc - 2, so this is a jump to itself.
+        // Nothing can ever break out of that loop, so the program is done.
+        if addr == self.pc.wrapping_sub(2) {
+            self.log_line(
+                Severity::Info,
+                Some(addr),
+                format_args!("Program ended at {:#x}. Halted.", addr),
+            );
+            self.halt(HaltReason::ProgramEnded);
+        }
         self.pc = addr;
     }
 
@@ -28,7 +57,11 @@ impl VirtualMachine {
         match self.stack.get_mut(self.sp.0 as usize) {
             Some(mem) => *mem = self.pc,
             None => {
-                writeln!(self.log, "Stack out of bounds. Ignoring write.").unwrap();
+                self.log_line(
+                    Severity::Warning,
+                    Some(self.pc.wrapping_sub(2)),
+                    format_args!("Stack out of bounds. Ignoring write."),
+                );
             }
         };
         self.pc = addr;
@@ -66,43 +99,65 @@ impl VirtualMachine {
 
     pub(super) fn set_vx_to_vx_or_vy(&mut self, x: usize, y: usize) {
         self.v[x] |= self.v[y];
+        self.logic_vf_reset();
     }
 
     pub(super) fn set_vx_to_vx_and_vy(&mut self, x: usize, y: usize) {
         self.v[x] &= self.v[y];
+        self.logic_vf_reset();
     }
 
     pub(super) fn set_vx_to_vx_xor_vy(&mut self, x: usize, y: usize) {
         self.v[x] ^= self.v[y];
+        self.logic_vf_reset();
     }
 
+    fn logic_vf_reset(&mut self) {
+        if self.quirks.logic_resets_vf {
+            self.v[0xF].0 = 0;
+        }
+    }
+
+    // In the arithmetic instructions, the flag is written after the result,
+    // so it wins when x is 0xF
     pub(super) fn add_vx_vy(&mut self, x: usize, y: usize) {
-        let cond = u16::from(self.v[x].0) + u16::from(self.v[y].0) > 255;
-        self.v[0xF].0 = cond.into();
-        self.v[x] += self.v[y];
+        let (sum, carry) = self.v[x].0.overflowing_add(self.v[y].0);
+        self.v[x].0 = sum;
+        self.v[0xF].0 = carry.into();
     }
 
     pub(super) fn sub_vx_vy(&mut self, x: usize, y: usize) {
-        let cond = self.v[x] > self.v[y];
-        self.v[0xF].0 = cond.into();
-        self.v[x] -= self.v[y];
+        let (diff, borrow) = self.v[x].0.overflowing_sub(self.v[y].0);
+        self.v[x].0 = diff;
+        self.v[0xF].0 = (!borrow).into();
     }
 
     pub(super) fn subn_vx_vy(&mut self, x: usize, y: usize) {
-        let cond = self.v[y] > self.v[x];
-        self.v[0xF].0 = cond.into();
-        self.v[x] = self.v[y] - self.v[x];
+        let (diff, borrow) = self.v[y].0.overflowing_sub(self.v[x].0);
+        self.v[x].0 = diff;
+        self.v[0xF].0 = (!borrow).into();
+    }
+
+    fn shift_source(&self, x: usize, y: usize) -> u8 {
+        if self.quirks.shift_uses_vy {
+            self.v[y].0
+        } else {
+            self.v[x].0
+        }
     }
 
     pub(super) fn set_vx_to_vy_shr_1(&mut self, x: usize, y: usize) {
-        self.v[0xF].0 = nth_bit(self.v[y].0, 7);
-        self.v[x] = self.v[y] >> 1;
+        let src = self.shift_source(x, y);
+        self.v[x].0 = src >> 1;
+        self.v[0xF].0 = nth_bit(src, 7);
     }
 
     pub(super) fn set_vx_to_vy_shl_1(&mut self, x: usize, y: usize) {
-        self.v[0xF].0 = nth_bit(self.v[y].0, 0);
-        self.v[x] = self.v[y] << 1;
+        let src = self.shift_source(x, y);
+        self.v[x].0 = src << 1;
+        self.v[0xF].0 = nth_bit(src, 0);
     }
+
     pub(super) fn skip_next_vx_ne_vy(&mut self, x: usize, y: usize) {
         if self.v[x] != self.v[y] {
             self.pc += 2;
@@ -113,73 +168,121 @@ impl VirtualMachine {
         self.i = to;
     }
 
+    pub(super) fn jump_addr_plus_v0(&mut self, x: usize, addr: u16) {
+        let offset = if self.quirks.jump_uses_vx { x } else { 0 };
+        self.pc = addr + u16::from(self.v[offset].0);
+    }
+
     pub(super) fn set_vx_rand_and(&mut self, x: usize, to: u8) {
-        use rand::Rng;
-        let mut rgen = rand::thread_rng();
-        self.v[x].0 = rgen.r#gen::<u8>() & to;
+        self.v[x].0 = self.rng.next_byte() & to;
     }
 
     pub(super) fn display_sprite(&mut self, vx: usize, vy: usize, n: usize) {
-        use super::{DISPLAY_HEIGHT, DISPLAY_WIDTH};
+        self.draw_sprite(vx, vy, 8, n);
+    }
+
+    // Draws a sprite of `width` (a multiple of 8) by `height` pixels, stored row by row at I,
+    // to each selected plane. The sprites for the planes follow one another.
+    pub(super) fn draw_sprite(&mut self, vx: usize, vy: usize, width: usize, height: usize) {
+        use super::SpriteDraw;
 
+        let (display_width, display_height) = (self.display.width, self.display.height);
+        // The starting position wraps around, but the sprite itself is clipped
+        let x0 = self.v[vx].0 as usize % display_width;
+        let y0 = self.v[vy].0 as usize % display_height;
         self.v[0xF].0 = 0;
 
-        for y in 0..n {
-            let b = self.ram[self.i as usize + y];
-            for x in 0..8 {
-                let xx = x + self.v[vx].0 as usize;
-                let yy = y + self.v[vy].0 as usize;
-
-                if xx < DISPLAY_WIDTH && yy < DISPLAY_HEIGHT {
-                    let idx = yy * DISPLAY_WIDTH + xx;
-                    if b & (0b1000_0000 >> x) != 0 {
-                        if self.display[idx] == 1 {
-                            self.v[0xF].0 = 1;
+        let (mut addr, planes) = (self.i as usize, self.planes);
+        for plane in [1, 2].into_iter().filter(|plane| planes & plane != 0) {
+            let mut b = 0;
+            for y in 0..height {
+                for x in 0..width {
+                    if x % 8 == 0 {
+                        b = self.read_mem(addr + (y * width + x) / 8);
+                    }
+                    let xx = x0 + x;
+                    let yy = y0 + y;
+
+                    if xx < display_width && yy < display_height {
+                        let idx = yy * display_width + xx;
+                        if b & (0b1000_0000 >> (x % 8)) != 0 {
+                            if self.display.pixels[idx] & plane != 0 {
+                                self.v[0xF].0 = 1;
+                            }
+                            self.display.pixels[idx] ^= plane;
                         }
-                        self.display[idx] ^= 1;
                     }
                 }
             }
+            addr += width * height / 8;
         }
 
-        self.display_updated = true;
+        self.emit(EventKind::SpriteDrawn(SpriteDraw {
+            pc: self.pc - 2,
+            x: x0 as u8,
+            y: y0 as u8,
+            width: width as u8,
+            height: height as u8,
+            addr: self.i,
+            collision: self.v[0xF].0 == 1,
+        }));
+        self.display_changed();
+    }
+
+    // Returns the key selected by Vx, which is only the low nibble
+    fn polled_key(&mut self, x: usize) -> u8 {
+        let key = self.v[x].0 & 0xF;
+        self.emit(EventKind::KeyPolled {
+            key,
+            addr: self.pc - 2,
+        });
+        key
     }
 
     pub(super) fn skip_next_key_vx_not_pressed(&mut self, x: usize) {
-        if !self.keys[self.v[x].0 as usize] {
+        let key = self.polled_key(x);
+        if !self.keys[usize::from(key)] {
             self.pc += 2;
         }
     }
 
     pub(super) fn skip_next_key_vx_pressed(&mut self, x: usize) {
-        if self.keys[self.v[x].0 as usize] {
+        let key = self.polled_key(x);
+        if self.keys[usize::from(key)] {
             self.pc += 2;
         }
     }
 
     pub(super) fn set_vx_to_delay_timer(&mut self, x: usize) {
         self.v[x].0 = self.delay_timer;
+        self.note_delay_read();
     }
 
     pub(super) fn wait_for_keypress_store_in_vx(&mut self, x: usize) {
+        self.emit(EventKind::KeyWait { addr: self.pc - 2 });
         self.keypress_wait.wait = true;
         self.keypress_wait.vx = x;
     }
 
     pub(super) fn set_delay_timer(&mut self, x: usize) {
         self.delay_timer = self.v[x].0;
+        self.reset_delay_spin();
     }
 
     pub(super) fn set_sound_timer(&mut self, x: usize) {
         self.sound_timer = self.v[x].0;
+        if self.quirks.min_beep && self.sound_timer == 1 && !self.sound_on {
+            self.send_audio(AudioEvent::BeepTooShort);
+        }
+        self.update_sound();
     }
 
     pub(super) fn add_vx_to_i(&mut self, x: usize) {
-        self.i += u16::from(self.v[x].0);
+        self.i = self.i.wrapping_add(u16::from(self.v[x].0));
     }
 
     pub(super) fn set_i_to_loc_of_digit_vx(&mut self, x: usize) {
-        self.i = u16::from((self.v[x] * Wrapping(5)).0);
+        self.i = super::FONT_ADDR + u16::from(self.v[x].0 & 0xF) * 5;
     }
 
     pub(super) fn store_bcd_of_vx_to_i(&mut self, x: usize) {
@@ -187,23 +290,30 @@ impl VirtualMachine {
         let h = num / 100;
         let t = (num - h * 100) / 10;
         let o = num - h * 100 - t * 10;
-        self.ram[self.i as usize] = h;
-        self.ram[self.i as usize + 1] = t;
-        self.ram[self.i as usize + 2] = o;
+        self.write_mem(self.i as usize, h);
+        self.write_mem(self.i as usize + 1, t);
+        self.write_mem(self.i as usize + 2, o);
     }
 
     pub(super) fn copy_v0_through_vx_to_mem(&mut self, x: u16) {
         for pos in 0..=x {
-            self.ram[(self.i + pos) as usize] = self.v[pos as usize].0;
+            self.write_mem(
+                usize::from(self.i) + usize::from(pos),
+                self.v[pos as usize].0,
+            );
+        }
+        if !self.quirks.load_store_keeps_i {
+            self.i = self.i.wrapping_add(x + 1);
         }
-        self.i += x + 1;
     }
 
     pub(super) fn read_v0_through_vx_from_mem(&mut self, x: u16) {
         for pos in 0..=x {
-            self.v[pos as usize].0 = self.ram[(self.i + pos) as usize];
+            self.v[pos as usize].0 = self.read_mem(usize::from(self.i) + usize::from(pos));
+        }
+        if !self.quirks.load_store_keeps_i {
+            self.i = self.i.wrapping_add(x + 1);
         }
-        self.i += x + 1;
     }
 }
 
@@ -237,3 +347,111 @@ fn test_strore_bcd_of_vx_to_i() {
     assert!(vm.ram[1] == 4);
     assert!(vm.ram[2] == 6);
 }
+
+#[test]
+fn test_self_jump_ends_program() {
+    let mut vm = VirtualMachine::new();
+    // 0x200: LD V0, 1
+    // 0x202: JP 0x202
+    vm.load_rom(&[0x60, 0x01, 0x12, 0x02]);
+    vm.do_cycle();
+    assert_eq!(vm.halt_reason(), None);
+    vm.do_cycle();
+    assert_eq!(vm.halt_reason(), Some(HaltReason::ProgramEnded));
+    assert_eq!(vm.pc(), 0x202);
+}
+
+#[test]
+fn test_jump_plus_v0() {
+    let mut vm = VirtualMachine::new();
+    // 0x200: LD V0, 4
+    // 0x202: JP V0, 0x200
+    vm.load_rom(&[0x60, 0x04, 0xB2, 0x00]);
+    vm.do_cycle();
+    vm.do_cycle();
+    assert_eq!(vm.pc(), 0x204);
+    assert!(matches!(
+        crate::decode(0xB2F0),
+        crate::Instruction::JumpToAddrPlusV0 { addr: 0x2F0 }
+    ));
+}
+
+#[test]
+fn test_jump_uses_vx() {
+    // 0x200: LD V0, 4
+    // 0x202: LD V2, 8
+    // 0x204: JP V2, 0x200
+    let rom = [0x60, 0x04, 0x62, 0x08, 0xB2, 0x00];
+    for (uses_vx, pc) in [(false, 0x204), (true, 0x208)] {
+        let mut vm = VirtualMachine::new();
+        vm.set_quirks(crate::Quirks {
+            jump_uses_vx: uses_vx,
+            ..crate::Quirks::default()
+        });
+        vm.load_rom(&rom);
+        for _ in 0..3 {
+            vm.do_cycle();
+        }
+        assert_eq!(vm.pc(), pc);
+    }
+}
+
+#[test]
+fn test_load_store_keeps_i() {
+    // 0x200: LD I, 0x300
+    // 0x202: LD [I], V2
+    // 0x204: LD V2, [I]
+    let rom = [0xA3, 0x00, 0xF2, 0x55, 0xF2, 0x65];
+    for (keeps_i, i) in [(false, 0x306), (true, 0x300)] {
+        let mut vm = VirtualMachine::new();
+        vm.set_quirks(crate::Quirks {
+            load_store_keeps_i: keeps_i,
+            ..crate::Quirks::default()
+        });
+        vm.load_rom(&rom);
+        for _ in 0..3 {
+            vm.do_cycle();
+        }
+        assert_eq!(vm.i(), i);
+    }
+}
+
+#[test]
+fn test_logic_resets_vf() {
+    // 0x200: LD VF, 1
+    // 0x202: OR V0, V1
+    let rom = [0x6F, 0x01, 0x80, 0x11];
+    for (resets, vf) in [(false, 1), (true, 0)] {
+        let mut vm = VirtualMachine::new();
+        vm.set_quirks(crate::Quirks {
+            logic_resets_vf: resets,
+            ..crate::Quirks::default()
+        });
+        vm.load_rom(&rom);
+        vm.do_cycle();
+        vm.do_cycle();
+        assert_eq!(vm.v(0xF), vf);
+    }
+}
+
+#[test]
+fn test_bad_accesses_halt() {
+    // 0x200: CALL 0x204
+    // 0x202: RET, a second time
+    // 0x204: RET
+    let mut vm = VirtualMachine::new();
+    vm.load_rom(&[0x22, 0x04, 0x00, 0xEE, 0x00, 0xEE]);
+    for _ in 0..4 {
+        vm.do_cycle();
+    }
+    assert_eq!(vm.halt_reason(), Some(HaltReason::StackUnderflow));
+    assert_eq!(vm.pc(), 0x204);
+    // 0x200: LD I, 0xFFE
+    // 0x202: LD [I], V2
+    let mut vm = VirtualMachine::new();
+    vm.load_rom(&[0xAF, 0xFE, 0xF2, 0x55]);
+    vm.do_cycle();
+    vm.do_cycle();
+    assert_eq!(vm.halt_reason(), Some(HaltReason::MemoryOutOfBounds));
+    assert!(HaltReason::MemoryOutOfBounds.is_error());
+}
@@ -0,0 +1,95 @@
+//! User-registered opcode handlers.
+
+use {
+    super::VirtualMachine,
+    std::sync::{Arc, Mutex},
+};
+
+/// What to do after an opcode override ran.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverrideAction {
+    /// The instruction was handled, so the built-in behavior is skipped.
+    Handled,
+    /// Run the built-in behavior as well.
+    RunBuiltin,
+}
+
+type Handler = Arc<Mutex<dyn FnMut(&mut VirtualMachine, u16) -> OverrideAction + Send>>;
+
+#[derive(Clone)]
+pub(super) struct OpcodeOverride {
+    pattern: u16,
+    mask: u16,
+    handler: Handler,
+}
+
+impl VirtualMachine {
+    /// Registers a handler for the instructions matching `pattern` under `mask`,
+    /// like in [`OpcodeSpec`](crate::opcodes::OpcodeSpec).
+    ///
+    /// The handler runs before the built-in behavior, and decides whether that runs too.
+    /// It gets the raw instruction, and the program counter already points past it.
+    /// Overrides registered later take precedence. They are shared with clones of this VM.
+    ///
+    /// A handler must not step the VM it's given, as it could end up calling itself.
+    pub fn override_opcode(
+        &mut self,
+        pattern: u16,
+        mask: u16,
+        handler: impl FnMut(&mut VirtualMachine, u16) -> OverrideAction + Send + 'static,
+    ) {
+        self.overrides.push(OpcodeOverride {
+            pattern,
+            mask,
+            handler: Arc::new(Mutex::new(handler)),
+        });
+    }
+
+    /// Removes all handlers registered with [`VirtualMachine::override_opcode`].
+    pub fn clear_opcode_overrides(&mut self) {
+        self.overrides.clear();
+    }
+
+    // Runs the override for `ins`, if there is one
+    pub(super) fn run_override(&mut self, ins: u16) -> OverrideAction {
+        let handler = self
+            .overrides
+            .iter()
+            .rev()
+            .find(|o| ins & o.mask == o.pattern)
+            .map(|o| o.handler.clone());
+        match handler {
+            Some(handler) => (handler.lock().unwrap())(self, ins),
+            None => OverrideAction::RunBuiltin,
+        }
+    }
+}
+
+#[test]
+fn test_opcode_overrides() {
+    let mut vm = VirtualMachine::new();
+    // 0x200: (breakpoint)
+    // 0x202: ADD V0, 1
+    // 0x204: ADD V0, 2
+    vm.load_rom(&[0xFF, 0xFF, 0x70, 0x01, 0x70, 0x02]);
+    let log = crate::MemoryLog::new(1000);
+    vm.set_log_sink(log.clone());
+    let hits = Arc::new(Mutex::new(Vec::new()));
+    let breakpoints = hits.clone();
+    vm.override_opcode(0xFFFF, 0xFFFF, move |vm, _| {
+        breakpoints.lock().unwrap().push(vm.pc() - 2);
+        OverrideAction::Handled
+    });
+    // Count additions, but still perform them
+    vm.override_opcode(0x7000, 0xF000, |vm, _| {
+        vm.v[1].0 += 1;
+        OverrideAction::RunBuiltin
+    });
+    for _ in 0..3 {
+        vm.do_cycle();
+    }
+    assert_eq!(*hits.lock().unwrap(), [0x200]);
+    assert_eq!(vm.v[0].0, 3);
+    assert_eq!(vm.v[1].0, 2);
+    assert!(!log.text().contains("Unknown instruction"));
+}
@@ -0,0 +1,218 @@
+use {
+    super::{FrameBuffer, HaltReason, VirtualMachine},
+    std::sync::{Arc, Mutex},
+};
+
+/// The number of instructions executed per second by default.
+pub const DEFAULT_IPS: u32 = 700;
+
+const MICROS_PER_SEC: u64 = 1_000_000;
+const TIMER_HZ: u64 = 60;
+
+/// Things that happened while the VM was stepped.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FrameEvents {
+    /// The display needs to be redrawn.
+    pub display_updated: bool,
+    /// The sound started playing.
+    pub sound_started: bool,
+    /// The sound stopped playing.
+    pub sound_stopped: bool,
+    /// The value of [`VirtualMachine::cycle_count`] at the end of the step.
+    pub cycle: u64,
+    /// The value of [`VirtualMachine::frame_count`] at the end of the step.
+    pub frame: u64,
+    /// Why the VM halted, if it did.
+    ///
+    /// A halted VM executes no more instructions, and its display keeps showing the final
+    /// frame, so a frontend can stop driving it until it's reset.
+    pub halt_reason: Option<HaltReason>,
+}
+
+/// The state of the VM at the end of a frame.
+pub struct FrameView<'a> {
+    /// The contents of the display.
+    pub display: &'a FrameBuffer,
+    /// The contents of the display at the end of the previous frame.
+    pub previous: &'a FrameBuffer,
+    /// Whether the display changed since it was last presented.
+    pub display_updated: bool,
+    /// Whether the sound is playing.
+    pub sound_playing: bool,
+    /// The value of [`VirtualMachine::cycle_count`] at the end of the frame.
+    pub cycle: u64,
+    /// The value of [`VirtualMachine::frame_count`] at the end of the frame.
+    pub frame: u64,
+}
+
+type FrameCallback = Arc<Mutex<dyn FnMut(&FrameView) + Send>>;
+
+#[derive(Clone)]
+pub(super) struct Pacer {
+    ips: u32,
+    // Both accumulators count in microseconds multiplied by the rate,
+    // so they overflow into a cycle (or tick) at MICROS_PER_SEC.
+    cycle_acc: u64,
+    timer_acc: u64,
+    pub(super) events: FrameEvents,
+    on_frame: Option<FrameCallback>,
+}
+
+impl Default for Pacer {
+    fn default() -> Self {
+        Self {
+            ips: DEFAULT_IPS,
+            cycle_acc: 0,
+            timer_acc: 0,
+            events: FrameEvents::default(),
+            on_frame: None,
+        }
+    }
+}
+
+impl VirtualMachine {
+    /// Advances the VM by `us` microseconds of emulated time.
+    ///
+    /// Executes instructions at the configured rate and decrements the timers at 60 Hz,
+    /// so a frontend only has to feed it the elapsed wall-clock time.
+    /// Returns what happened in the meantime.
+    pub fn step_micros(&mut self, us: u64) -> FrameEvents {
+        let mut remaining = us;
+        while remaining > 0 {
+            // Run up to the next timer tick, so instructions see the timers change
+            // at the right time even if `us` spans several ticks.
+            let until_tick = (MICROS_PER_SEC - self.pacer.timer_acc).div_ceil(TIMER_HZ);
+            let slice = remaining.min(until_tick);
+            remaining -= slice;
+            self.pacer.cycle_acc += slice * u64::from(self.pacer.ips);
+            while self.pacer.cycle_acc >= MICROS_PER_SEC {
+                self.pacer.cycle_acc -= MICROS_PER_SEC;
+                if !self.waiting_for_key() {
+                    self.do_cycle();
+                }
+            }
+            self.pacer.timer_acc += slice * TIMER_HZ;
+            if self.pacer.timer_acc >= MICROS_PER_SEC {
+                self.pacer.timer_acc -= MICROS_PER_SEC;
+                self.decrement_timers();
+                self.end_frame();
+            }
+        }
+        let mut events = std::mem::take(&mut self.pacer.events);
+        events.display_updated = self.display_updated;
+        events.cycle = self.cycles;
+        events.frame = self.frames;
+        events.halt_reason = self.halt;
+        events
+    }
+
+    /// Returns the number of instructions executed per second of emulated time.
+    pub fn speed(&self) -> u32 {
+        self.pacer.ips
+    }
+
+    /// Sets the number of instructions executed per second of emulated time
+    /// by [`VirtualMachine::step_micros`] and [`VirtualMachine::step_frame`].
+    ///
+    /// The default is [`DEFAULT_IPS`]. The speed is part of savestates.
+    /// At 0, no instructions are executed, but the timers still run.
+    pub fn set_speed(&mut self, ips: u32) {
+        self.pacer.ips = ips;
+    }
+
+    /// Advances the VM by one 60 Hz frame.
+    ///
+    /// Like [`VirtualMachine::step_micros`], but runs exactly up to the next timer tick.
+    pub fn step_frame(&mut self) -> FrameEvents {
+        let until_tick = (MICROS_PER_SEC - self.pacer.timer_acc).div_ceil(TIMER_HZ);
+        self.step_micros(until_tick)
+    }
+
+    /// Sets a callback that is invoked whenever a frame is completed
+    /// by [`VirtualMachine::step_frame`] or [`VirtualMachine::step_micros`].
+    ///
+    /// The callback is shared with clones of this VM.
+    pub fn on_frame(&mut self, callback: impl FnMut(&FrameView) + Send + 'static) {
+        self.pacer.on_frame = Some(Arc::new(Mutex::new(callback)));
+    }
+
+    /// Removes the callback set by [`VirtualMachine::on_frame`].
+    pub fn clear_on_frame(&mut self) {
+        self.pacer.on_frame = None;
+    }
+
+    fn end_frame(&mut self) {
+        self.advance_input_macros();
+        if let Some(callback) = self.pacer.on_frame.clone() {
+            let view = FrameView {
+                display: &self.display,
+                previous: &self.prev_frame,
+                display_updated: self.display_updated,
+                sound_playing: self.sound_on,
+                cycle: self.cycles,
+                frame: self.frames,
+            };
+            (callback.lock().unwrap())(&view);
+        }
+    }
+}
+
+#[test]
+fn test_step_micros_pacing() {
+    let mut vm = VirtualMachine::new();
+    // 0x200: LD V0, 3
+    // 0x202: LD ST, V0
+    // 0x204: ADD V1, 1
+    // 0x206: JP 0x204
+    vm.load_rom(&[0x60, 0x03, 0xF0, 0x18, 0x71, 0x01, 0x12, 0x04]);
+    let events = vm.step_micros(MICROS_PER_SEC / 60);
+    assert!(events.sound_started);
+    assert!(!events.sound_stopped);
+    let events = vm.step_micros(MICROS_PER_SEC);
+    assert!(events.sound_stopped);
+    // 11 + 700 instructions were executed. After the 2 setup instructions,
+    // every other instruction is an ADD, starting with one.
+    assert_eq!(vm.v[1].0, (709u16.div_ceil(2) % 256) as u8);
+}
+
+#[test]
+fn test_on_frame_called_per_frame() {
+    let mut vm = VirtualMachine::new();
+    let frames = Arc::new(Mutex::new(0));
+    let counter = frames.clone();
+    vm.on_frame(move |_| *counter.lock().unwrap() += 1);
+    for _ in 0..3 {
+        vm.step_frame();
+    }
+    vm.step_micros(MICROS_PER_SEC);
+    assert_eq!(*frames.lock().unwrap(), 63);
+}
+
+#[test]
+fn test_set_speed() {
+    let mut vm = VirtualMachine::new();
+    vm.set_speed(60 * 20);
+    vm.step_frame();
+    assert_eq!(vm.cycle_count(), 20);
+    vm.set_speed(0);
+    vm.step_frame();
+    assert_eq!(vm.cycle_count(), 20);
+    assert_eq!(vm.frame_count(), 2);
+}
+
+#[test]
+fn test_halt_is_reported() {
+    let mut vm = VirtualMachine::new();
+    // 0x200: CLS
+    // 0x202: JP 0x202
+    vm.load_rom(&[0x00, 0xE0, 0x12, 0x02]);
+    let events = vm.step_frame();
+    assert_eq!(events.halt_reason, Some(HaltReason::ProgramEnded));
+    assert!(events.display_updated);
+    // The final frame stays, and nothing else runs
+    vm.clear_du_flag();
+    let events = vm.step_frame();
+    assert_eq!(events.halt_reason, Some(HaltReason::ProgramEnded));
+    assert!(!events.display_updated);
+    assert_eq!(vm.cycle_count(), 2);
+}
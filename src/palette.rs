@@ -0,0 +1,299 @@
+//! Mapping of display planes to colors.
+//!
+//! XO-CHIP programs draw on two bit planes. A pixel of the [`FrameBuffer`](crate::FrameBuffer)
+//! holds the bitmask of the planes it's set in, so plain CHIP-8 pixels are 0 or 1,
+//! and a [`Palette`] maps each bitmask to the color it should be shown as.
+
+use std::fmt;
+
+/// An RGB color.
+pub type Rgb = [u8; 3];
+
+/// The number of distinct plane bitmasks.
+pub const PLANE_COMBINATIONS: usize = 4;
+
+/// Colors for every combination of planes a pixel can be set in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    /// The colors, indexed by plane bitmask.
+    pub colors: [Rgb; PLANE_COMBINATIONS],
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::MONOCHROME
+    }
+}
+
+impl Palette {
+    /// White on black. Pixels in the second plane only are shown in grey.
+    pub const MONOCHROME: Palette = Palette {
+        colors: [[0, 0, 0], [255, 255, 255], [170, 170, 170], [85, 85, 85]],
+    };
+
+    /// The default colors of Octo, which most XO-CHIP programs were written with.
+    pub const OCTO: Palette = Palette {
+        colors: [
+            [0x99, 0x66, 0x00],
+            [0xFF, 0xCC, 0x00],
+            [0xFF, 0x66, 0x00],
+            [0x66, 0x22, 0x00],
+        ],
+    };
+
+    /// Bright yellow on black, for low vision. The planes are told apart by hue as well as
+    /// brightness.
+    pub const HIGH_CONTRAST: Palette = Palette {
+        colors: [[0, 0, 0], [255, 255, 0], [0, 255, 255], [255, 255, 255]],
+    };
+
+    /// Black on white, for low vision and bright rooms.
+    pub const HIGH_CONTRAST_LIGHT: Palette = Palette {
+        colors: [[255, 255, 255], [0, 0, 0], [0, 0, 160], [96, 0, 0]],
+    };
+
+    /// Orange and sky blue planes on black, from the Okabe-Ito colors, which stay apart
+    /// with every kind of [`ColorBlindness`].
+    pub const COLORBLIND: Palette = Palette {
+        colors: [[0, 0, 0], [230, 159, 0], [86, 180, 233], [255, 255, 255]],
+    };
+
+    /// Blue and vermillion planes on white, from the Okabe-Ito colors.
+    pub const COLORBLIND_LIGHT: Palette = Palette {
+        colors: [[255, 255, 255], [0, 114, 178], [213, 94, 0], [0, 0, 0]],
+    };
+
+    /// Yellow and blue planes, the pair told apart best with red-green color blindness.
+    pub const COLORBLIND_BLUE_YELLOW: Palette = Palette {
+        colors: [[17, 17, 17], [240, 228, 66], [0, 114, 178], [255, 255, 255]],
+    };
+
+    /// The built-in palettes with their names, for frontends to offer.
+    pub const PRESETS: [(&'static str, Palette); 7] = [
+        ("Monochrome", Palette::MONOCHROME),
+        ("Octo", Palette::OCTO),
+        ("High contrast", Palette::HIGH_CONTRAST),
+        ("High contrast (light)", Palette::HIGH_CONTRAST_LIGHT),
+        ("Colorblind safe", Palette::COLORBLIND),
+        ("Colorblind safe (light)", Palette::COLORBLIND_LIGHT),
+        (
+            "Colorblind safe (blue/yellow)",
+            Palette::COLORBLIND_BLUE_YELLOW,
+        ),
+    ];
+
+    /// Returns the palette as it looks with `blindness`.
+    pub fn simulated(&self, blindness: ColorBlindness) -> Palette {
+        Palette {
+            colors: self.colors.map(|color| blindness.simulate(color)),
+        }
+    }
+
+    /// Returns the color of a pixel set in the planes in `mask`.
+    ///
+    /// Bits for planes beyond the palette are ignored.
+    pub fn color(&self, mask: u8) -> Rgb {
+        self.colors[usize::from(mask) % PLANE_COMBINATIONS]
+    }
+
+    /// Sets the color of pixels set in the planes in `mask`.
+    pub fn set_color(&mut self, mask: u8, color: Rgb) {
+        self.colors[usize::from(mask) % PLANE_COMBINATIONS] = color;
+    }
+
+    /// Takes the colors an Octo cartridge was saved with, keeping the current ones
+    /// for those it doesn't specify.
+    #[cfg(feature = "octo")]
+    pub fn apply_cartridge(&mut self, options: &crate::octo::CartridgeOptions) {
+        let colors = [
+            &options.background_color,
+            &options.fill_color,
+            &options.fill_color2,
+            &options.blend_color,
+        ];
+        for (mask, color) in colors.into_iter().enumerate() {
+            if let Some(rgb) = color.as_deref().and_then(|c| parse_hex_color(c).ok()) {
+                self.colors[mask] = rgb;
+            }
+        }
+    }
+}
+
+/// Returns the contrast ratio of two colors as defined by WCAG 2, from 1 for the same
+/// brightness to 21 for black on white.
+///
+/// Text is easy to read for most people at 4.5 and up, and for people with low vision
+/// at 7 and up.
+pub fn contrast_ratio(a: Rgb, b: Rgb) -> f32 {
+    let luminance = |rgb: Rgb| {
+        let [r, g, b] = to_linear(rgb);
+        0.2126 * r + 0.7152 * g + 0.0722 * b
+    };
+    let (la, lb) = (luminance(a), luminance(b));
+    (la.max(lb) + 0.05) / (la.min(lb) + 0.05)
+}
+
+// sRGB to linear light, between 0 and 1
+fn to_linear(rgb: Rgb) -> [f32; 3] {
+    rgb.map(|c| {
+        let c = f32::from(c) / 255.0;
+        if c <= 0.03928 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    })
+}
+
+fn from_linear(linear: [f32; 3]) -> Rgb {
+    linear.map(|c| {
+        let c = c.clamp(0.0, 1.0);
+        let c = if c <= 0.0031308 {
+            c * 12.92
+        } else {
+            1.055 * c.powf(1.0 / 2.4) - 0.055
+        };
+        (c * 255.0).round() as u8
+    })
+}
+
+/// A kind of color blindness, for previewing how colors look to colorblind players.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ColorBlindness {
+    /// No red cones, so red and green look alike and red looks dark.
+    Protanopia,
+    /// No green cones, the most common kind, so red and green look alike.
+    Deuteranopia,
+    /// No blue cones, so blue and green look alike, as do yellow and pink.
+    Tritanopia,
+}
+
+impl ColorBlindness {
+    /// All kinds of color blindness.
+    pub const ALL: [ColorBlindness; 3] = [
+        ColorBlindness::Protanopia,
+        ColorBlindness::Deuteranopia,
+        ColorBlindness::Tritanopia,
+    ];
+
+    /// The name of the kind of color blindness.
+    pub fn name(self) -> &'static str {
+        match self {
+            ColorBlindness::Protanopia => "Protanopia",
+            ColorBlindness::Deuteranopia => "Deuteranopia",
+            ColorBlindness::Tritanopia => "Tritanopia",
+        }
+    }
+
+    /// Returns how `color` looks with this kind of color blindness, using the model of
+    /// Machado, Oliveira and Fernandes (2009) at full severity.
+    pub fn simulate(self, color: Rgb) -> Rgb {
+        let matrix = match self {
+            ColorBlindness::Protanopia => [
+                [0.152286, 1.052583, -0.204868],
+                [0.114503, 0.786281, 0.099216],
+                [-0.003882, -0.048116, 1.051998],
+            ],
+            ColorBlindness::Deuteranopia => [
+                [0.367322, 0.860646, -0.227968],
+                [0.280085, 0.672501, 0.047413],
+                [-0.011820, 0.042940, 0.968881],
+            ],
+            ColorBlindness::Tritanopia => [
+                [1.255528, -0.076749, -0.178779],
+                [-0.078411, 0.930809, 0.147602],
+                [0.004733, 0.691367, 0.303900],
+            ],
+        };
+        let linear = to_linear(color);
+        from_linear(matrix.map(|row| row.iter().zip(linear).map(|(m, c)| m * c).sum()))
+    }
+}
+
+impl fmt::Display for ColorBlindness {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// An error parsing a color.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseColorError(String);
+
+impl fmt::Display for ParseColorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "invalid color `{}`, expected `#RRGGBB` or `#RGB`",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for ParseColorError {}
+
+/// Parses a color written like `#FFCC00` or `#FC0`, as used by Octo.
+pub fn parse_hex_color(s: &str) -> Result<Rgb, ParseColorError> {
+    let err = || ParseColorError(s.to_owned());
+    let digits = s.strip_prefix('#').unwrap_or(s);
+    if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(err());
+    }
+    let value = u32::from_str_radix(digits, 16).map_err(|_| err())?;
+    match digits.len() {
+        6 => Ok([(value >> 16) as u8, (value >> 8) as u8, value as u8]),
+        3 => Ok([2, 1, 0].map(|shift| ((value >> (shift * 4)) & 0xF) as u8 * 0x11)),
+        _ => Err(err()),
+    }
+}
+
+#[test]
+fn test_parse_hex_color() {
+    assert_eq!(parse_hex_color("#FFCC00"), Ok([0xFF, 0xCC, 0x00]));
+    assert_eq!(parse_hex_color("#f60"), Ok([0xFF, 0x66, 0x00]));
+    assert!(parse_hex_color("#12345").is_err());
+    assert!(parse_hex_color("#+12345").is_err());
+    assert_eq!(Palette::OCTO.color(1), [0xFF, 0xCC, 0x00]);
+    assert_eq!(Palette::MONOCHROME.color(0), [0, 0, 0]);
+}
+
+#[test]
+fn test_high_contrast() {
+    assert!((contrast_ratio([0, 0, 0], [255, 255, 255]) - 21.0).abs() < 0.01);
+    for palette in [Palette::HIGH_CONTRAST, Palette::HIGH_CONTRAST_LIGHT] {
+        for mask in 1..PLANE_COMBINATIONS as u8 {
+            assert!(contrast_ratio(palette.color(0), palette.color(mask)) >= 7.0);
+        }
+    }
+}
+
+#[test]
+fn test_colorblind_palettes() {
+    // How far apart two colors are, in sRGB
+    let distance = |a: Rgb, b: Rgb| {
+        a.iter()
+            .zip(b)
+            .map(|(&a, b)| (f32::from(a) - f32::from(b)).powi(2))
+            .sum::<f32>()
+            .sqrt()
+    };
+    assert_eq!(
+        ColorBlindness::Deuteranopia.simulate([255, 255, 255]),
+        [255, 255, 255]
+    );
+    for palette in [
+        Palette::COLORBLIND,
+        Palette::COLORBLIND_LIGHT,
+        Palette::COLORBLIND_BLUE_YELLOW,
+    ] {
+        for blindness in ColorBlindness::ALL {
+            let seen = palette.simulated(blindness);
+            for a in 0..PLANE_COMBINATIONS {
+                for b in a + 1..PLANE_COMBINATIONS {
+                    let d = distance(seen.colors[a], seen.colors[b]);
+                    assert!(d > 60.0, "{:?} {} {} {}: {}", palette, blindness, a, b, d);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,186 @@
+//! Lightweight performance counters, for showing how fast the VM runs.
+//!
+//! Unlike the [profiler](crate::profiler), the counters only cover the last second, and only
+//! tell drawing apart from everything else. Drawing is slow and rare, so every `Dxyn` is
+//! timed, while the other instructions are sampled like the profiler does.
+
+use {
+    super::{Operands, VirtualMachine, opcodes::OpcodeSpec, profiler::SAMPLE_INTERVAL},
+    std::{
+        collections::VecDeque,
+        time::{Duration, Instant},
+    },
+};
+
+/// How far back the counters go.
+pub const WINDOW: Duration = Duration::from_secs(1);
+
+// The window is made of buckets this long, and moves on a bucket at a time
+const BUCKET: Duration = Duration::from_millis(100);
+
+/// The performance of the VM over the last [`WINDOW`].
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PerfCounters {
+    /// The instructions executed per second.
+    pub cycles_per_second: f64,
+    /// The number of sprites drawn.
+    pub draws: u64,
+    /// The time spent drawing sprites.
+    pub draw_time: Duration,
+    /// The estimated time spent executing the other instructions.
+    pub other_time: Duration,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    start: Instant,
+    cycles: u64,
+    draws: u64,
+    draw_time: Duration,
+    others: u64,
+    other_samples: u64,
+    other_sampled_time: Duration,
+}
+
+impl Bucket {
+    fn new(start: Instant) -> Self {
+        Self {
+            start,
+            cycles: 0,
+            draws: 0,
+            draw_time: Duration::ZERO,
+            others: 0,
+            other_samples: 0,
+            other_sampled_time: Duration::ZERO,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct Perf {
+    // The current bucket is the last one
+    buckets: VecDeque<Bucket>,
+}
+
+fn is_draw(ins: u16) -> bool {
+    ins & 0xF000 == 0xD000
+}
+
+impl Perf {
+    fn new() -> Self {
+        Self {
+            buckets: VecDeque::from([Bucket::new(Instant::now())]),
+        }
+    }
+
+    fn should_time(&self, ins: u16) -> bool {
+        let current = self.buckets.back().unwrap();
+        is_draw(ins) || current.others.is_multiple_of(SAMPLE_INTERVAL)
+    }
+
+    fn record(&mut self, ins: u16, time: Option<Duration>) {
+        if time.is_some() {
+            self.rotate(Instant::now());
+        }
+        let current = self.buckets.back_mut().unwrap();
+        current.cycles += 1;
+        if is_draw(ins) {
+            current.draws += 1;
+            current.draw_time += time.unwrap_or_default();
+        } else {
+            current.others += 1;
+            if let Some(time) = time {
+                current.other_samples += 1;
+                current.other_sampled_time += time;
+            }
+        }
+    }
+
+    // Starts a new bucket if the current one is full, and drops those out of the window
+    fn rotate(&mut self, now: Instant) {
+        if now.duration_since(self.buckets.back().unwrap().start) < BUCKET {
+            return;
+        }
+        self.buckets.push_back(Bucket::new(now));
+        while now.duration_since(self.buckets[0].start) > WINDOW + BUCKET {
+            self.buckets.pop_front();
+        }
+    }
+
+    fn counters(&self) -> PerfCounters {
+        let elapsed = self.buckets[0].start.elapsed().as_secs_f64();
+        let mut total = Bucket::new(self.buckets[0].start);
+        for bucket in &self.buckets {
+            total.cycles += bucket.cycles;
+            total.draws += bucket.draws;
+            total.draw_time += bucket.draw_time;
+            total.others += bucket.others;
+            total.other_samples += bucket.other_samples;
+            total.other_sampled_time += bucket.other_sampled_time;
+        }
+        let other_time = if total.other_samples == 0 {
+            Duration::ZERO
+        } else {
+            total.other_sampled_time * total.others.try_into().unwrap_or(u32::MAX)
+                / total.other_samples.try_into().unwrap_or(u32::MAX)
+        };
+        PerfCounters {
+            cycles_per_second: if elapsed > 0.0 {
+                total.cycles as f64 / elapsed
+            } else {
+                0.0
+            },
+            draws: total.draws,
+            draw_time: total.draw_time,
+            other_time,
+        }
+    }
+}
+
+impl VirtualMachine {
+    /// Turns the performance counters on or off.
+    ///
+    /// They're off by default. While they're on, they cost about as much as profiling does.
+    pub fn set_perf_counters(&mut self, on: bool) {
+        self.perf = on.then(|| Box::new(Perf::new()));
+    }
+
+    /// Returns the performance counters, if they're on.
+    pub fn perf_counters(&self) -> Option<PerfCounters> {
+        self.perf.as_ref().map(|perf| perf.counters())
+    }
+
+    // Executes an instruction, timing it for the profiler and the performance counters
+    pub(super) fn exec_measured(&mut self, spec: &'static OpcodeSpec, ins: u16) {
+        let timed = self.profile.as_ref().is_some_and(|p| p.should_sample())
+            || self.perf.as_ref().is_some_and(|p| p.should_time(ins));
+        let start = timed.then(Instant::now);
+        (spec.exec)(self, Operands::new(ins));
+        let time = start.map(|start| start.elapsed());
+        if let Some(profile) = &mut self.profile {
+            profile.record(spec, time);
+        }
+        if let Some(perf) = &mut self.perf {
+            perf.record(ins, time);
+        }
+    }
+}
+
+#[test]
+fn test_perf_counters() {
+    let mut vm = VirtualMachine::new();
+    assert_eq!(vm.perf_counters(), None);
+    // 0x200: LD I, 0
+    // 0x202: DRW V0, V0, 5
+    // 0x204: ADD V0, 1
+    // 0x206: JP 0x202
+    vm.load_rom(&[0xA0, 0x00, 0xD0, 0x05, 0x70, 0x01, 0x12, 0x02]);
+    vm.set_perf_counters(true);
+    for _ in 0..301 {
+        vm.do_cycle();
+    }
+    let counters = vm.perf_counters().unwrap();
+    assert_eq!(counters.draws, 100);
+    assert!(counters.cycles_per_second > 0.0);
+    assert!(counters.draw_time > Duration::ZERO);
+}
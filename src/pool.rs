@@ -0,0 +1,387 @@
+//! A structure-of-arrays layout for stepping many VMs in lockstep.
+//!
+//! A `Vec<VirtualMachine>` keeps all of an instance's state together, so stepping thousands of
+//! them in lockstep touches a new 4 KiB of RAM, registers and display per instance. A
+//! [`VmPool`] instead keeps each part of the state of all instances together: the V registers
+//! of every instance are contiguous, as are the program counters, the timers, and so on.
+//!
+//! This is an experiment. The pool only runs the classic instruction set, treating everything
+//! else as unknown, and has none of the events, sinks, overrides or pacing of
+//! [`VirtualMachine`]. Otherwise, instances behave exactly like VMs with the same quirks that
+//! only understand CHIP-8, which [`VmPool::to_vm`] can be used to check. The `pool_bench`
+//! example compares the two layouts.
+
+use {
+    super::{
+        DISPLAY_HEIGHT, DISPLAY_WIDTH, FONT_ADDR, HaltReason, KeypressWait, MEM_SIZE, Quirks,
+        START_ADDR, VirtualMachine, load_fonts, opcodes::Extension, rng::Rng,
+    },
+    std::num::Wrapping,
+};
+
+const DISPLAY_SIZE: usize = DISPLAY_WIDTH * DISPLAY_HEIGHT;
+
+/// Many VMs, stored as a structure of arrays.
+#[derive(Clone, Default)]
+pub struct VmPool {
+    quirks: Quirks,
+    frames: u64,
+    // MEM_SIZE bytes per instance
+    ram: Vec<u8>,
+    v: Vec<[u8; 16]>,
+    i: Vec<u16>,
+    pc: Vec<u16>,
+    sp: Vec<u8>,
+    stack: Vec<[u16; 16]>,
+    delay_timer: Vec<u8>,
+    sound_timer: Vec<u8>,
+    // Bitmasks of the pressed keys
+    keys: Vec<u16>,
+    // The register waiting for a key, if any
+    key_wait: Vec<Option<u8>>,
+    halt: Vec<Option<HaltReason>>,
+    rng: Vec<Rng>,
+    cycles: Vec<u64>,
+    // DISPLAY_SIZE pixels per instance
+    display: Vec<u8>,
+}
+
+impl VmPool {
+    /// Creates an empty pool, whose instances will run with `quirks`.
+    ///
+    /// Only the quirks of the classic instruction set have an effect.
+    pub fn new(quirks: Quirks) -> Self {
+        Self {
+            quirks,
+            ..Default::default()
+        }
+    }
+
+    /// Adds an instance running `rom`, with its random number generator seeded like
+    /// [`VirtualMachine::set_rng_seed`]. Returns the index of the instance.
+    pub fn push(&mut self, rom: &[u8], seed: u64) -> usize {
+        let index = self.len();
+        let base = self.ram.len();
+        self.ram.resize(base + MEM_SIZE, 0);
+        load_fonts(&mut self.ram[base..]);
+        let start = base + START_ADDR as usize;
+        let len = rom.len().min(MEM_SIZE - START_ADDR as usize);
+        self.ram[start..start + len].copy_from_slice(&rom[..len]);
+        self.v.push([0; 16]);
+        self.i.push(0);
+        self.pc.push(START_ADDR);
+        self.sp.push(0);
+        self.stack.push([0; 16]);
+        self.delay_timer.push(0);
+        self.sound_timer.push(0);
+        self.keys.push(0);
+        self.key_wait.push(None);
+        self.halt.push(None);
+        self.rng.push(Rng::new(seed));
+        self.cycles.push(0);
+        self.display.resize(self.display.len() + DISPLAY_SIZE, 0);
+        index
+    }
+
+    /// Returns the number of instances.
+    pub fn len(&self) -> usize {
+        self.pc.len()
+    }
+
+    /// Returns whether the pool has no instances.
+    pub fn is_empty(&self) -> bool {
+        self.pc.is_empty()
+    }
+
+    /// Executes an instruction on every instance that isn't halted or waiting for a key.
+    pub fn step_all(&mut self) {
+        for n in 0..self.len() {
+            if self.halt[n].is_none() && self.key_wait[n].is_none() {
+                self.step(n);
+            }
+        }
+    }
+
+    /// Decrements the timers of every instance, like [`VirtualMachine::decrement_timers`].
+    pub fn decrement_timers(&mut self) {
+        self.frames += 1;
+        for timer in self.delay_timer.iter_mut().chain(&mut self.sound_timer) {
+            *timer = timer.saturating_sub(1);
+        }
+    }
+
+    /// Presses a key of instance `n`, like [`VirtualMachine::press_key`].
+    pub fn press_key(&mut self, n: usize, key: u8) {
+        assert!(key <= 15);
+        if self.quirks.single_key {
+            self.keys[n] = 0;
+        }
+        self.keys[n] |= 1 << key;
+        if let Some(x) = self.key_wait[n].take() {
+            self.v[n][usize::from(x)] = key;
+        }
+    }
+
+    /// Releases a key of instance `n`.
+    pub fn release_key(&mut self, n: usize, key: u8) {
+        assert!(key <= 15);
+        self.keys[n] &= !(1 << key);
+    }
+
+    /// Returns why instance `n` halted, or `None` if it's still running.
+    pub fn halt_reason(&self, n: usize) -> Option<HaltReason> {
+        self.halt[n]
+    }
+
+    /// Returns the display of instance `n`, one byte per pixel, row by row.
+    pub fn display(&self, n: usize) -> &[u8] {
+        &self.display[n * DISPLAY_SIZE..][..DISPLAY_SIZE]
+    }
+
+    /// Copies instance `n` into a [`VirtualMachine`], to inspect it or to keep running it
+    /// with everything the VM supports.
+    pub fn to_vm(&self, n: usize) -> VirtualMachine {
+        let mut vm = VirtualMachine::new();
+        vm.quirks = self.quirks;
+        vm.extension = Extension::Chip8;
+        vm.ram.copy_from_slice(self.ram(n));
+        vm.v = self.v[n].map(Wrapping);
+        vm.i = self.i[n];
+        vm.pc = self.pc[n];
+        vm.sp = Wrapping(self.sp[n]);
+        vm.stack = self.stack[n];
+        vm.delay_timer = self.delay_timer[n];
+        vm.sound_timer = self.sound_timer[n];
+        vm.sound_on = vm.sound_timer > 0;
+        vm.keys = std::array::from_fn(|key| self.keys[n] & (1 << key) != 0);
+        vm.keypress_wait = KeypressWait {
+            wait: self.key_wait[n].is_some(),
+            vx: self.key_wait[n].map_or(0, usize::from),
+        };
+        vm.halt = self.halt[n];
+        vm.rng = self.rng[n].clone();
+        vm.cycles = self.cycles[n];
+        vm.frames = self.frames;
+        vm.display.pixels.copy_from_slice(self.display(n));
+        vm
+    }
+
+    fn ram(&self, n: usize) -> &[u8] {
+        &self.ram[n * MEM_SIZE..][..MEM_SIZE]
+    }
+
+    // Executes an instruction of instance `n`, the same way VirtualMachine does
+    fn step(&mut self, n: usize) {
+        self.cycles[n] += 1;
+        let ram = &mut self.ram[n * MEM_SIZE..][..MEM_SIZE];
+        let pc = usize::from(self.pc[n]);
+        // Like the VM, a fetch past the end of memory halts, reading zeros for the missing bytes
+        let ins = match (ram.get(pc), ram.get(pc + 1)) {
+            (Some(&hi), Some(&lo)) => u16::from_be_bytes([hi, lo]),
+            (hi, _) => {
+                self.halt[n] = Some(HaltReason::OutOfBounds);
+                u16::from(hi.copied().unwrap_or(0)) << 8
+            }
+        };
+        self.pc[n] += 2;
+        let nnn = ins & 0x0FFF;
+        let kk = (ins & 0xFF) as u8;
+        let height = usize::from(ins & 0xF);
+        let x = usize::from((ins >> 8) & 0xF);
+        let y = usize::from((ins >> 4) & 0xF);
+        let v = &mut self.v[n];
+        let (vx, vy) = (v[x], v[y]);
+        let i = usize::from(self.i[n]);
+        let skip = |pc: &mut u16, cond: bool| {
+            if cond {
+                *pc += 2;
+            }
+        };
+        match (ins >> 12, ins & 0xF) {
+            _ if ins == 0x00E0 => self.display[n * DISPLAY_SIZE..][..DISPLAY_SIZE].fill(0),
+            _ if ins == 0x00EE => match self.stack[n].get(usize::from(self.sp[n])) {
+                Some(&addr) if self.sp[n] != 0 => {
+                    self.pc[n] = addr;
+                    self.sp[n] -= 1;
+                }
+                _ => self.halt[n] = Some(HaltReason::StackUnderflow),
+            },
+            (0x0, _) => {}
+            (0x1, _) => {
+                if nnn == self.pc[n].wrapping_sub(2) {
+                    self.halt[n] = Some(HaltReason::ProgramEnded);
+                }
+                self.pc[n] = nnn;
+            }
+            (0x2, _) => {
+                self.sp[n] = self.sp[n].wrapping_add(1);
+                if let Some(slot) = self.stack[n].get_mut(usize::from(self.sp[n])) {
+                    *slot = self.pc[n];
+                }
+                self.pc[n] = nnn;
+            }
+            (0x3, _) => skip(&mut self.pc[n], vx == kk),
+            (0x4, _) => skip(&mut self.pc[n], vx != kk),
+            (0x5, 0x0) => skip(&mut self.pc[n], vx == vy),
+            (0x6, _) => v[x] = kk,
+            (0x7, _) => v[x] = vx.wrapping_add(kk),
+            (0x8, 0x0) => v[x] = vy,
+            (0x8, 0x1..=0x3) => {
+                v[x] = match ins & 0xF {
+                    0x1 => vx | vy,
+                    0x2 => vx & vy,
+                    _ => vx ^ vy,
+                };
+                if self.quirks.logic_resets_vf {
+                    v[0xF] = 0;
+                }
+            }
+            (0x8, 0x4) => {
+                let (sum, carry) = vx.overflowing_add(vy);
+                v[x] = sum;
+                v[0xF] = u8::from(carry);
+            }
+            (0x8, 0x5) => {
+                v[x] = vx.wrapping_sub(vy);
+                v[0xF] = u8::from(vx >= vy);
+            }
+            (0x8, 0x6) => {
+                let src = if self.quirks.shift_uses_vy { vy } else { vx };
+                v[x] = src >> 1;
+                v[0xF] = src & 1;
+            }
+            (0x8, 0x7) => {
+                v[x] = vy.wrapping_sub(vx);
+                v[0xF] = u8::from(vy >= vx);
+            }
+            (0x8, 0xE) => {
+                let src = if self.quirks.shift_uses_vy { vy } else { vx };
+                v[x] = src << 1;
+                v[0xF] = src >> 7;
+            }
+            (0x9, 0x0) => skip(&mut self.pc[n], vx != vy),
+            (0xA, _) => self.i[n] = nnn,
+            (0xB, _) => {
+                let offset = if self.quirks.jump_uses_vx { vx } else { v[0] };
+                self.pc[n] = nnn + u16::from(offset);
+            }
+            (0xC, _) => v[x] = self.rng[n].next_byte() & kk,
+            (0xD, _) => {
+                let display = &mut self.display[n * DISPLAY_SIZE..][..DISPLAY_SIZE];
+                let x0 = usize::from(vx) % DISPLAY_WIDTH;
+                let y0 = usize::from(vy) % DISPLAY_HEIGHT;
+                let mut collision = 0;
+                for (row, yy) in (y0..DISPLAY_HEIGHT).take(height).enumerate() {
+                    let bits = load(ram, &mut self.halt[n], i + row);
+                    for (col, xx) in (x0..DISPLAY_WIDTH).take(8).enumerate() {
+                        if bits & (0x80 >> col) != 0 {
+                            let px = &mut display[yy * DISPLAY_WIDTH + xx];
+                            collision |= *px;
+                            *px ^= 1;
+                        }
+                    }
+                }
+                v[0xF] = collision;
+            }
+            (0xE, _) if kk == 0x9E => skip(&mut self.pc[n], self.keys[n] & (1 << (vx & 0xF)) != 0),
+            (0xE, _) if kk == 0xA1 => skip(&mut self.pc[n], self.keys[n] & (1 << (vx & 0xF)) == 0),
+            (0xF, _) => match kk {
+                0x07 => v[x] = self.delay_timer[n],
+                0x0A => self.key_wait[n] = Some(x as u8),
+                0x15 => self.delay_timer[n] = vx,
+                0x18 => self.sound_timer[n] = vx,
+                0x1E => self.i[n] = self.i[n].wrapping_add(u16::from(vx)),
+                0x29 => self.i[n] = FONT_ADDR + u16::from(vx & 0xF) * 5,
+                0x33 => {
+                    for (pos, digit) in [vx / 100, vx / 10 % 10, vx % 10].into_iter().enumerate() {
+                        store(ram, &mut self.halt[n], i + pos, digit);
+                    }
+                }
+                0x55 => {
+                    for (pos, &value) in v[..=x].iter().enumerate() {
+                        store(ram, &mut self.halt[n], i + pos, value);
+                    }
+                    if !self.quirks.load_store_keeps_i {
+                        self.i[n] = self.i[n].wrapping_add(x as u16 + 1);
+                    }
+                }
+                0x65 => {
+                    for (pos, value) in v[..=x].iter_mut().enumerate() {
+                        *value = load(ram, &mut self.halt[n], i + pos);
+                    }
+                    if !self.quirks.load_store_keeps_i {
+                        self.i[n] = self.i[n].wrapping_add(x as u16 + 1);
+                    }
+                }
+                // Unknown instructions are skipped
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+}
+
+// Like the VM, an access past the end of memory halts, reading 0 and writing nothing
+fn load(ram: &[u8], halt: &mut Option<HaltReason>, addr: usize) -> u8 {
+    ram.get(addr).copied().unwrap_or_else(|| {
+        halt.get_or_insert(HaltReason::MemoryOutOfBounds);
+        0
+    })
+}
+
+fn store(ram: &mut [u8], halt: &mut Option<HaltReason>, addr: usize, value: u8) {
+    match ram.get_mut(addr) {
+        Some(byte) => *byte = value,
+        None => {
+            halt.get_or_insert(HaltReason::MemoryOutOfBounds);
+        }
+    }
+}
+
+#[test]
+fn test_pool_matches_vms() {
+    use super::romgen::Generator;
+
+    let mut generator = Generator::new(0x5A);
+    let roms: Vec<_> = (0..16).map(|_| generator.generate(200)).collect();
+    for quirks in [
+        Quirks::default(),
+        Quirks {
+            shift_uses_vy: false,
+            load_store_keeps_i: true,
+            logic_resets_vf: true,
+            jump_uses_vx: true,
+            ..Quirks::default()
+        },
+    ] {
+        let mut pool = VmPool::new(quirks);
+        let mut vms: Vec<_> = roms
+            .iter()
+            .enumerate()
+            .map(|(seed, rom)| {
+                pool.push(rom, seed as u64);
+                let mut vm = VirtualMachine::new();
+                vm.set_quirks(quirks);
+                vm.extension = Extension::Chip8;
+                vm.set_rng_seed(seed as u64);
+                vm.load_rom(rom);
+                vm
+            })
+            .collect();
+        for cycle in 0..2_000 {
+            pool.step_all();
+            for vm in &mut vms {
+                vm.do_cycle();
+            }
+            if cycle % 10 == 9 {
+                pool.decrement_timers();
+                vms.iter_mut().for_each(VirtualMachine::decrement_timers);
+            }
+        }
+        for (n, vm) in vms.iter().enumerate() {
+            assert_eq!(pool.to_vm(n).diff(vm), [], "instance {}", n);
+            assert_eq!(pool.halt_reason(n), vm.halt_reason());
+            assert_eq!(pool.to_vm(n).cycle_count(), vm.cycle_count());
+        }
+    }
+}
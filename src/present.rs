@@ -0,0 +1,109 @@
+//! Helpers for presenting the display in a window.
+//!
+//! Frontends share these so that they agree on how the display is shown.
+
+use super::{DISPLAY_HEIGHT, DISPLAY_WIDTH};
+
+/// Fits a `width` by `height` image into a `window_w` by `window_h` window,
+/// returning `(scale, offset_x, offset_y)`.
+///
+/// The scale is the largest whole number at which the image fits, so that every pixel
+/// is the same size, and the offset centers the image, leaving even borders around it.
+/// The scale is at least 1, so an image larger than the window is cut off
+/// at the right and bottom.
+pub fn fit(width: u32, height: u32, window_w: u32, window_h: u32) -> (u32, u32, u32) {
+    let scale = (window_w / width.max(1))
+        .min(window_h / height.max(1))
+        .max(1);
+    let offset_x = window_w.saturating_sub(width * scale) / 2;
+    let offset_y = window_h.saturating_sub(height * scale) / 2;
+    (scale, offset_x, offset_y)
+}
+
+/// Fits the display into a `window_w` by `window_h` window, like [`fit`].
+pub fn fit_display(window_w: u32, window_h: u32) -> (u32, u32, u32) {
+    fit(
+        DISPLAY_WIDTH as u32,
+        DISPLAY_HEIGHT as u32,
+        window_w,
+        window_h,
+    )
+}
+
+/// Where to draw an image in a window, and how much to stretch it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Placement {
+    /// The horizontal scale.
+    pub scale_x: f32,
+    /// The vertical scale.
+    pub scale_y: f32,
+    /// The distance from the left edge of the window.
+    pub offset_x: f32,
+    /// The distance from the top edge of the window.
+    pub offset_y: f32,
+}
+
+/// Fits an image like [`fit`], for displays with pixels that aren't square.
+///
+/// `pixel_aspect` is the width of a pixel divided by its height, so 1 gives the same
+/// result as [`fit`], and 2 stretches the image to twice its width. The scale along the
+/// shorter side of a pixel stays a whole number, and the other one is stretched from it.
+///
+/// # Panics
+///
+/// Panics if `pixel_aspect` isn't positive.
+pub fn fit_with_aspect(
+    width: u32,
+    height: u32,
+    window_w: u32,
+    window_h: u32,
+    pixel_aspect: f32,
+) -> Placement {
+    assert!(pixel_aspect > 0.0, "pixel aspect must be positive");
+    let (stretch_x, stretch_y) = if pixel_aspect >= 1.0 {
+        (pixel_aspect, 1.0)
+    } else {
+        (1.0, 1.0 / pixel_aspect)
+    };
+    let (w, h) = (
+        width.max(1) as f32 * stretch_x,
+        height.max(1) as f32 * stretch_y,
+    );
+    let scale = (window_w as f32 / w)
+        .min(window_h as f32 / h)
+        .floor()
+        .max(1.0);
+    Placement {
+        scale_x: scale * stretch_x,
+        scale_y: scale * stretch_y,
+        offset_x: ((window_w as f32 - w * scale) / 2.0).max(0.0).floor(),
+        offset_y: ((window_h as f32 - h * scale) / 2.0).max(0.0).floor(),
+    }
+}
+
+#[test]
+fn test_fit_with_aspect() {
+    let square = fit_with_aspect(64, 32, 700, 400, 1.0);
+    assert_eq!(
+        (square.scale_x, square.offset_x, square.offset_y),
+        (10.0, 30.0, 40.0)
+    );
+    assert_eq!(square.scale_y, 10.0);
+    // Pixels twice as wide, as 128 by 32 square ones
+    let wide = fit_with_aspect(64, 32, 700, 400, 2.0);
+    assert_eq!((wide.scale_x, wide.scale_y), (10.0, 5.0));
+    assert_eq!((wide.offset_x, wide.offset_y), (30.0, 120.0));
+    let tall = fit_with_aspect(64, 32, 640, 640, 0.5);
+    assert_eq!((tall.scale_x, tall.scale_y), (10.0, 20.0));
+    assert_eq!((tall.offset_x, tall.offset_y), (0.0, 0.0));
+}
+
+#[test]
+fn test_fit_display() {
+    assert_eq!(fit_display(640, 320), (10, 0, 0));
+    assert_eq!(fit_display(700, 400), (10, 30, 40));
+    assert_eq!(fit_display(1920, 1080), (30, 0, 60));
+    assert_eq!(fit_display(10, 10), (1, 0, 0));
+    // A display turned sideways
+    assert_eq!(fit(32, 64, 640, 640), (10, 160, 0));
+}
@@ -0,0 +1,166 @@
+//! Counting and timing the instructions a program executes.
+//!
+//! While profiling is on, every instruction is counted by opcode, like `DRW Vx, Vy, n`.
+//! Timing each one would take longer than most instructions do, so only every
+//! [`SAMPLE_INTERVAL`]th instruction is timed, and the time of each opcode is estimated
+//! from its samples.
+
+use {
+    super::{VirtualMachine, opcodes::OpcodeSpec},
+    std::{collections::HashMap, fmt, time::Duration},
+};
+
+/// How many instructions apart the timed ones are.
+///
+/// It's a prime, so that loops don't keep sampling the same instructions.
+pub const SAMPLE_INTERVAL: u64 = 61;
+
+/// The counts and timing of one opcode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpcodeStats {
+    /// The mnemonic of the opcode.
+    pub mnemonic: &'static str,
+    /// How many times it was executed.
+    pub count: u64,
+    /// How many of those were timed.
+    pub samples: u64,
+    /// The total time of the timed executions.
+    pub sampled_time: Duration,
+}
+
+impl OpcodeStats {
+    /// Estimates the time spent in all executions from the timed ones.
+    pub fn estimated_time(&self) -> Duration {
+        if self.samples == 0 {
+            return Duration::ZERO;
+        }
+        let nanos =
+            self.sampled_time.as_nanos() * u128::from(self.count) / u128::from(self.samples);
+        Duration::from_nanos(nanos.try_into().unwrap_or(u64::MAX))
+    }
+}
+
+/// The instructions executed since profiling was started.
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    instructions: u64,
+    opcodes: HashMap<&'static str, OpcodeStats>,
+}
+
+impl Profile {
+    /// Returns the number of instructions counted, leaving out unknown ones.
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    /// Returns the stats of every opcode that was executed, the most time-consuming first.
+    pub fn opcodes(&self) -> Vec<&OpcodeStats> {
+        let mut opcodes: Vec<_> = self.opcodes.values().collect();
+        opcodes.sort_by(|a, b| {
+            b.estimated_time()
+                .cmp(&a.estimated_time())
+                .then(b.count.cmp(&a.count))
+                .then(a.mnemonic.cmp(b.mnemonic))
+        });
+        opcodes
+    }
+
+    /// Returns the estimated time spent executing instructions.
+    pub fn estimated_time(&self) -> Duration {
+        self.opcodes.values().map(OpcodeStats::estimated_time).sum()
+    }
+
+    // Returns whether the next instruction should be timed
+    pub(super) fn should_sample(&self) -> bool {
+        self.instructions.is_multiple_of(SAMPLE_INTERVAL)
+    }
+
+    pub(super) fn record(&mut self, spec: &'static OpcodeSpec, time: Option<Duration>) {
+        self.instructions += 1;
+        let stats = self.opcodes.entry(spec.mnemonic).or_insert(OpcodeStats {
+            mnemonic: spec.mnemonic,
+            count: 0,
+            samples: 0,
+            sampled_time: Duration::ZERO,
+        });
+        stats.count += 1;
+        if let Some(time) = time {
+            stats.samples += 1;
+            stats.sampled_time += time;
+        }
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let total = self.estimated_time().as_secs_f64();
+        writeln!(
+            f,
+            "{:<16} {:>12} {:>12} {:>7}",
+            "Opcode", "Count", "Time (µs)", "Time %"
+        )?;
+        for stats in self.opcodes() {
+            let time = stats.estimated_time().as_secs_f64();
+            let share = if total > 0.0 {
+                time / total * 100.0
+            } else {
+                0.0
+            };
+            writeln!(
+                f,
+                "{:<16} {:>12} {:>12.1} {:>6.1}%",
+                stats.mnemonic,
+                stats.count,
+                time * 1e6,
+                share
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl VirtualMachine {
+    /// Starts or stops profiling the executed instructions.
+    ///
+    /// Profiling is off by default. Starting it discards the previous profile.
+    pub fn set_profiling(&mut self, profiling: bool) {
+        self.profile = profiling.then(Box::default);
+    }
+
+    /// Returns the profile of the instructions executed since profiling was started,
+    /// if it's on.
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_deref()
+    }
+}
+
+#[test]
+fn test_profile_counts() {
+    let mut vm = VirtualMachine::new();
+    // 0x200: LD I, 0
+    // 0x202: DRW V0, V0, 5
+    // 0x204: ADD V0, 1
+    // 0x206: JP 0x202
+    vm.load_rom(&[0xA0, 0x00, 0xD0, 0x05, 0x70, 0x01, 0x12, 0x02]);
+    vm.do_cycle();
+    vm.set_profiling(true);
+    for _ in 0..300 {
+        vm.do_cycle();
+    }
+    let profile = vm.profile().unwrap();
+    assert_eq!(profile.instructions(), 300);
+    let mut counts: Vec<_> = profile
+        .opcodes()
+        .iter()
+        .map(|s| (s.mnemonic, s.count))
+        .collect();
+    counts.sort();
+    assert_eq!(
+        counts,
+        [("ADD Vx, kk", 100), ("DRW Vx, Vy, n", 100), ("JP nnn", 100)]
+    );
+    let samples: u64 = profile.opcodes().iter().map(|s| s.samples).sum();
+    assert_eq!(samples, 300_u64.div_ceil(SAMPLE_INTERVAL));
+    vm.set_profiling(false);
+    assert!(vm.profile().is_none());
+}
@@ -0,0 +1,188 @@
+//! Behaviors that differ between CHIP-8 interpreters.
+
+/// Behaviors that differ between CHIP-8 interpreters.
+///
+/// The defaults match the original COSMAC VIP interpreter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// `8xy6` and `8xyE` shift Vy into Vx. Otherwise, Vx is shifted in place.
+    pub shift_uses_vy: bool,
+    /// `00FE` and `00FF` clear the display when switching resolution, like SUPER-CHIP 1.1
+    /// and Octo do. The original SUPER-CHIP 1.0 leaves the display alone.
+    pub resolution_switch_clears: bool,
+    /// Only one key of the keypad is registered at a time, the one pressed last, like on
+    /// hex keypads without rollover. Off by default, so that every held key is seen.
+    pub single_key: bool,
+    /// Setting the sound timer to 1 makes no sound, like on the COSMAC VIP, where a beep
+    /// needs a value of at least 2 to be heard. Off by default.
+    pub min_beep: bool,
+    /// `Fx55` and `Fx65` leave I unchanged, like SUPER-CHIP and most modern interpreters.
+    /// Otherwise, I is left pointing past the last register stored or loaded, like on the
+    /// COSMAC VIP. Off by default.
+    pub load_store_keeps_i: bool,
+    /// `8xy1`, `8xy2` and `8xy3` reset VF to 0, like on the COSMAC VIP, where the logic
+    /// instructions clobber it. Off by default.
+    pub logic_resets_vf: bool,
+    /// `Bxnn` jumps to xnn plus Vx, like on CHIP-48 and SUPER-CHIP. Otherwise, `Bnnn` jumps
+    /// to nnn plus V0, like on the COSMAC VIP. Off by default.
+    pub jump_uses_vx: bool,
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self {
+            shift_uses_vy: true,
+            resolution_switch_clears: true,
+            single_key: false,
+            min_beep: false,
+            load_store_keeps_i: false,
+            logic_resets_vf: false,
+            jump_uses_vx: false,
+        }
+    }
+}
+
+impl Quirks {
+    /// Returns the flags of all quirks, in the order of [`QUIRKS`].
+    pub fn flags(&self) -> [bool; 7] {
+        [
+            self.shift_uses_vy,
+            self.resolution_switch_clears,
+            self.single_key,
+            self.min_beep,
+            self.load_store_keeps_i,
+            self.logic_resets_vf,
+            self.jump_uses_vx,
+        ]
+    }
+
+    /// Returns the flags of all quirks mutably, in the order of [`QUIRKS`], for editing
+    /// them generically, like in a settings window.
+    pub fn flags_mut(&mut self) -> [&mut bool; 7] {
+        [
+            &mut self.shift_uses_vy,
+            &mut self.resolution_switch_clears,
+            &mut self.single_key,
+            &mut self.min_beep,
+            &mut self.load_store_keeps_i,
+            &mut self.logic_resets_vf,
+            &mut self.jump_uses_vx,
+        ]
+    }
+}
+
+/// Description of a single quirk, for documentation and tooling.
+pub struct QuirkSpec {
+    /// The name of the field in [`Quirks`].
+    pub name: &'static str,
+    /// What the quirk does when enabled.
+    pub description: &'static str,
+    /// Whether the quirk is enabled by default.
+    pub default: bool,
+    /// Patterns of the opcodes the quirk affects, as in [`OpcodeSpec::pattern`].
+    ///
+    /// [`OpcodeSpec::pattern`]: crate::opcodes::OpcodeSpec::pattern
+    pub opcodes: &'static [u16],
+}
+
+/// All quirks, in the order of the fields of [`Quirks`].
+pub static QUIRKS: &[QuirkSpec] = &[
+    QuirkSpec {
+        name: "shift_uses_vy",
+        description: "8xy6 and 8xyE shift Vy into Vx. Otherwise, Vx is shifted in place.",
+        default: true,
+        opcodes: &[0x8006, 0x800E],
+    },
+    QuirkSpec {
+        name: "resolution_switch_clears",
+        description: "00FE and 00FF clear the display when switching resolution.",
+        default: true,
+        opcodes: &[0x00FE, 0x00FF],
+    },
+    QuirkSpec {
+        name: "single_key",
+        description: "Only the key pressed last is registered, as on keypads without rollover.",
+        default: false,
+        opcodes: &[0xE09E, 0xE0A1, 0xF00A],
+    },
+    QuirkSpec {
+        name: "min_beep",
+        description: "Setting the sound timer to 1 makes no sound, as on the COSMAC VIP.",
+        default: false,
+        opcodes: &[0xF018],
+    },
+    QuirkSpec {
+        name: "load_store_keeps_i",
+        description: "Fx55 and Fx65 leave I unchanged, instead of moving it past the registers.",
+        default: false,
+        opcodes: &[0xF055, 0xF065],
+    },
+    QuirkSpec {
+        name: "logic_resets_vf",
+        description: "8xy1, 8xy2 and 8xy3 reset VF to 0, as on the COSMAC VIP.",
+        default: false,
+        opcodes: &[0x8001, 0x8002, 0x8003],
+    },
+    QuirkSpec {
+        name: "jump_uses_vx",
+        description: "Bxnn jumps to xnn + Vx instead of nnn + V0, as on CHIP-48 and SUPER-CHIP.",
+        default: false,
+        opcodes: &[0xB000],
+    },
+];
+
+#[test]
+fn test_quirk_flags_follow_specs() {
+    let defaults = Quirks::default().flags();
+    assert_eq!(defaults.len(), QUIRKS.len());
+    for (quirk, default) in QUIRKS.iter().zip(defaults) {
+        assert_eq!(quirk.default, default, "{}", quirk.name);
+    }
+}
+
+#[test]
+fn test_quirk_opcodes_exist() {
+    for quirk in QUIRKS {
+        for &pattern in quirk.opcodes {
+            // Extensions that aren't compiled in have no specs to check against
+            if crate::analysis::extension_of(pattern).is_some_and(|ext| !ext.is_compiled_in()) {
+                continue;
+            }
+            assert!(
+                crate::opcodes::all().any(|spec| spec.pattern == pattern),
+                "{}: {:#06x}",
+                quirk.name,
+                pattern
+            );
+        }
+    }
+}
+
+#[test]
+fn test_single_key() {
+    let mut vm = crate::VirtualMachine::new();
+    // 0x200: LD V0, 1
+    // 0x202: SKP V0
+    // 0x204: LD V1, 1
+    vm.load_rom(&[0x60, 0x01, 0xE0, 0x9E, 0x61, 0x01]);
+    vm.set_quirks(Quirks {
+        single_key: true,
+        ..Quirks::default()
+    });
+    vm.press_key(1);
+    vm.press_key(2);
+    assert!(!vm.key_pressed(1));
+    assert!(vm.key_pressed(2));
+    for _ in 0..3 {
+        vm.do_cycle();
+    }
+    // Key 1 is still held, but isn't registered
+    assert_eq!(vm.v(1), 1);
+    vm.release_key(2);
+    assert!(!vm.key_pressed(1));
+
+    let mut vm = crate::VirtualMachine::new();
+    vm.press_key(1);
+    vm.press_key(2);
+    assert!(vm.key_pressed(1) && vm.key_pressed(2));
+}
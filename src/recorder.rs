@@ -0,0 +1,122 @@
+//! Recording values over time, for graphing game variables in external tools.
+//!
+//! A [`Recorder`] samples a set of [expressions](crate::expr) once per frame, and writes
+//! them out as CSV with one row per sample.
+
+use {
+    super::{
+        VirtualMachine,
+        expr::{self, Expr, ExprError, Value},
+    },
+    std::{
+        io::{self, Write},
+        ops::Range,
+    },
+};
+
+/// Samples values of the VM state over time.
+#[derive(Debug, Clone, Default)]
+pub struct Recorder {
+    columns: Vec<(String, Expr)>,
+    // The frame of every sample, and the values, which are `None` where evaluation failed
+    rows: Vec<(u64, Vec<Option<Value>>)>,
+}
+
+impl Recorder {
+    /// Creates a recorder without any columns.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a column with the value of an expression, like `v3` or `[0x2F0]`.
+    ///
+    /// The expression is also the name of the column.
+    pub fn watch(&mut self, src: &str) -> Result<(), ExprError> {
+        self.columns.push((src.to_owned(), expr::parse(src)?));
+        Ok(())
+    }
+
+    /// Adds a column for every byte of memory in `range`.
+    pub fn watch_range(&mut self, range: Range<u16>) {
+        for addr in range {
+            self.watch(&format!("[{:#05X}]", addr))
+                .expect("memory expressions are valid");
+        }
+    }
+
+    /// Adds a column for each of the registers V0 to VF and I.
+    pub fn watch_registers(&mut self) {
+        for x in 0..16 {
+            self.watch(&format!("v{:x}", x))
+                .expect("register expressions are valid");
+        }
+        self.watch("i").expect("register expressions are valid");
+    }
+
+    /// Takes a sample of every column. Call this once per frame.
+    pub fn sample(&mut self, vm: &VirtualMachine) {
+        let values = self
+            .columns
+            .iter()
+            .map(|(_, expr)| expr.eval(vm).ok())
+            .collect();
+        self.rows.push((vm.frame_count(), values));
+    }
+
+    /// Returns the number of samples taken.
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Returns whether no samples were taken yet.
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Writes the samples as CSV, with a header row naming the columns.
+    ///
+    /// The first column is the frame of the sample. Values that couldn't be evaluated,
+    /// like divisions by zero, are left empty.
+    pub fn write_csv<W: Write>(&self, mut w: W) -> io::Result<()> {
+        write!(w, "frame")?;
+        for (name, _) in &self.columns {
+            write!(w, ",\"{}\"", name.replace('"', "\"\""))?;
+        }
+        writeln!(w)?;
+        for (frame, values) in &self.rows {
+            write!(w, "{}", frame)?;
+            for value in values {
+                match value {
+                    Some(value) => write!(w, ",{}", value)?,
+                    None => write!(w, ",")?,
+                }
+            }
+            writeln!(w)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_recorder() {
+    let mut vm = VirtualMachine::new();
+    // 0x200: ADD V1, 3
+    // 0x202: LD I, 0x300
+    // 0x204: LD [I], V1
+    // 0x206: JP 0x206
+    vm.load_rom(&[0x71, 0x03, 0xA3, 0x00, 0xF1, 0x55, 0x12, 0x06]);
+    let mut rec = Recorder::new();
+    rec.watch("v1").unwrap();
+    rec.watch_range(0x301..0x302);
+    rec.watch("1 / v1").unwrap();
+    assert!(rec.watch("v1 +").is_err());
+    rec.sample(&vm);
+    vm.step_frame();
+    rec.sample(&vm);
+    let mut csv = Vec::new();
+    rec.write_csv(&mut csv).unwrap();
+    assert_eq!(
+        String::from_utf8(csv).unwrap(),
+        "frame,\"v1\",\"[0x301]\",\"1 / v1\"\n0,0,0,\n1,3,3,0\n"
+    );
+}
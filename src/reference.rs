@@ -0,0 +1,422 @@
+//! A slow but obviously correct interpreter, used as an oracle for testing.
+//!
+//! Every instruction is implemented in the most straightforward way possible,
+//! directly from its description, and anything it doesn't define is a [`Fault`].
+//! It's not meant for running programs, but it documents the intended semantics
+//! and backs the differential tests of [`VirtualMachine`].
+
+use {
+    super::{
+        DISPLAY_HEIGHT, DISPLAY_WIDTH, FONT_ADDR, MEM_SIZE, Quirks, START_ADDR, VirtualMachine,
+        load_fonts, opcodes::Extension, rng::Rng,
+    },
+    std::fmt,
+};
+
+/// Something the reference interpreter doesn't define, which stops it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// Memory was accessed past the end, at the address.
+    OutOfBounds(usize),
+    /// A subroutine was called with 16 already running.
+    StackOverflow,
+    /// A subroutine returned without being called.
+    StackUnderflow,
+    /// The instruction isn't a CHIP-8 one.
+    UnknownInstruction(u16),
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Fault::OutOfBounds(addr) => write!(f, "out of bounds at {:#x}", addr),
+            Fault::StackOverflow => write!(f, "stack overflow"),
+            Fault::StackUnderflow => write!(f, "return without a call"),
+            Fault::UnknownInstruction(ins) => write!(f, "unknown instruction {:#06x}", ins),
+        }
+    }
+}
+
+/// The state of the reference interpreter.
+pub struct Reference {
+    /// Memory
+    pub ram: Vec<u8>,
+    /// Registers V0 through VF
+    pub v: [u8; 16],
+    /// The I register
+    pub i: u16,
+    /// The program counter
+    pub pc: u16,
+    /// Return addresses of the subroutines that are being executed
+    pub stack: Vec<u16>,
+    /// The delay timer
+    pub delay_timer: u8,
+    /// The sound timer
+    pub sound_timer: u8,
+    /// The display, one byte per pixel
+    pub display: Vec<u8>,
+    /// The state of the keys of the hexadecimal keypad
+    pub keys: [bool; 16],
+    /// The quirks to run with
+    pub quirks: Quirks,
+    rng: Rng,
+}
+
+impl Reference {
+    /// Creates an interpreter with `rom` loaded.
+    ///
+    /// `seed` seeds the random number generator the same way as
+    /// [`VirtualMachine::set_rng_seed`].
+    pub fn new(rom: &[u8], quirks: Quirks, seed: u64) -> Self {
+        let mut ram = vec![0; MEM_SIZE];
+        load_fonts(&mut ram);
+        let start = START_ADDR as usize;
+        let len = rom.len().min(MEM_SIZE - start);
+        ram[start..start + len].copy_from_slice(&rom[..len]);
+        Self {
+            ram,
+            v: [0; 16],
+            i: 0,
+            pc: START_ADDR,
+            stack: Vec::new(),
+            delay_timer: 0,
+            sound_timer: 0,
+            display: vec![0; DISPLAY_WIDTH * DISPLAY_HEIGHT],
+            keys: [false; 16],
+            quirks,
+            rng: Rng::new(seed),
+        }
+    }
+
+    /// Decrements the timers, like [`VirtualMachine::decrement_timers`].
+    pub fn decrement_timers(&mut self) {
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+    }
+
+    fn read(&self, addr: usize) -> Result<u8, Fault> {
+        self.ram.get(addr).copied().ok_or(Fault::OutOfBounds(addr))
+    }
+
+    fn write(&mut self, addr: usize, value: u8) -> Result<(), Fault> {
+        *self.ram.get_mut(addr).ok_or(Fault::OutOfBounds(addr))? = value;
+        Ok(())
+    }
+
+    /// Returns the instruction the program counter points to.
+    pub fn next_ins(&self) -> Result<u16, Fault> {
+        let pc = usize::from(self.pc);
+        Ok(u16::from_be_bytes([self.read(pc)?, self.read(pc + 1)?]))
+    }
+
+    /// Executes one instruction.
+    ///
+    /// On a fault, the state is left as it was partway through the instruction.
+    pub fn step(&mut self) -> Result<(), Fault> {
+        let ins = self.next_ins()?;
+        self.pc += 2;
+        let nnn = ins & 0x0FFF;
+        let kk = (ins & 0xFF) as u8;
+        let n = (ins & 0xF) as usize;
+        let x = ((ins >> 8) & 0xF) as usize;
+        let y = ((ins >> 4) & 0xF) as usize;
+        let vx = self.v[x];
+        let vy = self.v[y];
+        match ins >> 12 {
+            0x0 if ins == 0x00E0 => self.display.iter_mut().for_each(|px| *px = 0),
+            0x0 if ins == 0x00EE => self.pc = self.stack.pop().ok_or(Fault::StackUnderflow)?,
+            // Machine code routines can't be run, so they do nothing
+            0x0 => {}
+            0x1 => self.pc = nnn,
+            0x2 => {
+                if self.stack.len() == 16 {
+                    return Err(Fault::StackOverflow);
+                }
+                self.stack.push(self.pc);
+                self.pc = nnn;
+            }
+            0x3 if vx == kk => self.pc += 2,
+            0x4 if vx != kk => self.pc += 2,
+            0x5 if n == 0 && vx == vy => self.pc += 2,
+            0x3..=0x5 => {}
+            0x6 => self.v[x] = kk,
+            0x7 => self.v[x] = vx.wrapping_add(kk),
+            0x8 => match n {
+                0x0 => self.v[x] = vy,
+                0x1..=0x3 => {
+                    self.v[x] = match n {
+                        0x1 => vx | vy,
+                        0x2 => vx & vy,
+                        _ => vx ^ vy,
+                    };
+                    if self.quirks.logic_resets_vf {
+                        self.v[0xF] = 0;
+                    }
+                }
+                0x4 => {
+                    self.v[x] = vx.wrapping_add(vy);
+                    self.v[0xF] = u8::from(u16::from(vx) + u16::from(vy) > 0xFF);
+                }
+                0x5 => {
+                    self.v[x] = vx.wrapping_sub(vy);
+                    self.v[0xF] = u8::from(vx >= vy);
+                }
+                0x6 => {
+                    let src = if self.quirks.shift_uses_vy { vy } else { vx };
+                    self.v[x] = src / 2;
+                    self.v[0xF] = src % 2;
+                }
+                0x7 => {
+                    self.v[x] = vy.wrapping_sub(vx);
+                    self.v[0xF] = u8::from(vy >= vx);
+                }
+                0xE => {
+                    let src = if self.quirks.shift_uses_vy { vy } else { vx };
+                    self.v[x] = src.wrapping_mul(2);
+                    self.v[0xF] = src / 128;
+                }
+                _ => return Err(Fault::UnknownInstruction(ins)),
+            },
+            0x9 if n == 0 => {
+                if vx != vy {
+                    self.pc += 2;
+                }
+            }
+            0xA => self.i = nnn,
+            0xB => {
+                let offset = if self.quirks.jump_uses_vx {
+                    vx
+                } else {
+                    self.v[0]
+                };
+                self.pc = nnn + u16::from(offset);
+            }
+            0xC => self.v[x] = self.rng.next_byte() & kk,
+            0xD => {
+                let x0 = vx as usize % DISPLAY_WIDTH;
+                let y0 = vy as usize % DISPLAY_HEIGHT;
+                self.v[0xF] = 0;
+                for row in 0..n {
+                    let bits = self.read(usize::from(self.i) + row)?;
+                    for col in 0..8 {
+                        let (px, py) = (x0 + col, y0 + row);
+                        let sprite_px = bits & (0x80 >> col) != 0;
+                        if px >= DISPLAY_WIDTH || py >= DISPLAY_HEIGHT || !sprite_px {
+                            continue;
+                        }
+                        let idx = py * DISPLAY_WIDTH + px;
+                        if self.display[idx] == 1 {
+                            self.v[0xF] = 1;
+                        }
+                        self.display[idx] ^= 1;
+                    }
+                }
+            }
+            0xE if kk == 0x9E => {
+                if self.keys[usize::from(vx & 0xF)] {
+                    self.pc += 2;
+                }
+            }
+            0xE if kk == 0xA1 => {
+                if !self.keys[usize::from(vx & 0xF)] {
+                    self.pc += 2;
+                }
+            }
+            0xF => match kk {
+                0x07 => self.v[x] = self.delay_timer,
+                0x0A => match self.keys.iter().position(|&pressed| pressed) {
+                    Some(key) => self.v[x] = key as u8,
+                    // Wait by executing this instruction again
+                    None => self.pc -= 2,
+                },
+                0x15 => self.delay_timer = vx,
+                0x18 => self.sound_timer = vx,
+                0x1E => self.i = self.i.wrapping_add(u16::from(vx)),
+                0x29 => self.i = FONT_ADDR + u16::from(vx & 0xF) * 5,
+                0x33 => {
+                    let i = usize::from(self.i);
+                    self.write(i, vx / 100)?;
+                    self.write(i + 1, vx / 10 % 10)?;
+                    self.write(i + 2, vx % 10)?;
+                }
+                0x55 => {
+                    for reg in 0..=x {
+                        self.write(usize::from(self.i) + reg, self.v[reg])?;
+                    }
+                    if !self.quirks.load_store_keeps_i {
+                        self.i = self.i.wrapping_add(x as u16 + 1);
+                    }
+                }
+                0x65 => {
+                    for reg in 0..=x {
+                        self.v[reg] = self.read(usize::from(self.i) + reg)?;
+                    }
+                    if !self.quirks.load_store_keeps_i {
+                        self.i = self.i.wrapping_add(x as u16 + 1);
+                    }
+                }
+                _ => return Err(Fault::UnknownInstruction(ins)),
+            },
+            _ => return Err(Fault::UnknownInstruction(ins)),
+        }
+        Ok(())
+    }
+}
+
+/// A point where [`VirtualMachine`] and [`Reference`] disagree.
+#[derive(Debug)]
+pub struct Divergence {
+    /// The number of instructions executed before the states differed.
+    pub cycle: usize,
+    /// The address of the instruction that caused the divergence.
+    pub pc: u16,
+    /// The instruction that caused the divergence.
+    pub ins: u16,
+    /// Descriptions of the differences.
+    pub differences: Vec<String>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "diverged at cycle {} executing {:#06x} at {:#x}: {}",
+            self.cycle,
+            self.ins,
+            self.pc,
+            self.differences.join(", ")
+        )
+    }
+}
+
+/// How many instructions are executed between timer ticks in [`differential`].
+pub const CYCLES_PER_TICK: usize = 10;
+
+/// Runs `rom` on both [`VirtualMachine`] and [`Reference`] for `cycles` instructions,
+/// comparing their states after every instruction. Like the reference, the VM only
+/// understands CHIP-8 instructions.
+///
+/// A [`Fault`] of the reference is a divergence too, unless the VM halted along with it.
+pub fn differential(
+    rom: &[u8],
+    quirks: Quirks,
+    seed: u64,
+    cycles: usize,
+) -> Result<(), Divergence> {
+    let mut vm = VirtualMachine::new();
+    vm.set_quirks(quirks);
+    vm.extension = Extension::Chip8;
+    vm.set_rng_seed(seed);
+    vm.load_rom(rom);
+    let mut reference = Reference::new(rom, quirks, seed);
+    for cycle in 0..cycles {
+        let pc = reference.pc;
+        let ins = reference.next_ins().unwrap_or(0);
+        vm.do_cycle();
+        if let Err(fault) = reference.step() {
+            if vm.halt.is_some() {
+                return Ok(());
+            }
+            return Err(Divergence {
+                cycle,
+                pc,
+                ins,
+                differences: vec![format!("the reference stopped: {}", fault)],
+            });
+        }
+        if cycle % CYCLES_PER_TICK == CYCLES_PER_TICK - 1 {
+            vm.decrement_timers();
+            reference.decrement_timers();
+        }
+        let differences = compare(&vm, &reference);
+        if !differences.is_empty() {
+            return Err(Divergence {
+                cycle,
+                pc,
+                ins,
+                differences,
+            });
+        }
+    }
+    Ok(())
+}
+
+fn compare(vm: &VirtualMachine, reference: &Reference) -> Vec<String> {
+    let mut differences = Vec::new();
+    for x in 0..16 {
+        if vm.v[x].0 != reference.v[x] {
+            differences.push(format!(
+                "V{:X}: {:#x} != {:#x}",
+                x, vm.v[x].0, reference.v[x]
+            ));
+        }
+    }
+    let mut cmp = |what: &str, a: u16, b: u16| {
+        if a != b {
+            differences.push(format!("{}: {:#x} != {:#x}", what, a, b));
+        }
+    };
+    cmp("I", vm.i, reference.i);
+    cmp("PC", vm.pc, reference.pc);
+    cmp("DT", vm.delay_timer.into(), reference.delay_timer.into());
+    cmp("ST", vm.sound_timer.into(), reference.sound_timer.into());
+    if vm.ram[..] != reference.ram[..] {
+        let addr = (0..MEM_SIZE).find(|&addr| vm.ram[addr] != reference.ram[addr]);
+        differences.push(format!("RAM differs from {:#x}", addr.unwrap_or(0)));
+    }
+    if vm.display.pixels() != reference.display.as_slice() {
+        differences.push("display differs".into());
+    }
+    differences
+}
+
+#[test]
+fn test_differential_against_generated_programs() {
+    use super::romgen::{Generator, minimize};
+
+    let mut generator = Generator::new(0xC8);
+    for quirks in [
+        Quirks::default(),
+        Quirks {
+            shift_uses_vy: false,
+            load_store_keeps_i: true,
+            logic_resets_vf: true,
+            jump_uses_vx: true,
+            ..Quirks::default()
+        },
+    ] {
+        for seed in 0..20 {
+            let rom = generator.generate(300);
+            if let Err(divergence) = differential(&rom, quirks, seed, 2_000) {
+                // Keep to the same instruction, so the minimized program shows this divergence
+                // rather than one introduced by the minimizing
+                let min = minimize(&rom, |rom| {
+                    differential(rom, quirks, seed, 2_000).is_err_and(|d| d.ins == divergence.ins)
+                });
+                panic!(
+                    "{}\nminimized program: {:02x?}",
+                    divergence,
+                    min.chunks(2).collect::<Vec<_>>()
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_reference_faults() {
+    // 0x200: RET
+    let mut reference = Reference::new(&[0x00, 0xEE], Quirks::default(), 0);
+    assert_eq!(reference.step(), Err(Fault::StackUnderflow));
+    // 0x200: JP 0xFFF
+    let mut reference = Reference::new(&[0x1F, 0xFF], Quirks::default(), 0);
+    reference.step().unwrap();
+    assert_eq!(reference.step(), Err(Fault::OutOfBounds(MEM_SIZE)));
+    // The VM halts in both cases as well, so they agree
+    assert!(differential(&[0x1F, 0xFF], Quirks::default(), 0, 10).is_ok());
+    assert!(differential(&[0x00, 0xEE], Quirks::default(), 0, 10).is_ok());
+    // 0x200: CALL 0x200
+    // The VM runs on past a full stack, so they don't
+    let divergence = differential(&[0x22, 0x00], Quirks::default(), 0, 20).unwrap_err();
+    assert_eq!(divergence.cycle, 16);
+}
@@ -0,0 +1,22 @@
+/// Random number generator behind `Cxkk`.
+///
+/// SplitMix64, so that runs can be reproduced from a seed, and the whole
+/// generator state fits in a single integer.
+#[derive(Clone)]
+pub(crate) struct Rng {
+    pub(crate) state: u64,
+}
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub(crate) fn next_byte(&mut self) -> u8 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        (z ^ (z >> 31)) as u8
+    }
+}
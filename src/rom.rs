@@ -0,0 +1,432 @@
+//! Loading ROMs from the various forms they're distributed in, and tidying them up.
+
+use {
+    super::{MAX_ROM_LEN, START_ADDR, Variant, analysis},
+    std::{
+        fmt, fs,
+        io::{self, Read},
+        path::Path,
+    },
+};
+
+/// File extensions ROMs are commonly distributed with.
+pub const ROM_EXTENSIONS: &[&str] = &["ch8", "c8", "sc8", "xo8", "c8x", "mc8"];
+
+/// Returns whether `name` has one of the [`ROM_EXTENSIONS`].
+pub fn has_rom_extension(name: &str) -> bool {
+    name.rsplit_once('.').is_some_and(|(_, ext)| {
+        ROM_EXTENSIONS
+            .iter()
+            .any(|rom_ext| ext.eq_ignore_ascii_case(rom_ext))
+    })
+}
+
+/// Returns the variant a ROM has to run as, going by the extension of `name`.
+///
+/// Only CHIP-8X ROMs (`.c8x`) and MegaChip ROMs (`.mc8`) need one, as they reuse opcodes
+/// of classic CHIP-8 and SUPER-CHIP, and CHIP-8X ones start at a different address.
+/// Everything else runs fine with the defaults.
+pub fn required_variant(name: &str) -> Option<Variant> {
+    let (_, ext) = name.rsplit_once('.')?;
+    [("c8x", Variant::Chip8X), ("mc8", Variant::MegaChip)]
+        .into_iter()
+        .find(|(variant_ext, _)| ext.eq_ignore_ascii_case(variant_ext))
+        .map(|(_, variant)| variant)
+}
+
+/// An error while loading a ROM.
+#[derive(Debug)]
+pub enum RomError {
+    /// Reading the ROM failed.
+    Io(io::Error),
+    /// The archive is malformed.
+    #[cfg(feature = "zip")]
+    Zip(zip::result::ZipError),
+    /// There is no ROM in the archive.
+    NoRom,
+    /// A hex text ROM is malformed.
+    Hex {
+        /// The line the error is on, starting from 1.
+        line: usize,
+        /// What's wrong with it.
+        message: String,
+    },
+    /// The ROM doesn't fit in memory.
+    TooLarge(usize),
+    /// The file is larger than [`MAX_FILE_LEN`], too large to hold a ROM in any form.
+    FileTooLarge,
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomError::Io(e) => write!(f, "{}", e),
+            #[cfg(feature = "zip")]
+            RomError::Zip(e) => write!(f, "{}", e),
+            RomError::NoRom => write!(f, "no ROM found"),
+            RomError::Hex { line, message } => write!(f, "line {}: {}", line, message),
+            RomError::TooLarge(len) => write!(
+                f,
+                "ROM is {} bytes, but at most {} fit in memory",
+                len, MAX_ROM_LEN
+            ),
+            RomError::FileTooLarge => {
+                write!(
+                    f,
+                    "file is larger than {} bytes, too large for a ROM",
+                    MAX_FILE_LEN
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for RomError {}
+
+impl From<io::Error> for RomError {
+    fn from(e: io::Error) -> Self {
+        RomError::Io(e)
+    }
+}
+
+#[cfg(feature = "zip")]
+impl From<zip::result::ZipError> for RomError {
+    fn from(e: zip::result::ZipError) -> Self {
+        RomError::Zip(e)
+    }
+}
+
+/// The largest file [`read_file`] reads. Archives, Octo cartridges and hex dumps take more
+/// room than the ROM they hold, but nowhere near this.
+pub const MAX_FILE_LEN: usize = 256 * MAX_ROM_LEN;
+
+/// Reads a file holding a ROM in any of its forms, without reading more than
+/// [`MAX_FILE_LEN`] bytes of files that can't be one, like a mistakenly opened disk image.
+pub fn read_file(path: &Path) -> Result<Vec<u8>, RomError> {
+    let mut data = Vec::new();
+    fs::File::open(path)?
+        .take(MAX_FILE_LEN as u64 + 1)
+        .read_to_end(&mut data)?;
+    if data.len() > MAX_FILE_LEN {
+        return Err(RomError::FileTooLarge);
+    }
+    Ok(data)
+}
+
+/// Returns whether `data` looks like a zip archive.
+pub fn is_zip(data: &[u8]) -> bool {
+    data.starts_with(b"PK\x03\x04") || data.starts_with(b"PK\x05\x06")
+}
+
+/// Returns the names of the ROMs in a zip archive, sorted.
+///
+/// Only files with one of the [`ROM_EXTENSIONS`] are considered ROMs.
+#[cfg(feature = "zip")]
+pub fn zip_rom_names(data: &[u8]) -> Result<Vec<String>, RomError> {
+    let archive = zip::ZipArchive::new(io::Cursor::new(data))?;
+    let mut names: Vec<String> = archive
+        .file_names()
+        .filter(|name| has_rom_extension(name))
+        .map(str::to_owned)
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+/// Reads the ROM called `name` from a zip archive.
+#[cfg(feature = "zip")]
+pub fn read_zip_rom(data: &[u8], name: &str) -> Result<Vec<u8>, RomError> {
+    let mut archive = zip::ZipArchive::new(io::Cursor::new(data))?;
+    let file = archive.by_name(name)?;
+    let len = file.size() as usize;
+    if len > MAX_ROM_LEN {
+        return Err(RomError::TooLarge(len));
+    }
+    let mut rom = Vec::with_capacity(len);
+    file.take(MAX_ROM_LEN as u64).read_to_end(&mut rom)?;
+    Ok(rom)
+}
+
+/// Reads the ROM from a zip archive containing exactly one.
+///
+/// Returns [`RomError::NoRom`] if there are none. If there are several, the caller should
+/// let the user pick one of [`zip_rom_names`], and read it with [`read_zip_rom`].
+#[cfg(feature = "zip")]
+pub fn read_single_zip_rom(data: &[u8]) -> Result<Option<Vec<u8>>, RomError> {
+    match zip_rom_names(data)?.as_slice() {
+        [] => Err(RomError::NoRom),
+        [name] => read_zip_rom(data, name).map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// Parses a ROM written as hexadecimal text, like the listings printed in old magazines.
+///
+/// Bytes are written as pairs of hex digits, optionally prefixed with `0x`, and can be
+/// separated by whitespace or commas, or run together (`6A02` is two bytes).
+/// Words ending in a colon, like `0200:`, are taken to be addresses and skipped.
+/// Comments start with `#`, `;` or `//` and run to the end of the line.
+pub fn load_hex(text: &str) -> Result<Vec<u8>, RomError> {
+    let mut rom = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let err = |message: String| RomError::Hex {
+            line: i + 1,
+            message,
+        };
+        let line = ["#", ";", "//"]
+            .iter()
+            .filter_map(|marker| line.find(marker))
+            .min()
+            .map_or(line, |end| &line[..end]);
+        for word in line.split(|c: char| c.is_whitespace() || c == ',') {
+            if word.is_empty() || word.ends_with(':') {
+                continue;
+            }
+            let digits = word
+                .strip_prefix("0x")
+                .or_else(|| word.strip_prefix("0X"))
+                .unwrap_or(word);
+            if let Some(bad) = digits.chars().find(|c| !c.is_ascii_hexdigit()) {
+                return Err(err(format!("invalid hex digit `{}` in `{}`", bad, word)));
+            }
+            if digits.len() % 2 != 0 {
+                return Err(err(format!("odd number of hex digits in `{}`", word)));
+            }
+            for pair in digits.as_bytes().chunks(2) {
+                // Both are ASCII hex digits, checked above
+                let pair = std::str::from_utf8(pair).unwrap();
+                rom.push(u8::from_str_radix(pair, 16).unwrap());
+            }
+        }
+        if rom.len() > MAX_ROM_LEN {
+            return Err(RomError::TooLarge(rom.len()));
+        }
+    }
+    if rom.is_empty() {
+        return Err(RomError::NoRom);
+    }
+    Ok(rom)
+}
+
+/// Strips the zero bytes from the end of `rom`.
+///
+/// Some distributions pad ROMs to a round size, which changes their hash.
+/// Executing zeros is a `SYS 0` call, so programs don't rely on them being there.
+pub fn trim_padding(rom: &[u8]) -> &[u8] {
+    let len = rom.iter().rposition(|&b| b != 0).map_or(0, |last| last + 1);
+    &rom[..len]
+}
+
+/// Pads `rom` with zeros to a multiple of `align` bytes.
+///
+/// # Panics
+///
+/// Panics if `align` is 0.
+pub fn pad(rom: &[u8], align: usize) -> Vec<u8> {
+    let mut padded = rom.to_vec();
+    padded.resize(rom.len().next_multiple_of(align), 0);
+    padded
+}
+
+/// Something wrong with a ROM that [`lenient`] worked around.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RomWarning {
+    /// The ROM has an odd length, so the instruction at `addr` is missing its second byte.
+    OddLength {
+        /// The address of the incomplete instruction.
+        addr: u16,
+    },
+    /// The ROM is this many bytes, more than fit in memory, so the rest was cut off.
+    Truncated(usize),
+}
+
+impl fmt::Display for RomWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomWarning::OddLength { addr } => write!(
+                f,
+                "ROM has an odd length, the instruction at {:#05x} is missing its second byte",
+                addr
+            ),
+            RomWarning::Truncated(len) => write!(
+                f,
+                "ROM is {} bytes, only the first {} were loaded",
+                len, MAX_ROM_LEN
+            ),
+        }
+    }
+}
+
+/// Makes a ROM loadable as a whole number of instructions, reporting what had to be changed.
+///
+/// Hand-typed listings often end in half an instruction, and oversized dumps don't fit in
+/// memory. The ROM is cut off at [`MAX_ROM_LEN`] and padded with a zero to an even length.
+/// The interpreter reads the missing byte as the zero following the ROM in memory,
+/// so analyzing the result agrees with running the original.
+pub fn lenient(rom: &[u8]) -> (Vec<u8>, Vec<RomWarning>) {
+    let mut warnings = Vec::new();
+    if rom.len() > MAX_ROM_LEN {
+        warnings.push(RomWarning::Truncated(rom.len()));
+    }
+    let rom = &rom[..rom.len().min(MAX_ROM_LEN)];
+    if !rom.len().is_multiple_of(2) {
+        warnings.push(RomWarning::OddLength {
+            addr: START_ADDR + rom.len() as u16 - 1,
+        });
+    }
+    (pad(rom, 2), warnings)
+}
+
+/// How many bytes of data after the last address loaded into I are assumed to be used.
+///
+/// This is enough for a 16x16 SUPER-CHIP sprite, and for `Fx65` loading every register.
+pub const DATA_MARGIN: usize = 32;
+
+/// Returns the offset of data appended after everything the program references, if any.
+///
+/// A ROM is taken to end after its last [reachable](analysis::reachable_instructions)
+/// instruction, or [`DATA_MARGIN`] bytes after the last address an `Annn` or `F000 nnnn`
+/// instruction loads into I, whichever is further. Trailing zero padding isn't counted
+/// as appended data.
+///
+/// This is a heuristic: programs that compute addresses, or jump with `Bnnn`,
+/// can use data beyond what it finds.
+pub fn appended_data(rom: &[u8]) -> Option<usize> {
+    let offset = |addr: usize| addr.saturating_sub(usize::from(START_ADDR));
+    let mut end = 0;
+    for addr in analysis::reachable_instructions(rom, None) {
+        let at = offset(usize::from(addr));
+        let ins = u16::from_be_bytes([rom[at], rom[at + 1]]);
+        end = end.max(at + 2);
+        let target = match ins {
+            0xF000 => rom
+                .get(at + 2..at + 4)
+                .map(|w| u16::from_be_bytes([w[0], w[1]])),
+            _ if ins >> 12 == 0xA => Some(ins & 0x0FFF),
+            _ => None,
+        };
+        if let Some(target) = target {
+            end = end.max(offset(usize::from(target)) + DATA_MARGIN);
+        }
+    }
+    let trimmed = trim_padding(rom).len();
+    (end < trimmed).then_some(end)
+}
+
+#[test]
+fn test_load_hex() {
+    let text = "\
+# Draws a zero
+0200: 00E0 A0,00 ; clear, point I at the font
+0204: 0xD0 0x05  // draw
+0206: 1206
+";
+    assert_eq!(
+        load_hex(text).unwrap(),
+        [0x00, 0xE0, 0xA0, 0x00, 0xD0, 0x05, 0x12, 0x06]
+    );
+    assert_eq!(
+        load_hex("12\n6A0").unwrap_err().to_string(),
+        "line 2: odd number of hex digits in `6A0`"
+    );
+    assert_eq!(
+        load_hex("6G").unwrap_err().to_string(),
+        "line 1: invalid hex digit `G` in `6G`"
+    );
+    assert!(matches!(load_hex("# nothing"), Err(RomError::NoRom)));
+}
+
+#[test]
+fn test_trim_and_pad() {
+    let rom = [0x12, 0x00, 0x00, 0x00];
+    assert_eq!(trim_padding(&rom), [0x12]);
+    assert_eq!(pad(trim_padding(&rom), 4), rom);
+    assert_eq!(pad(&rom, 4), rom);
+    assert!(trim_padding(&[0, 0]).is_empty());
+}
+
+#[test]
+fn test_lenient() {
+    // 0x200: LD V0, 1
+    // 0x202: JP 0x2__ (truncated)
+    let (rom, warnings) = lenient(&[0x60, 0x01, 0x12]);
+    assert_eq!(rom, [0x60, 0x01, 0x12, 0x00]);
+    assert_eq!(warnings, [RomWarning::OddLength { addr: 0x202 }]);
+    assert_eq!(analysis::reachable_instructions(&rom, None), [0x200, 0x202]);
+    let (rom, warnings) = lenient(&[0x12; MAX_ROM_LEN + 3]);
+    assert_eq!(rom.len(), MAX_ROM_LEN);
+    assert_eq!(warnings, [RomWarning::Truncated(MAX_ROM_LEN + 3)]);
+    let mut vm = crate::VirtualMachine::new();
+    let log = crate::MemoryLog::new(1000);
+    vm.set_log_sink(log.clone());
+    assert!(vm.load_rom_lenient(&[0x00, 0xE0]).is_empty());
+    vm.load_rom_lenient(&[0x60, 0x01, 0x12]);
+    assert!(log.text().contains("missing its second byte"));
+}
+
+#[test]
+fn test_appended_data() {
+    let mut rom = vec![
+        0xA2, 0x06, // 0x200: LD I, 0x206
+        0xD0, 0x05, // 0x202: DRW V0, V0, 5
+        0x12, 0x04, // 0x204: JP 0x204
+        0xF0, 0x90, 0x90, 0x90, 0xF0, // 0x206: sprite
+    ];
+    rom.resize(64, 0);
+    assert_eq!(appended_data(&rom), None);
+    rom.extend_from_slice(b"ripped by someone");
+    assert_eq!(appended_data(&rom), Some(6 + DATA_MARGIN));
+}
+
+#[test]
+fn test_has_rom_extension() {
+    assert!(has_rom_extension("games/PONG.CH8"));
+    assert!(has_rom_extension("a.b.xo8"));
+    assert!(!has_rom_extension("readme.txt"));
+    assert!(!has_rom_extension("ch8"));
+    assert_eq!(required_variant("games/Wipeoff.C8X"), Some(Variant::Chip8X));
+    assert_eq!(required_variant("games/Sonic.mc8"), Some(Variant::MegaChip));
+    assert_eq!(required_variant("games/PONG.ch8"), None);
+}
+
+#[test]
+fn test_read_file() {
+    let path = std::env::temp_dir().join(format!("crusty-chip-rom-{}.ch8", std::process::id()));
+    fs::write(&path, [0x12, 0x00]).unwrap();
+    assert_eq!(read_file(&path).unwrap(), [0x12, 0x00]);
+    fs::write(&path, vec![0; MAX_FILE_LEN + 1]).unwrap();
+    assert!(matches!(read_file(&path), Err(RomError::FileTooLarge)));
+    fs::remove_file(&path).unwrap();
+}
+
+#[cfg(all(test, feature = "zip"))]
+fn make_zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+    use std::io::Write;
+
+    let mut zip = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+    for (name, data) in files {
+        zip.start_file(*name, zip::write::SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(data).unwrap();
+    }
+    zip.finish().unwrap().into_inner()
+}
+
+#[cfg(feature = "zip")]
+#[test]
+fn test_zip_roms() {
+    let single = make_zip(&[("readme.txt", b"hi"), ("pong.ch8", &[0x12, 0x00])]);
+    assert!(is_zip(&single));
+    assert_eq!(
+        read_single_zip_rom(&single).unwrap(),
+        Some(vec![0x12, 0x00])
+    );
+
+    let multi = make_zip(&[("b.ch8", &[2]), ("a.sc8", &[1])]);
+    assert_eq!(read_single_zip_rom(&multi).unwrap(), None);
+    assert_eq!(zip_rom_names(&multi).unwrap(), ["a.sc8", "b.ch8"]);
+    assert_eq!(read_zip_rom(&multi, "a.sc8").unwrap(), [1]);
+
+    let none = make_zip(&[("readme.txt", b"hi")]);
+    assert!(matches!(read_single_zip_rom(&none), Err(RomError::NoRom)));
+}
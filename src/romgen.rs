@@ -0,0 +1,222 @@
+//! Generation of random but plausible programs, for differential testing.
+//!
+//! Generated programs only jump to the start of their own instructions, and set I right before
+//! every instruction that uses it, so they run without crashing and exercise the interesting
+//! parts of the interpreter. Instructions that depend on the host (SYS, key waits) or need
+//! balanced stack usage (CALL, RET) are not generated.
+
+use {
+    super::{Instruction, START_ADDR, decode, opcodes},
+    rand::{Rng, SeedableRng, rngs::StdRng},
+};
+
+// Memory that generated programs may write to. Programs never extend this far.
+const DATA_START: u16 = 0xA00;
+const DATA_END: u16 = 0xE00;
+/// Maximum number of instructions in a generated program.
+pub const MAX_PROGRAM_LEN: usize = (DATA_START - START_ADDR) as usize / 2;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Plain,
+    Jump,
+    Skip,
+    ReadsI,
+    WritesI,
+    AddsToI,
+}
+
+fn kind(ins: Instruction) -> Option<Kind> {
+    use Instruction::*;
+    Some(match ins {
+        JumpToAddress { .. } => Kind::Jump,
+        SkipNextVxEq { .. }
+        | SkipNextVxNe { .. }
+        | SkipNextVxEqVy { .. }
+        | SkipNextVxNeVy { .. }
+        | SkipNextKeyVxPressed { .. }
+        | SkipNextKeyVxNotPressed { .. } => Kind::Skip,
+        DisplaySprite { .. } | ReadV0ThroughVxFromMem { .. } => Kind::ReadsI,
+        StoreBcdOfVxToI { .. } | CopyV0ThroughVxToMem { .. } => Kind::WritesI,
+        AddVxToI { .. } => Kind::AddsToI,
+        ClearDisplay
+        | SetVxByte { .. }
+        | AddVxByte { .. }
+        | SetVxToVy { .. }
+        | SetVxToVxOrVy { .. }
+        | SetVxToVxAndVy { .. }
+        | SetVxToVxXorVy { .. }
+        | AddVxVy { .. }
+        | SubVxVy { .. }
+        | SetVxToVyShr1 { .. }
+        | SubnVxVy { .. }
+        | SetVxToVyShl1 { .. }
+        | SetI { .. }
+        | SetVxRandAnd { .. }
+        | SetVxToDelayTimer { .. }
+        | SetDelayTimer { .. }
+        | SetSoundTimer { .. }
+        | SetIToLocOfDigitVx { .. } => Kind::Plain,
+        _ => return None,
+    })
+}
+
+/// Generator of random programs.
+///
+/// Opcodes that were generated less often are preferred, so every opcode gets covered
+/// even in short runs.
+pub struct Generator {
+    rng: StdRng,
+    // Generatable opcodes, as (spec index in `opcodes::all()`, pattern, mask, kind)
+    candidates: Vec<(usize, u16, u16, Kind)>,
+    coverage: Vec<u32>,
+}
+
+impl Generator {
+    /// Creates a generator. The same seed always generates the same programs.
+    pub fn new(seed: u64) -> Self {
+        let candidates: Vec<_> = opcodes::all()
+            .enumerate()
+            .filter_map(|(i, spec)| {
+                kind(decode(spec.pattern)).map(|kind| (i, spec.pattern, spec.mask, kind))
+            })
+            .collect();
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+            candidates,
+            coverage: vec![0; opcodes::all().count()],
+        }
+    }
+
+    /// How many times each opcode was generated, indexed like [`opcodes::all`].
+    pub fn coverage(&self) -> &[u32] {
+        &self.coverage
+    }
+
+    /// Generates a program of about `len` instructions (at most [`MAX_PROGRAM_LEN`]).
+    ///
+    /// The program ends by jumping to itself.
+    pub fn generate(&mut self, len: usize) -> Vec<u8> {
+        let len = len.min(MAX_PROGRAM_LEN);
+        // Each slot is a group of instructions that always run together.
+        // Jumps land on, and skips skip over whole slots.
+        let mut slots: Vec<Vec<u16>> = Vec::new();
+        let mut n_words = 0;
+        let mut jumps = Vec::new();
+        while n_words + 3 < len {
+            let (pattern, mask, kind) = self.pick();
+            let ins = pattern | (self.rng.r#gen::<u16>() & !mask);
+            let slot = match kind {
+                Kind::Plain => vec![ins],
+                Kind::Jump => {
+                    jumps.push(slots.len());
+                    vec![ins]
+                }
+                Kind::Skip => {
+                    // The skipped slot must be a single instruction
+                    vec![ins, 0x7000 | (self.rng.r#gen::<u16>() & 0x0FFF)]
+                }
+                Kind::ReadsI | Kind::AddsToI => {
+                    vec![0xA000 | self.rng.gen_range(0..DATA_END), ins]
+                }
+                Kind::WritesI => {
+                    vec![0xA000 | self.rng.gen_range(DATA_START..DATA_END - 16), ins]
+                }
+            };
+            n_words += slot.len();
+            slots.push(slot);
+        }
+        let mut slot_addrs = Vec::with_capacity(slots.len());
+        let mut addr = START_ADDR;
+        for slot in &slots {
+            slot_addrs.push(addr);
+            addr += 2 * slot.len() as u16;
+        }
+        // End the program cleanly instead of running off into empty memory
+        slots.push(vec![0x1000 | addr]);
+        for &slot in &jumps {
+            let target = slot_addrs[self.rng.gen_range(0..slot_addrs.len())];
+            slots[slot][0] = 0x1000 | target;
+        }
+        slots
+            .iter()
+            .flatten()
+            .flat_map(|ins| ins.to_be_bytes())
+            .collect()
+    }
+
+    fn pick(&mut self) -> (u16, u16, Kind) {
+        let a = self.rng.gen_range(0..self.candidates.len());
+        let b = self.rng.gen_range(0..self.candidates.len());
+        let (a, b) = (self.candidates[a], self.candidates[b]);
+        let (idx, pattern, mask, kind) = if self.coverage[a.0] <= self.coverage[b.0] {
+            a
+        } else {
+            b
+        };
+        self.coverage[idx] += 1;
+        (pattern, mask, kind)
+    }
+}
+
+/// A no-op instruction (`LD V0, V0`), used to blank out instructions while minimizing.
+pub const NOP: u16 = 0x8000;
+
+/// Shrinks a program while `interesting` keeps returning true for it.
+///
+/// Instructions are replaced by [`NOP`] rather than removed, so jump targets stay intact,
+/// and trailing no-ops are trimmed at the end. `interesting` should return true for `rom`
+/// itself, and is responsible for dealing with programs that crash.
+pub fn minimize(rom: &[u8], mut interesting: impl FnMut(&[u8]) -> bool) -> Vec<u8> {
+    let mut rom = rom.to_vec();
+    let n_words = rom.len() / 2;
+    let mut chunk = n_words.div_ceil(2).max(1);
+    loop {
+        for start in (0..n_words).step_by(chunk) {
+            let end = (start + chunk).min(n_words);
+            let mut candidate = rom.clone();
+            for word in start..end {
+                candidate[word * 2..word * 2 + 2].copy_from_slice(&NOP.to_be_bytes());
+            }
+            if candidate != rom && interesting(&candidate) {
+                rom = candidate;
+            }
+        }
+        if chunk == 1 {
+            break;
+        }
+        chunk = chunk.div_ceil(2);
+    }
+    while rom.len() >= 2 && rom[rom.len() - 2..] == NOP.to_be_bytes() {
+        rom.truncate(rom.len() - 2);
+    }
+    rom
+}
+
+#[test]
+fn test_generated_programs_run() {
+    let mut generator = Generator::new(1);
+    for _ in 0..50 {
+        let rom = generator.generate(200);
+        let mut vm = super::VirtualMachine::new();
+        vm.load_rom(&rom);
+        for _ in 0..10_000 {
+            vm.do_cycle();
+        }
+        assert!(vm.halt_reason() != Some(super::HaltReason::OutOfBounds));
+    }
+    let generatable = generator.candidates.iter().map(|c| c.0);
+    assert!(generatable.into_iter().all(|i| generator.coverage()[i] > 0));
+}
+
+#[test]
+fn test_minimize() {
+    let rom = Generator::new(2).generate(100);
+    // Pretend that the program is interesting as long as it contains a LD V0, 0x42
+    let mut rom = rom;
+    rom.splice(40..42, [0x60, 0x42]);
+    let contains = |rom: &[u8]| rom.chunks(2).any(|w| w == [0x60, 0x42]);
+    let min = minimize(&rom, contains);
+    assert_eq!(min.len(), 42);
+    assert_eq!(min.chunks(2).filter(|w| *w != NOP.to_be_bytes()).count(), 1);
+}
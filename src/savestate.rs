@@ -0,0 +1,756 @@
+//! Serialization of the VM state.
+//!
+//! A state starts with a header holding the format version and a thumbnail, which is simply
+//! the first plane of the display packed at 1 bit per pixel. It can be read with
+//! [`read_thumbnail`] without decoding the rest of the state. The second XO-CHIP plane is
+//! only saved near the end, once something was drawn to it. A checksum at the end guards
+//! against corruption.
+//!
+//! Everything is little-endian.
+//!
+//! States are mostly empty memory, so they compress well. With the `zstd` feature,
+//! `compress` shrinks them for storing on disk, and compressed states can be loaded like
+//! uncompressed ones.
+
+#[cfg(feature = "chip8x")]
+use super::chip8x;
+#[cfg(feature = "megachip")]
+use super::megachip;
+use {
+    super::{
+        FrameBuffer, HaltReason, KeypressWait, MEM_SIZE, Resolution, VirtualMachine,
+        display::pack_row, opcodes::Extension,
+    },
+    std::{borrow::Cow, fmt, num::Wrapping},
+};
+
+const MAGIC: &[u8; 4] = b"CCST";
+// The extensions, in the order of their number in the state
+const EXTENSIONS: [Extension; 5] = [
+    Extension::Chip8,
+    Extension::SuperChip,
+    Extension::XoChip,
+    Extension::Chip8X,
+    Extension::MegaChip,
+];
+// The start of a zstd frame
+const ZSTD_MAGIC: &[u8; 4] = &[0x28, 0xB5, 0x2F, 0xFD];
+// A generous bound on the size of a decompressed state, so corrupt data can't claim more. The
+// two screens of MegaChip make up most of the largest states.
+#[cfg(feature = "zstd")]
+const MAX_STATE_LEN: usize = MEM_SIZE * 32;
+/// The version of the state format written by [`VirtualMachine::save_state`].
+pub const VERSION: u16 = 9;
+
+/// An error while loading a state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    /// The data isn't a state.
+    BadMagic,
+    /// The state was written by a newer version.
+    UnsupportedVersion(u16),
+    /// The data ended early.
+    Truncated,
+    /// The checksum doesn't match, so the state is corrupt.
+    ChecksumMismatch,
+    /// A field has an invalid value.
+    Invalid(&'static str),
+    /// The state is compressed, but support for compressed states wasn't compiled in.
+    CompressionUnsupported,
+    /// The state uses a platform whose support wasn't compiled in, named by its feature.
+    FeatureUnsupported(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "not a savestate"),
+            StateError::UnsupportedVersion(v) => write!(f, "unsupported state version {}", v),
+            StateError::Truncated => write!(f, "state is truncated"),
+            StateError::ChecksumMismatch => write!(f, "state is corrupt (checksum mismatch)"),
+            StateError::Invalid(what) => write!(f, "state is corrupt (invalid {})", what),
+            StateError::CompressionUnsupported => {
+                write!(f, "state is compressed, but zstd support isn't compiled in")
+            }
+            StateError::FeatureUnsupported(feature) => {
+                write!(
+                    f,
+                    "state needs the {} feature, which isn't compiled in",
+                    feature
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+// 64-bit FNV-1a
+pub(crate) fn checksum(data: &[u8]) -> u64 {
+    data.iter().fold(0xCBF2_9CE4_8422_2325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01B3)
+    })
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() < len {
+            return Err(StateError::Truncated);
+        }
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Ok(head)
+    }
+    fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+    fn u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+    fn u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+    fn u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+    fn bool(&mut self) -> Result<bool, StateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Invalid("flag")),
+        }
+    }
+}
+
+/// Compresses a state for storing it, typically to about a tenth of its size.
+#[cfg(feature = "zstd")]
+pub fn compress(state: &[u8]) -> Vec<u8> {
+    zstd::bulk::compress(state, 0).expect("compressing in memory can't fail")
+}
+
+// Returns the state in `data`, decompressing it if needed
+fn decompressed(data: &[u8]) -> Result<Cow<'_, [u8]>, StateError> {
+    if !data.starts_with(ZSTD_MAGIC) {
+        return Ok(Cow::Borrowed(data));
+    }
+    #[cfg(feature = "zstd")]
+    {
+        zstd::bulk::decompress(data, MAX_STATE_LEN)
+            .map(Cow::Owned)
+            .map_err(|_| StateError::Invalid("compressed data"))
+    }
+    #[cfg(not(feature = "zstd"))]
+    Err(StateError::CompressionUnsupported)
+}
+
+// Reads the header, returning the version and a reader positioned after it
+fn read_header(data: &[u8]) -> Result<(u16, Reader<'_>), StateError> {
+    let mut r = Reader { data };
+    if r.bytes(MAGIC.len())? != MAGIC {
+        return Err(StateError::BadMagic);
+    }
+    let version = r.u16()?;
+    if version > VERSION {
+        return Err(StateError::UnsupportedVersion(version));
+    }
+    if version == 0 {
+        return Err(StateError::Invalid("version"));
+    }
+    Ok((version, r))
+}
+
+fn read_display(r: &mut Reader) -> Result<FrameBuffer, StateError> {
+    let (w, h) = (usize::from(r.u8()?), usize::from(r.u8()?));
+    let resolution = Resolution::ALL
+        .into_iter()
+        .find(|res| (res.width(), res.height()) == (w, h))
+        .ok_or(StateError::Invalid("display size"))?;
+    let packed = r.bytes(w * h / 8)?;
+    let mut fb = FrameBuffer::new(resolution);
+    for (i, px) in fb.pixels.iter_mut().enumerate() {
+        *px = (packed[i / 8] >> (7 - i % 8)) & 1;
+    }
+    Ok(fb)
+}
+
+fn write_display(out: &mut Vec<u8>, display: &FrameBuffer) {
+    out.push(display.width as u8);
+    out.push(display.height as u8);
+    for row in display.pixels.chunks(display.width) {
+        let plane: Vec<_> = row.iter().map(|&px| px & 1).collect();
+        out.extend_from_slice(&pack_row(&plane));
+    }
+}
+
+// The CHIP-8X colors and second keypad follow a flag, returned here
+#[cfg(feature = "chip8x")]
+fn read_chip8x(r: &mut Reader, vm: &mut VirtualMachine) -> Result<bool, StateError> {
+    let chip8x = r.bool()?;
+    if chip8x {
+        vm.colors.background = r.u8()?;
+        let zones = r.bytes(vm.colors.zones.len())?;
+        vm.colors.zones.copy_from_slice(zones);
+        if usize::from(vm.colors.background) >= chip8x::BACKGROUNDS.len()
+            || vm
+                .colors
+                .zones
+                .iter()
+                .any(|&c| usize::from(c) >= chip8x::COLORS.len())
+        {
+            return Err(StateError::Invalid("color"));
+        }
+        for key in &mut vm.keys2 {
+            *key = r.bool()?;
+        }
+    } else {
+        vm.colors = chip8x::ColorMap::default();
+        vm.keys2 = [false; 16];
+    }
+    Ok(chip8x)
+}
+
+#[cfg(not(feature = "chip8x"))]
+fn read_chip8x(r: &mut Reader, _: &mut VirtualMachine) -> Result<bool, StateError> {
+    if r.bool()? {
+        return Err(StateError::FeatureUnsupported("chip8x"));
+    }
+    Ok(false)
+}
+
+#[cfg(feature = "chip8x")]
+fn write_chip8x(out: &mut Vec<u8>, vm: &VirtualMachine) {
+    let colors = vm.chip8x_colors();
+    out.push(u8::from(colors.is_some()));
+    if let Some(colors) = colors {
+        out.push(colors.background);
+        out.extend_from_slice(&colors.zones);
+        out.extend(vm.keys2.iter().map(|&k| u8::from(k)));
+    }
+}
+
+#[cfg(not(feature = "chip8x"))]
+fn write_chip8x(out: &mut Vec<u8>, _: &VirtualMachine) {
+    out.push(0);
+}
+
+// The MegaChip screen follows a flag, and only comes with the MegaChip extension
+#[cfg(feature = "megachip")]
+fn read_megachip(r: &mut Reader, vm: &mut VirtualMachine) -> Result<(), StateError> {
+    if !r.bool()? {
+        vm.mega = megachip::Screen::default();
+        return Ok(());
+    }
+    if vm.extension != Extension::MegaChip {
+        return Err(StateError::Invalid("extension"));
+    }
+    let mut screen = megachip::Screen {
+        on: r.bool()?,
+        sprite_width: r.u16()?,
+        sprite_height: r.u16()?,
+        collision: r.u8()?,
+        ..megachip::Screen::default()
+    };
+    if !(1..=256).contains(&screen.sprite_width) || !(1..=256).contains(&screen.sprite_height) {
+        return Err(StateError::Invalid("sprite size"));
+    }
+    for color in &mut screen.palette {
+        color.copy_from_slice(r.bytes(3)?);
+    }
+    if screen.on {
+        let len = megachip::WIDTH * megachip::HEIGHT;
+        screen.shown = r.bytes(len)?.to_vec();
+        screen.drawing = r.bytes(len)?.to_vec();
+    }
+    vm.mega = screen;
+    Ok(())
+}
+
+#[cfg(not(feature = "megachip"))]
+fn read_megachip(r: &mut Reader, _: &mut VirtualMachine) -> Result<(), StateError> {
+    if r.bool()? {
+        return Err(StateError::FeatureUnsupported("megachip"));
+    }
+    Ok(())
+}
+
+#[cfg(feature = "megachip")]
+fn write_megachip(out: &mut Vec<u8>, vm: &VirtualMachine) {
+    let mega = vm.extension == Extension::MegaChip;
+    out.push(u8::from(mega));
+    if !mega {
+        return;
+    }
+    let screen = &vm.mega;
+    out.push(u8::from(screen.on));
+    out.extend_from_slice(&screen.sprite_width.to_le_bytes());
+    out.extend_from_slice(&screen.sprite_height.to_le_bytes());
+    out.push(screen.collision);
+    out.extend(screen.palette.iter().flatten());
+    out.extend_from_slice(&screen.shown);
+    out.extend_from_slice(&screen.drawing);
+}
+
+#[cfg(not(feature = "megachip"))]
+fn write_megachip(out: &mut Vec<u8>, _: &VirtualMachine) {
+    out.push(0);
+}
+
+// The length of the fields between the display and the resolution flag: ram, registers, I,
+// PC, SP, stack, timers, keys, key wait, halt reason, sound flag, RNG state and counters
+const HIGH_RES_OFFSET: usize = MEM_SIZE + 16 + 2 + 2 + 1 + 16 * 2 + 2 + 16 + 2 + 1 + 1 + 8 * 3;
+
+// Returns the length of the display at the start of `body`, if it's valid
+fn display_len(body: &[u8]) -> Option<usize> {
+    let mut r = Reader { data: body };
+    read_display(&mut r).ok()?;
+    Some(body.len() - r.data.len())
+}
+
+// States from before the 128x64 mode have a 64x32 display in high resolution
+fn upgrade_display(body: &mut Vec<u8>, _: &VirtualMachine) {
+    let mut r = Reader { data: body };
+    let Ok(display) = read_display(&mut r) else {
+        return;
+    };
+    if display.resolution() != Resolution::Low || r.data.get(HIGH_RES_OFFSET) != Some(&1) {
+        return;
+    }
+    let display_len = body.len() - r.data.len();
+    let mut upgraded = Vec::new();
+    write_display(&mut upgraded, &display.resized(Resolution::High));
+    body.splice(..display_len, upgraded);
+}
+
+// Upgrades the body of a state, everything between the header and the checksum, from one
+// version to the next. Fields that older versions didn't have are taken from the VM the state
+// is loaded into.
+type Upgrade = fn(&mut Vec<u8>, &VirtualMachine);
+
+// The upgrade from each version to the next, starting with version 1. When the layout changes,
+// VERSION is bumped and an upgrade from the previous layout is added here, so that loading only
+// has to understand the latest one.
+const UPGRADES: [Upgrade; VERSION as usize - 1] = [
+    // Version 2 added the SUPER-CHIP resolution
+    |body, _| body.push(0),
+    // Version 3 added the speed. Older states keep the current one.
+    |body, vm| body.extend_from_slice(&vm.speed().to_le_bytes()),
+    // Version 4 added the quirks, preceded by their number so that quirks added later keep
+    // their current setting. Older states keep all of them.
+    |body, _| body.push(0),
+    // Version 5 added the CHIP-8X colors and second keypad, after a flag
+    |body, _| body.push(0),
+    // Version 6 sized the display by the resolution
+    upgrade_display,
+    // Version 7 added the understood extensions
+    upgrade_extension,
+    // Version 8 added the MegaChip screen, after a flag
+    |body, _| body.push(0),
+    // Version 9 added the selected XO-CHIP planes, and the second plane after a flag
+    |body, _| body.extend_from_slice(&[1, 0]),
+];
+
+// Older states keep the current extensions, unless the current ones are CHIP-8X and the state
+// isn't, which gets the default ones along with the default colors
+fn upgrade_extension(body: &mut Vec<u8>, vm: &VirtualMachine) {
+    // The quirk count follows the resolution flag and the speed
+    let quirks = display_len(body).map_or(0, |len| len + HIGH_RES_OFFSET + 1 + 4);
+    let chip8x = body
+        .get(quirks)
+        .and_then(|&count| body.get(quirks + 1 + usize::from(count)))
+        == Some(&1);
+    let extension = match vm.extension {
+        _ if chip8x => Extension::Chip8X,
+        Extension::Chip8X => VirtualMachine::new().extension,
+        extension => extension,
+    };
+    body.push(extension_number(extension));
+}
+
+fn extension_number(extension: Extension) -> u8 {
+    EXTENSIONS.iter().position(|&ext| ext == extension).unwrap() as u8
+}
+
+/// Reads the thumbnail of a state, without loading or verifying the rest of it.
+pub fn read_thumbnail(data: &[u8]) -> Result<FrameBuffer, StateError> {
+    let data = decompressed(data)?;
+    let (_, mut r) = read_header(&data)?;
+    read_display(&mut r)
+}
+
+impl VirtualMachine {
+    /// Serializes the state of the VM.
+    ///
+    /// The speed, quirks and understood extensions are saved along with the machine, so
+    /// timing-sensitive programs behave the same when the state is loaded elsewhere. So is
+    /// the 256-color screen of MegaChip. Callbacks, input macros and the log aren't part of
+    /// the state.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(MEM_SIZE + 512);
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&VERSION.to_le_bytes());
+        write_display(&mut out, &self.display);
+        out.extend_from_slice(&self.ram);
+        out.extend(self.v.iter().map(|v| v.0));
+        out.extend_from_slice(&self.i.to_le_bytes());
+        out.extend_from_slice(&self.pc.to_le_bytes());
+        out.push(self.sp.0);
+        for addr in self.stack {
+            out.extend_from_slice(&addr.to_le_bytes());
+        }
+        out.push(self.delay_timer);
+        out.push(self.sound_timer);
+        out.extend(self.keys.iter().map(|&k| u8::from(k)));
+        out.push(u8::from(self.keypress_wait.wait));
+        out.push(self.keypress_wait.vx as u8);
+        out.push(match self.halt {
+            None => 0,
+            Some(HaltReason::ProgramEnded) => 1,
+            Some(HaltReason::OutOfBounds) => 2,
+            Some(HaltReason::Exited) => 3,
+            Some(HaltReason::MemoryOutOfBounds) => 4,
+            Some(HaltReason::StackUnderflow) => 5,
+        });
+        out.push(u8::from(self.sound_on));
+        out.extend_from_slice(&self.rng.state.to_le_bytes());
+        out.extend_from_slice(&self.cycles.to_le_bytes());
+        out.extend_from_slice(&self.frames.to_le_bytes());
+        out.push(u8::from(self.high_res));
+        out.extend_from_slice(&self.speed().to_le_bytes());
+        let quirks = self.quirks.flags();
+        out.push(quirks.len() as u8);
+        out.extend(quirks.map(u8::from));
+        write_chip8x(&mut out, self);
+        out.push(extension_number(self.extension));
+        write_megachip(&mut out, self);
+        out.push(self.planes);
+        let second_plane = self.display.pixels.iter().any(|&px| px & 2 != 0);
+        out.push(u8::from(second_plane));
+        if second_plane {
+            for row in self.display.pixels.chunks(self.display.width) {
+                let plane: Vec<_> = row.iter().map(|&px| px & 2).collect();
+                out.extend_from_slice(&pack_row(&plane));
+            }
+        }
+        let sum = checksum(&out);
+        out.extend_from_slice(&sum.to_le_bytes());
+        out
+    }
+
+    /// Restores a state serialized by [`VirtualMachine::save_state`].
+    ///
+    /// States written by older versions are upgraded to the current format first, keeping
+    /// the current setting of anything they didn't save. The state may be compressed.
+    ///
+    /// On error, the VM is left unchanged.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let data = &decompressed(data)?[..];
+        let (version, r) = read_header(data)?;
+        let Some(body_len) = data.len().checked_sub(8) else {
+            return Err(StateError::Truncated);
+        };
+        let sum = u64::from_le_bytes(data[body_len..].try_into().unwrap());
+        if checksum(&data[..body_len]) != sum {
+            return Err(StateError::ChecksumMismatch);
+        }
+        let header_len = data.len() - r.data.len();
+        let mut body = data
+            .get(header_len..body_len)
+            .ok_or(StateError::Truncated)?
+            .to_vec();
+        for upgrade in &UPGRADES[usize::from(version) - 1..] {
+            upgrade(&mut body, self);
+        }
+        let mut r = Reader { data: &body };
+        let mut vm = self.clone();
+        vm.display = read_display(&mut r)?;
+        // Only the two-page mode has a 64x64 display
+        #[cfg(feature = "hires")]
+        {
+            vm.two_page = vm.display.resolution() == Resolution::TwoPage;
+        }
+        #[cfg(not(feature = "hires"))]
+        if vm.display.resolution() == Resolution::TwoPage {
+            return Err(StateError::FeatureUnsupported("hires"));
+        }
+        vm.display_updated = true;
+        vm.ram.copy_from_slice(r.bytes(MEM_SIZE)?);
+        for v in &mut vm.v {
+            *v = Wrapping(r.u8()?);
+        }
+        vm.i = r.u16()?;
+        vm.pc = r.u16()?;
+        vm.sp = Wrapping(r.u8()?);
+        for addr in &mut vm.stack {
+            *addr = r.u16()?;
+        }
+        vm.delay_timer = r.u8()?;
+        vm.reset_delay_spin();
+        vm.sound_timer = r.u8()?;
+        for key in &mut vm.keys {
+            *key = r.bool()?;
+        }
+        vm.keypress_wait = KeypressWait {
+            wait: r.bool()?,
+            vx: usize::from(r.u8()?),
+        };
+        if vm.keypress_wait.vx > 0xF {
+            return Err(StateError::Invalid("key wait register"));
+        }
+        vm.halt = match r.u8()? {
+            0 => None,
+            1 => Some(HaltReason::ProgramEnded),
+            2 => Some(HaltReason::OutOfBounds),
+            3 => Some(HaltReason::Exited),
+            4 => Some(HaltReason::MemoryOutOfBounds),
+            5 => Some(HaltReason::StackUnderflow),
+            _ => return Err(StateError::Invalid("halt reason")),
+        };
+        vm.sound_on = r.bool()?;
+        vm.rng.state = r.u64()?;
+        vm.cycles = r.u64()?;
+        vm.frames = r.u64()?;
+        vm.high_res = r.bool()?;
+        if vm.display.resolution() != vm.current_resolution() {
+            return Err(StateError::Invalid("display size"));
+        }
+        vm.set_speed(r.u32()?);
+        let count = usize::from(r.u8()?);
+        let mut flags = vm.quirks.flags_mut().into_iter();
+        for _ in 0..count {
+            let flag = flags.next().ok_or(StateError::Invalid("quirk count"))?;
+            *flag = r.bool()?;
+        }
+        drop(flags);
+        let chip8x = read_chip8x(&mut r, &mut vm)?;
+        // Builds without the `chip8x` feature save CHIP-8X states without the colors
+        vm.extension = *EXTENSIONS
+            .get(usize::from(r.u8()?))
+            .filter(|&&ext| !chip8x || ext == Extension::Chip8X)
+            .ok_or(StateError::Invalid("extension"))?;
+        read_megachip(&mut r, &mut vm)?;
+        vm.planes = r.u8()?;
+        if vm.planes > 0b11 {
+            return Err(StateError::Invalid("planes"));
+        }
+        if r.bool()? {
+            let packed = r.bytes(vm.display.pixels.len() / 8)?;
+            for (i, px) in vm.display.pixels.iter_mut().enumerate() {
+                *px |= ((packed[i / 8] >> (7 - i % 8)) & 1) << 1;
+            }
+        }
+        if !r.data.is_empty() {
+            return Err(StateError::Invalid("length"));
+        }
+        *self = vm;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_state_roundtrip() {
+    let mut vm = VirtualMachine::new();
+    // 0x200: LD I, 0
+    // 0x202: DRW V0, V0, 5
+    // 0x204: RND V1, 0xFF
+    // 0x206: JP 0x204
+    vm.load_rom(&[0xA0, 0x00, 0xD0, 0x05, 0xC1, 0xFF, 0x12, 0x04]);
+    vm.set_quirks(crate::Quirks {
+        shift_uses_vy: false,
+        ..Default::default()
+    });
+    for _ in 0..5 {
+        vm.do_cycle();
+    }
+    let state = vm.save_state();
+    let thumbnail = read_thumbnail(&state).unwrap();
+    assert!(thumbnail == *vm.framebuffer());
+    let mut loaded = VirtualMachine::new();
+    loaded.set_speed(1);
+    loaded.load_state(&state).unwrap();
+    assert!(vm.diff(&loaded).is_empty());
+    assert_eq!(loaded.speed(), vm.speed());
+    assert_eq!(loaded.quirks(), vm.quirks());
+    // The random number generator continues where it left off
+    for _ in 0..4 {
+        vm.do_cycle();
+        loaded.do_cycle();
+    }
+    assert_eq!(vm.v[1], loaded.v[1]);
+    assert_eq!(loaded.cycle_count(), 9);
+}
+
+// Turns a state of the current version into one of an older version
+#[cfg(test)]
+fn downgrade(state: &[u8], version: u16) -> Vec<u8> {
+    let mut body = state[..state.len() - 8].to_vec();
+    let quirk_count = usize::from(body[body.len() - crate::quirks::QUIRKS.len() - 6]);
+    // The fields each version added, newest first: the planes, the MegaChip flag, the
+    // extension, none (the display size), the CHIP-8X flag, the quirks, the speed, the
+    // resolution
+    let added = [2, 1, 1, 0, 1, 1 + quirk_count, 4, 1];
+    for len in &added[..usize::from(VERSION - version)] {
+        body.truncate(body.len() - len);
+    }
+    body[4..6].copy_from_slice(&version.to_le_bytes());
+    let sum = checksum(&body);
+    body.extend_from_slice(&sum.to_le_bytes());
+    body
+}
+
+#[test]
+fn test_old_versions_are_upgraded() {
+    let mut vm = VirtualMachine::new();
+    // 0x200: LD I, 0
+    // 0x202: DRW V0, V0, 5
+    // 0x204: RND V1, 0xFF
+    vm.load_rom(&[0xA0, 0x00, 0xD0, 0x05, 0xC1, 0xFF]);
+    vm.set_quirks(crate::Quirks {
+        single_key: true,
+        ..Default::default()
+    });
+    vm.set_speed(1000);
+    for _ in 0..3 {
+        vm.do_cycle();
+    }
+    let state = vm.save_state();
+    for version in 1..=VERSION {
+        let old = downgrade(&state, version);
+        assert_eq!(read_header(&old).unwrap().0, version);
+        let mut loaded = VirtualMachine::new();
+        loaded.set_speed(123);
+        loaded.load_state(&old).unwrap();
+        assert!(vm.diff(&loaded).is_empty(), "version {}", version);
+        assert_eq!(loaded.cycle_count(), 3);
+        // Fields the version didn't have keep the current setting
+        let (speed, single_key) = if version >= 4 {
+            (1000, true)
+        } else if version == 3 {
+            (1000, false)
+        } else {
+            (123, false)
+        };
+        assert_eq!(loaded.speed(), speed, "version {}", version);
+        assert_eq!(
+            loaded.quirks().single_key,
+            single_key,
+            "version {}",
+            version
+        );
+        // Loading and saving again writes the current version
+        assert_eq!(read_header(&loaded.save_state()).unwrap().0, VERSION);
+        // A MegaChip VM keeps its extension for states that didn't save one, and has no
+        // screen to restore
+        let mut mega = VirtualMachine::with_variant(crate::Variant::MegaChip);
+        mega.load_state(&old).unwrap();
+        assert!(vm.diff(&mega).is_empty(), "version {}", version);
+        let extension = if version >= 7 {
+            vm.extension()
+        } else {
+            Extension::MegaChip
+        };
+        assert_eq!(mega.extension(), extension, "version {}", version);
+        #[cfg(feature = "megachip")]
+        assert_eq!(
+            mega.mega,
+            megachip::Screen::default(),
+            "version {}",
+            version
+        );
+    }
+}
+
+#[test]
+fn test_low_res_display_is_upgraded() {
+    let mut vm = VirtualMachine::new();
+    // 0x200: LD I, 0
+    // 0x202: DRW V0, V0, 5
+    vm.load_rom(&[0xA0, 0x00, 0xD0, 0x05]);
+    vm.do_cycle();
+    vm.do_cycle();
+    // Older versions could write a 64x32 display in high resolution
+    vm.high_res = true;
+    let state = downgrade(&vm.save_state(), 5);
+    let mut loaded = VirtualMachine::new();
+    loaded.load_state(&state).unwrap();
+    assert!(*loaded.framebuffer() == vm.display.resized(Resolution::High));
+    // The current version must match the resolution
+    assert_eq!(
+        loaded.load_state(&vm.save_state()),
+        Err(StateError::Invalid("display size"))
+    );
+}
+
+#[test]
+fn test_extension_is_restored() {
+    use crate::Variant;
+
+    let state = VirtualMachine::with_variant(Variant::SuperChip).save_state();
+    let mut loaded = VirtualMachine::new();
+    loaded.load_state(&state).unwrap();
+    assert_eq!(loaded.extension(), Extension::SuperChip);
+    // Builds without the `chip8x` feature save no colors, but still the extension
+    let chip8x = VirtualMachine::with_variant(Variant::Chip8X).save_state();
+    loaded.load_state(&chip8x).unwrap();
+    assert_eq!(loaded.extension(), Extension::Chip8X);
+}
+
+#[cfg(feature = "chip8x")]
+#[test]
+fn test_chip8x_colors_are_dropped() {
+    use crate::Variant;
+
+    let state = VirtualMachine::with_variant(Variant::SuperChip).save_state();
+    // A CHIP-8X VM loading another state drops its colors
+    let mut chip8x = VirtualMachine::with_variant(Variant::Chip8X);
+    chip8x.colors.background = 2;
+    chip8x.load_state(&state).unwrap();
+    assert_eq!(chip8x.extension(), Extension::SuperChip);
+    assert_eq!(chip8x.colors, chip8x::ColorMap::default());
+    // Older states don't say, so they get the default extensions
+    let mut chip8x = VirtualMachine::with_variant(Variant::Chip8X);
+    chip8x.load_state(&downgrade(&state, 6)).unwrap();
+    assert_eq!(chip8x.extension(), VirtualMachine::new().extension());
+    assert_eq!(chip8x.chip8x_colors(), None);
+}
+
+#[test]
+fn test_corrupt_state_is_rejected() {
+    let vm = VirtualMachine::new();
+    let mut state = vm.save_state();
+    let mut loaded = VirtualMachine::new();
+    state[300] ^= 1;
+    assert_eq!(loaded.load_state(&state), Err(StateError::ChecksumMismatch));
+    assert_eq!(
+        loaded.load_state(&state[..100]),
+        Err(StateError::ChecksumMismatch)
+    );
+    assert_eq!(loaded.load_state(b"nope"), Err(StateError::BadMagic));
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn test_compressed_state() {
+    let mut vm = VirtualMachine::new();
+    // 0x200: LD I, 0
+    // 0x202: DRW V0, V0, 5
+    vm.load_rom(&[0xA0, 0x00, 0xD0, 0x05]);
+    vm.do_cycle();
+    vm.do_cycle();
+    let state = vm.save_state();
+    let compressed = compress(&state);
+    assert!(compressed.len() * 10 < state.len());
+    assert!(read_thumbnail(&compressed).unwrap() == *vm.framebuffer());
+    let mut loaded = VirtualMachine::new();
+    loaded.load_state(&compressed).unwrap();
+    assert!(vm.diff(&loaded).is_empty());
+    let mut corrupt = compressed;
+    corrupt.truncate(corrupt.len() / 2);
+    assert_eq!(
+        loaded.load_state(&corrupt),
+        Err(StateError::Invalid("compressed data"))
+    );
+}
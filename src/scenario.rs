@@ -0,0 +1,351 @@
+//! Scripted runs of a ROM, for compatibility tests.
+//!
+//! A scenario is a text file with one step per line:
+//!
+//! ```text
+//! # Comments start with '#'
+//! rom game.ch8
+//! frame 10 press 5
+//! frame 12 release 5
+//! frame 20 expect pixel 3 4 on
+//! frame 20 expect v3 7
+//! frame 20 expect i 0x2A0
+//! frame 20 expect sound on
+//! ```
+//!
+//! The ROM path is relative to the scenario file. Steps for frame N happen after N frames
+//! have been run, and need not be in order. N goes up to [`MAX_FRAME`]. Registers that can
+//! be checked are `v0` to `vf`, `i`, `pc`, `dt` and `st`. Pixels are checked on the display
+//! at its current resolution.
+//! Checking the sound at consecutive frames pins down exactly when a beep starts and stops.
+//! Numbers can be decimal or hexadecimal with a `0x` prefix.
+//! A ROM that halts with an error, like by returning without a call, fails the scenario.
+
+use {
+    super::{HaltReason, Resolution, VirtualMachine},
+    std::{
+        fmt, fs, io,
+        path::{Path, PathBuf},
+    },
+};
+
+/// The latest frame a step can happen at, an hour into the run at 60 frames per second.
+pub const MAX_FRAME: u64 = 60 * 60 * 60;
+
+/// A register that a scenario can check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    /// Vx
+    V(u8),
+    /// I
+    I,
+    /// The program counter
+    Pc,
+    /// The delay timer
+    Dt,
+    /// The sound timer
+    St,
+}
+
+/// What happens at a step of a scenario.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// Press a key.
+    Press(u8),
+    /// Release a key.
+    Release(u8),
+    /// Check that a pixel is on or off.
+    ExpectPixel {
+        /// X position
+        x: usize,
+        /// Y position
+        y: usize,
+        /// Whether the pixel should be on
+        on: bool,
+    },
+    /// Check the value of a register.
+    ExpectRegister(Register, u16),
+    /// Check whether the sound is playing.
+    ExpectSound(bool),
+}
+
+/// A single step of a scenario.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    /// The frame the step happens at.
+    pub frame: u64,
+    /// What happens.
+    pub action: Action,
+}
+
+/// A scripted run of a ROM.
+#[derive(Debug, Clone)]
+pub struct Scenario {
+    /// Path of the ROM to run.
+    pub rom: PathBuf,
+    /// The steps, ordered by frame.
+    pub steps: Vec<Step>,
+}
+
+/// Error from loading or running a scenario.
+#[derive(Debug)]
+pub enum ScenarioError {
+    /// Reading the scenario or the ROM failed.
+    Io(io::Error),
+    /// The scenario is malformed.
+    Parse {
+        /// The line the error is on, starting from 1.
+        line: usize,
+        /// What's wrong with it.
+        message: String,
+    },
+    /// The scenario has no `rom <path>` line.
+    MissingRom,
+    /// An expectation wasn't met.
+    Failed {
+        /// The frame the expectation was checked at.
+        frame: u64,
+        /// What was expected and what was found.
+        message: String,
+    },
+    /// The ROM halted with an error.
+    Crashed {
+        /// The frame the ROM halted in.
+        frame: u64,
+        /// Why it halted.
+        reason: HaltReason,
+    },
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScenarioError::Io(e) => write!(f, "{}", e),
+            ScenarioError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            ScenarioError::MissingRom => write!(f, "missing `rom <path>`"),
+            ScenarioError::Failed { frame, message } => write!(f, "frame {}: {}", frame, message),
+            ScenarioError::Crashed { frame, reason } => {
+                write!(f, "frame {}: crashed ({:?})", frame, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ScenarioError {}
+
+impl From<io::Error> for ScenarioError {
+    fn from(e: io::Error) -> Self {
+        ScenarioError::Io(e)
+    }
+}
+
+impl Scenario {
+    /// Loads a scenario from a file.
+    pub fn load(path: &Path) -> Result<Scenario, ScenarioError> {
+        let text = fs::read_to_string(path)?;
+        Scenario::parse(&text, path.parent().unwrap_or(Path::new("")))
+    }
+
+    /// Parses a scenario. The ROM path is resolved relative to `base_dir`.
+    pub fn parse(text: &str, base_dir: &Path) -> Result<Scenario, ScenarioError> {
+        let mut rom = None;
+        let mut steps = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let err = |message: &str| ScenarioError::Parse {
+                line: i + 1,
+                message: message.to_owned(),
+            };
+            let line = line.split('#').next().unwrap_or("").trim();
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                [] => {}
+                ["rom", path] => rom = Some(base_dir.join(path)),
+                ["frame", frame, action @ ..] => {
+                    let frame = parse_num(frame).ok_or_else(|| err("invalid frame number"))?;
+                    if frame > MAX_FRAME {
+                        return Err(err(&format!("frame {} is past the last one", frame)));
+                    }
+                    let action = parse_action(action).map_err(|e| err(&e))?;
+                    steps.push(Step { frame, action });
+                }
+                _ => return Err(err("expected `rom <path>` or `frame <n> <action>`")),
+            }
+        }
+        let rom = rom.ok_or(ScenarioError::MissingRom)?;
+        steps.sort_by_key(|step| step.frame);
+        Ok(Scenario { rom, steps })
+    }
+
+    /// Runs the scenario on a fresh VM, returning the VM in its final state.
+    pub fn run(&self) -> Result<VirtualMachine, ScenarioError> {
+        let rom = fs::read(&self.rom)?;
+        let mut vm = VirtualMachine::new();
+        vm.load_rom(&rom);
+        self.run_on(&mut vm)?;
+        Ok(vm)
+    }
+
+    /// Runs the steps of the scenario on an already set up VM.
+    pub fn run_on(&self, vm: &mut VirtualMachine) -> Result<(), ScenarioError> {
+        let mut frame = 0;
+        for step in &self.steps {
+            while frame < step.frame {
+                vm.step_frame();
+                if let Some(reason) = vm.halt_reason().filter(|reason| reason.is_error()) {
+                    return Err(ScenarioError::Crashed { frame, reason });
+                }
+                frame += 1;
+            }
+            vm.apply(&step.action)
+                .map_err(|message| ScenarioError::Failed { frame, message })?;
+        }
+        Ok(())
+    }
+}
+
+impl VirtualMachine {
+    fn apply(&mut self, action: &Action) -> Result<(), String> {
+        match *action {
+            Action::Press(key) => self.press_key(key),
+            Action::Release(key) => self.release_key(key),
+            Action::ExpectPixel { x, y, on } => {
+                let (width, height) = self.display_size();
+                if x >= width || y >= height {
+                    return Err(format!(
+                        "pixel ({}, {}) is outside the {}x{} display",
+                        x, y, width, height
+                    ));
+                }
+                if self.display.get(x, y) != on {
+                    return Err(format!(
+                        "expected pixel ({}, {}) to be {}",
+                        x,
+                        y,
+                        if on { "on" } else { "off" }
+                    ));
+                }
+            }
+            Action::ExpectRegister(reg, expected) => {
+                let value = match reg {
+                    Register::V(x) => u16::from(self.v[usize::from(x)].0),
+                    Register::I => self.i,
+                    Register::Pc => self.pc,
+                    Register::Dt => u16::from(self.delay_timer),
+                    Register::St => u16::from(self.sound_timer),
+                };
+                if value != expected {
+                    return Err(format!(
+                        "expected {:?} to be {:#x}, but it's {:#x}",
+                        reg, expected, value
+                    ));
+                }
+            }
+            Action::ExpectSound(on) => {
+                if self.sound_playing() != on {
+                    return Err(format!(
+                        "expected the sound to be {}",
+                        if on { "on" } else { "off" }
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_num<T: TryFrom<u64>>(s: &str) -> Option<T> {
+    let n = match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok()?,
+        None => s.parse().ok()?,
+    };
+    T::try_from(n).ok()
+}
+
+fn parse_key(s: &str) -> Result<u8, String> {
+    match u8::from_str_radix(s.trim_start_matches("0x"), 16) {
+        Ok(key) if key <= 0xF => Ok(key),
+        _ => Err(format!("invalid key: {}", s)),
+    }
+}
+
+fn parse_on_off(s: &str) -> Result<bool, String> {
+    match s {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(format!("expected `on` or `off`, got {}", s)),
+    }
+}
+
+fn parse_action(words: &[&str]) -> Result<Action, String> {
+    match words {
+        ["press", key] => Ok(Action::Press(parse_key(key)?)),
+        ["release", key] => Ok(Action::Release(parse_key(key)?)),
+        ["expect", "pixel", x, y, state] => {
+            let pos = |s: &str, max| {
+                parse_num(s)
+                    .filter(|&n| n < max)
+                    .ok_or_else(|| format!("invalid pixel position: {}", s))
+            };
+            Ok(Action::ExpectPixel {
+                x: pos(x, Resolution::High.width())?,
+                y: pos(y, Resolution::High.height())?,
+                on: parse_on_off(state)?,
+            })
+        }
+        ["expect", "sound", state] => Ok(Action::ExpectSound(parse_on_off(state)?)),
+        ["expect", reg, value] => {
+            let reg = match *reg {
+                "i" => Register::I,
+                "pc" => Register::Pc,
+                "dt" => Register::Dt,
+                "st" => Register::St,
+                _ => match reg.strip_prefix('v') {
+                    Some(x) => Register::V(parse_key(x)?),
+                    None => return Err(format!("unknown register: {}", reg)),
+                },
+            };
+            let value = parse_num(value).ok_or_else(|| format!("invalid value: {}", value))?;
+            Ok(Action::ExpectRegister(reg, value))
+        }
+        _ => Err(format!("unknown action: {}", words.join(" "))),
+    }
+}
+
+#[test]
+fn test_expect_pixel_uses_display_size() {
+    let scenario = Scenario::parse(
+        "rom none.ch8\nframe 0 expect pixel 100 40 off",
+        Path::new(""),
+    )
+    .unwrap();
+    let mut vm = VirtualMachine::new();
+    assert!(matches!(
+        scenario.run_on(&mut vm),
+        Err(ScenarioError::Failed { frame: 0, .. })
+    ));
+    vm.high_res = true;
+    vm.display = vm.display.resized(Resolution::High);
+    scenario.run_on(&mut vm).unwrap();
+    assert!(
+        Scenario::parse(
+            "rom none.ch8\nframe 0 expect pixel 128 0 off",
+            Path::new("")
+        )
+        .is_err()
+    );
+}
+
+#[test]
+fn test_parse_errors() {
+    assert!(matches!(
+        Scenario::parse("frame 0 press 1", Path::new("")),
+        Err(ScenarioError::MissingRom)
+    ));
+    let max = format!("rom none.ch8\nframe {} press 1", MAX_FRAME);
+    assert!(Scenario::parse(&max, Path::new("")).is_ok());
+    let past = format!("rom none.ch8\nframe {} press 1", MAX_FRAME + 1);
+    assert!(matches!(
+        Scenario::parse(&past, Path::new("")),
+        Err(ScenarioError::Parse { line: 2, .. })
+    ));
+}
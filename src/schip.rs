@@ -0,0 +1,259 @@
+//! SUPER-CHIP extensions.
+//!
+//! Only compiled in with the `schip` feature.
+//!
+//! In high resolution, the display is 128x64 pixels, and sprites are drawn at that
+//! resolution. Unless the switch clears the display, the old contents are scaled to the
+//! new resolution.
+//!
+//! Scrolling moves the display by pixels of the current resolution, as on XO-CHIP. The
+//! original SUPER-CHIP scrolled by half as many pixels in low resolution.
+//!
+//! `DRW Vx, Vy, 0` draws a 16x16 sprite of 32 bytes, two per row, in either resolution, as
+//! later interpreters like Octo do. The original SUPER-CHIP drew 8x16 sprites in low
+//! resolution.
+//!
+//! The big 8x10 font, for score digits that are legible in high resolution, is kept at
+//! [`BIG_FONT_ADDR`](crate::BIG_FONT_ADDR).
+
+use {
+    super::{EventKind, HaltReason, Instruction::*, Severity, VirtualMachine},
+    crate::opcodes::{OpcodeSpec, op},
+};
+
+#[rustfmt::skip]
+pub(crate) static OPCODES: &[OpcodeSpec] = &[
+    op!(SuperChip, 0x00C0, 0xFFF0, "SCD n", "Scroll the display down by n pixels.",
+        |o| ScrollDown { n: o.n }, |vm, o| vm.scroll(0, o.n as isize)),
+    op!(SuperChip, 0x00FB, 0xFFFF, "SCR", "Scroll the display right by 4 pixels.",
+        |_| ScrollRight, |vm, _| vm.scroll(4, 0)),
+    op!(SuperChip, 0x00FC, 0xFFFF, "SCL", "Scroll the display left by 4 pixels.",
+        |_| ScrollLeft, |vm, _| vm.scroll(-4, 0)),
+    op!(SuperChip, 0x00FD, 0xFFFF, "EXIT", "Exit the interpreter.",
+        |_| Exit, |vm, _| vm.exit()),
+    op!(SuperChip, 0xD000, 0xF00F, "DRW Vx, Vy, 0",
+        "Draw 16x16 sprite from memory location I at (Vx, Vy), VF = collision.",
+        |o| DisplaySprite { x: o.x, y: o.y, n: 0 },
+        |vm, o| vm.draw_sprite(o.x as usize, o.y as usize, 16, 16)),
+    op!(SuperChip, 0x00FE, 0xFFFF, "LOW", "Switch to low resolution (64x32).",
+        |_| DisableHighRes, |vm, _| vm.set_high_res(false)),
+    op!(SuperChip, 0x00FF, 0xFFFF, "HIGH", "Switch to high resolution (128x64).",
+        |_| EnableHighRes, |vm, _| vm.set_high_res(true)),
+    op!(SuperChip, 0xF030, 0xF0FF, "LD HF, Vx", "Set I = location of big sprite for digit Vx.",
+        |o| SetIToLocOfBigDigitVx { x: o.x },
+        |vm, o| vm.set_i_to_loc_of_big_digit_vx(o.x as usize)),
+];
+
+impl VirtualMachine {
+    fn set_high_res(&mut self, high_res: bool) {
+        if self.high_res != high_res {
+            self.high_res = high_res;
+            self.display = self.display.resized(self.current_resolution());
+            self.emit(EventKind::ResolutionChanged(self.current_resolution()));
+        }
+        if self.quirks.resolution_switch_clears {
+            self.clear_display();
+        }
+    }
+
+    fn exit(&mut self) {
+        let addr = self.pc.wrapping_sub(2);
+        self.log_line(
+            Severity::Info,
+            Some(addr),
+            format_args!("Program exited at {:#x}. Halted.", addr),
+        );
+        self.halt(HaltReason::Exited);
+    }
+
+    // Moves the selected planes by dx pixels to the right and dy pixels down. Pixels moved off
+    // the edge are lost, and the uncovered ones are cleared.
+    pub(super) fn scroll(&mut self, dx: isize, dy: isize) {
+        let (width, height) = (self.display.width as isize, self.display.height as isize);
+        let old = self.display.pixels.clone();
+        for y in 0..height {
+            for x in 0..width {
+                let (src_x, src_y) = (x - dx, y - dy);
+                let inside = (0..width).contains(&src_x) && (0..height).contains(&src_y);
+                let moved = if inside {
+                    old[(src_y * width + src_x) as usize] & self.planes
+                } else {
+                    0
+                };
+                let px = &mut self.display.pixels[(y * width + x) as usize];
+                *px = *px & !self.planes | moved;
+            }
+        }
+        self.display_changed();
+    }
+
+    fn set_i_to_loc_of_big_digit_vx(&mut self, x: usize) {
+        self.i = crate::BIG_FONT_ADDR + u16::from(self.v[x].0 & 0xF) * 10;
+    }
+}
+
+#[test]
+fn test_resolution_switch_clears() {
+    use crate::Quirks;
+
+    // 0x200: DRW V0, V0, 5
+    // 0x202: HIGH
+    let rom = [0xD0, 0x05, 0x00, 0xFF];
+    for clears in [true, false] {
+        let mut vm = VirtualMachine::new();
+        vm.set_quirks(Quirks {
+            resolution_switch_clears: clears,
+            ..Quirks::default()
+        });
+        vm.load_rom(&rom);
+        vm.record_events(true);
+        vm.do_cycle();
+        vm.do_cycle();
+        assert_eq!(vm.current_resolution(), crate::Resolution::High);
+        assert!(
+            vm.take_events()
+                .iter()
+                .any(|e| e.kind == EventKind::ResolutionChanged(crate::Resolution::High))
+        );
+        assert_eq!(vm.framebuffer().pixels().contains(&1), !clears);
+    }
+}
+
+#[test]
+fn test_high_res_display() {
+    // 0x200: HIGH
+    // 0x202: LD V0, 100
+    // 0x204: LD V1, 60
+    // 0x206: DRW V0, V1, 1
+    // 0x208: LOW
+    let mut vm = VirtualMachine::new();
+    vm.load_rom(&[0x00, 0xFF, 0x60, 100, 0x61, 60, 0xD0, 0x11, 0x00, 0xFE]);
+    vm.do_cycle();
+    assert_eq!(vm.display_size(), (128, 64));
+    for _ in 0..3 {
+        vm.do_cycle();
+    }
+    // The first row of the font's "0" is 0xF0
+    assert_eq!(
+        vm.display()[60 * 128 + 100..][..8],
+        [1, 1, 1, 1, 0, 0, 0, 0]
+    );
+    vm.do_cycle();
+    assert_eq!(vm.display_size(), (64, 32));
+    assert_eq!(vm.display().len(), 64 * 32);
+}
+
+#[test]
+fn test_big_digits() {
+    // 0x200: HIGH
+    // 0x202: LD V0, 8
+    // 0x204: LD HF, V0
+    // 0x206: DRW V1, V1, 10
+    let mut vm = VirtualMachine::new();
+    vm.load_rom(&[0x00, 0xFF, 0x60, 0x08, 0xF0, 0x30, 0xD1, 0x1A]);
+    for _ in 0..4 {
+        vm.do_cycle();
+    }
+    assert!(crate::FONT_MEMORY.contains(&vm.i));
+    let rows: Vec<u8> = (0..10)
+        .map(|y| (0..8).fold(0, |row, x| row << 1 | vm.display()[y * 128 + x]))
+        .collect();
+    assert_eq!(
+        rows,
+        [0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF]
+    );
+}
+
+#[test]
+fn test_scroll() {
+    // 0x200: DRW V0, V0, 1
+    // 0x202: SCD 2
+    // 0x204: SCR
+    // 0x206: SCL
+    // 0x208: SCL
+    let rom = [0xD0, 0x01, 0x00, 0xC2, 0x00, 0xFB, 0x00, 0xFC, 0x00, 0xFC];
+    for high in [false, true] {
+        let mut vm = VirtualMachine::new();
+        vm.load_rom(&rom);
+        vm.set_high_res(high);
+        let width = vm.display_size().0;
+        let lit = |vm: &VirtualMachine| -> Vec<(usize, usize)> {
+            (0..vm.display().len())
+                .filter(|&i| vm.display()[i] != 0)
+                .map(|i| (i % width, i / width))
+                .collect()
+        };
+        // The top row of the font's "0" is 0xF0
+        vm.do_cycle();
+        assert_eq!(lit(&vm), [(0, 0), (1, 0), (2, 0), (3, 0)]);
+        vm.do_cycle();
+        assert_eq!(lit(&vm), [(0, 2), (1, 2), (2, 2), (3, 2)]);
+        vm.do_cycle();
+        assert_eq!(lit(&vm), [(4, 2), (5, 2), (6, 2), (7, 2)]);
+        vm.do_cycle();
+        vm.do_cycle();
+        // Scrolled off the left edge
+        assert!(lit(&vm).is_empty());
+    }
+}
+
+#[test]
+fn test_exit() {
+    // 0x200: EXIT
+    let mut vm = VirtualMachine::new();
+    vm.load_rom(&[0x00, 0xFD]);
+    vm.record_events(true);
+    vm.do_cycle();
+    assert_eq!(vm.halt_reason(), Some(HaltReason::Exited));
+    assert!(!HaltReason::Exited.is_error());
+    let events = vm.take_events();
+    assert!(
+        events
+            .iter()
+            .all(|event| !matches!(event.kind, EventKind::UnknownInstruction(_)))
+    );
+    assert!(
+        events
+            .iter()
+            .any(|event| event.kind == EventKind::Halted(HaltReason::Exited))
+    );
+    // Nothing runs after exiting
+    vm.do_cycle();
+    assert_eq!(vm.pc(), 0x202);
+}
+
+#[test]
+fn test_big_sprite() {
+    // 0x200: HIGH
+    // 0x202: LD I, 0x20C
+    // 0x204: LD V0, 120
+    // 0x206: DRW V0, V1, 0
+    // 0x208: DRW V0, V1, 0
+    // 0x20A: JP 0x20A
+    let mut rom = vec![
+        0x00, 0xFF, 0xA2, 0x0C, 0x60, 120, 0xD0, 0x10, 0xD0, 0x10, 0x12, 0x0A,
+    ];
+    // A 16x16 sprite whose rows are 0x8001
+    rom.extend([0x80, 0x01].repeat(16));
+    let mut vm = VirtualMachine::new();
+    vm.load_rom(&rom);
+    vm.record_events(true);
+    for _ in 0..4 {
+        vm.do_cycle();
+    }
+    // The sprite is clipped at the right edge
+    for y in 0..16 {
+        assert_eq!(vm.display()[y * 128 + 120..][..8], [1, 0, 0, 0, 0, 0, 0, 0]);
+    }
+    assert_eq!(vm.display()[16 * 128 + 120], 0);
+    assert_eq!(vm.v[0xF].0, 0);
+    let draw = vm.take_events().into_iter().find_map(|e| match e.kind {
+        EventKind::SpriteDrawn(draw) => Some(draw),
+        _ => None,
+    });
+    assert_eq!(draw.map(|draw| (draw.width, draw.height)), Some((16, 16)));
+    // Drawing it again erases it
+    vm.do_cycle();
+    assert_eq!(vm.v[0xF].0, 1);
+    assert!(!vm.display().contains(&1));
+}
@@ -0,0 +1,129 @@
+//! Sharing a VM between an emulation thread and a UI thread.
+//!
+//! The SFML frontend doesn't use this. Its replays, trace log and debugger windows work on
+//! the VM in between frames, so it still runs the emulation on the UI thread.
+
+use {
+    super::{FrameBuffer, FrameEvents, HaltReason, VirtualMachine},
+    std::{
+        collections::VecDeque,
+        sync::{Arc, Mutex, MutexGuard, RwLock},
+    },
+};
+
+/// A command for the emulation thread, applied before the next step.
+pub enum Command {
+    /// Press a key on the hexadecimal keypad.
+    PressKey(u8),
+    /// Release a key on the hexadecimal keypad.
+    ReleaseKey(u8),
+    /// Replace the VM, e.g. to load a saved state or to reset.
+    Replace(Box<VirtualMachine>),
+    /// Run arbitrary code on the VM.
+    Run(Box<dyn FnOnce(&mut VirtualMachine) + Send>),
+}
+
+/// What the UI thread needs to present the VM.
+#[derive(Clone)]
+pub struct Snapshot {
+    /// The contents of the display.
+    pub display: FrameBuffer,
+    /// Whether the sound is playing.
+    pub sound_playing: bool,
+    /// Why the VM halted, if it did.
+    pub halt_reason: Option<HaltReason>,
+}
+
+struct Inner {
+    vm: Mutex<VirtualMachine>,
+    commands: Mutex<VecDeque<Command>>,
+    snapshot: RwLock<Snapshot>,
+}
+
+/// A VM that can be shared between threads.
+///
+/// The emulation thread calls [`SharedVm::step_micros`], while the UI thread sends
+/// [`Command`]s and reads [`Snapshot`]s, neither of which wait for the emulation to finish
+/// a step.
+#[derive(Clone)]
+pub struct SharedVm {
+    inner: Arc<Inner>,
+}
+
+impl SharedVm {
+    /// Wraps a VM for sharing.
+    pub fn new(vm: VirtualMachine) -> Self {
+        let snapshot = Snapshot::of(&vm);
+        Self {
+            inner: Arc::new(Inner {
+                vm: Mutex::new(vm),
+                commands: Mutex::new(VecDeque::new()),
+                snapshot: RwLock::new(snapshot),
+            }),
+        }
+    }
+
+    /// Queues a command for the emulation thread.
+    pub fn send(&self, command: Command) {
+        self.inner.commands.lock().unwrap().push_back(command);
+    }
+
+    /// Applies the queued commands, then advances the VM by `us` microseconds.
+    ///
+    /// This is meant to be called by the emulation thread.
+    pub fn step_micros(&self, us: u64) -> FrameEvents {
+        let mut vm = self.inner.vm.lock().unwrap();
+        let commands = std::mem::take(&mut *self.inner.commands.lock().unwrap());
+        for command in commands {
+            match command {
+                Command::PressKey(key) => vm.press_key(key),
+                Command::ReleaseKey(key) => vm.release_key(key),
+                Command::Replace(new) => *vm = *new,
+                Command::Run(f) => f(&mut vm),
+            }
+        }
+        let events = vm.step_micros(us);
+        *self.inner.snapshot.write().unwrap() = Snapshot::of(&vm);
+        vm.clear_du_flag();
+        events
+    }
+
+    /// Returns the latest snapshot, or `None` if it's being updated right now.
+    ///
+    /// Never blocks, so it's suitable for calling from a render loop.
+    pub fn snapshot(&self) -> Option<Snapshot> {
+        self.inner.snapshot.try_read().ok().map(|snap| snap.clone())
+    }
+
+    /// Locks the VM for direct access, blocking until the emulation thread is done stepping.
+    pub fn lock(&self) -> MutexGuard<'_, VirtualMachine> {
+        self.inner.vm.lock().unwrap()
+    }
+}
+
+impl Snapshot {
+    fn of(vm: &VirtualMachine) -> Self {
+        Self {
+            display: vm.framebuffer().clone(),
+            sound_playing: vm.sound_playing(),
+            halt_reason: vm.halt_reason(),
+        }
+    }
+}
+
+#[test]
+fn test_shared_vm_across_threads() {
+    let mut vm = VirtualMachine::new();
+    // 0x200: LD F, V0
+    // 0x202: DRW V0, V0, 5
+    // 0x204: JP 0x204
+    vm.load_rom(&[0xF0, 0x29, 0xD0, 0x05, 0x12, 0x04]);
+    let shared = SharedVm::new(vm);
+    let worker = shared.clone();
+    std::thread::spawn(move || worker.step_micros(1_000_000))
+        .join()
+        .unwrap();
+    let snap = shared.snapshot().unwrap();
+    assert!(snap.display.get(0, 0));
+    assert_eq!(snap.halt_reason, Some(HaltReason::ProgramEnded));
+}
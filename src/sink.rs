@@ -0,0 +1,342 @@
+//! Push-style outputs of the VM.
+//!
+//! Frontends can either pull the state out of the VM after stepping it, or plug in sinks
+//! that the VM calls as things happen.
+
+use {
+    super::{FrameBuffer, VirtualMachine},
+    std::{
+        fmt,
+        sync::{Arc, Mutex},
+    },
+};
+
+/// Receives the display whenever it changes.
+pub trait DisplaySink: Send {
+    /// Called after every instruction that changed the display.
+    fn present(&mut self, fb: &FrameBuffer);
+}
+
+impl<F: FnMut(&FrameBuffer) + Send> DisplaySink for F {
+    fn present(&mut self, fb: &FrameBuffer) {
+        self(fb)
+    }
+}
+
+/// A change in the sound output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AudioEvent {
+    /// The beep started, because the sound timer was set.
+    BeepStarted,
+    /// The beep stopped, because the sound timer ran out.
+    BeepStopped,
+    /// The sound timer was set to 1, which is too short to make a sound with the
+    /// [`min_beep`](crate::Quirks::min_beep) quirk.
+    BeepTooShort,
+}
+
+/// Receives changes in the sound output.
+pub trait AudioSink: Send {
+    /// Called when the sound output changes.
+    fn event(&mut self, event: AudioEvent);
+}
+
+impl<F: FnMut(AudioEvent) + Send> AudioSink for F {
+    fn event(&mut self, event: AudioEvent) {
+        self(event)
+    }
+}
+
+/// Receives the messages the VM logs, like warnings about the ROM and why it halted.
+pub trait LogSink: Send {
+    /// Called with every message. The text doesn't include a trailing newline.
+    fn log(&mut self, message: &str);
+}
+
+impl<F: FnMut(&str) + Send> LogSink for F {
+    fn log(&mut self, message: &str) {
+        self(message)
+    }
+}
+
+/// A sink writing messages to stderr.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StderrLog;
+
+impl LogSink for StderrLog {
+    fn log(&mut self, message: &str) {
+        eprintln!("{}", message);
+    }
+}
+
+/// A sink keeping the most recent messages in memory, for showing them in a log window.
+///
+/// Clones share the same log, so one can be given to the VM and another kept for reading.
+/// Once the text grows past the capacity, the oldest lines are dropped.
+/// The log can also be written to with [`fmt::Write`], for messages of the frontend itself.
+#[derive(Debug, Clone)]
+pub struct MemoryLog {
+    text: Arc<Mutex<String>>,
+    capacity: usize,
+}
+
+impl MemoryLog {
+    /// Creates an empty log keeping up to `capacity` bytes of text.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            text: Arc::default(),
+            capacity,
+        }
+    }
+
+    /// Returns the text of the log, one message per line.
+    pub fn text(&self) -> String {
+        self.text.lock().unwrap().clone()
+    }
+
+    /// Removes all messages.
+    pub fn clear(&self) {
+        self.text.lock().unwrap().clear();
+    }
+
+    fn append(&self, s: &str) {
+        let mut text = self.text.lock().unwrap();
+        text.push_str(s);
+        let Some(excess) = text.len().checked_sub(self.capacity).filter(|&n| n > 0) else {
+            return;
+        };
+        // Never cut into a character, and drop whole lines where possible, so the log
+        // doesn't start in the middle of one
+        let excess = (excess..=text.len())
+            .find(|&i| text.is_char_boundary(i))
+            .unwrap();
+        let cut = match text[excess..].find('\n') {
+            Some(newline) => excess + newline + 1,
+            None => excess,
+        };
+        text.drain(..cut);
+    }
+}
+
+impl LogSink for MemoryLog {
+    fn log(&mut self, message: &str) {
+        self.append(message);
+        self.append("\n");
+    }
+}
+
+impl fmt::Write for MemoryLog {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.append(s);
+        Ok(())
+    }
+}
+
+/// How serious a logged message is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// Something worth knowing, like the program ending.
+    Info,
+    /// Something that might make the program misbehave.
+    Warning,
+    /// Something that stopped the program.
+    Error,
+}
+
+impl Severity {
+    /// All severities, from the least serious.
+    pub const ALL: [Severity; 3] = [Severity::Info, Severity::Warning, Severity::Error];
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+#[derive(Clone, Default)]
+pub(super) struct Sinks {
+    display: Option<Arc<Mutex<dyn DisplaySink>>>,
+    audio: Option<Arc<Mutex<dyn AudioSink>>>,
+    log: Option<Arc<Mutex<dyn LogSink>>>,
+}
+
+impl VirtualMachine {
+    /// Sets the sink the display is presented to whenever it changes.
+    ///
+    /// Closures taking a `&FrameBuffer` are sinks too.
+    /// The sink is shared with clones of this VM.
+    pub fn set_display_sink(&mut self, sink: impl DisplaySink + 'static) {
+        self.sinks.display = Some(Arc::new(Mutex::new(sink)));
+    }
+
+    /// Removes the sink set by [`VirtualMachine::set_display_sink`].
+    pub fn clear_display_sink(&mut self) {
+        self.sinks.display = None;
+    }
+
+    /// Sets the sink that receives changes in the sound output.
+    ///
+    /// Closures taking an [`AudioEvent`] are sinks too.
+    /// The sink is shared with clones of this VM.
+    pub fn set_audio_sink(&mut self, sink: impl AudioSink + 'static) {
+        self.sinks.audio = Some(Arc::new(Mutex::new(sink)));
+    }
+
+    /// Removes the sink set by [`VirtualMachine::set_audio_sink`].
+    pub fn clear_audio_sink(&mut self) {
+        self.sinks.audio = None;
+    }
+
+    /// Sets the sink that receives the messages the VM logs.
+    ///
+    /// Closures taking a `&str` are sinks too. Without a sink, messages are only available
+    /// as [`EventKind::Log`](crate::EventKind::Log) events, if those are recorded.
+    /// The sink is shared with clones of this VM.
+    pub fn set_log_sink(&mut self, sink: impl LogSink + 'static) {
+        self.sinks.log = Some(Arc::new(Mutex::new(sink)));
+    }
+
+    /// Removes the sink set by [`VirtualMachine::set_log_sink`].
+    pub fn clear_log_sink(&mut self) {
+        self.sinks.log = None;
+    }
+
+    pub(super) fn send_log(&self, severity: Severity, args: fmt::Arguments) {
+        #[cfg(feature = "log")]
+        {
+            let level = match severity {
+                Severity::Info => log::Level::Info,
+                Severity::Warning => log::Level::Warn,
+                Severity::Error => log::Level::Error,
+            };
+            log::log!(target: "crusty_chip", level, "{}", args);
+        }
+        #[cfg(not(feature = "log"))]
+        let _ = severity;
+        if let Some(sink) = &self.sinks.log {
+            sink.lock().unwrap().log(&args.to_string());
+        }
+    }
+
+    pub(super) fn send_audio(&self, event: AudioEvent) {
+        if let Some(sink) = &self.sinks.audio {
+            sink.lock().unwrap().event(event);
+        }
+    }
+
+    pub(super) fn present_display(&self) {
+        if let Some(sink) = &self.sinks.display {
+            sink.lock().unwrap().present(&self.display);
+        }
+    }
+}
+
+#[test]
+fn test_display_sink() {
+    let mut vm = VirtualMachine::new();
+    let presented = Arc::new(Mutex::new(Vec::new()));
+    let frames = presented.clone();
+    vm.set_display_sink(move |fb: &FrameBuffer| frames.lock().unwrap().push(fb.get(0, 0)));
+    // 0x200: LD I, 0
+    // 0x202: DRW V0, V0, 5
+    // 0x204: CLS
+    // 0x206: LD V0, 1
+    vm.load_rom(&[0xA0, 0x00, 0xD0, 0x05, 0x00, 0xE0, 0x60, 0x01]);
+    for _ in 0..4 {
+        vm.do_cycle();
+    }
+    assert_eq!(*presented.lock().unwrap(), [true, false]);
+}
+
+#[test]
+fn test_min_beep() {
+    let mut vm = VirtualMachine::new();
+    vm.set_quirks(crate::Quirks {
+        min_beep: true,
+        ..Default::default()
+    });
+    let beeps = Arc::new(Mutex::new(Vec::new()));
+    let log = beeps.clone();
+    vm.set_audio_sink(move |event| log.lock().unwrap().push(event));
+    // 0x200: LD V0, 1
+    // 0x202: LD ST, V0
+    // 0x204: LD V0, 2
+    // 0x206: LD ST, V0
+    vm.load_rom(&[0x60, 0x01, 0xF0, 0x18, 0x60, 0x02, 0xF0, 0x18]);
+    vm.do_cycle();
+    vm.do_cycle();
+    assert!(!vm.sound_playing());
+    vm.decrement_timers();
+    vm.do_cycle();
+    vm.do_cycle();
+    assert!(vm.sound_playing());
+    // Once playing, the beep lasts until the timer runs out
+    vm.decrement_timers();
+    assert!(vm.sound_playing());
+    vm.decrement_timers();
+    assert_eq!(
+        *beeps.lock().unwrap(),
+        [
+            AudioEvent::BeepTooShort,
+            AudioEvent::BeepStarted,
+            AudioEvent::BeepStopped
+        ]
+    );
+}
+
+#[test]
+fn test_audio_sink() {
+    let mut vm = VirtualMachine::new();
+    let beeps = Arc::new(Mutex::new(Vec::new()));
+    let log = beeps.clone();
+    let frame = Arc::new(Mutex::new(0));
+    let frame_in_sink = frame.clone();
+    vm.set_audio_sink(move |event| {
+        log.lock()
+            .unwrap()
+            .push((event, *frame_in_sink.lock().unwrap()))
+    });
+    // 0x200: LD V0, 4
+    // 0x202: LD ST, V0
+    // 0x204: JP 0x204
+    vm.load_rom(&[0x60, 0x04, 0xF0, 0x18, 0x12, 0x04]);
+    for _ in 0..10 {
+        vm.step_frame();
+        *frame.lock().unwrap() += 1;
+    }
+    // The sound timer runs out at the end of the fourth frame
+    assert_eq!(
+        *beeps.lock().unwrap(),
+        [(AudioEvent::BeepStarted, 0), (AudioEvent::BeepStopped, 3)]
+    );
+}
+
+#[test]
+fn test_memory_log() {
+    let mut vm = VirtualMachine::new();
+    let log = MemoryLog::new(40);
+    vm.set_log_sink(log.clone());
+    // 0x200: JP 0x200
+    vm.load_rom(&[0x12, 0x00]);
+    vm.do_cycle();
+    assert_eq!(log.text(), "Program ended at 0x200. Halted.\n");
+    fmt::Write::write_str(&mut log.clone(), "Saved state 1.\n").unwrap();
+    // The oldest line is dropped to stay within the capacity
+    fmt::Write::write_str(&mut log.clone(), "Loaded state 1.\n").unwrap();
+    assert_eq!(log.text(), "Saved state 1.\nLoaded state 1.\n");
+    log.clear();
+    assert_eq!(log.text(), "");
+    // Cutting the excess doesn't split a character
+    let log = MemoryLog::new(5);
+    fmt::Write::write_str(&mut log.clone(), "ééé").unwrap();
+    fmt::Write::write_str(&mut log.clone(), "é").unwrap();
+    assert_eq!(log.text(), "éé");
+    fmt::Write::write_str(&mut log.clone(), "ü\nö").unwrap();
+    assert_eq!(log.text(), "ö");
+}
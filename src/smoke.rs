@@ -0,0 +1,171 @@
+//! Bulk smoke testing of ROM collections.
+
+use {
+    super::{
+        FrameBuffer, HaltReason, MAX_ROM_LEN, VirtualMachine, batch, events::EventKind, rom,
+        savestate,
+    },
+    std::{
+        fmt, fs, io,
+        path::{Path, PathBuf},
+        sync::{
+            Arc,
+            atomic::{AtomicBool, Ordering},
+        },
+    },
+};
+
+/// How a single ROM fared.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SmokeResult {
+    /// The path of the ROM.
+    pub path: PathBuf,
+    /// The program halted with an error, like running out of memory.
+    pub crashed: bool,
+    /// Nothing was ever drawn on the display.
+    pub drew_nothing: bool,
+    /// The instructions that weren't understood, in the order they were first encountered.
+    pub unknown_opcodes: Vec<u16>,
+    /// Why the ROM halted, if it did.
+    pub halt_reason: Option<HaltReason>,
+    /// A hash of the final state, for spotting behavior changes between versions.
+    pub state_hash: u64,
+}
+
+impl SmokeResult {
+    /// Returns whether anything looks wrong with the ROM.
+    pub fn is_suspicious(&self) -> bool {
+        self.crashed || self.drew_nothing || !self.unknown_opcodes.is_empty()
+    }
+}
+
+/// The results of [`smoke_test_dir`].
+#[derive(Debug, Clone, Default)]
+pub struct SmokeReport {
+    /// The results of the individual ROMs, sorted by path.
+    pub results: Vec<SmokeResult>,
+}
+
+impl SmokeReport {
+    /// Returns the results that look wrong.
+    pub fn suspicious(&self) -> impl Iterator<Item = &SmokeResult> {
+        self.results.iter().filter(|r| r.is_suspicious())
+    }
+}
+
+impl fmt::Display for SmokeReport {
+    /// Formats the report as a table, one ROM per line.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for r in &self.results {
+            let mut problems = Vec::new();
+            if r.crashed {
+                problems.push("crashed".to_owned());
+            }
+            if r.drew_nothing {
+                problems.push("drew nothing".to_owned());
+            }
+            if !r.unknown_opcodes.is_empty() {
+                problems.push(format!("unknown opcodes {:04X?}", r.unknown_opcodes));
+            }
+            let status = if problems.is_empty() {
+                "ok".to_owned()
+            } else {
+                problems.join(", ")
+            };
+            writeln!(f, "{:016x}  {}  {}", r.state_hash, r.path.display(), status)?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs `rom` headless for `frames` frames, with a fixed random seed.
+pub fn smoke_test(rom: &[u8], frames: u64) -> SmokeResult {
+    let drew = Arc::new(AtomicBool::new(false));
+    let sink_drew = drew.clone();
+    let mut vm = VirtualMachine::new();
+    vm.set_rng_seed(0);
+    vm.load_rom(rom);
+    vm.record_events(true);
+    vm.set_display_sink(move |fb: &FrameBuffer| {
+        if fb.pixels().contains(&1) {
+            sink_drew.store(true, Ordering::Relaxed);
+        }
+    });
+    let mut unknown_opcodes = Vec::new();
+    for _ in 0..frames {
+        vm.step_frame();
+        for event in vm.take_events() {
+            if let EventKind::UnknownInstruction(ins) = event.kind
+                && !unknown_opcodes.contains(&ins)
+            {
+                unknown_opcodes.push(ins);
+            }
+        }
+        if vm.halt_reason().is_some() {
+            break;
+        }
+    }
+    let halt_reason = vm.halt_reason();
+    SmokeResult {
+        path: PathBuf::new(),
+        crashed: halt_reason.is_some_and(HaltReason::is_error),
+        drew_nothing: !drew.load(Ordering::Relaxed),
+        unknown_opcodes,
+        halt_reason,
+        state_hash: savestate::checksum(&vm.save_state()),
+    }
+}
+
+/// Runs every ROM in `dir` headless for `frames` frames, with [`smoke_test`].
+///
+/// The ROMs are run in parallel.
+/// ROMs are recognized by their extension, see [`rom::ROM_EXTENSIONS`].
+/// Subdirectories aren't searched.
+pub fn smoke_test_dir(dir: &Path, frames: u64) -> io::Result<SmokeReport> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_rom = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(rom::has_rom_extension);
+        if is_rom && path.is_file() {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    let mut roms = Vec::new();
+    for path in paths {
+        let mut rom = fs::read(&path)?;
+        rom.truncate(MAX_ROM_LEN);
+        roms.push((path, rom));
+    }
+    let results = batch::parallel_map(&roms, |(path, rom)| SmokeResult {
+        path: path.clone(),
+        ..smoke_test(rom, frames)
+    });
+    Ok(SmokeReport { results })
+}
+
+#[test]
+fn test_smoke_test() {
+    // 0x200: LD I, 0
+    // 0x202: DRW V0, V0, 5
+    // 0x204: JP 0x204
+    let good = smoke_test(&[0xA0, 0x00, 0xD0, 0x05, 0x12, 0x04], 10);
+    assert!(!good.is_suspicious());
+    assert_eq!(good.halt_reason, Some(HaltReason::ProgramEnded));
+
+    // 0x200: (unknown)
+    // 0x202: JP 0xFFE
+    let bad = smoke_test(&[0xFF, 0xFF, 0x1F, 0xFE], 10);
+    assert!(bad.crashed);
+    assert!(bad.drew_nothing);
+    assert_eq!(bad.unknown_opcodes, [0xFFFF]);
+    assert_eq!(bad.halt_reason, Some(HaltReason::OutOfBounds));
+
+    // 0x200: RET
+    let underflow = smoke_test(&[0x00, 0xEE], 10);
+    assert!(underflow.crashed);
+    assert_eq!(underflow.halt_reason, Some(HaltReason::StackUnderflow));
+}
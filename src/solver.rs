@@ -0,0 +1,240 @@
+//! Searching for input sequences that reach a goal, for solving or verifying puzzle ROMs.
+//!
+//! A search starts from a VM and tries holding each of a set of inputs for a fixed number of
+//! frames, then does the same from every state that reached, until the goal holds. States are
+//! deduplicated by hash, so inputs that don't change anything aren't explored twice.
+//! The VM should have a fixed random seed, see [`VirtualMachine::set_rng_seed`],
+//! or the same inputs can lead to different states.
+
+use {
+    super::{VirtualMachine, bot::KeyState},
+    std::{
+        collections::{HashSet, VecDeque},
+        hash::{DefaultHasher, Hash, Hasher},
+    },
+};
+
+/// The parameters of a search.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Search {
+    /// The inputs to try at every step.
+    pub inputs: Vec<KeyState>,
+    /// How many frames every input is held for.
+    pub frames_per_input: u64,
+    /// The longest input sequence to try.
+    pub max_depth: usize,
+    /// How many states to explore at most, to bound the time and memory a search takes.
+    pub max_states: usize,
+}
+
+/// The result of a search.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchResult {
+    /// Indices into [`Search::inputs`] of the shortest input sequence found to reach the goal.
+    pub solution: Option<Vec<usize>>,
+    /// How many states were explored.
+    pub explored: usize,
+}
+
+// Hashes everything that affects how the VM continues, but not the counters. Going through
+// the savestate format keeps this covering state added later.
+fn state_hash(vm: &VirtualMachine) -> u64 {
+    let mut vm = vm.clone();
+    (vm.cycles, vm.frames) = (0, 0);
+    let mut h = DefaultHasher::new();
+    vm.save_state().hash(&mut h);
+    h.finish()
+}
+
+impl Search {
+    // Runs `vm` for one step with input `i` held
+    fn step(&self, vm: &mut VirtualMachine, i: usize) {
+        for (key, &pressed) in (0..16).zip(&self.inputs[i]) {
+            if pressed {
+                vm.press_key(key);
+            } else {
+                vm.release_key(key);
+            }
+        }
+        for _ in 0..self.frames_per_input {
+            vm.step_frame();
+        }
+    }
+
+    /// Searches breadth-first, so the solution found is a shortest one.
+    ///
+    /// Every state of the frontier is kept in memory, so this suits short solutions.
+    pub fn breadth_first(
+        &self,
+        start: &VirtualMachine,
+        mut goal: impl FnMut(&VirtualMachine) -> bool,
+    ) -> SearchResult {
+        let mut result = SearchResult {
+            solution: None,
+            explored: 1,
+        };
+        if goal(start) {
+            result.solution = Some(Vec::new());
+            return result;
+        }
+        let mut seen = HashSet::from([state_hash(start)]);
+        let mut frontier = VecDeque::from([(start.clone(), Vec::new())]);
+        while let Some((vm, path)) = frontier.pop_front() {
+            if path.len() >= self.max_depth || vm.halt_reason().is_some() {
+                continue;
+            }
+            for i in 0..self.inputs.len() {
+                if result.explored >= self.max_states {
+                    return result;
+                }
+                let mut next = vm.clone();
+                self.step(&mut next, i);
+                if !seen.insert(state_hash(&next)) {
+                    continue;
+                }
+                result.explored += 1;
+                let mut next_path = path.clone();
+                next_path.push(i);
+                if goal(&next) {
+                    result.solution = Some(next_path);
+                    return result;
+                }
+                frontier.push_back((next, next_path));
+            }
+        }
+        result
+    }
+
+    /// Searches depth-first with increasing depth limits, so the solution found is a shortest
+    /// one, while only keeping the states along the current path in memory.
+    ///
+    /// States are revisited at every depth, so this takes longer than
+    /// [`Search::breadth_first`] when both fit in memory.
+    pub fn iterative_deepening(
+        &self,
+        start: &VirtualMachine,
+        mut goal: impl FnMut(&VirtualMachine) -> bool,
+    ) -> SearchResult {
+        let mut result = SearchResult {
+            solution: None,
+            explored: 0,
+        };
+        for depth in 0..=self.max_depth {
+            let mut path = Vec::new();
+            // Only the current path is deduplicated against, which is what bounds memory
+            let mut on_path = vec![state_hash(start)];
+            if self.deepen(
+                start,
+                depth,
+                &mut goal,
+                &mut path,
+                &mut on_path,
+                &mut result,
+            ) {
+                result.solution = Some(path);
+                return result;
+            }
+            if result.explored >= self.max_states {
+                break;
+            }
+        }
+        result
+    }
+
+    fn deepen(
+        &self,
+        vm: &VirtualMachine,
+        depth: usize,
+        goal: &mut impl FnMut(&VirtualMachine) -> bool,
+        path: &mut Vec<usize>,
+        on_path: &mut Vec<u64>,
+        result: &mut SearchResult,
+    ) -> bool {
+        result.explored += 1;
+        if depth == 0 {
+            return goal(vm);
+        }
+        if vm.halt_reason().is_some() {
+            return false;
+        }
+        for i in 0..self.inputs.len() {
+            if result.explored >= self.max_states {
+                return false;
+            }
+            let mut next = vm.clone();
+            self.step(&mut next, i);
+            let hash = state_hash(&next);
+            if on_path.contains(&hash) {
+                continue;
+            }
+            path.push(i);
+            on_path.push(hash);
+            if self.deepen(&next, depth - 1, goal, path, on_path, result) {
+                return true;
+            }
+            path.pop();
+            on_path.pop();
+        }
+        false
+    }
+}
+
+#[test]
+fn test_search() {
+    // A lock that opens with key 1 followed by key 2, setting V2 to 3
+    let rom = [
+        0x60, 0x01, // 0x200: LD V0, 1
+        0x61, 0x02, // 0x202: LD V1, 2
+        0xE0, 0xA1, // 0x204: SKNP V0
+        0x62, 0x01, // 0x206: LD V2, 1
+        0xE1, 0xA1, // 0x208: SKNP V1
+        0x12, 0x10, // 0x20A: JP 0x210
+        0x12, 0x04, // 0x20C: JP 0x204
+        0x00, 0x00, // 0x20E: unused
+        0x32, 0x01, // 0x210: SE V2, 1
+        0x12, 0x04, // 0x212: JP 0x204
+        0x62, 0x03, // 0x214: LD V2, 3
+        0x12, 0x04, // 0x216: JP 0x204
+    ];
+    let mut vm = VirtualMachine::new();
+    vm.set_rng_seed(0);
+    vm.load_rom(&rom);
+    let key = |k: usize| {
+        let mut keys = KeyState::default();
+        keys[k] = true;
+        keys
+    };
+    let search = Search {
+        inputs: vec![KeyState::default(), key(1), key(2)],
+        frames_per_input: 2,
+        max_depth: 4,
+        max_states: 1000,
+    };
+    let open = |vm: &VirtualMachine| vm.v(2) == 3;
+    let bfs = search.breadth_first(&vm, open);
+    assert_eq!(bfs.solution, Some(vec![1, 2]));
+    let iddfs = search.iterative_deepening(&vm, open);
+    assert_eq!(iddfs.solution, Some(vec![1, 2]));
+
+    let shallow = Search {
+        max_depth: 1,
+        ..search
+    };
+    assert_eq!(shallow.breadth_first(&vm, open).solution, None);
+}
+
+#[test]
+fn test_state_hash() {
+    let vm = VirtualMachine::new();
+    let mut other = vm.clone();
+    other.cycles += 1;
+    assert_eq!(state_hash(&vm), state_hash(&other));
+    other.high_res = true;
+    assert_ne!(state_hash(&vm), state_hash(&other));
+    let mut other = vm.clone();
+    other.set_quirks(crate::Quirks {
+        jump_uses_vx: !vm.quirks().jump_uses_vx,
+        ..vm.quirks()
+    });
+    assert_ne!(state_hash(&vm), state_hash(&other));
+}
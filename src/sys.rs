@@ -0,0 +1,145 @@
+//! A hook for `SYS` calls.
+//!
+//! On the COSMAC VIP, `0nnn` ran the machine code routine at `nnn`. There's no 1802 to run
+//! it on here, so `SYS` calls are skipped, unless a handler is set. The handler runs in place
+//! of the routine, so test ROMs can use `SYS` to signal that they're done, and hybrid VIP
+//! programs can have their routines stood in for by the host.
+
+use {
+    super::{HaltReason, HostCallResult, VirtualMachine},
+    std::{
+        num::Wrapping,
+        sync::{Arc, Mutex},
+    },
+};
+
+/// The context of a `SYS` call.
+///
+/// Changes to the registers and memory are written back to the VM when the handler returns.
+#[derive(Debug)]
+pub struct SysCall<'a> {
+    /// The address of the routine, the low 12 bits of the instruction.
+    pub addr: u16,
+    /// The address of the instruction.
+    pub pc: u16,
+    /// The general purpose registers.
+    pub v: [u8; 16],
+    /// The I register.
+    pub i: u16,
+    /// The memory of the VM.
+    pub ram: &'a mut [u8],
+}
+
+pub(super) type Handler = Arc<Mutex<dyn FnMut(&mut SysCall) -> HostCallResult + Send>>;
+
+impl VirtualMachine {
+    /// Makes `0nnn` instructions call `handler` instead of doing nothing.
+    ///
+    /// [Host calls](VirtualMachine::set_host_call_handler) go first, so while they're
+    /// enabled, `0Fnn` instructions don't reach this handler. The handler is shared with
+    /// clones of this VM.
+    pub fn set_sys_handler(
+        &mut self,
+        handler: impl FnMut(&mut SysCall) -> HostCallResult + Send + 'static,
+    ) {
+        self.sys_calls = Some(Arc::new(Mutex::new(handler)));
+    }
+
+    /// Removes the handler, so `SYS` calls do nothing again.
+    pub fn clear_sys_handler(&mut self) {
+        self.sys_calls = None;
+    }
+
+    pub(super) fn jump_to_sys_routine(&mut self, addr: u16) {
+        #[cfg(feature = "hires")]
+        if self.two_page_routine(addr) {
+            return;
+        }
+        let Some(handler) = self.sys_calls.clone() else {
+            return;
+        };
+        let mut call = SysCall {
+            addr,
+            pc: self.pc.wrapping_sub(2),
+            v: self.v.map(|v| v.0),
+            i: self.i,
+            ram: &mut self.ram,
+        };
+        let result = (handler.lock().unwrap())(&mut call);
+        let (v, i) = (call.v, call.i);
+        self.v = v.map(Wrapping);
+        self.i = i;
+        if result == HostCallResult::Exit {
+            self.halt(HaltReason::Exited);
+        }
+    }
+}
+
+#[test]
+fn test_sys_handler() {
+    let mut vm = VirtualMachine::new();
+    // 0x200: SYS 0x123
+    // 0x202: SYS 0x456
+    // 0x204: SYS 0x789
+    vm.load_rom(&[0x01, 0x23, 0x04, 0x56, 0x07, 0x89]);
+    // Without a handler, SYS does nothing but move on
+    let mut unhandled = vm.clone();
+    unhandled.do_cycle();
+    assert_eq!(unhandled.pc(), 0x202);
+    assert_eq!(vm.diff(&unhandled), [crate::Difference::Pc]);
+    assert_eq!(unhandled.halt_reason(), None);
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let seen = calls.clone();
+    vm.set_sys_handler(move |call| {
+        seen.lock().unwrap().push((call.pc, call.addr));
+        match call.addr {
+            0x789 => HostCallResult::Exit,
+            _ => {
+                call.v[0] += 1;
+                call.ram[0x300] = call.v[0];
+                HostCallResult::Continue
+            }
+        }
+    });
+    for _ in 0..4 {
+        vm.do_cycle();
+    }
+    assert_eq!(
+        *calls.lock().unwrap(),
+        [(0x200, 0x123), (0x202, 0x456), (0x204, 0x789)]
+    );
+    assert_eq!(vm.v(0), 2);
+    assert_eq!(vm.ram[0x300], 2);
+    assert_eq!(vm.halt_reason(), Some(HaltReason::Exited));
+}
+
+#[test]
+fn test_sys_handler_is_not_called_past_memory() {
+    let mut vm = VirtualMachine::new();
+    // 0x200: JP 0xFFE
+    vm.load_rom(&[0x1F, 0xFE]);
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let seen = calls.clone();
+    vm.set_sys_handler(move |call| {
+        seen.lock().unwrap().push(call.pc);
+        // A call from past the end of memory would replace why the program stopped
+        if call.pc > 0xFFE {
+            HostCallResult::Exit
+        } else {
+            HostCallResult::Continue
+        }
+    });
+    vm.record_events(true);
+    // 0xFFE holds SYS 0x000, and the next fetch is past the end of memory
+    for _ in 0..4 {
+        vm.do_cycle();
+    }
+    assert_eq!(*calls.lock().unwrap(), [0xFFE]);
+    assert_eq!(vm.halt_reason(), Some(HaltReason::OutOfBounds));
+    let halts = vm
+        .take_events()
+        .into_iter()
+        .filter(|event| matches!(event.kind, crate::EventKind::Halted(_)))
+        .count();
+    assert_eq!(halts, 1);
+}
@@ -0,0 +1,175 @@
+//! Recognizing the results of test ROMs.
+//!
+//! Test ROMs report whether they passed in a few common ways: by writing a magic value to a
+//! known address, by drawing a pass or fail glyph, or, for ROMs written for this interpreter,
+//! with a [host call](crate::HostCall). A [`Protocol`] describes how a ROM reports, so a
+//! harness can tell the outcome without comparing screenshots.
+
+use {
+    super::{HostCallResult, VirtualMachine},
+    std::sync::{Arc, Mutex},
+};
+
+/// The host call function a ROM makes when it passed.
+pub const PASS_CALL: u8 = 0x00;
+/// The host call function a ROM makes when it failed.
+pub const FAIL_CALL: u8 = 0x01;
+
+/// How a test ROM reports its result.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Protocol {
+    /// The ROM writes `pass` or `fail` to `addr`.
+    MagicValue {
+        /// The address of the result.
+        addr: u16,
+        /// The value written when the ROM passed.
+        pass: u8,
+        /// The value written when the ROM failed.
+        fail: u8,
+    },
+    /// The ROM draws one of two glyphs, given as sprite rows like for `Dxyn`.
+    ///
+    /// The glyph must appear exactly, with the pixels around it in its 8 pixel wide rows off.
+    Glyph {
+        /// The glyph drawn when the ROM passed.
+        pass: Vec<u8>,
+        /// The glyph drawn when the ROM failed.
+        fail: Vec<u8>,
+    },
+    /// The ROM makes the host call [`PASS_CALL`] or [`FAIL_CALL`].
+    ///
+    /// A failing ROM can put an error code in V0.
+    HostCall,
+}
+
+/// The result of a test ROM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// The ROM reported that it passed.
+    Passed,
+    /// The ROM reported that it failed, with an error code if the protocol has them.
+    Failed(Option<u8>),
+    /// The ROM didn't report a result.
+    Undecided,
+}
+
+impl Protocol {
+    /// Checks whether the ROM running in `vm` reported a result.
+    ///
+    /// Results reported with host calls are only seen by [`run_test_rom`].
+    pub fn check(&self, vm: &VirtualMachine) -> Verdict {
+        match self {
+            Protocol::MagicValue { addr, pass, fail } => match vm.ram.get(usize::from(*addr)) {
+                Some(v) if v == pass => Verdict::Passed,
+                Some(v) if v == fail => Verdict::Failed(None),
+                _ => Verdict::Undecided,
+            },
+            Protocol::Glyph { pass, fail } => {
+                if shows_glyph(vm, pass) {
+                    Verdict::Passed
+                } else if shows_glyph(vm, fail) {
+                    Verdict::Failed(None)
+                } else {
+                    Verdict::Undecided
+                }
+            }
+            Protocol::HostCall => Verdict::Undecided,
+        }
+    }
+}
+
+fn shows_glyph(vm: &VirtualMachine, rows: &[u8]) -> bool {
+    let (width, height) = vm.display_size();
+    if rows.is_empty() || rows.len() > height {
+        return false;
+    }
+    let fb = vm.framebuffer();
+    let matches_at = |x: usize, y: usize| {
+        rows.iter().enumerate().all(|(dy, row)| {
+            (0..8).all(|dx| fb.get(x + dx, y + dy) == ((row >> (7 - dx)) & 1 == 1))
+        })
+    };
+    (0..=height - rows.len()).any(|y| (0..=width - 8).any(|x| matches_at(x, y)))
+}
+
+/// Runs a test ROM for up to `frames` frames, until it reports a result or halts.
+pub fn run_test_rom(rom: &[u8], protocol: &Protocol, frames: u64) -> Verdict {
+    let mut vm = VirtualMachine::new();
+    vm.set_rng_seed(0);
+    vm.load_rom(rom);
+    let reported = Arc::new(Mutex::new(Verdict::Undecided));
+    if *protocol == Protocol::HostCall {
+        let reported = reported.clone();
+        vm.set_host_call_handler(move |call| match call.function {
+            PASS_CALL => {
+                *reported.lock().unwrap() = Verdict::Passed;
+                HostCallResult::Exit
+            }
+            FAIL_CALL => {
+                *reported.lock().unwrap() = Verdict::Failed(Some(call.v[0]));
+                HostCallResult::Exit
+            }
+            _ => HostCallResult::Continue,
+        });
+    }
+    for _ in 0..frames {
+        vm.step_frame();
+        let verdict = match *reported.lock().unwrap() {
+            Verdict::Undecided => protocol.check(&vm),
+            verdict => verdict,
+        };
+        if verdict != Verdict::Undecided || vm.halt_reason().is_some() {
+            return verdict;
+        }
+    }
+    Verdict::Undecided
+}
+
+#[test]
+fn test_test_rom_protocols() {
+    // 0x200: LD V0, 0xAA
+    // 0x202: LD I, 0xFFF
+    // 0x204: LD [I], V0
+    // 0x206: JP 0x206
+    let magic = [0x60, 0xAA, 0xAF, 0xFF, 0xF0, 0x55, 0x12, 0x06];
+    let protocol = Protocol::MagicValue {
+        addr: 0xFFF,
+        pass: 0xAA,
+        fail: 0x55,
+    };
+    assert_eq!(run_test_rom(&magic, &protocol, 10), Verdict::Passed);
+
+    // 0x200: LD V0, 1
+    // 0x202: LD F, V0
+    // 0x204: LD V1, 10
+    // 0x206: DRW V1, V1, 5
+    // 0x208: JP 0x208
+    let glyph = [0x60, 0x01, 0xF0, 0x29, 0x61, 0x0A, 0xD1, 0x15, 0x12, 0x08];
+    let protocol = Protocol::Glyph {
+        pass: vec![0xF0, 0x90, 0x90, 0x90, 0xF0],
+        fail: vec![0x20, 0x60, 0x20, 0x20, 0x70],
+    };
+    assert_eq!(run_test_rom(&glyph, &protocol, 10), Verdict::Failed(None));
+
+    // 0x200: LD V0, 3
+    // 0x202: (fail)
+    let host_call = [0x60, 0x03, 0x0F, 0x01];
+    assert_eq!(
+        run_test_rom(&host_call, &Protocol::HostCall, 10),
+        Verdict::Failed(Some(3))
+    );
+    assert_eq!(
+        run_test_rom(&magic, &Protocol::HostCall, 10),
+        Verdict::Undecided
+    );
+}
+
+#[test]
+fn test_glyph_in_high_res() {
+    let mut vm = VirtualMachine::new();
+    vm.high_res = true;
+    vm.display = vm.display.resized(crate::Resolution::High);
+    vm.display.pixels[50 * 128 + 100] = 1;
+    assert!(shows_glyph(&vm, &[0x80]));
+    assert!(!shows_glyph(&vm, &[0xC0]));
+}
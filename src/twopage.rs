@@ -0,0 +1,100 @@
+//! HI-RES CHIP-8, the COSMAC VIP interpreter patched for a 64x64 display.
+//!
+//! The display takes up two pages of VIP memory instead of one. Programs bring the patch
+//! along: they start with `1260`, jumping into 1802 code that sets up the display and then
+//! runs the CHIP-8 program at `0x2C0`. Clearing the display is a call to the patched routine
+//! at `0x230`, as the interpreter's own `00E0` only clears the first page.
+//!
+//! The VM doesn't run the 1802 code, so in two-page mode it skips straight to `0x2C0`, and
+//! treats `SYS 0x230` as clearing the whole display.
+
+use super::{EventKind, FrameBuffer, VirtualMachine};
+
+// The jump HI-RES programs start with, into the patch
+const PATCH_ADDR: u16 = 0x260;
+/// The address the program of a HI-RES CHIP-8 ROM starts at, after the patch.
+pub const PROGRAM_ADDR: u16 = 0x2C0;
+/// The address of the patched routine clearing the 64x64 display.
+pub const CLEAR_ROUTINE: u16 = 0x230;
+
+/// Returns whether `rom` looks like a HI-RES CHIP-8 program, starting with `1260`.
+///
+/// Programs for the unpatched interpreter rarely begin by jumping over 94 bytes, but it
+/// can happen, so this is a guess.
+pub fn is_two_page_rom(rom: &[u8]) -> bool {
+    rom.starts_with(&[0x12, 0x60])
+}
+
+impl VirtualMachine {
+    /// Switches the 64x64 display of HI-RES CHIP-8 on or off, clearing the display.
+    ///
+    /// The SUPER-CHIP high resolution mode takes precedence while it's on.
+    pub fn set_two_page(&mut self, two_page: bool) {
+        if self.two_page != two_page {
+            self.two_page = two_page;
+            self.display = FrameBuffer::new(self.current_resolution());
+            self.emit(EventKind::ResolutionChanged(self.current_resolution()));
+            self.display_changed();
+        }
+    }
+
+    /// Returns whether the 64x64 display of HI-RES CHIP-8 is on.
+    pub fn is_two_page(&self) -> bool {
+        self.two_page
+    }
+
+    // The address a jump to `addr` ends up at, skipping the patch of a HI-RES program
+    pub(super) fn two_page_jump(&self, addr: u16) -> u16 {
+        if self.two_page && addr == PATCH_ADDR && self.pc == 0x202 {
+            PROGRAM_ADDR
+        } else {
+            addr
+        }
+    }
+
+    // Runs the patched routines a HI-RES program calls, returning whether there was one
+    pub(super) fn two_page_routine(&mut self, addr: u16) -> bool {
+        if self.two_page && addr == CLEAR_ROUTINE {
+            self.clear_display();
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[test]
+fn test_two_page() {
+    let mut rom = vec![0; usize::from(PROGRAM_ADDR - 0x200) + 10];
+    // 0x200: JP 0x260
+    rom[..2].copy_from_slice(&[0x12, 0x60]);
+    // 0x2C0: LD V0, 40
+    // 0x2C2: DRW V0, V0, 1, drawing the top left pixel of the sprite at 0x2C1
+    // 0x2C4: SYS 0x230
+    // 0x2C6: DRW V0, V0, 1
+    // 0x2C8: JP 0x2C8
+    rom[usize::from(PROGRAM_ADDR - 0x200)..]
+        .copy_from_slice(&[0x60, 0x28, 0xD0, 0x01, 0x02, 0x30, 0xD0, 0x01, 0x12, 0xC8]);
+    assert!(is_two_page_rom(&rom));
+    let mut vm = VirtualMachine::new();
+    vm.load_rom(&rom);
+    vm.set_two_page(true);
+    assert_eq!(vm.display_size(), (64, 64));
+    for _ in 0..3 {
+        vm.do_cycle();
+    }
+    assert!(vm.display.get(40, 40));
+    // Saving and loading keeps the display
+    let mut loaded = VirtualMachine::new();
+    loaded.load_state(&vm.save_state()).unwrap();
+    assert!(loaded.is_two_page());
+    assert!(loaded.display.get(40, 40));
+    vm.do_cycle();
+    assert!(!vm.display.pixels().contains(&1));
+    assert_eq!(vm.pc(), 0x2C6);
+    // Without the patch, the same ROM is a plain CHIP-8 program, and SYS does nothing
+    let mut vm = VirtualMachine::new();
+    vm.load_rom(&rom);
+    vm.do_cycle();
+    assert_eq!(vm.pc(), PATCH_ADDR);
+}
@@ -0,0 +1,201 @@
+//! Presets for the platforms CHIP-8 programs were written for.
+//!
+//! Programs expect the quirks and instructions of the interpreter they were written for.
+//! Rather than setting a dozen quirks one by one, a [`Variant`] sets all of them at once,
+//! along with the extensions whose instructions are understood.
+
+use {
+    super::{VirtualMachine, opcodes::Extension, quirks::Quirks},
+    std::{fmt, str::FromStr},
+};
+
+/// A CHIP-8 platform.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Variant {
+    /// The original interpreter on the COSMAC VIP.
+    CosmacVip,
+    /// [HI-RES CHIP-8](crate::twopage), the VIP interpreter with a 64x64 display.
+    HiresVip,
+    /// CHIP-48 on the HP 48 calculators.
+    Chip48,
+    /// SUPER-CHIP 1.1 on the HP 48 calculators.
+    SuperChip,
+    /// [MegaChip](crate::megachip), SUPER-CHIP with a 256-color screen.
+    MegaChip,
+    /// Octo's XO-CHIP.
+    XoChip,
+    /// [CHIP-8X](crate::chip8x), the VIP interpreter for the color board.
+    Chip8X,
+}
+
+impl Variant {
+    /// All variants, oldest first.
+    pub const ALL: [Variant; 7] = [
+        Variant::CosmacVip,
+        Variant::HiresVip,
+        Variant::Chip48,
+        Variant::SuperChip,
+        Variant::MegaChip,
+        Variant::XoChip,
+        Variant::Chip8X,
+    ];
+
+    /// The name of the variant, as parsed by [`FromStr`].
+    pub fn name(self) -> &'static str {
+        match self {
+            Variant::CosmacVip => "vip",
+            Variant::HiresVip => "hires",
+            Variant::Chip48 => "chip48",
+            Variant::SuperChip => "schip",
+            Variant::MegaChip => "megachip",
+            Variant::XoChip => "xochip",
+            Variant::Chip8X => "chip8x",
+        }
+    }
+
+    /// The quirks of the platform.
+    pub fn quirks(self) -> Quirks {
+        let defaults = Quirks::default();
+        match self {
+            Variant::CosmacVip | Variant::HiresVip | Variant::Chip8X => Quirks {
+                shift_uses_vy: true,
+                min_beep: true,
+                load_store_keeps_i: false,
+                logic_resets_vf: true,
+                jump_uses_vx: false,
+                ..defaults
+            },
+            Variant::Chip48 => Quirks {
+                shift_uses_vy: false,
+                load_store_keeps_i: false,
+                logic_resets_vf: false,
+                jump_uses_vx: true,
+                ..defaults
+            },
+            Variant::SuperChip | Variant::MegaChip => Quirks {
+                shift_uses_vy: false,
+                resolution_switch_clears: true,
+                load_store_keeps_i: true,
+                logic_resets_vf: false,
+                jump_uses_vx: true,
+                ..defaults
+            },
+            Variant::XoChip => Quirks {
+                shift_uses_vy: true,
+                resolution_switch_clears: true,
+                load_store_keeps_i: false,
+                logic_resets_vf: false,
+                jump_uses_vx: false,
+                ..defaults
+            },
+        }
+    }
+
+    /// The address programs of the platform are loaded at.
+    pub const fn start_addr(self) -> u16 {
+        match self {
+            // The CHIP-8X interpreter takes up more memory
+            Variant::Chip8X => 0x300,
+            _ => crate::START_ADDR,
+        }
+    }
+
+    /// The newest extension the platform understands. Each extension builds on the ones
+    /// before it, except for [`Extension::Chip8X`], which only builds on CHIP-8, and
+    /// [`Extension::MegaChip`], which only builds on SUPER-CHIP.
+    pub fn extension(self) -> Extension {
+        match self {
+            Variant::CosmacVip | Variant::HiresVip | Variant::Chip48 => Extension::Chip8,
+            Variant::SuperChip => Extension::SuperChip,
+            Variant::MegaChip => Extension::MegaChip,
+            Variant::XoChip => Extension::XoChip,
+            Variant::Chip8X => Extension::Chip8X,
+        }
+    }
+}
+
+impl fmt::Display for Variant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Variant {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Variant::ALL
+            .into_iter()
+            .find(|variant| variant.name() == s)
+            .ok_or_else(|| {
+                format!(
+                    "Unknown variant: {} (expected vip, hires, chip48, schip, megachip, xochip or chip8x)",
+                    s
+                )
+            })
+    }
+}
+
+impl VirtualMachine {
+    /// Creates a VM set up like `variant`.
+    pub fn with_variant(variant: Variant) -> Self {
+        let mut vm = VirtualMachine::new();
+        vm.set_variant(variant);
+        vm
+    }
+
+    /// Sets the quirks and the understood extensions to those of `variant`. With the `hires`
+    /// feature, also turns the 64x64 display of HI-RES CHIP-8 on for [`Variant::HiresVip`].
+    ///
+    /// Before anything ran, the program counter moves to where programs of the variant
+    /// start, so set the variant before loading the ROM.
+    ///
+    /// Instructions of newer extensions are treated as unknown, as are those of extensions
+    /// that weren't compiled in. Memory stays 4 KiB for every variant, so XO-CHIP programs
+    /// using more memory don't run.
+    pub fn set_variant(&mut self, variant: Variant) {
+        self.set_quirks(variant.quirks());
+        self.extension = variant.extension();
+        #[cfg(feature = "hires")]
+        self.set_two_page(variant == Variant::HiresVip);
+        if self.cycles == 0 {
+            self.pc = self.start_addr();
+        }
+    }
+
+    /// Returns the newest extension whose instructions are understood.
+    ///
+    /// Unless a [`Variant`] was set, all extensions that were compiled in are understood.
+    pub fn extension(&self) -> Extension {
+        self.extension
+    }
+}
+
+#[test]
+fn test_variants() {
+    for variant in Variant::ALL {
+        assert_eq!(variant.name().parse(), Ok(variant));
+    }
+    let mut vm = VirtualMachine::with_variant(Variant::Chip48);
+    assert!(vm.quirks().jump_uses_vx);
+    assert_eq!(vm.extension(), Extension::Chip8);
+    // 0x200: LD HF, V0, a SUPER-CHIP instruction
+    // 0x202: LD V0, 1
+    vm.load_rom(&[0xF0, 0x30, 0x60, 0x01]);
+    vm.record_events(true);
+    vm.do_cycle();
+    assert!(
+        vm.take_events()
+            .iter()
+            .any(|event| event.kind == crate::EventKind::UnknownInstruction(0xF030))
+    );
+    vm.do_cycle();
+    assert_eq!(vm.v(0), 1);
+}
+
+#[cfg(feature = "hires")]
+#[test]
+fn test_hires_variant_has_two_pages() {
+    let vm = VirtualMachine::with_variant(Variant::HiresVip);
+    assert_eq!(vm.display_size(), (64, 64));
+}
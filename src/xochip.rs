@@ -0,0 +1,61 @@
+//! XO-CHIP extensions.
+//!
+//! Only compiled in with the `xochip` feature.
+//!
+//! The display has two bit planes, and `PLANE n` selects the ones that are drawn to,
+//! cleared and scrolled, the first one by default. A sprite is drawn to each selected plane
+//! in turn, with the sprite for the second plane following the one for the first in memory.
+//! Pixels of the [`FrameBuffer`](crate::FrameBuffer) hold the planes they're set in.
+
+use {
+    super::{Instruction::*, VirtualMachine},
+    crate::opcodes::{OpcodeSpec, op},
+};
+
+#[rustfmt::skip]
+pub(crate) static OPCODES: &[OpcodeSpec] = &[
+    op!(XoChip, 0xF001, 0xF0FF, "PLANE n", "Select the planes in the bitmask n for drawing.",
+        |o| SelectPlanes { n: o.x }, |vm, o| vm.select_planes(o.x)),
+];
+
+impl VirtualMachine {
+    fn select_planes(&mut self, n: u8) {
+        // There are only two planes
+        self.planes = n & 0b11;
+    }
+}
+
+#[test]
+fn test_planes() {
+    // 0x200: PLANE 2
+    // 0x202: LD I, 0x20E
+    // 0x204: DRW V0, V0, 1
+    // 0x206: PLANE 3
+    // 0x208: DRW V0, V0, 1
+    // 0x20A: PLANE 1
+    // 0x20C: CLS
+    // 0x20E: 0xC0, then 0x80 for the second plane
+    let mut vm = VirtualMachine::new();
+    vm.load_rom(&[
+        0xF2, 0x01, 0xA2, 0x0E, 0xD0, 0x01, 0xF3, 0x01, 0xD0, 0x01, 0xF1, 0x01, 0x00, 0xE0, 0xC0,
+        0x80,
+    ]);
+    for _ in 0..3 {
+        vm.do_cycle();
+    }
+    assert_eq!(&vm.display()[..3], [2, 2, 0]);
+    assert_eq!(vm.v(0xF), 0);
+    vm.do_cycle();
+    vm.do_cycle();
+    // The first plane gets 0xC0, the second 0x80, which collides at the first pixel
+    assert_eq!(&vm.display()[..3], [1, 3, 0]);
+    assert_eq!(vm.v(0xF), 1);
+    vm.do_cycle();
+    vm.do_cycle();
+    // Clearing the first plane leaves the second
+    assert_eq!(&vm.display()[..3], [0, 2, 0]);
+    // The state keeps both
+    let mut loaded = VirtualMachine::new();
+    loaded.load_state(&vm.save_state()).unwrap();
+    assert_eq!(loaded.display(), vm.display());
+}
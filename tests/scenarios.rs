@@ -0,0 +1,18 @@
+use {crusty_chip::scenario::Scenario, std::path::Path};
+
+#[test]
+fn run_scenarios() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/scenarios");
+    let mut ran = 0;
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|ext| ext == "scenario") {
+            let scenario = Scenario::load(&path).unwrap();
+            if let Err(e) = scenario.run() {
+                panic!("{}: {}", path.display(), e);
+            }
+            ran += 1;
+        }
+    }
+    assert!(ran > 0);
+}